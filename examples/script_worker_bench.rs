@@ -0,0 +1,69 @@
+//! Compares one-shot `osascript` execution with the persistent script worker.
+//!
+//! Runs a cheap AppleScript (reading the output volume) repeatedly through
+//! both paths and prints the latency of each:
+//!
+//! ```bash
+//! cargo run --release --example script_worker_bench -- 50
+//! ```
+//!
+//! Requires macOS.
+
+use mac_cli::VolumeController;
+use mac_cli::runner::{self, CommandRunner};
+use mac_cli::script_worker::ScriptWorker;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn main() -> mac_cli::Result<()> {
+    let iterations: usize = std::env::args()
+        .nth(1)
+        .map(|n| n.parse().expect("iterations must be a number"))
+        .unwrap_or(20)
+        .max(1);
+
+    let one_shot = measure(runner::system(), iterations)?;
+    let worker = measure(Arc::new(ScriptWorker::new(runner::system())), iterations)?;
+
+    println!(
+        "{} iterations of `output volume of (get volume settings)`",
+        iterations
+    );
+    report("one-shot osascript", &one_shot);
+    report("script worker", &worker);
+    println!(
+        "speed-up (median): {:.1}x",
+        median(&one_shot).as_secs_f64() / median(&worker).as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Times every call, including the first, so the worker's start-up is counted.
+fn measure(runner: Arc<dyn CommandRunner>, iterations: usize) -> mac_cli::Result<Vec<Duration>> {
+    let volume = VolumeController::with_runner(runner);
+    let mut timings = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let started = Instant::now();
+        volume.get()?;
+        timings.push(started.elapsed());
+    }
+    timings.sort();
+    Ok(timings)
+}
+
+fn median(sorted: &[Duration]) -> Duration {
+    sorted[sorted.len() / 2]
+}
+
+fn report(name: &str, sorted: &[Duration]) {
+    let total: Duration = sorted.iter().sum();
+    println!(
+        "{:<20} total {:>8.1?}  mean {:>8.1?}  median {:>8.1?}  p95 {:>8.1?}  max {:>8.1?}",
+        name,
+        total,
+        total / sorted.len() as u32,
+        median(sorted),
+        sorted[(sorted.len() * 95 / 100).min(sorted.len() - 1)],
+        sorted[sorted.len() - 1],
+    );
+}
@@ -0,0 +1,90 @@
+//! Selection of what the controllers talk to.
+//!
+//! Controllers either drive the real machine, executing commands through a
+//! [`CommandRunner`], drive the simulated machine described in [`crate::sim`],
+//! or only record what they would do, see [`crate::dry_run`].
+
+use crate::dry_run::DryRun;
+use crate::runner::{self, CommandRunner};
+use crate::script_worker::ScriptWorker;
+use crate::sim::SimStore;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Environment variable selecting the backend (`macos` or `sim`).
+pub const BACKEND_ENV: &str = "MAC_CLI_BACKEND";
+
+/// Environment variable overriding the simulation state file.
+pub const SIM_STATE_ENV: &str = "MAC_CLI_SIM_STATE";
+
+/// The backend shared by all controllers.
+#[derive(Clone)]
+pub enum Backend {
+    /// Control macOS, executing external commands through the runner.
+    System(Arc<dyn CommandRunner>),
+    /// Read and write the simulated state file.
+    Sim(SimStore),
+    /// Execute nothing; record every command and native call in the plan.
+    DryRun(Arc<DryRun>),
+}
+
+impl Backend {
+    /// The real macOS backend, spawning child processes.
+    pub fn system() -> Self {
+        Backend::System(runner::system())
+    }
+
+    /// The real macOS backend, running AppleScript in one persistent
+    /// interpreter instead of an `osascript` process per script.
+    ///
+    /// Suited to processes issuing many commands; see [`crate::script_worker`].
+    pub fn persistent() -> Self {
+        Backend::System(Arc::new(ScriptWorker::new(runner::system())))
+    }
+
+    /// The simulation backend using the state file at `path`.
+    pub fn sim(path: impl Into<PathBuf>) -> Self {
+        Backend::Sim(SimStore::new(path))
+    }
+
+    /// Selects the backend from [`BACKEND_ENV`] and [`SIM_STATE_ENV`].
+    ///
+    /// Defaults to [`Backend::system`] unless `MAC_CLI_BACKEND=sim`.
+    pub fn from_env() -> Self {
+        match std::env::var(BACKEND_ENV).as_deref() {
+            Ok("sim") => Backend::sim(
+                std::env::var_os(SIM_STATE_ENV)
+                    .map(PathBuf::from)
+                    .unwrap_or_else(SimStore::default_path),
+            ),
+            _ => Backend::system(),
+        }
+    }
+
+    /// Returns `true` for the simulation backend.
+    pub fn is_sim(&self) -> bool {
+        matches!(self, Backend::Sim(_))
+    }
+
+    /// Returns `true` for the dry-run backend.
+    pub fn is_dry_run(&self) -> bool {
+        matches!(self, Backend::DryRun(_))
+    }
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::system()
+    }
+}
+
+impl fmt::Debug for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::System(_) => f.write_str("System"),
+            Backend::Sim(store) => f.debug_tuple("Sim").field(&store.path()).finish(),
+            Backend::DryRun(_) => f.write_str("DryRun"),
+        }
+    }
+}
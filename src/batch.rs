@@ -0,0 +1,302 @@
+//! `mac batch`: many commands in one process.
+//!
+//! Each line of the script is parsed with the same grammar as the `mac`
+//! command line, split into words like a shell would. The whole script is
+//! parsed before anything runs, so a typo on the last line doesn't leave the
+//! machine half-configured.
+
+use crate::{Commands, Context, execute, long_running, report_error};
+use clap::Parser;
+use mac_cli::output::{Output, OutputFormat};
+use mac_cli::{ErrorKind, MacCliError, Result, Subsystem};
+use serde_json::json;
+use std::io::Read;
+use std::path::Path;
+
+/// One line of a batch script.
+#[derive(Parser, Debug)]
+#[command(name = "mac", no_binary_name = true)]
+struct Line {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+/// A parsed command with its position in the script.
+#[derive(Debug)]
+struct Step {
+    line: usize,
+    text: String,
+    command: Commands,
+}
+
+/// Runs the script at `path` (`-` for stdin), printing each command's output
+/// and then a summary.
+///
+/// Fails with the kind of the first failed command if any command failed.
+pub(crate) fn run(
+    ctx: &Context,
+    path: &Path,
+    keep_going: bool,
+    format: &OutputFormat,
+    json_errors: bool,
+) -> Result<()> {
+    let steps = parse(&read_script(path)?)?;
+    let total = steps.len();
+
+    let mut failures = Vec::new();
+    let mut executed = 0;
+    for step in steps {
+        executed += 1;
+        match execute(ctx, step.command, &format!("mac {}", step.text)) {
+            Ok(output) => println!("{}", output.render(format)),
+            Err(e) => {
+                report_error(
+                    &MacCliError::new(
+                        e.subsystem(),
+                        e.kind(),
+                        format!("line {} (`{}`): {}", step.line, step.text, e),
+                    ),
+                    json_errors,
+                );
+                failures.push((step.line, step.text, e));
+                if !keep_going {
+                    break;
+                }
+            }
+        }
+    }
+
+    let succeeded = executed - failures.len();
+    let skipped = total - executed;
+    let summary = Output::new(
+        format!(
+            "Batch: {} succeeded, {} failed, {} skipped",
+            succeeded,
+            failures.len(),
+            skipped
+        ),
+        json!({
+            "batch": {
+                "total": total,
+                "succeeded": succeeded,
+                "failed": failures.len(),
+                "skipped": skipped,
+                "failures": failures
+                    .iter()
+                    .map(|(line, text, e)| json!({
+                        "line": line,
+                        "command": text,
+                        "error": e.to_json()["error"],
+                    }))
+                    .collect::<Vec<_>>(),
+            }
+        }),
+    );
+
+    // A template describes the commands' documents, not the summary
+    let summary_format = match format {
+        OutputFormat::Template(_) => &OutputFormat::Text,
+        format => format,
+    };
+    println!("{}", summary.render(summary_format));
+
+    match failures.first() {
+        Some((_, _, e)) => Err(MacCliError::new(
+            Subsystem::Cli,
+            e.kind(),
+            format!("{} of {} batch commands failed", failures.len(), total),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn read_script(path: &Path) -> Result<String> {
+    let mut script = String::new();
+    let result = if path == Path::new("-") {
+        std::io::stdin().read_to_string(&mut script).map(|_| ())
+    } else {
+        std::fs::File::open(path).and_then(|mut f| f.read_to_string(&mut script).map(|_| ()))
+    };
+
+    result.map_err(|e| {
+        let kind = if e.kind() == std::io::ErrorKind::NotFound {
+            ErrorKind::NotFound
+        } else {
+            ErrorKind::Other
+        };
+        MacCliError::new(
+            Subsystem::Cli,
+            kind,
+            format!("Failed to read batch script {}", path.display()),
+        )
+        .with_source(e)
+    })?;
+
+    Ok(script)
+}
+
+/// Parses every line, skipping blank lines and `#` comments, and reports all
+/// invalid lines at once.
+fn parse(script: &str) -> Result<Vec<Step>> {
+    let mut steps = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, raw) in script.lines().enumerate() {
+        let line = index + 1;
+        let text = raw.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+
+        match parse_line(text) {
+            Ok(command) => steps.push(Step {
+                line,
+                text: text.to_string(),
+                command,
+            }),
+            Err(message) => errors.push(format!("line {}: {}", line, message)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(MacCliError::invalid_argument(
+            Subsystem::Cli,
+            format!(
+                "Invalid batch script, nothing was run:\n  {}",
+                errors.join("\n  ")
+            ),
+        ));
+    }
+
+    Ok(steps)
+}
+
+/// Parses one command line without the leading `mac`, like a line of a batch
+/// script. Commands that keep running, such as servers, are rejected.
+pub(crate) fn parse_line(text: &str) -> std::result::Result<Commands, String> {
+    let Some(words) = shlex::split(text) else {
+        return Err("unbalanced quotes".to_string());
+    };
+
+    match Line::try_parse_from(words) {
+        Ok(Line { command }) => {
+            if let Some(name) = long_running(&command) {
+                return Err(format!("cannot run {}", name));
+            }
+            match command {
+                Commands::Batch { .. } => Err("cannot run another batch".to_string()),
+                // Plugins replace the process, so only the built-in commands run here
+                Commands::External(args) => Err(format!(
+                    "unrecognized subcommand '{}'",
+                    args.first()
+                        .map(|a| a.to_string_lossy())
+                        .unwrap_or_default()
+                )),
+                command => Ok(command),
+            }
+        }
+        Err(e) => {
+            // Keep clap's one-line description, without usage and help hints
+            let rendered = e.render().to_string();
+            let message = rendered.lines().next().unwrap_or_default();
+            Err(message
+                .strip_prefix("error: ")
+                .unwrap_or(message)
+                .to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConfigCommands;
+    use crate::test_support::temp_path;
+    use mac_cli::VolumeController;
+
+    #[test]
+    fn blank_lines_and_comments_are_skipped() {
+        let steps = parse("\n# Set up\nvolume 40\n   \n  # indented\n  music play  \n").unwrap();
+
+        let lines: Vec<(usize, &str)> = steps.iter().map(|s| (s.line, s.text.as_str())).collect();
+        assert_eq!(lines, [(3, "volume 40"), (6, "music play")]);
+    }
+
+    #[test]
+    fn words_are_split_like_a_shell() {
+        for text in [
+            r#"config set weather.location "New York, NY""#,
+            "config set weather.location 'New York, NY'",
+            r"config set weather.location New\ York,\ NY",
+        ] {
+            match parse_line(text) {
+                Ok(Commands::Config(ConfigCommands::Set { key, value })) => {
+                    assert_eq!(key, "weather.location");
+                    assert_eq!(value, "New York, NY", "{}", text);
+                }
+                other => panic!("{}: {:?}", text, other),
+            }
+        }
+
+        assert_eq!(
+            parse_line("config set weather.location 'New York").unwrap_err(),
+            "unbalanced quotes"
+        );
+    }
+
+    #[test]
+    fn long_running_and_nested_commands_are_rejected() {
+        for (text, message) in [
+            ("serve", "cannot run mac serve"),
+            ("daemon --socket /tmp/mac.sock", "cannot run mac daemon"),
+            ("rules run", "cannot run mac rules run"),
+            ("schedule run", "cannot run mac schedule run"),
+            ("batch script.txt", "cannot run another batch"),
+            ("frobnicate", "unrecognized subcommand 'frobnicate'"),
+        ] {
+            assert_eq!(parse_line(text).unwrap_err(), message, "{}", text);
+        }
+    }
+
+    #[test]
+    fn every_invalid_line_is_reported() {
+        let err = parse("volume 40\nvolum 40\nmusic play\nserve\n").unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+        let message = err.to_string();
+        assert!(message.contains("nothing was run"), "{}", message);
+        assert!(
+            message.contains("line 2: unrecognized subcommand 'volum'"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("line 4: cannot run mac serve"),
+            "{}",
+            message
+        );
+        assert!(!message.contains("line 1"), "{}", message);
+    }
+
+    #[test]
+    fn keep_going_runs_the_commands_after_a_failure() {
+        for keep_going in [false, true] {
+            let name = if keep_going { "keep-going" } else { "stop" };
+            let script = temp_path("batch", name, "txt");
+            std::fs::write(&script, "volume 40\nvolume 150\nvolume 30\n").unwrap();
+            let ctx = Context::sim("batch", name);
+
+            let err = run(&ctx, &script, keep_going, &OutputFormat::Json, false).unwrap_err();
+            let volume = VolumeController::with_backend(ctx.backend.clone())
+                .get()
+                .unwrap();
+            let _ = std::fs::remove_file(&script);
+            ctx.remove_files();
+
+            assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+            assert_eq!(err.message(), "1 of 3 batch commands failed");
+            let expected = if keep_going { 0.3 } else { 0.4 };
+            assert_eq!(volume, expected, "keep_going = {}", keep_going);
+        }
+    }
+}
@@ -1,32 +1,190 @@
 //! Bluetooth device listing for macOS using system_profiler.
 //!
 //! This module provides an interface to list Bluetooth devices on macOS
-//! by querying system_profiler.
+//! by querying system_profiler, or on the simulated machine.
 
-use std::process::Command;
+use crate::backend::Backend;
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::runner::{self, CommandOutput, CommandRunner, CommandSpec};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A paired Bluetooth device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    pub name: String,
+    #[serde(default)]
+    pub connected: bool,
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Battery level in percent, if the device reports one.
+    #[serde(default)]
+    pub battery: Option<u8>,
+}
 
 /// Controller for Bluetooth device information on macOS.
 ///
 /// Uses system_profiler to retrieve information about paired and connected Bluetooth devices.
-pub struct BluetoothController;
+pub struct BluetoothController {
+    backend: Backend,
+}
+
+impl Default for BluetoothController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl BluetoothController {
-    pub fn list_devices() -> Result<String, String> {
+    /// Creates a new Bluetooth controller.
+    pub fn new() -> Self {
+        Self::with_backend(Backend::system())
+    }
+
+    /// Creates a controller that executes commands through `runner`.
+    pub fn with_runner(runner: Arc<dyn CommandRunner>) -> Self {
+        Self::with_backend(Backend::System(runner))
+    }
+
+    /// Creates a controller for the given backend.
+    pub fn with_backend(backend: Backend) -> Self {
+        BluetoothController { backend }
+    }
+
+    fn system_profiler(runner: &dyn CommandRunner, command: CommandSpec) -> Result<String> {
+        let output = runner::run_checked(
+            runner,
+            Subsystem::Bluetooth,
+            &command,
+            |o: &CommandOutput| {
+                MacCliError::new(
+                    Subsystem::Bluetooth,
+                    ErrorKind::CommandFailed,
+                    format!("system_profiler error: {}", o.stderr.trim()),
+                )
+            },
+        )?;
+
+        Ok(output.stdout)
+    }
+
+    /// Returns the raw `system_profiler SPBluetoothDataType -json` output.
+    ///
+    /// The simulation backend produces a document of the same shape.
+    pub fn list_devices(&self) -> Result<String> {
+        let runner: &dyn CommandRunner = match &self.backend {
+            Backend::System(runner) => runner.as_ref(),
+            Backend::DryRun(plan) => plan.as_ref(),
+            Backend::Sim(sim) => {
+                let devices = sim.load(Subsystem::Bluetooth)?.bluetooth;
+                return Ok(Self::sim_profile(&devices).to_string());
+            }
+        };
+
         // Use system_profiler to get Bluetooth device info
-        let output = Command::new("system_profiler")
-            .arg("SPBluetoothDataType")
-            .arg("-json")
-            .output()
-            .map_err(|e| format!("Failed to execute system_profiler: {}", e))?;
-
-        if !output.status.success() {
-            return Err(format!(
-                "system_profiler error: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
+        Self::system_profiler(
+            runner,
+            CommandSpec::new("system_profiler")
+                .arg("SPBluetoothDataType")
+                .arg("-json"),
+        )
+    }
+
+    /// Renders simulated devices as `system_profiler -json` output.
+    fn sim_profile(devices: &[Device]) -> serde_json::Value {
+        let entries = |connected: bool| -> Vec<serde_json::Value> {
+            devices
+                .iter()
+                .filter(|d| d.connected == connected)
+                .map(|d| {
+                    let mut props = serde_json::Map::new();
+                    if let Some(address) = &d.address {
+                        props.insert("device_address".into(), address.clone().into());
+                    }
+                    if let Some(battery) = d.battery {
+                        props.insert(
+                            "device_batteryLevelMain".into(),
+                            format!("{}%", battery).into(),
+                        );
+                    }
+                    serde_json::json!({ d.name.clone(): props })
+                })
+                .collect()
+        };
+
+        serde_json::json!({
+            "SPBluetoothDataType": [{
+                "device_connected": entries(true),
+                "device_not_connected": entries(false),
+            }]
+        })
+    }
+
+    /// Lists paired Bluetooth devices with their connection state and battery level.
+    pub fn devices(&self) -> Result<Vec<Device>> {
+        if let Backend::Sim(sim) = &self.backend {
+            return Ok(sim.load(Subsystem::Bluetooth)?.bluetooth);
         }
 
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        let json = self.list_devices()?;
+        let profile: serde_json::Value = serde_json::from_str(&json).map_err(|e| {
+            MacCliError::new(
+                Subsystem::Bluetooth,
+                ErrorKind::Parse,
+                "Failed to parse system_profiler output",
+            )
+            .with_source(e)
+        })?;
+
+        Ok(Self::parse_profile(&profile))
+    }
+
+    /// Extracts devices from `system_profiler SPBluetoothDataType -json` output.
+    ///
+    /// Devices are grouped under `device_connected` and `device_not_connected`,
+    /// each an array of single-key objects mapping the device name to its properties.
+    fn parse_profile(profile: &serde_json::Value) -> Vec<Device> {
+        let mut devices = Vec::new();
+        let sections = profile["SPBluetoothDataType"]
+            .as_array()
+            .into_iter()
+            .flatten();
+
+        for section in sections {
+            for (key, connected) in [("device_connected", true), ("device_not_connected", false)] {
+                let entries = section[key].as_array().into_iter().flatten();
+                let devices_in_section = entries
+                    .filter_map(|e| e.as_object())
+                    .flatten()
+                    .filter_map(|(name, props)| Some((name, props.as_object()?)));
+
+                for (name, props) in devices_in_section {
+                    // Prefer the main battery, falling back to e.g. the left earbud
+                    let battery = props
+                        .get("device_batteryLevelMain")
+                        .or_else(|| {
+                            props
+                                .iter()
+                                .find(|(k, _)| k.starts_with("device_batteryLevel"))
+                                .map(|(_, v)| v)
+                        })
+                        .and_then(|v| v.as_str())
+                        .and_then(|v| v.trim_end_matches('%').parse().ok());
+
+                    devices.push(Device {
+                        name: name.clone(),
+                        connected,
+                        address: props
+                            .get("device_address")
+                            .and_then(|v| v.as_str())
+                            .map(str::to_string),
+                        battery,
+                    });
+                }
+            }
+        }
+
+        devices
     }
 
     /// Lists Bluetooth devices in a simple, parsed format.
@@ -34,21 +192,21 @@ impl BluetoothController {
     /// # Returns
     ///
     /// Returns a vector of Bluetooth device names.
-    pub fn list_devices_simple() -> Result<Vec<String>, String> {
-        // Simple approach: parse the output to get device names
-        let output = Command::new("system_profiler")
-            .arg("SPBluetoothDataType")
-            .output()
-            .map_err(|e| format!("Failed to execute system_profiler: {}", e))?;
-
-        if !output.status.success() {
-            return Err(format!(
-                "system_profiler error: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
-        }
+    pub fn list_devices_simple(&self) -> Result<Vec<String>> {
+        let runner: &dyn CommandRunner = match &self.backend {
+            Backend::System(runner) => runner.as_ref(),
+            Backend::DryRun(plan) => plan.as_ref(),
+            Backend::Sim(sim) => {
+                let devices = sim.load(Subsystem::Bluetooth)?.bluetooth;
+                return Ok(devices.into_iter().map(|d| d.name).collect());
+            }
+        };
 
-        let output_str = String::from_utf8_lossy(&output.stdout);
+        // Simple approach: parse the output to get device names
+        let output_str = Self::system_profiler(
+            runner,
+            CommandSpec::new("system_profiler").arg("SPBluetoothDataType"),
+        )?;
         let mut devices = Vec::new();
 
         // Parse connected devices
@@ -75,3 +233,99 @@ impl BluetoothController {
         Ok(devices)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{Expectation, FakeRunner};
+
+    fn profiler(output: CommandOutput) -> Arc<FakeRunner> {
+        Arc::new(
+            FakeRunner::new().expect(
+                Expectation::new("system_profiler")
+                    .args(["SPBluetoothDataType", "-json"])
+                    .returns(output),
+            ),
+        )
+    }
+
+    #[test]
+    fn devices_parses_profile() {
+        let profile = r#"{
+            "SPBluetoothDataType": [{
+                "device_connected": [
+                    { "AirPods": { "device_address": "AA:BB", "device_batteryLevelLeft": "70%" } }
+                ],
+                "device_not_connected": [
+                    { "Keyboard": { "device_batteryLevelMain": "80%" } }
+                ]
+            }]
+        }"#;
+        let runner = profiler(CommandOutput::success(profile));
+
+        let devices = BluetoothController::with_runner(runner.clone())
+            .devices()
+            .unwrap();
+        assert_eq!(
+            devices,
+            [
+                Device {
+                    name: "AirPods".into(),
+                    connected: true,
+                    address: Some("AA:BB".into()),
+                    battery: Some(70),
+                },
+                Device {
+                    name: "Keyboard".into(),
+                    connected: false,
+                    address: None,
+                    battery: Some(80),
+                },
+            ]
+        );
+        runner.assert_done();
+    }
+
+    #[test]
+    fn devices_rejects_invalid_json() {
+        let runner = profiler(CommandOutput::success("not json"));
+
+        let err = BluetoothController::with_runner(runner)
+            .devices()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Parse);
+    }
+
+    #[test]
+    fn profiler_failure_maps_to_command_failed() {
+        let runner = profiler(CommandOutput::failure(1, "no Bluetooth controller"));
+
+        let err = BluetoothController::with_runner(runner)
+            .devices()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::CommandFailed);
+        assert_eq!(
+            err.message(),
+            "system_profiler error: no Bluetooth controller"
+        );
+    }
+
+    #[test]
+    fn list_devices_simple_skips_section_headers() {
+        let text = "Bluetooth:\n\n      Bluetooth Controller:\n          Address: AA\n      \
+                    Connected:\n          AirPods:\n              Address: BB\n      \
+                    Not Connected:\n          Keyboard:\n";
+        let runner = Arc::new(
+            FakeRunner::new().expect(
+                Expectation::new("system_profiler")
+                    .args(["SPBluetoothDataType"])
+                    .returns(CommandOutput::success(text)),
+            ),
+        );
+
+        let names = BluetoothController::with_runner(runner)
+            .list_devices_simple()
+            .unwrap();
+        assert_eq!(names, ["AirPods", "Keyboard"]);
+    }
+}
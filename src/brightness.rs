@@ -1,11 +1,17 @@
 //! Brightness control for macOS displays using the DisplayServices framework.
 //!
 //! This module provides an interface to get and set screen brightness on macOS
-//! by accessing the private DisplayServices framework.
+//! by accessing the private DisplayServices framework, or on the simulated machine.
 
+use crate::backend::Backend;
+use crate::dry_run::DryRun;
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::sim::SimStore;
+#[cfg(target_os = "macos")]
 use core_graphics::display::{CGDirectDisplayID, CGGetActiveDisplayList};
 use std::ffi::CString;
 use std::os::raw::{c_char, c_float, c_int, c_void};
+use std::sync::Arc;
 
 const RTLD_LAZY: c_int = 0x1;
 const RTLD_DEFAULT: *mut c_void = -2isize as *mut c_void;
@@ -16,16 +22,57 @@ unsafe extern "C" {
     fn dlclose(handle: *mut c_void) -> c_int;
 }
 
+/// Display identifier as used by CoreGraphics.
+#[cfg(not(target_os = "macos"))]
+type CGDirectDisplayID = u32;
+
 type DisplayServicesGetBrightnessFn =
     unsafe extern "C" fn(display: CGDirectDisplayID, brightness: *mut c_float) -> c_int;
 
 type DisplayServicesSetBrightnessFn =
     unsafe extern "C" fn(display: CGDirectDisplayID, brightness: c_float) -> c_int;
 
+/// The display ID of the simulated machine's only display.
+pub const SIM_DISPLAY_ID: u32 = 1;
+
+/// Checks a brightness percentage requested by the user: it must be between
+/// `min`, the `brightness.min` setting, and 100.
+///
+/// 0 is rejected even when `min` allows it, as it turns the display off.
+pub fn check_percentage(percentage: f32, min: f32) -> Result<()> {
+    if percentage == 0.0 {
+        return Err(MacCliError::invalid_argument(
+            Subsystem::Brightness,
+            "Brightness cannot be 0",
+        ));
+    }
+    if !(min..=100.0).contains(&percentage) {
+        return Err(MacCliError::invalid_argument(
+            Subsystem::Brightness,
+            format!("Brightness must be between {} and 100", min),
+        ));
+    }
+    Ok(())
+}
+
 /// Controller for managing display brightness on macOS.
 ///
-/// Uses the DisplayServices framework to control the brightness of the primary display.
+/// Uses the DisplayServices framework to control the brightness of one display,
+/// the primary display unless created with [`BrightnessController::for_display`].
 pub struct BrightnessController {
+    display: Display,
+}
+
+enum Display {
+    Native(DisplayServices),
+    Sim(SimStore),
+    /// Records DisplayServices calls; the ID is `None` when the displays
+    /// cannot be listed on this system.
+    DryRun(Arc<DryRun>, Option<CGDirectDisplayID>),
+}
+
+/// Resolved DisplayServices entry points for one display.
+struct DisplayServices {
     display_id: CGDirectDisplayID,
     handle: *mut c_void,
     get_brightness_fn: DisplayServicesGetBrightnessFn,
@@ -39,22 +86,129 @@ impl BrightnessController {
     ///
     /// Returns an error if no active displays are found or if the DisplayServices
     /// framework functions are not available.
-    pub fn new() -> Result<Self, String> {
-        let mut display_count: u32 = 0;
-        let mut displays: [CGDirectDisplayID; 16] = [0; 16];
+    pub fn new() -> Result<Self> {
+        Self::with_backend(&Backend::system())
+    }
 
-        unsafe {
-            let result =
-                CGGetActiveDisplayList(16, displays.as_mut_ptr(), &mut display_count);
+    /// Creates a brightness controller for the given backend.
+    ///
+    /// The command runner of [`Backend::System`] is not used: brightness is
+    /// controlled through DisplayServices directly.
+    pub fn with_backend(backend: &Backend) -> Result<Self> {
+        let display = match backend {
+            Backend::System(_) => Display::Native(DisplayServices::open(active_displays()?[0])?),
+            Backend::Sim(store) => Display::Sim(store.clone()),
+            Backend::DryRun(plan) => {
+                Display::DryRun(plan.clone(), active_displays().ok().map(|ids| ids[0]))
+            }
+        };
 
-            if result != 0 {
-                return Err("Failed to get active displays".to_string());
+        Ok(BrightnessController { display })
+    }
+
+    /// Creates a brightness controller for the display with the given ID, as
+    /// returned by [`Self::displays`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ErrorKind::NotFound`] if no such display is active.
+    pub fn for_display(backend: &Backend, display_id: u32) -> Result<Self> {
+        if !Self::displays(backend)?.contains(&display_id) {
+            return Err(MacCliError::new(
+                Subsystem::Brightness,
+                ErrorKind::NotFound,
+                format!("Display not found: {}", display_id),
+            ));
+        }
+
+        let display = match backend {
+            Backend::System(_) => Display::Native(DisplayServices::open(display_id)?),
+            Backend::Sim(store) => Display::Sim(store.clone()),
+            Backend::DryRun(plan) => Display::DryRun(plan.clone(), Some(display_id)),
+        };
+
+        Ok(BrightnessController { display })
+    }
+
+    /// Lists the IDs of the active displays, primary display first.
+    pub fn displays(backend: &Backend) -> Result<Vec<u32>> {
+        match backend {
+            Backend::System(_) => active_displays(),
+            Backend::Sim(_) => Ok(vec![SIM_DISPLAY_ID]),
+            // Listing displays changes nothing, but may be unsupported here
+            Backend::DryRun(_) => Ok(active_displays().unwrap_or_default()),
+        }
+    }
+
+    /// The ID of the display this controller adjusts.
+    ///
+    /// A dry run on a system without displays reports `0`.
+    pub fn display_id(&self) -> u32 {
+        match &self.display {
+            Display::Native(ds) => ds.display_id,
+            Display::Sim(_) => SIM_DISPLAY_ID,
+            Display::DryRun(_, id) => id.unwrap_or(0),
+        }
+    }
+
+    /// Gets the current brightness level.
+    ///
+    /// # Returns
+    ///
+    /// Returns a value between 0.0 (minimum) and 1.0 (maximum).
+    pub fn get(&self) -> Result<f32> {
+        match &self.display {
+            Display::Native(ds) => ds.get(),
+            Display::Sim(store) => Ok(store.load(Subsystem::Brightness)?.brightness),
+            Display::DryRun(plan, id) => {
+                // Like a command's empty output, the result of a dry run is a placeholder
+                plan.record(format!(
+                    "DisplayServicesGetBrightness({})",
+                    display_label(*id)
+                ));
+                Ok(0.0)
             }
+        }
+    }
+
+    /// Sets the brightness level.
+    ///
+    /// # Arguments
+    ///
+    /// * `brightness` - A value between 0.0 (minimum) and 1.0 (maximum).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the brightness value is out of range or if setting fails.
+    pub fn set(&self, brightness: f32) -> Result<()> {
+        if !(0.0..=1.0).contains(&brightness) {
+            return Err(MacCliError::invalid_argument(
+                Subsystem::Brightness,
+                "Brightness must be between 0.0 and 1.0",
+            ));
+        }
 
-            if display_count == 0 {
-                return Err("No active displays found".to_string());
+        match &self.display {
+            Display::Native(ds) => ds.set(brightness),
+            Display::Sim(store) => store.update(Subsystem::Brightness, |state| {
+                state.brightness = brightness;
+                Ok(())
+            }),
+            Display::DryRun(plan, id) => {
+                plan.record(format!(
+                    "DisplayServicesSetBrightness({}, {:.2})",
+                    display_label(*id),
+                    brightness
+                ));
+                Ok(())
             }
+        }
+    }
+}
 
+impl DisplayServices {
+    fn open(display_id: CGDirectDisplayID) -> Result<Self> {
+        unsafe {
             let framework_paths = vec![
                 "/System/Library/PrivateFrameworks/DisplayServices.framework/DisplayServices",
                 "/System/Library/PrivateFrameworks/SkyLight.framework/SkyLight",
@@ -64,6 +218,7 @@ impl BrightnessController {
             for path in &framework_paths {
                 let framework_path = CString::new(*path).unwrap();
                 handle = dlopen(framework_path.as_ptr(), RTLD_LAZY);
+                tracing::debug!(path, loaded = !handle.is_null(), "dlopen");
                 if !handle.is_null() {
                     break;
                 }
@@ -80,21 +235,30 @@ impl BrightnessController {
 
             let get_fn_ptr = dlsym(search_handle, get_brightness_name.as_ptr());
             let set_fn_ptr = dlsym(search_handle, set_brightness_name.as_ptr());
+            tracing::debug!(
+                display_id,
+                in_default_namespace = handle.is_null(),
+                get_found = !get_fn_ptr.is_null(),
+                set_found = !set_fn_ptr.is_null(),
+                "dlsym DisplayServicesGetBrightness/DisplayServicesSetBrightness"
+            );
 
             if get_fn_ptr.is_null() || set_fn_ptr.is_null() {
                 if !handle.is_null() {
                     dlclose(handle);
                 }
-                return Err("DisplayServices functions not available on this system.".to_string());
+                return Err(MacCliError::new(
+                    Subsystem::Brightness,
+                    ErrorKind::Unavailable,
+                    "DisplayServices functions not available on this system.",
+                ));
             }
 
-            let get_brightness_fn: DisplayServicesGetBrightnessFn =
-                std::mem::transmute(get_fn_ptr);
-            let set_brightness_fn: DisplayServicesSetBrightnessFn =
-                std::mem::transmute(set_fn_ptr);
+            let get_brightness_fn: DisplayServicesGetBrightnessFn = std::mem::transmute(get_fn_ptr);
+            let set_brightness_fn: DisplayServicesSetBrightnessFn = std::mem::transmute(set_fn_ptr);
 
-            Ok(BrightnessController {
-                display_id: displays[0],
+            Ok(DisplayServices {
+                display_id,
                 handle,
                 get_brightness_fn,
                 set_brightness_fn,
@@ -102,42 +266,44 @@ impl BrightnessController {
         }
     }
 
-    /// Gets the current brightness level.
-    ///
-    /// # Returns
-    ///
-    /// Returns a value between 0.0 (minimum) and 1.0 (maximum).
-    pub fn get(&self) -> Result<f32, String> {
+    fn get(&self) -> Result<f32> {
         let mut brightness: c_float = 0.0;
 
         unsafe {
             let result = (self.get_brightness_fn)(self.display_id, &mut brightness);
+            tracing::debug!(
+                display_id = self.display_id,
+                result,
+                brightness,
+                "DisplayServicesGetBrightness"
+            );
             if result != 0 {
-                return Err(format!("Failed to get brightness: error code {}", result));
+                return Err(MacCliError::new(
+                    Subsystem::Brightness,
+                    ErrorKind::CommandFailed,
+                    format!("Failed to get brightness: error code {}", result),
+                ));
             }
         }
 
         Ok(brightness)
     }
 
-    /// Sets the brightness level.
-    ///
-    /// # Arguments
-    ///
-    /// * `brightness` - A value between 0.0 (minimum) and 1.0 (maximum).
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if the brightness value is out of range or if setting fails.
-    pub fn set(&self, brightness: f32) -> Result<(), String> {
-        if !(0.0..=1.0).contains(&brightness) {
-            return Err("Brightness must be between 0.0 and 1.0".to_string());
-        }
-
+    fn set(&self, brightness: f32) -> Result<()> {
         unsafe {
             let result = (self.set_brightness_fn)(self.display_id, brightness);
+            tracing::debug!(
+                display_id = self.display_id,
+                result,
+                brightness,
+                "DisplayServicesSetBrightness"
+            );
             if result != 0 {
-                return Err(format!("Failed to set brightness: error code {}", result));
+                return Err(MacCliError::new(
+                    Subsystem::Brightness,
+                    ErrorKind::CommandFailed,
+                    format!("Failed to set brightness: error code {}", result),
+                ));
             }
         }
 
@@ -145,7 +311,53 @@ impl BrightnessController {
     }
 }
 
-impl Drop for BrightnessController {
+/// Describes a display in a dry-run step, e.g. `display 1`.
+fn display_label(id: Option<CGDirectDisplayID>) -> String {
+    match id {
+        Some(id) => format!("display {}", id),
+        None => "primary display".to_string(),
+    }
+}
+
+/// Returns the IDs of the active displays; the first is the primary display.
+#[cfg(target_os = "macos")]
+fn active_displays() -> Result<Vec<CGDirectDisplayID>> {
+    let mut display_count: u32 = 0;
+    let mut displays: [CGDirectDisplayID; 16] = [0; 16];
+
+    let result = unsafe { CGGetActiveDisplayList(16, displays.as_mut_ptr(), &mut display_count) };
+    tracing::debug!(result, display_count, "CGGetActiveDisplayList");
+
+    if result != 0 {
+        return Err(MacCliError::new(
+            Subsystem::Brightness,
+            ErrorKind::Unavailable,
+            format!("Failed to get active displays: error code {}", result),
+        ));
+    }
+
+    if display_count == 0 {
+        return Err(MacCliError::new(
+            Subsystem::Brightness,
+            ErrorKind::Unavailable,
+            "No active displays found",
+        ));
+    }
+
+    Ok(displays[..display_count as usize].to_vec())
+}
+
+/// CoreGraphics is only available on macOS; elsewhere there is no display to control.
+#[cfg(not(target_os = "macos"))]
+fn active_displays() -> Result<Vec<CGDirectDisplayID>> {
+    Err(MacCliError::new(
+        Subsystem::Brightness,
+        ErrorKind::Unavailable,
+        "Brightness control is only supported on macOS",
+    ))
+}
+
+impl Drop for DisplayServices {
     fn drop(&mut self) {
         unsafe {
             if !self.handle.is_null() {
@@ -154,3 +366,57 @@ impl Drop for BrightnessController {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_run_records_display_services_calls() {
+        let plan = Arc::new(DryRun::new());
+        let brightness =
+            BrightnessController::with_backend(&Backend::DryRun(plan.clone())).unwrap();
+
+        brightness.set(0.5).unwrap();
+        assert_eq!(brightness.get().unwrap(), 0.0);
+        assert_eq!(plan.steps().len(), 2);
+        assert!(plan.steps()[0].starts_with("DisplayServicesSetBrightness("));
+        assert!(plan.steps()[0].ends_with(", 0.50)"));
+    }
+
+    #[test]
+    fn set_rejects_out_of_range_levels() {
+        let plan = Arc::new(DryRun::new());
+        let brightness =
+            BrightnessController::with_backend(&Backend::DryRun(plan.clone())).unwrap();
+
+        for level in [-0.1, 1.1, f32::NAN] {
+            let err = brightness.set(level).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+        }
+        assert!(plan.steps().is_empty());
+    }
+
+    #[test]
+    fn check_percentage_applies_minimum() {
+        assert!(check_percentage(10.0, 10.0).is_ok());
+        assert!(check_percentage(100.0, 10.0).is_ok());
+        for percentage in [0.0, 5.0, 101.0, f32::NAN] {
+            let err = check_percentage(percentage, 10.0).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+        }
+        assert_eq!(
+            check_percentage(0.0, 0.0).unwrap_err().message(),
+            "Brightness cannot be 0"
+        );
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn native_backend_is_unavailable() {
+        let err = BrightnessController::with_backend(&Backend::system())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::Unavailable);
+    }
+}
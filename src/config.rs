@@ -0,0 +1,603 @@
+//! User configuration stored in `~/.config/mac-cli/config.toml`.
+//!
+//! The file holds per-subsystem defaults; command-line flags override it.
+//! Every key is optional:
+//!
+//! ```toml
+//! [output]
+//! format = "text"          # "text" or "json"
+//! template = "{volume}%"   # used when no --format/--json flag is given
+//!
+//! [weather]
+//! location = "London, UK"
+//! units = "metric"         # "metric" or "imperial"
+//!
+//! [brightness]
+//! min = 10                 # lowest percentage `mac brightness N` accepts
+//!
+//! [music]
+//! player = "Music"
+//! fzf_options = ["--prompt=Select playlist: ", "--height=40%", "--reverse"]
+//!
+//! [status]
+//! weather_max_age = 600    # seconds a weather report is reused by `mac status`
+//!
+//! [status.timeouts]        # seconds each subsystem may take in `mac status`
+//! bluetooth = 5.0
+//!
+//! [hooks]                  # see `crate::hooks`
+//! on_track_change = "~/bin/scrobble"
+//!
+//! [scenes.focus]           # see `crate::scene`
+//! volume = 30
+//! playlist = "Focus"
+//! ```
+
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::hooks::Hooks;
+use crate::scene::Scene;
+use crate::status::Timeouts;
+use crate::weather::Units;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, TableLike};
+
+/// Environment variable overriding the configuration file path.
+pub const CONFIG_ENV: &str = "MAC_CLI_CONFIG";
+
+/// The complete configuration.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub output: OutputConfig,
+    pub weather: WeatherConfig,
+    pub brightness: BrightnessConfig,
+    pub music: MusicConfig,
+    pub status: StatusConfig,
+    /// Commands run by `mac hooks` when the state changes.
+    pub hooks: Hooks,
+    /// Named scenes applied by `mac scene apply`.
+    pub scenes: BTreeMap<String, Scene>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Default output format: `"text"` or `"json"`.
+    pub format: Option<OutputKind>,
+    /// Default `--format` template.
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputKind {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WeatherConfig {
+    /// Location used when none is given on the command line.
+    pub location: Option<String>,
+    pub units: Units,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrightnessConfig {
+    /// Lowest brightness percentage accepted by `mac brightness N`.
+    pub min: f32,
+}
+
+impl Default for BrightnessConfig {
+    fn default() -> Self {
+        BrightnessConfig { min: 10.0 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MusicConfig {
+    /// Name of the scriptable player application.
+    pub player: String,
+    /// Arguments passed to fzf by the interactive playlist picker.
+    pub fzf_options: Vec<String>,
+}
+
+impl Default for MusicConfig {
+    fn default() -> Self {
+        MusicConfig {
+            player: crate::music::DEFAULT_PLAYER.to_string(),
+            fzf_options: crate::music::DEFAULT_FZF_OPTIONS
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusConfig {
+    /// Seconds a cached weather report is reused by `mac status`.
+    pub weather_max_age: u64,
+    pub timeouts: Timeouts,
+}
+
+impl Default for StatusConfig {
+    fn default() -> Self {
+        StatusConfig {
+            weather_max_age: 600,
+            timeouts: Timeouts::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The default configuration file, `~/.config/mac-cli/config.toml`.
+    pub fn default_path() -> PathBuf {
+        crate::paths::config_dir().join("config.toml")
+    }
+
+    /// Loads the configuration at `path`, or the defaults if it does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        let table = read_table(path)?;
+        Self::from_table(path, table)
+    }
+
+    fn from_table(path: &Path, table: toml::Table) -> Result<Self> {
+        let config: Config =
+            toml::Value::Table(table)
+                .try_into()
+                .map_err(|e: toml::de::Error| {
+                    MacCliError::new(
+                        Subsystem::Cli,
+                        ErrorKind::Parse,
+                        format!("Invalid configuration in {}", path.display()),
+                    )
+                    .with_source(e)
+                })?;
+        config.validate(path)?;
+        Ok(config)
+    }
+
+    /// Checks the values the types alone don't constrain.
+    fn validate(&self, path: &Path) -> Result<()> {
+        let min = self.brightness.min;
+        if !(0.0..=100.0).contains(&min) {
+            return Err(MacCliError::invalid_argument(
+                Subsystem::Cli,
+                format!(
+                    "Invalid configuration in {}: brightness.min must be between 0 and 100, not {}",
+                    path.display(),
+                    min
+                ),
+            ));
+        }
+
+        let timeouts = &self.status.timeouts;
+        for (name, seconds) in [
+            ("volume", timeouts.volume),
+            ("brightness", timeouts.brightness),
+            ("music", timeouts.music),
+            ("bluetooth", timeouts.bluetooth),
+            ("weather", timeouts.weather),
+        ] {
+            if !(seconds > 0.0 && seconds.is_finite()) {
+                return Err(MacCliError::invalid_argument(
+                    Subsystem::Cli,
+                    format!(
+                        "Invalid configuration in {}: status.timeouts.{} must be a positive number of seconds, not {}",
+                        path.display(),
+                        name,
+                        seconds
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns every effective setting, including defaults, as `(key, value)`
+    /// pairs with dotted keys.
+    pub fn entries(&self) -> Vec<(String, toml::Value)> {
+        let value = toml::Value::try_from(self).expect("Config is always serializable");
+        let mut entries = Vec::new();
+        flatten("", &value, &mut entries);
+        entries
+    }
+
+    /// Returns the effective value of a dotted key such as `weather.location`,
+    /// or `None` if it is unset or unknown.
+    pub fn get(&self, key: &str) -> Option<toml::Value> {
+        self.entries()
+            .into_iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Returns the scene called `name`.
+    pub fn scene(&self, name: &str) -> Result<&Scene> {
+        self.scenes.get(name).ok_or_else(|| {
+            MacCliError::new(
+                Subsystem::Cli,
+                ErrorKind::NotFound,
+                format!("Scene not found: {}", name),
+            )
+        })
+    }
+}
+
+/// Sets a dotted key in the configuration file at `path` and returns the new
+/// configuration. `value` is parsed as a TOML value when possible (`40`,
+/// `true`, `["a", "b"]`) and stored as a string otherwise.
+///
+/// The file is edited in place, keeping its comments and layout, and only
+/// written if the result is a valid configuration.
+pub fn set(path: &Path, key: &str, value: &str) -> Result<Config> {
+    let value = parse_value(value);
+
+    let (sections, leaf) = match key.rsplit_once('.') {
+        Some((sections, leaf)) => (sections.split('.').collect::<Vec<_>>(), leaf),
+        None => (Vec::new(), key),
+    };
+
+    edit(path, &format!("Cannot set {}", key), |document| {
+        let mut current: &mut dyn TableLike = document.as_table_mut();
+        for name in sections {
+            current = section(current, name, key)?;
+        }
+
+        replace(current, leaf, value);
+        Ok(())
+    })
+}
+
+/// Stores `scene` as `[scenes.<name>]` in the configuration file at `path`,
+/// replacing any scene of the same name, and returns the new configuration.
+///
+/// Settings the replaced scene shares with `scene` keep their comments.
+pub fn save_scene(path: &Path, name: &str, scene: &Scene) -> Result<Config> {
+    let values = toml_edit::ser::to_document(scene).expect("Scene is always serializable");
+
+    edit(path, &format!("Cannot save scene {}", name), |document| {
+        let scenes = section(document.as_table_mut(), "scenes", "scenes")?;
+        let table = section(scenes, name, "scenes")?;
+
+        let stale: Vec<String> = table
+            .iter()
+            .map(|(key, _)| key.to_string())
+            .filter(|key| !values.contains_key(key))
+            .collect();
+        for key in stale {
+            table.remove(&key);
+        }
+        for (key, item) in values.iter() {
+            if let Some(value) = item.as_value() {
+                let mut value = value.clone();
+                value.decor_mut().clear();
+                replace(table, key, value);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Applies `f` to the document in `path` and writes the result back, but only
+/// if it is still a valid configuration.
+fn edit(
+    path: &Path,
+    context: &str,
+    f: impl FnOnce(&mut DocumentMut) -> Result<()>,
+) -> Result<Config> {
+    let mut document = read_document(path)?;
+    f(&mut document)?;
+
+    let contents = document.to_string();
+    let config = parse_table(path, &contents)
+        .and_then(|table| Config::from_table(path, table))
+        .map_err(|e| {
+            MacCliError::invalid_argument(Subsystem::Cli, context.to_string()).with_source(e)
+        })?;
+
+    write_contents(path, &contents)?;
+    Ok(config)
+}
+
+/// Returns the table `name` inside `table`, creating it if it is missing.
+fn section<'a>(
+    table: &'a mut dyn TableLike,
+    name: &str,
+    key: &str,
+) -> Result<&'a mut dyn TableLike> {
+    table
+        .entry(name)
+        .or_insert_with(|| {
+            let mut table = toml_edit::Table::new();
+            // Only give the section a header once it has settings of its own
+            table.set_implicit(true);
+            Item::Table(table)
+        })
+        .as_table_like_mut()
+        .ok_or_else(|| unknown_key(key))
+}
+
+/// Sets `key` to `value`, keeping the comments around an existing entry.
+fn replace(table: &mut dyn TableLike, key: &str, mut value: toml_edit::Value) {
+    match table.get_mut(key) {
+        Some(item) => {
+            if let Some(old) = item.as_value() {
+                *value.decor_mut() = old.decor().clone();
+            }
+            *item = Item::Value(value);
+        }
+        None => {
+            table.insert(key, Item::Value(value));
+        }
+    }
+}
+
+fn parse_value(value: &str) -> toml_edit::Value {
+    match value.parse::<toml_edit::Value>() {
+        Ok(mut parsed) => {
+            parsed.decor_mut().clear();
+            parsed
+        }
+        Err(_) => toml_edit::Value::from(value),
+    }
+}
+
+fn read_table(path: &Path) -> Result<toml::Table> {
+    match read_contents(path)? {
+        Some(contents) => parse_table(path, &contents),
+        None => Ok(toml::Table::new()),
+    }
+}
+
+fn read_document(path: &Path) -> Result<DocumentMut> {
+    let Some(contents) = read_contents(path)? else {
+        return Ok(DocumentMut::new());
+    };
+
+    contents.parse().map_err(|e: toml_edit::TomlError| {
+        MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Parse,
+            format!("Invalid configuration in {}", path.display()),
+        )
+        .with_source(e)
+    })
+}
+
+fn read_contents(path: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(io_error("read", path, e)),
+    }
+}
+
+fn parse_table(path: &Path, contents: &str) -> Result<toml::Table> {
+    contents.parse().map_err(|e: toml::de::Error| {
+        MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Parse,
+            format!("Invalid configuration in {}", path.display()),
+        )
+        .with_source(e)
+    })
+}
+
+fn write_contents(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|e| io_error("write", path, e))?;
+    }
+
+    // Write to a sibling file and rename so readers never see a partial file;
+    // through a symlink, the sibling of its target, so the link survives
+    let target = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut tmp = target.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    std::fs::write(&tmp, contents).map_err(|e| io_error("write", path, e))?;
+    std::fs::rename(&tmp, &target).map_err(|e| io_error("write", path, e))
+}
+
+fn flatten(prefix: &str, value: &toml::Value, out: &mut Vec<(String, toml::Value)>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, out);
+            }
+        }
+        value => out.push((prefix.to_string(), value.clone())),
+    }
+}
+
+fn unknown_key(key: &str) -> MacCliError {
+    MacCliError::invalid_argument(
+        Subsystem::Cli,
+        format!("Unknown configuration key: {}", key),
+    )
+}
+
+fn io_error(action: &str, path: &Path, err: std::io::Error) -> MacCliError {
+    MacCliError::new(
+        Subsystem::Cli,
+        ErrorKind::Other,
+        format!("Failed to {} configuration {}", action, path.display()),
+    )
+    .with_source(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_path;
+
+    fn temp_config(name: &str, contents: &str) -> PathBuf {
+        let path = temp_path("config", name, "toml");
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn load_rejects_brightness_min_out_of_range() {
+        for min in ["150", "-1", "nan"] {
+            let path = temp_config("min", &format!("[brightness]\nmin = {}\n", min));
+            let err = Config::load(&path).unwrap_err();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidArgument, "min = {}", min);
+        }
+    }
+
+    #[test]
+    fn load_rejects_timeouts_that_are_not_positive() {
+        for seconds in ["0.0", "-1.0", "nan", "inf"] {
+            let path = temp_config(
+                "timeout",
+                &format!("[status.timeouts]\nmusic = {}\n", seconds),
+            );
+            let err = Config::load(&path).unwrap_err();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(
+                err.kind(),
+                ErrorKind::InvalidArgument,
+                "music = {}",
+                seconds
+            );
+            assert!(
+                err.message().contains("status.timeouts.music"),
+                "{}",
+                err.message()
+            );
+        }
+    }
+
+    #[test]
+    fn set_keeps_file_when_brightness_min_is_out_of_range() {
+        let path = temp_config("set", "[brightness]\nmin = 20\n");
+
+        let err = set(&path, "brightness.min", "101").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+        assert_eq!(Config::load(&path).unwrap().brightness.min, 20.0);
+
+        assert_eq!(
+            set(&path, "brightness.min", "0").unwrap().brightness.min,
+            0.0
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    const COMMENTED: &str = "\
+# Written by hand
+[weather]
+units = \"imperial\"        # Fahrenheit, please
+location = \"London, UK\"
+
+# Keep the screen readable
+[brightness]
+min = 20 # percent
+";
+
+    #[test]
+    fn set_keeps_comments_and_key_order() {
+        let path = temp_config("comments", COMMENTED);
+
+        let config = set(&path, "brightness.min", "15").unwrap();
+        assert_eq!(config.brightness.min, 15.0);
+        let config = set(&path, "weather.location", "Paris, FR").unwrap();
+        assert_eq!(config.weather.location.as_deref(), Some("Paris, FR"));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            COMMENTED
+                .replace("min = 20", "min = 15")
+                .replace("London, UK", "Paris, FR")
+        );
+    }
+
+    #[test]
+    fn set_appends_new_sections_after_the_existing_ones() {
+        let path = temp_config("new-section", COMMENTED);
+
+        set(&path, "music.player", "Spotify").unwrap();
+        set(&path, "status.timeouts.weather", "2.5").unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            format!(
+                "{}\n[music]\nplayer = \"Spotify\"\n\n[status.timeouts]\nweather = 2.5\n",
+                COMMENTED
+            )
+        );
+    }
+
+    #[test]
+    fn get_and_entries_see_values_written_by_set() {
+        let path = temp_config("get", COMMENTED);
+
+        set(&path, "music.fzf_options", r#"["--reverse"]"#).unwrap();
+        let config = Config::load(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(contents.starts_with(COMMENTED), "{}", contents);
+        assert_eq!(
+            config.get("music.fzf_options"),
+            Some(toml::Value::Array(vec!["--reverse".into()]))
+        );
+        assert_eq!(config.get("brightness.min"), Some(toml::Value::Float(20.0)));
+        assert_eq!(config.get("weather.nonexistent"), None);
+
+        let keys: Vec<String> = config.entries().into_iter().map(|(k, _)| k).collect();
+        assert!(keys.contains(&"weather.units".to_string()));
+        assert!(keys.contains(&"status.timeouts.weather".to_string()));
+    }
+
+    #[test]
+    fn set_rejects_keys_below_a_value() {
+        let path = temp_config("below", COMMENTED);
+
+        let err = set(&path, "brightness.min.value", "1").unwrap_err();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+        assert_eq!(contents, COMMENTED);
+    }
+
+    #[test]
+    fn save_scene_replaces_settings_in_place() {
+        let path = temp_config(
+            "scene",
+            "[scenes.focus]\n# Quiet enough to think\nvolume = 30\nmusic = \"play\" # always\n\n[scenes.meeting]\nvolume = 60\n",
+        );
+
+        let scene = Scene {
+            volume: Some(25),
+            brightness: Some(60),
+            ..Scene::default()
+        };
+        let config = save_scene(&path, "focus", &scene).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.scene("focus").unwrap(), &scene);
+        assert_eq!(
+            contents,
+            "[scenes.focus]\n# Quiet enough to think\nvolume = 25\nbrightness = 60\n\n[scenes.meeting]\nvolume = 60\n"
+        );
+    }
+}
@@ -0,0 +1,710 @@
+//! `mac daemon`: a long-lived process serving the JSON-RPC protocol of
+//! [`mac_cli::rpc`] on a Unix domain socket.
+//!
+//! The daemon owns a single [`Context`], and with it the brightness controller
+//! and a persistent AppleScript interpreter, and runs every request on it in
+//! turn. Each connection is served on its own thread. While there are
+//! subscribers, a [`Watcher`] polls between requests and publishes changes.
+//!
+//! Regular commands are forwarded to a running daemon by [`forward`], so they
+//! skip the setup cost of the process-per-command path.
+
+use crate::{Cli, Commands, Context, MusicCommands, SceneCommands, execute};
+use clap::Parser;
+use mac_cli::output::Output;
+use mac_cli::rpc::{
+    self, Client, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, Notification, PARSE_ERROR,
+    Request, Response, RpcError,
+};
+use mac_cli::runner::CommandSpec;
+use mac_cli::watch::{BLUETOOTH_POLL_EVERY, Change, WATCHABLE, Watcher};
+use mac_cli::{Backend, Config, ErrorKind, MacCliError, Result, Subsystem};
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+/// A request handed from a connection thread to the thread owning the context.
+struct Job {
+    method: String,
+    params: Value,
+    reply: mpsc::Sender<std::result::Result<Value, RpcError>>,
+}
+
+/// The connections that subscribed to changes.
+#[derive(Default)]
+struct Hub {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+struct Subscriber {
+    connection: usize,
+    writer: Arc<Mutex<UnixStream>>,
+    subsystems: BTreeSet<Subsystem>,
+}
+
+/// Listens on `socket` and serves requests until the process is killed.
+pub(crate) fn serve(mut ctx: Context, socket: &Path, interval: Duration) -> Result<()> {
+    let listener = bind(socket)?;
+    eprintln!("mac daemon listening on {}", socket.display());
+
+    let hub = Arc::new(Hub::default());
+    let (jobs, queue) = mpsc::channel();
+    {
+        let hub = hub.clone();
+        std::thread::spawn(move || accept(listener, jobs, hub));
+    }
+
+    // The context is not thread-safe, so every request runs on this thread
+    let identity = identity(&ctx.backend);
+    let default_config = ctx.config_path.clone();
+    let mut watcher = Watcher::new(ctx.backend.clone(), ctx.music());
+    let mut ticks: u64 = 0;
+    let mut next_poll = Instant::now() + interval;
+
+    loop {
+        match queue.recv_timeout(next_poll.saturating_duration_since(Instant::now())) {
+            Ok(Job {
+                method,
+                params,
+                reply,
+            }) => {
+                let started = Instant::now();
+                let result = call(&mut ctx, &identity, &default_config, &method, params);
+                tracing::debug!(%method, ok = result.is_ok(), elapsed = ?started.elapsed(), "daemon request");
+                let _ = reply.send(result);
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let watched = hub.watched(ticks.is_multiple_of(BLUETOOTH_POLL_EVERY));
+                if watched.is_empty() {
+                    watcher.reset();
+                } else {
+                    for change in watcher.poll(&watched) {
+                        hub.publish(&change);
+                    }
+                }
+                ticks += 1;
+                next_poll = Instant::now() + interval;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(MacCliError::new(
+                    Subsystem::Cli,
+                    ErrorKind::Other,
+                    "mac daemon stopped accepting connections",
+                ));
+            }
+        }
+    }
+}
+
+/// Runs the command in the daemon if one is listening and serves the same
+/// backend; returns `None` if the command should run in this process.
+pub(crate) fn forward(
+    ctx: &Context,
+    args: &[String],
+    command: &Commands,
+) -> Result<Option<Output>> {
+    if !forwardable(command) || ctx.backend.is_dry_run() {
+        return Ok(None);
+    }
+
+    let socket = rpc::default_socket_path();
+    let mut client = match Client::connect(&socket) {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::debug!(error = %e, "running without daemon");
+            return Ok(None);
+        }
+    };
+
+    // Nothing has run yet, so any mismatch can still fall back to this process
+    match client.call("info", Value::Null) {
+        Ok(info) if info == identity(&ctx.backend) => {}
+        Ok(info) => {
+            tracing::debug!(%info, "daemon serves another backend, running without daemon");
+            return Ok(None);
+        }
+        Err(e) => {
+            tracing::debug!(error = %e, "daemon did not answer, running without daemon");
+            return Ok(None);
+        }
+    }
+
+    tracing::debug!(socket = %socket.display(), "running in daemon");
+    // The daemon's working directory is not ours
+    let config = std::path::absolute(&ctx.config_path).unwrap_or_else(|_| ctx.config_path.clone());
+    let result = client.call("run", json!({ "args": args, "config": config }))?;
+    Ok(Some(Output::new(
+        result["text"].as_str().unwrap_or_default(),
+        result["data"].clone(),
+    )))
+}
+
+/// Returns `true` for commands the daemon runs on behalf of the CLI.
+///
+/// Commands that read or write files relative to the caller, need a terminal
+/// or manage the daemon itself always run in the calling process.
+fn forwardable(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Brightness { .. }
+            | Commands::Volume { .. }
+            | Commands::Bluetooth
+            | Commands::Weather { .. }
+            | Commands::Status { .. }
+            | Commands::Undo { .. }
+            | Commands::Scene(SceneCommands::Apply { .. })
+            | Commands::Music(
+                MusicCommands::Play
+                    | MusicCommands::Pause
+                    | MusicCommands::Next
+                    | MusicCommands::Previous
+                    | MusicCommands::Current
+                    | MusicCommands::Playlists { list: true, .. }
+                    | MusicCommands::Playlists { name: Some(_), .. }
+            )
+    )
+}
+
+/// Describes the machine a backend controls, to match clients with the daemon.
+fn identity(backend: &Backend) -> Value {
+    match backend {
+        Backend::System(_) => json!({ "backend": "macos" }),
+        Backend::Sim(store) => json!({
+            "backend": "sim",
+            "sim_state": std::path::absolute(store.path()).unwrap_or_else(|_| store.path().to_path_buf()),
+        }),
+        Backend::DryRun(_) => json!({ "backend": "dry-run" }),
+    }
+}
+
+fn bind(socket: &Path) -> Result<UnixListener> {
+    let io_error = |e: std::io::Error| {
+        MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Other,
+            format!("Failed to listen on {}", socket.display()),
+        )
+        .with_source(e)
+    };
+
+    if UnixStream::connect(socket).is_ok() {
+        return Err(MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Other,
+            format!("mac daemon is already running at {}", socket.display()),
+        ));
+    }
+    // Left behind by a daemon that was killed. Anything else at the path
+    // is most likely a typo, and must not be replaced.
+    match std::fs::symlink_metadata(socket) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            std::fs::remove_file(socket).map_err(io_error)?
+        }
+        Ok(_) => {
+            return Err(MacCliError::new(
+                Subsystem::Cli,
+                ErrorKind::InvalidArgument,
+                format!("{} exists and is not a socket", socket.display()),
+            ));
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(io_error(e)),
+    }
+    let parent = socket
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    std::fs::create_dir_all(parent).map_err(io_error)?;
+
+    // Only the owner may control this Mac through the socket. It is bound in
+    // a directory only the owner can enter and moved into place once its own
+    // permissions are restricted, so it is never reachable by anyone else.
+    let private = parent.join(format!(".mac-daemon-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&private);
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .map_err(io_error)?;
+    let bound = private.join("socket");
+    let result = UnixListener::bind(&bound).and_then(|listener| {
+        std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&bound, socket)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_dir_all(&private);
+    result.map_err(io_error)
+}
+
+fn accept(listener: UnixListener, jobs: mpsc::Sender<Job>, hub: Arc<Hub>) {
+    let ids = AtomicUsize::new(1);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let connection = ids.fetch_add(1, Ordering::Relaxed);
+                let jobs = jobs.clone();
+                let hub = hub.clone();
+                std::thread::spawn(move || {
+                    tracing::debug!(connection, "daemon connection opened");
+                    if let Err(e) = serve_connection(connection, stream, &jobs, &hub) {
+                        tracing::debug!(connection, error = %e, "daemon connection failed");
+                    }
+                    hub.unsubscribe(connection);
+                    tracing::debug!(connection, "daemon connection closed");
+                });
+            }
+            Err(e) => tracing::debug!(error = %e, "failed to accept connection"),
+        }
+    }
+}
+
+fn serve_connection(
+    connection: usize,
+    stream: UnixStream,
+    jobs: &mpsc::Sender<Job>,
+    hub: &Hub,
+) -> std::io::Result<()> {
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: Request = match serde_json::from_str::<Value>(&line) {
+            Err(e) => {
+                let error = RpcError::new(PARSE_ERROR, format!("Invalid JSON: {}", e));
+                send(&writer, &Response::err(Value::Null, error))?;
+                continue;
+            }
+            Ok(value) => match serde_json::from_value(value) {
+                Ok(request) => request,
+                Err(e) => {
+                    let error = RpcError::new(INVALID_REQUEST, format!("Invalid request: {}", e));
+                    send(&writer, &Response::err(Value::Null, error))?;
+                    continue;
+                }
+            },
+        };
+
+        let result = match request.method.as_str() {
+            "subscribe" => subscribe_params(&request.params).map(|subsystems| {
+                hub.subscribe(connection, writer.clone(), subsystems.clone());
+                json!({ "subscribed": subsystems })
+            }),
+            "unsubscribe" => {
+                hub.unsubscribe(connection);
+                Ok(json!({ "subscribed": [] }))
+            }
+            _ => {
+                let (reply, answer) = mpsc::channel();
+                let job = Job {
+                    method: request.method,
+                    params: request.params,
+                    reply,
+                };
+                if jobs.send(job).is_err() {
+                    return Ok(());
+                }
+                answer.recv().unwrap_or_else(|_| {
+                    Err(RpcError::new(
+                        rpc::COMMAND_FAILED,
+                        "mac daemon is shutting down",
+                    ))
+                })
+            }
+        };
+
+        // Requests without an id are notifications and get no response
+        if let Some(id) = request.id {
+            let response = match result {
+                Ok(result) => Response::ok(id, result),
+                Err(error) => Response::err(id, error),
+            };
+            send(&writer, &response)?;
+        }
+    }
+    Ok(())
+}
+
+fn send(writer: &Mutex<UnixStream>, message: &impl serde::Serialize) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(message).expect("messages are always serializable");
+    line.push('\n');
+    writer.lock().unwrap().write_all(line.as_bytes())
+}
+
+/// Parses `{"subsystems": [...]}`; every watchable subsystem if omitted.
+fn subscribe_params(params: &Value) -> std::result::Result<BTreeSet<Subsystem>, RpcError> {
+    let subsystems: BTreeSet<Subsystem> = match params.get("subsystems") {
+        None | Some(Value::Null) => WATCHABLE.into_iter().collect(),
+        Some(list) => serde_json::from_value(list.clone())
+            .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid subsystems: {}", e)))?,
+    };
+
+    match subsystems.iter().find(|s| !WATCHABLE.contains(s)) {
+        Some(s) => Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("{} cannot be watched", s),
+        )),
+        None => Ok(subsystems),
+    }
+}
+
+impl Hub {
+    fn subscribe(
+        &self,
+        connection: usize,
+        writer: Arc<Mutex<UnixStream>>,
+        subsystems: BTreeSet<Subsystem>,
+    ) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|s| s.connection != connection);
+        subscribers.push(Subscriber {
+            connection,
+            writer,
+            subsystems,
+        });
+    }
+
+    fn unsubscribe(&self, connection: usize) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|s| s.connection != connection);
+    }
+
+    /// The subsystems anyone subscribed to, optionally without Bluetooth.
+    fn watched(&self, include_bluetooth: bool) -> Vec<Subsystem> {
+        let subscribers = self.subscribers.lock().unwrap();
+        WATCHABLE
+            .into_iter()
+            .filter(|s| include_bluetooth || *s != Subsystem::Bluetooth)
+            .filter(|s| subscribers.iter().any(|sub| sub.subsystems.contains(s)))
+            .collect()
+    }
+
+    /// Sends `change` to its subscribers, dropping those that disconnected.
+    fn publish(&self, change: &Change) {
+        let notification = Notification::event(change);
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            !subscriber.subsystems.contains(&change.subsystem)
+                || send(&subscriber.writer, &notification).is_ok()
+        });
+    }
+}
+
+/// Runs one method on the daemon's context.
+fn call(
+    ctx: &mut Context,
+    identity: &Value,
+    default_config: &Path,
+    method: &str,
+    params: Value,
+) -> std::result::Result<Value, RpcError> {
+    let command = match method {
+        "info" => return Ok(identity.clone()),
+        "run" => return run(ctx, default_config, &params),
+        "volume.get" => Commands::Volume { percentage: None },
+        "volume.set" => Commands::Volume {
+            percentage: Some(number(&params, "volume")?),
+        },
+        "brightness.get" => Commands::Brightness { percentage: None },
+        "brightness.set" => Commands::Brightness {
+            percentage: Some(number(&params, "brightness")?),
+        },
+        "music.get" => Commands::Music(MusicCommands::Current),
+        "music.play" => Commands::Music(MusicCommands::Play),
+        "music.pause" => Commands::Music(MusicCommands::Pause),
+        "music.next" => Commands::Music(MusicCommands::Next),
+        "music.previous" => Commands::Music(MusicCommands::Previous),
+        "music.playlists" => Commands::Music(MusicCommands::Playlists {
+            name: None,
+            list: true,
+        }),
+        "music.playlist" => Commands::Music(MusicCommands::Playlists {
+            name: Some(string(&params, "name")?),
+            list: false,
+        }),
+        "bluetooth.get" => Commands::Bluetooth,
+        "weather.get" => Commands::Weather {
+            location: optional_string(&params, "location")?,
+        },
+        "status.get" => Commands::Status {
+            table: false,
+            timeout: params.get("timeout").and_then(Value::as_f64),
+        },
+        "scene.apply" => Commands::Scene(SceneCommands::Apply {
+            name: string(&params, "name")?,
+        }),
+        "undo" => Commands::Undo {
+            count: params.get("count").and_then(Value::as_u64).unwrap_or(1) as usize,
+        },
+        _ => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method: {}", method),
+            ));
+        }
+    };
+
+    load_config(ctx, default_config.to_path_buf())?;
+    let command_line = format!("{} {}", method, params);
+    execute(ctx, command, &command_line)
+        .map(|output| output.data().clone())
+        .map_err(|e| RpcError::from(&e))
+}
+
+/// Runs a `mac` command line on behalf of the CLI and returns its output.
+fn run(
+    ctx: &mut Context,
+    default_config: &Path,
+    params: &Value,
+) -> std::result::Result<Value, RpcError> {
+    let args: Vec<String> = serde_json::from_value(params.get("args").cloned().unwrap_or_default())
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid args: {}", e)))?;
+    let config = match optional_string(params, "config")? {
+        Some(path) => PathBuf::from(path),
+        None => default_config.to_path_buf(),
+    };
+
+    // Global flags such as --json only affect how the client prints the result
+    let parsed =
+        Cli::try_parse_from(std::iter::once("mac".to_string()).chain(args.iter().cloned()))
+            .map(|cli| cli.command)
+            .map_err(|e| {
+                let rendered = e.render().to_string();
+                let message = rendered.lines().next().unwrap_or_default().to_string();
+                RpcError::new(INVALID_PARAMS, message)
+            })?;
+    if !forwardable(&parsed) {
+        return Err(RpcError::new(
+            INVALID_PARAMS,
+            "This command cannot run in the daemon",
+        ));
+    }
+
+    load_config(ctx, config)?;
+    let command_line = CommandSpec::new("mac").args(&args).to_string();
+    execute(ctx, parsed, &command_line)
+        .map(|output| json!({ "text": output.text(), "data": output.data() }))
+        .map_err(|e| RpcError::from(&e))
+}
+
+/// Reloads the configuration so edits apply without restarting the daemon.
+fn load_config(ctx: &mut Context, path: PathBuf) -> std::result::Result<(), RpcError> {
+    ctx.config = Config::load(&path).map_err(|e| RpcError::from(&e))?;
+    ctx.config_path = path;
+    Ok(())
+}
+
+fn number(params: &Value, name: &str) -> std::result::Result<f32, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_f64)
+        .map(|n| n as f32)
+        .ok_or_else(|| {
+            RpcError::new(
+                INVALID_PARAMS,
+                format!("Missing number parameter: {}", name),
+            )
+        })
+}
+
+fn string(params: &Value, name: &str) -> std::result::Result<String, RpcError> {
+    optional_string(params, name)?.ok_or_else(|| {
+        RpcError::new(
+            INVALID_PARAMS,
+            format!("Missing string parameter: {}", name),
+        )
+    })
+}
+
+fn optional_string(params: &Value, name: &str) -> std::result::Result<Option<String>, RpcError> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("Parameter must be a string: {}", name),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_path;
+
+    fn call_sim(
+        ctx: &mut Context,
+        method: &str,
+        params: Value,
+    ) -> std::result::Result<Value, RpcError> {
+        let identity = identity(&ctx.backend);
+        let config = ctx.config_path.clone();
+        call(ctx, &identity, &config, method, params)
+    }
+
+    #[test]
+    fn subscribe_defaults_to_every_watchable_subsystem() {
+        let all: BTreeSet<Subsystem> = WATCHABLE.into_iter().collect();
+        assert_eq!(subscribe_params(&Value::Null).unwrap(), all);
+        assert_eq!(
+            subscribe_params(&json!({ "subsystems": null })).unwrap(),
+            all
+        );
+        assert_eq!(
+            subscribe_params(&json!({ "subsystems": ["music", "volume", "music"] })).unwrap(),
+            BTreeSet::from([Subsystem::Volume, Subsystem::Music])
+        );
+    }
+
+    #[test]
+    fn subscribe_rejects_unwatchable_and_unknown_subsystems() {
+        for params in [
+            json!({ "subsystems": ["weather"] }),
+            json!({ "subsystems": ["volume", "kettle"] }),
+            json!({ "subsystems": "volume" }),
+        ] {
+            let err = subscribe_params(&params).unwrap_err();
+            assert_eq!(err.code, INVALID_PARAMS, "{}", params);
+        }
+    }
+
+    #[test]
+    fn methods_run_on_the_context() {
+        let mut ctx = Context::sim("daemon", "methods");
+
+        let set = call_sim(&mut ctx, "volume.set", json!({ "volume": 40 }));
+        let get = call_sim(&mut ctx, "volume.get", Value::Null);
+        let info = call_sim(&mut ctx, "info", Value::Null);
+        ctx.remove_files();
+
+        assert_eq!(set.unwrap(), json!({ "volume": 40, "previous": 50 }));
+        assert_eq!(get.unwrap()["volume"], 40);
+        assert_eq!(info.unwrap()["backend"], "sim");
+    }
+
+    #[test]
+    fn bad_methods_and_params_are_protocol_errors() {
+        let mut ctx = Context::sim("daemon", "params");
+
+        let unknown = call_sim(&mut ctx, "volume.mute", Value::Null).unwrap_err();
+        let missing = call_sim(&mut ctx, "volume.set", json!({})).unwrap_err();
+        let not_string = call_sim(&mut ctx, "scene.apply", json!({ "name": 1 })).unwrap_err();
+        let not_forwardable =
+            call_sim(&mut ctx, "run", json!({ "args": ["config", "list"] })).unwrap_err();
+        let unparsable = call_sim(&mut ctx, "run", json!({ "args": ["volum"] })).unwrap_err();
+        ctx.remove_files();
+
+        assert_eq!(unknown.code, METHOD_NOT_FOUND);
+        assert_eq!(missing.code, INVALID_PARAMS);
+        assert_eq!(not_string.code, INVALID_PARAMS);
+        assert_eq!(not_forwardable.code, INVALID_PARAMS);
+        assert_eq!(unparsable.code, INVALID_PARAMS);
+        assert_eq!(unknown.into_error().exit_code(), 2);
+    }
+
+    #[test]
+    fn command_failures_keep_their_exit_code() {
+        let mut ctx = Context::sim("daemon", "failure");
+
+        let err = call_sim(&mut ctx, "volume.set", json!({ "volume": 150 })).unwrap_err();
+        let run = call_sim(
+            &mut ctx,
+            "run",
+            json!({ "args": ["--json", "volume", "30"] }),
+        );
+        ctx.remove_files();
+
+        assert_eq!(err.code, rpc::COMMAND_FAILED);
+        let error = err.into_error();
+        assert_eq!(error.subsystem(), Subsystem::Volume);
+        assert_eq!(error.kind(), ErrorKind::InvalidArgument);
+        assert_eq!(error.exit_code(), 2);
+
+        let run = run.unwrap();
+        assert_eq!(run["text"], "Volume set to 30%");
+        assert_eq!(run["data"]["volume"], 30);
+    }
+
+    #[test]
+    fn connections_answer_malformed_lines_and_skip_notifications() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let worker = std::thread::spawn(move || {
+            for job in queue {
+                let _ = job.reply.send(Ok(json!({ "method": job.method })));
+            }
+        });
+        let connection = std::thread::spawn(move || {
+            serve_connection(1, server, &jobs, &Hub::default()).unwrap();
+        });
+
+        let mut writer = client.try_clone().unwrap();
+        writeln!(writer, "not json").unwrap();
+        writeln!(writer, r#"{{"jsonrpc": "2.0", "id": 1}}"#).unwrap();
+        writeln!(writer, r#"{{"jsonrpc": "2.0", "method": "music.play"}}"#).unwrap();
+        writeln!(writer).unwrap();
+        writeln!(
+            writer,
+            r#"{{"jsonrpc": "2.0", "id": 2, "method": "subscribe", "params": {{"subsystems": ["volume"]}}}}"#
+        )
+        .unwrap();
+        writeln!(
+            writer,
+            r#"{{"jsonrpc": "2.0", "id": 3, "method": "volume.get"}}"#
+        )
+        .unwrap();
+        writer.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let responses: Vec<Response> = BufReader::new(client)
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+        connection.join().unwrap();
+        worker.join().unwrap();
+
+        let codes: Vec<Option<i64>> = responses
+            .iter()
+            .map(|r| r.error.as_ref().map(|e| e.code))
+            .collect();
+        assert_eq!(
+            codes,
+            [Some(PARSE_ERROR), Some(INVALID_REQUEST), None, None]
+        );
+        assert_eq!(responses[2].id, json!(2));
+        assert_eq!(
+            responses[2].result,
+            Some(json!({ "subscribed": ["volume"] }))
+        );
+        assert_eq!(responses[3].id, json!(3));
+        assert_eq!(responses[3].result, Some(json!({ "method": "volume.get" })));
+    }
+
+    #[test]
+    fn bind_replaces_stale_sockets_but_not_other_files() {
+        let socket = temp_path("daemon", "bind", "sock");
+        drop(UnixListener::bind(&socket).unwrap());
+        let listener = bind(&socket).unwrap();
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        drop(listener);
+        std::fs::remove_file(&socket).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let file = temp_path("daemon", "bind", "txt");
+        std::fs::write(&file, "keep me").unwrap();
+        let err = bind(&file).unwrap_err();
+        let contents = std::fs::read_to_string(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+        assert!(err.message().contains("not a socket"), "{}", err.message());
+        assert_eq!(contents, "keep me");
+    }
+}
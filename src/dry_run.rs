@@ -0,0 +1,59 @@
+//! Recording what would be executed instead of executing it.
+//!
+//! With [`Backend::DryRun`](crate::Backend::DryRun), controllers describe every
+//! external command (`osascript`, `system_profiler`, `curl`, `fzf`) and every
+//! DisplayServices call to a [`DryRun`] plan instead of performing it:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use mac_cli::{Backend, VolumeController};
+//! use mac_cli::dry_run::DryRun;
+//!
+//! let plan = Arc::new(DryRun::new());
+//! VolumeController::with_backend(Backend::DryRun(plan.clone())).set(0.4).unwrap();
+//! assert_eq!(plan.steps(), ["osascript -e 'set volume output volume 40'"]);
+//! ```
+//!
+//! Commands report success with empty output, so a step whose result is
+//! needed to continue (for example parsing the current volume) ends the plan
+//! with an error.
+
+use crate::runner::{CommandOutput, CommandRunner, CommandSpec};
+use std::io;
+use std::sync::Mutex;
+
+/// The steps a command would have executed.
+#[derive(Debug, Default)]
+pub struct DryRun {
+    steps: Mutex<Vec<String>>,
+}
+
+impl DryRun {
+    /// Creates an empty plan.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a step, such as a shell command line or a native call.
+    pub fn record(&self, step: impl Into<String>) {
+        self.steps.lock().unwrap().push(step.into());
+    }
+
+    /// Returns the recorded steps in order.
+    pub fn steps(&self) -> Vec<String> {
+        self.steps.lock().unwrap().clone()
+    }
+
+    /// Returns the recorded steps in order and clears the plan.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.steps.lock().unwrap())
+    }
+}
+
+impl CommandRunner for DryRun {
+    /// Records the shell-quoted command line and reports success with no output.
+    fn run(&self, command: &CommandSpec) -> io::Result<CommandOutput> {
+        self.record(command.to_string());
+        Ok(CommandOutput::success(""))
+    }
+}
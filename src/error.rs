@@ -0,0 +1,299 @@
+//! Error types shared by all controllers.
+//!
+//! Every fallible operation in this crate returns a [`MacCliError`], which records
+//! which subsystem failed, what kind of failure it was, and the underlying cause
+//! when there is one. Each [`ErrorKind`] maps to a distinct process exit code so
+//! that scripts calling `mac` can tell failures apart:
+//!
+//! | Code | Kind               | Meaning                                                   |
+//! |------|--------------------|-----------------------------------------------------------|
+//! | 1    | `other`            | Unexpected failure (I/O, internal error)                  |
+//! | 2    | `invalid_argument` | A value was out of range or malformed                     |
+//! | 3    | `command_not_found`| A required tool (`osascript`, `curl`, `fzf`, ...) is missing |
+//! | 4    | `command_failed`   | An external command or system API reported an error       |
+//! | 5    | `app_not_running`  | The target application (e.g. Music) is not running        |
+//! | 6    | `unavailable`      | The feature is not available on this system               |
+//! | 7    | `network`          | A network request failed                                  |
+//! | 8    | `parse`            | Output from a command could not be understood             |
+//! | 9    | `cancelled`        | An interactive selection was cancelled                    |
+//! | 10   | `not_found`        | The requested item (e.g. a playlist) does not exist       |
+//! | 11   | `timeout`          | A subsystem did not answer in time                        |
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+/// Convenience alias used throughout the crate.
+pub type Result<T, E = MacCliError> = std::result::Result<T, E>;
+
+/// The part of the system an error originated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Subsystem {
+    Brightness,
+    Volume,
+    Music,
+    Bluetooth,
+    Weather,
+    Cli,
+}
+
+impl Subsystem {
+    /// Returns the lowercase name used in messages and JSON output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Subsystem::Brightness => "brightness",
+            Subsystem::Volume => "volume",
+            Subsystem::Music => "music",
+            Subsystem::Bluetooth => "bluetooth",
+            Subsystem::Weather => "weather",
+            Subsystem::Cli => "cli",
+        }
+    }
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The category of an error. See the [module documentation](self) for exit codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Other,
+    InvalidArgument,
+    CommandNotFound,
+    CommandFailed,
+    AppNotRunning,
+    Unavailable,
+    Network,
+    Parse,
+    Cancelled,
+    NotFound,
+    Timeout,
+}
+
+impl ErrorKind {
+    /// Returns the snake_case name used in JSON output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Other => "other",
+            ErrorKind::InvalidArgument => "invalid_argument",
+            ErrorKind::CommandNotFound => "command_not_found",
+            ErrorKind::CommandFailed => "command_failed",
+            ErrorKind::AppNotRunning => "app_not_running",
+            ErrorKind::Unavailable => "unavailable",
+            ErrorKind::Network => "network",
+            ErrorKind::Parse => "parse",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Timeout => "timeout",
+        }
+    }
+
+    /// Returns the process exit code for this kind of error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::InvalidArgument => 2,
+            ErrorKind::CommandNotFound => 3,
+            ErrorKind::CommandFailed => 4,
+            ErrorKind::AppNotRunning => 5,
+            ErrorKind::Unavailable => 6,
+            ErrorKind::Network => 7,
+            ErrorKind::Parse => 8,
+            ErrorKind::Cancelled => 9,
+            ErrorKind::NotFound => 10,
+            ErrorKind::Timeout => 11,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error returned by every controller in this crate.
+#[derive(Debug)]
+pub struct MacCliError {
+    subsystem: Subsystem,
+    kind: ErrorKind,
+    message: String,
+    source: Option<Box<dyn Error + Send + Sync + 'static>>,
+}
+
+impl MacCliError {
+    /// Creates a new error without an underlying cause.
+    pub fn new(subsystem: Subsystem, kind: ErrorKind, message: impl Into<String>) -> Self {
+        MacCliError {
+            subsystem,
+            kind,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// Creates an [`ErrorKind::InvalidArgument`] error.
+    pub fn invalid_argument(subsystem: Subsystem, message: impl Into<String>) -> Self {
+        Self::new(subsystem, ErrorKind::InvalidArgument, message)
+    }
+
+    /// Creates an error for a failure to spawn `program`.
+    ///
+    /// A missing executable is reported as [`ErrorKind::CommandNotFound`]; any other
+    /// I/O failure as [`ErrorKind::CommandFailed`].
+    pub fn spawn(subsystem: Subsystem, program: &str, err: std::io::Error) -> Self {
+        let kind = if err.kind() == std::io::ErrorKind::NotFound {
+            ErrorKind::CommandNotFound
+        } else {
+            ErrorKind::CommandFailed
+        };
+        Self::new(subsystem, kind, format!("Failed to execute {}", program)).with_source(err)
+    }
+
+    /// Creates an error for an AppleScript that exited unsuccessfully.
+    ///
+    /// Scripts that fail because the target application is not running
+    /// (AppleScript error -600) are reported as [`ErrorKind::AppNotRunning`].
+    pub fn script(subsystem: Subsystem, stderr: &str) -> Self {
+        let stderr = stderr.trim();
+        let kind = if stderr.contains("(-600)")
+            || stderr.contains("isn't running")
+            || stderr.contains("isn’t running")
+        {
+            ErrorKind::AppNotRunning
+        } else {
+            ErrorKind::CommandFailed
+        };
+        Self::new(subsystem, kind, format!("AppleScript error: {}", stderr))
+    }
+
+    /// Attaches an underlying cause to this error.
+    pub fn with_source(
+        mut self,
+        source: impl Into<Box<dyn Error + Send + Sync + 'static>>,
+    ) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// The subsystem the error originated from.
+    pub fn subsystem(&self) -> Subsystem {
+        self.subsystem
+    }
+
+    /// The category of the error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The human-readable message, without the underlying cause.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The process exit code for this error.
+    pub fn exit_code(&self) -> i32 {
+        self.kind.exit_code()
+    }
+
+    /// Returns a machine-readable representation of the error.
+    ///
+    /// ```json
+    /// {"error": {"subsystem": "music", "kind": "app_not_running", "exit_code": 5,
+    ///            "message": "...", "source": null}}
+    /// ```
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error": {
+                "subsystem": self.subsystem.as_str(),
+                "kind": self.kind.as_str(),
+                "exit_code": self.exit_code(),
+                "message": self.message,
+                "source": self.source.as_ref().map(|s| s.to_string()),
+            }
+        })
+    }
+
+    /// Rebuilds an error from the `error` object of [`Self::to_json`], such as
+    /// one reported by `mac daemon`. Returns `None` if `value` is not one.
+    pub fn from_json(value: &serde_json::Value) -> Option<Self> {
+        let subsystem = serde_json::from_value(value.get("subsystem")?.clone()).ok()?;
+        let kind = serde_json::from_value(value.get("kind")?.clone()).ok()?;
+        let error = MacCliError::new(subsystem, kind, value.get("message")?.as_str()?);
+        Some(match value.get("source").and_then(|s| s.as_str()) {
+            Some(source) => error.with_source(source),
+            None => error,
+        })
+    }
+}
+
+impl fmt::Display for MacCliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{}: {}", self.message, source),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl Error for MacCliError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|s| s.as_ref() as &(dyn Error + 'static))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_errors_detect_apps_that_are_not_running() {
+        for stderr in [
+            "execution error: Music got an error: Application isn't running. (-600)\n",
+            "execution error: Music isn’t running",
+        ] {
+            let err = MacCliError::script(Subsystem::Music, stderr);
+            assert_eq!(err.kind(), ErrorKind::AppNotRunning);
+            assert_eq!(err.kind().exit_code(), 5);
+        }
+
+        let err = MacCliError::script(
+            Subsystem::Music,
+            "syntax error: Expected end of line (-2741)\n",
+        );
+        assert_eq!(err.kind(), ErrorKind::CommandFailed);
+        assert_eq!(
+            err.message(),
+            "AppleScript error: syntax error: Expected end of line (-2741)"
+        );
+    }
+
+    #[test]
+    fn spawn_errors_distinguish_missing_programs() {
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        let err = MacCliError::spawn(Subsystem::Weather, "curl", missing);
+        assert_eq!(err.kind(), ErrorKind::CommandNotFound);
+        assert_eq!(err.message(), "Failed to execute curl");
+
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let err = MacCliError::spawn(Subsystem::Weather, "curl", denied);
+        assert_eq!(err.kind(), ErrorKind::CommandFailed);
+    }
+
+    #[test]
+    fn json_round_trip_keeps_kind_and_source() {
+        let err = MacCliError::new(Subsystem::Volume, ErrorKind::Timeout, "No reply")
+            .with_source("daemon busy");
+        let back = MacCliError::from_json(&err.to_json()["error"]).unwrap();
+        assert_eq!(back.kind(), ErrorKind::Timeout);
+        assert_eq!(back.subsystem(), Subsystem::Volume);
+        assert_eq!(back.to_string(), "No reply: daemon busy");
+    }
+}
@@ -0,0 +1,66 @@
+//! `mac exporter`: Prometheus metrics over HTTP.
+//!
+//! Every scrape of `/metrics` reads all subsystems through
+//! [`StatusQuery`](mac_cli::status::StatusQuery), concurrently and with the
+//! configured timeouts, and answers with [`mac_cli::metrics::encode`]. Weather
+//! reports come from the cache shared with `mac status`, so scrapes do not
+//! hit the weather service more than once per `status.weather_max_age`.
+
+use crate::{Context, status_query};
+use mac_cli::metrics::{self, CONTENT_TYPE};
+use mac_cli::{ErrorKind, MacCliError, Result, Subsystem};
+use std::net::SocketAddr;
+use std::time::Instant;
+use tiny_http::{Header, Method, Response, Server};
+
+/// Listens on `listen` and serves metrics until the process is killed.
+pub(crate) fn serve(mut ctx: Context, listen: SocketAddr) -> Result<()> {
+    let server = Server::http(listen).map_err(|e| {
+        MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Other,
+            format!("Failed to listen on {}", listen),
+        )
+        .with_source(e)
+    })?;
+    eprintln!("mac exporter listening on http://{}/metrics", listen);
+
+    for request in server.incoming_requests() {
+        let path = request.url().split('?').next().unwrap_or_default();
+        let (status, content_type, body) = match (request.method(), path) {
+            (Method::Get, "/metrics") => match scrape(&mut ctx) {
+                Ok(body) => (200, CONTENT_TYPE, body),
+                Err(e) => (500, "text/plain; charset=utf-8", format!("{}\n", e)),
+            },
+            (Method::Get, "/") => (
+                200,
+                "text/html; charset=utf-8",
+                "<html><body><h1>mac exporter</h1><p><a href=\"/metrics\">Metrics</a></p></body></html>\n"
+                    .to_string(),
+            ),
+            (Method::Get, _) => (404, "text/plain; charset=utf-8", "Not found\n".to_string()),
+            _ => (405, "text/plain; charset=utf-8", "Method not allowed\n".to_string()),
+        };
+
+        let header =
+            Header::from_bytes("Content-Type", content_type).expect("content types are ASCII");
+        let response = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header);
+        if let Err(e) = request.respond(response) {
+            tracing::debug!(error = %e, "failed to send response");
+        }
+    }
+    Ok(())
+}
+
+/// Reads every subsystem and renders the metrics.
+fn scrape(ctx: &mut Context) -> Result<String> {
+    ctx.reload_config()?;
+
+    let started = Instant::now();
+    let status = status_query(ctx).run();
+    let elapsed = started.elapsed();
+    tracing::debug!(?elapsed, errors = status.errors.len(), "scrape");
+    Ok(metrics::encode(&status, elapsed))
+}
@@ -0,0 +1,112 @@
+//! `mac hooks`: runs the hooks from the configuration file whenever the state
+//! changes; see [`mac_cli::hooks`].
+//!
+//! Only the subsystems with a declared hook are polled. The configuration is
+//! read again on every poll, so hooks can be added and changed while it runs.
+
+use crate::Context;
+use mac_cli::hooks::{Event, HookEvent};
+use mac_cli::watch::{BLUETOOTH_POLL_EVERY, Watcher};
+use mac_cli::weather::{Weather, WeatherCache, WeatherController};
+use mac_cli::{Result, Subsystem};
+use std::process::Child;
+use std::time::{Duration, Instant};
+
+/// Weather reports are not fetched more often than this, whatever
+/// `hooks.weather_interval` says.
+const MIN_WEATHER_INTERVAL: u64 = 60;
+
+/// Polls every `interval` and runs hooks until the process is killed.
+pub(crate) fn run(mut ctx: Context, interval: Duration) -> Result<()> {
+    let mut watcher = Watcher::new(ctx.backend.clone(), ctx.music());
+    let weather = WeatherController::with_backend(ctx.backend.clone())
+        .cache(WeatherCache::new(WeatherCache::default_path()));
+    let mut last_weather: Option<Weather> = None;
+    let mut next_weather = Instant::now();
+    let mut running: Vec<(HookEvent, Child)> = Vec::new();
+
+    let declared: Vec<&str> = HookEvent::ALL
+        .iter()
+        .filter(|e| ctx.config.hooks.command(**e).is_some())
+        .map(HookEvent::as_str)
+        .collect();
+    match declared.is_empty() {
+        true => eprintln!(
+            "mac hooks: no hooks in {} yet, waiting for some",
+            ctx.config_path.display()
+        ),
+        false => eprintln!("mac hooks: watching for {}", declared.join(", ")),
+    }
+
+    for tick in 0u64.. {
+        if tick > 0 {
+            std::thread::sleep(interval);
+            if let Err(e) = ctx.reload_config() {
+                eprintln!("mac hooks: {}", e);
+            }
+        }
+        reap(&mut running);
+
+        let hooks = &ctx.config.hooks;
+        let mut subsystems = hooks.subsystems();
+        if !tick.is_multiple_of(BLUETOOTH_POLL_EVERY) {
+            subsystems.retain(|s| *s != Subsystem::Bluetooth);
+        }
+        let mut events: Vec<Event> = watcher
+            .poll(&subsystems)
+            .iter()
+            .flat_map(Event::from_change)
+            .collect();
+
+        if hooks.command(HookEvent::WeatherUpdate).is_some() && Instant::now() >= next_weather {
+            let every = Duration::from_secs(hooks.weather_interval.max(MIN_WEATHER_INTERVAL));
+            next_weather = Instant::now() + every;
+            // Reports fetched by `mac status` and `mac weather` count too
+            match weather.cached(ctx.config.weather.location.as_deref(), every) {
+                Ok(report) => {
+                    // Like other states, the first report is only a baseline
+                    if let Some(previous) = &last_weather
+                        && *previous != report
+                    {
+                        events.push(Event::weather_update(
+                            &report,
+                            Some(previous),
+                            ctx.config.weather.units,
+                        ));
+                    }
+                    last_weather = Some(report);
+                }
+                Err(e) => tracing::debug!(error = %e, "hooks: reading the weather failed"),
+            }
+        }
+
+        for event in events {
+            let Some(command) = hooks.command(event.kind) else {
+                continue;
+            };
+            tracing::debug!(event = %event.kind, data = %event.data, command, "hooks: running");
+            match event.spawn(command) {
+                Ok(child) => running.push((event.kind, child)),
+                Err(e) => eprintln!("mac hooks: {}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collects the hooks that exited, reporting those that failed.
+fn reap(running: &mut Vec<(HookEvent, Child)>) {
+    running.retain_mut(|(event, child)| match child.try_wait() {
+        Ok(Some(status)) => {
+            if !status.success() {
+                eprintln!("mac hooks: the {} hook failed with {}", event, status);
+            }
+            false
+        }
+        Ok(None) => true,
+        Err(e) => {
+            tracing::debug!(%event, error = %e, "hooks: failed to wait for hook");
+            false
+        }
+    });
+}
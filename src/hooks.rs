@@ -0,0 +1,469 @@
+//! Event hooks: shell commands run when the state of the Mac changes.
+//!
+//! Hooks are declared in the `[hooks]` section of the configuration file and
+//! run by `mac hooks`:
+//!
+//! ```toml
+//! [hooks]
+//! on_track_change = "~/bin/scrobble"
+//! on_volume_change = "echo $MAC_VOLUME >> ~/volume.log"
+//! on_bluetooth_connect = "mac scene apply headphones"
+//! on_bluetooth_disconnect = "mac music pause"
+//! on_weather_update = "~/bin/weather-widget"
+//! weather_interval = 900   # seconds between weather reports
+//! ```
+//!
+//! Each hook runs with `sh -c`, with the [`Event`] as a JSON document on stdin
+//! and as environment variables:
+//!
+//! | Event                  | JSON on stdin                                   | Environment                                  |
+//! |------------------------|-------------------------------------------------|----------------------------------------------|
+//! | `track_change`         | `state`, `track`, `previous`                    | `MAC_TRACK_NAME`, `MAC_TRACK_ARTIST`, `MAC_TRACK_ALBUM`, `MAC_PLAYER_STATE` |
+//! | `volume_change`        | `volume`, `muted`, `previous`                   | `MAC_VOLUME`, `MAC_MUTED`, `MAC_PREVIOUS_VOLUME` |
+//! | `bluetooth_connect`    | `device`                                        | `MAC_DEVICE_NAME`, `MAC_DEVICE_ADDRESS`, `MAC_DEVICE_BATTERY` |
+//! | `bluetooth_disconnect` | `device`                                        | Same as `bluetooth_connect`                  |
+//! | `weather_update`       | `weather`, `previous`                           | `MAC_WEATHER_LOCATION`, `MAC_WEATHER_CONDITION`, `MAC_WEATHER_ICON`, `MAC_WEATHER_TEMPERATURE`, `MAC_WEATHER_UNITS` |
+//!
+//! Every document has an `event` field, and every hook gets `MAC_EVENT`, with
+//! the name of the event. Values that are not known are empty.
+//!
+//! ```no_run
+//! use mac_cli::hooks::{Event, HookEvent};
+//! use mac_cli::watch::Watcher;
+//! use mac_cli::{Backend, MusicController, Subsystem};
+//!
+//! let mut watcher = Watcher::new(Backend::system(), MusicController::new());
+//! for change in watcher.poll(&[Subsystem::Music]) {
+//!     for event in Event::from_change(&change) {
+//!         if event.kind == HookEvent::TrackChange {
+//!             event.spawn("say \"$MAC_TRACK_NAME\"")?.wait().ok();
+//!         }
+//!     }
+//! }
+//! # Ok::<(), mac_cli::MacCliError>(())
+//! ```
+
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::watch::Change;
+use crate::weather::{Units, Weather};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::fmt;
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+
+/// The kinds of events hooks can be declared for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    TrackChange,
+    VolumeChange,
+    BluetoothConnect,
+    BluetoothDisconnect,
+    WeatherUpdate,
+}
+
+/// The commands declared in the `[hooks]` section of the configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    /// Runs when another track starts.
+    pub on_track_change: Option<String>,
+    /// Runs when the volume changes or the output is muted or unmuted.
+    pub on_volume_change: Option<String>,
+    /// Runs for every Bluetooth device that connects.
+    pub on_bluetooth_connect: Option<String>,
+    /// Runs for every Bluetooth device that disconnects.
+    pub on_bluetooth_disconnect: Option<String>,
+    /// Runs when the weather report changes.
+    pub on_weather_update: Option<String>,
+    /// Seconds between weather reports for `on_weather_update`.
+    pub weather_interval: u64,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Hooks {
+            on_track_change: None,
+            on_volume_change: None,
+            on_bluetooth_connect: None,
+            on_bluetooth_disconnect: None,
+            on_weather_update: None,
+            weather_interval: 900,
+        }
+    }
+}
+
+/// Something that happened, with the data passed to its hook.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub kind: HookEvent,
+    /// The document written to the hook's stdin.
+    pub data: Value,
+    /// Environment variables of the hook besides `MAC_EVENT`.
+    pub env: Vec<(&'static str, String)>,
+}
+
+impl HookEvent {
+    pub const ALL: [HookEvent; 5] = [
+        HookEvent::TrackChange,
+        HookEvent::VolumeChange,
+        HookEvent::BluetoothConnect,
+        HookEvent::BluetoothDisconnect,
+        HookEvent::WeatherUpdate,
+    ];
+
+    /// Returns the snake_case name used in `MAC_EVENT` and JSON.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HookEvent::TrackChange => "track_change",
+            HookEvent::VolumeChange => "volume_change",
+            HookEvent::BluetoothConnect => "bluetooth_connect",
+            HookEvent::BluetoothDisconnect => "bluetooth_disconnect",
+            HookEvent::WeatherUpdate => "weather_update",
+        }
+    }
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Hooks {
+    /// Returns the command declared for `event`.
+    pub fn command(&self, event: HookEvent) -> Option<&str> {
+        let command = match event {
+            HookEvent::TrackChange => &self.on_track_change,
+            HookEvent::VolumeChange => &self.on_volume_change,
+            HookEvent::BluetoothConnect => &self.on_bluetooth_connect,
+            HookEvent::BluetoothDisconnect => &self.on_bluetooth_disconnect,
+            HookEvent::WeatherUpdate => &self.on_weather_update,
+        };
+        command.as_deref().filter(|c| !c.trim().is_empty())
+    }
+
+    /// Returns the subsystems the declared hooks depend on, except weather.
+    pub fn subsystems(&self) -> Vec<Subsystem> {
+        let mut subsystems = Vec::new();
+        if self.command(HookEvent::VolumeChange).is_some() {
+            subsystems.push(Subsystem::Volume);
+        }
+        if self.command(HookEvent::TrackChange).is_some() {
+            subsystems.push(Subsystem::Music);
+        }
+        if self.command(HookEvent::BluetoothConnect).is_some()
+            || self.command(HookEvent::BluetoothDisconnect).is_some()
+        {
+            subsystems.push(Subsystem::Bluetooth);
+        }
+        subsystems
+    }
+}
+
+impl Event {
+    /// Returns the events a [`Change`] amounts to: one for a volume change,
+    /// one for a new track, and one per Bluetooth device that connected or
+    /// disconnected.
+    ///
+    /// Pausing, resuming and quitting the player are not track changes.
+    pub fn from_change(change: &Change) -> Vec<Event> {
+        match change.subsystem {
+            Subsystem::Volume => vec![volume_change(&change.value, &change.previous)],
+            Subsystem::Music => track_change(&change.value, &change.previous)
+                .into_iter()
+                .collect(),
+            Subsystem::Bluetooth => bluetooth_changes(&change.value, &change.previous),
+            Subsystem::Brightness | Subsystem::Weather | Subsystem::Cli => Vec::new(),
+        }
+    }
+
+    /// A `weather_update` from `weather`, the report before it and the
+    /// configured units.
+    pub fn weather_update(weather: &Weather, previous: Option<&Weather>, units: Units) -> Event {
+        let document = |weather: &Weather| {
+            let mut data = json!(weather);
+            data["temperature"] = json!(weather.temperature_c.map(|t| units.convert(t)));
+            data["units"] = json!(units);
+            data
+        };
+        let temperature = weather
+            .temperature_c
+            .map(|t| format!("{:.0}", units.convert(t)))
+            .unwrap_or_default();
+
+        Event {
+            kind: HookEvent::WeatherUpdate,
+            data: json!({
+                "event": HookEvent::WeatherUpdate,
+                "weather": document(weather),
+                "previous": previous.map(document),
+            }),
+            env: vec![
+                ("MAC_WEATHER_LOCATION", weather.location.clone()),
+                ("MAC_WEATHER_CONDITION", weather.condition.clone()),
+                ("MAC_WEATHER_ICON", weather.icon.clone()),
+                ("MAC_WEATHER_TEMPERATURE", temperature),
+                (
+                    "MAC_WEATHER_UNITS",
+                    json!(units).as_str().unwrap_or_default().to_string(),
+                ),
+            ],
+        }
+    }
+
+    /// Starts `command` with `sh -c`, writes the event to its stdin and
+    /// returns without waiting for it. Its stdout and stderr are ours.
+    pub fn spawn(&self, command: &str) -> Result<Child> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("MAC_EVENT", self.kind.as_str())
+            .envs(self.env.iter().map(|(name, value)| (name, value)))
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                MacCliError::new(
+                    Subsystem::Cli,
+                    ErrorKind::CommandFailed,
+                    format!("Failed to run the {} hook", self.kind),
+                )
+                .with_source(e)
+            })?;
+
+        // The document fits in the pipe buffer; a hook that ignores it may
+        // already have exited, which is fine
+        if let Some(mut stdin) = child.stdin.take()
+            && let Err(e) = writeln!(stdin, "{}", self.data)
+        {
+            tracing::debug!(event = %self.kind, error = %e, "hooks: failed to write stdin");
+        }
+        Ok(child)
+    }
+}
+
+fn volume_change(value: &Value, previous: &Value) -> Event {
+    Event {
+        kind: HookEvent::VolumeChange,
+        data: json!({
+            "event": HookEvent::VolumeChange,
+            "volume": value["volume"],
+            "muted": value["muted"],
+            "previous": previous,
+        }),
+        env: vec![
+            ("MAC_VOLUME", text(&value["volume"])),
+            ("MAC_MUTED", text(&value["muted"])),
+            ("MAC_PREVIOUS_VOLUME", text(&previous["volume"])),
+        ],
+    }
+}
+
+fn track_change(value: &Value, previous: &Value) -> Option<Event> {
+    let track = &value["track"];
+    if !track.is_object() || *track == previous["track"] {
+        return None;
+    }
+
+    Some(Event {
+        kind: HookEvent::TrackChange,
+        data: json!({
+            "event": HookEvent::TrackChange,
+            "state": value["state"],
+            "track": track,
+            "previous": previous["track"],
+        }),
+        env: vec![
+            ("MAC_TRACK_NAME", text(&track["name"])),
+            ("MAC_TRACK_ARTIST", text(&track["artist"])),
+            ("MAC_TRACK_ALBUM", text(&track["album"])),
+            ("MAC_PLAYER_STATE", text(&value["state"])),
+        ],
+    })
+}
+
+fn bluetooth_changes(value: &Value, previous: &Value) -> Vec<Event> {
+    let connected = |devices: &Value| -> Vec<Value> {
+        devices["devices"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|d| d["connected"].as_bool().unwrap_or(false))
+            .cloned()
+            .collect()
+    };
+    // Devices without an address are told apart by name
+    let key = |device: &Value| match &device["address"] {
+        Value::Null => device["name"].clone(),
+        address => address.clone(),
+    };
+    let (now, before) = (connected(value), connected(previous));
+
+    let connects = now
+        .iter()
+        .filter(|d| !before.iter().any(|b| key(b) == key(d)))
+        .map(|d| bluetooth_event(HookEvent::BluetoothConnect, d));
+    let disconnects = before
+        .iter()
+        .filter(|b| !now.iter().any(|d| key(d) == key(b)))
+        .map(|b| bluetooth_event(HookEvent::BluetoothDisconnect, b));
+    connects.chain(disconnects).collect()
+}
+
+fn bluetooth_event(kind: HookEvent, device: &Value) -> Event {
+    Event {
+        kind,
+        data: json!({ "event": kind, "device": device }),
+        env: vec![
+            ("MAC_DEVICE_NAME", text(&device["name"])),
+            ("MAC_DEVICE_ADDRESS", text(&device["address"])),
+            ("MAC_DEVICE_BATTERY", text(&device["battery"])),
+        ],
+    }
+}
+
+/// A JSON value as an environment variable: strings unquoted, `null` empty.
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(subsystem: Subsystem, previous: Value, value: Value) -> Change {
+        Change {
+            subsystem,
+            value,
+            previous,
+        }
+    }
+
+    fn env(event: &Event, name: &str) -> String {
+        let (_, value) = event.env.iter().find(|(n, _)| *n == name).unwrap();
+        value.clone()
+    }
+
+    fn devices(devices: Value) -> Value {
+        json!({ "devices": devices })
+    }
+
+    #[test]
+    fn a_new_track_is_a_track_change() {
+        let track = json!({ "name": "Song", "artist": "Band", "album": null });
+        let events = Event::from_change(&change(
+            Subsystem::Music,
+            json!({ "state": "playing", "track": { "name": "Other" } }),
+            json!({ "state": "playing", "track": track }),
+        ));
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, HookEvent::TrackChange);
+        assert_eq!(events[0].data["track"], track);
+        assert_eq!(events[0].data["previous"]["name"], "Other");
+        assert_eq!(env(&events[0], "MAC_TRACK_NAME"), "Song");
+        assert_eq!(env(&events[0], "MAC_PLAYER_STATE"), "playing");
+    }
+
+    #[test]
+    fn pausing_resuming_and_quitting_are_not_track_changes() {
+        let track = json!({ "name": "Song", "artist": "Band" });
+        let playing = json!({ "state": "playing", "track": track });
+        let paused = json!({ "state": "paused", "track": track });
+        let quit = json!({ "state": "not_running", "track": null });
+
+        for (previous, value) in [(&playing, &paused), (&paused, &playing), (&playing, &quit)] {
+            let change = change(Subsystem::Music, previous.clone(), value.clone());
+            assert_eq!(Event::from_change(&change), [], "{} -> {}", previous, value);
+        }
+    }
+
+    #[test]
+    fn null_values_are_empty_variables() {
+        let events = Event::from_change(&change(
+            Subsystem::Volume,
+            json!({ "volume": null, "muted": false }),
+            json!({ "volume": 40, "muted": null }),
+        ));
+
+        assert_eq!(events[0].kind, HookEvent::VolumeChange);
+        assert_eq!(env(&events[0], "MAC_VOLUME"), "40");
+        assert_eq!(env(&events[0], "MAC_MUTED"), "");
+        assert_eq!(env(&events[0], "MAC_PREVIOUS_VOLUME"), "");
+
+        let event = bluetooth_event(
+            HookEvent::BluetoothConnect,
+            &json!({ "name": "Keyboard", "address": null, "battery": null }),
+        );
+        assert_eq!(env(&event, "MAC_DEVICE_NAME"), "Keyboard");
+        assert_eq!(env(&event, "MAC_DEVICE_ADDRESS"), "");
+        assert_eq!(env(&event, "MAC_DEVICE_BATTERY"), "");
+    }
+
+    #[test]
+    fn every_device_gets_its_own_event() {
+        let events = Event::from_change(&change(
+            Subsystem::Bluetooth,
+            devices(json!([
+                { "name": "Mouse", "address": "aa", "connected": true },
+                { "name": "Speaker", "address": "bb", "connected": true },
+            ])),
+            devices(json!([
+                { "name": "Mouse", "address": "aa", "connected": true },
+                { "name": "Speaker", "address": "bb", "connected": false },
+                { "name": "Headphones", "address": "cc", "connected": true, "battery": 80 },
+                { "name": "Keyboard", "address": "dd", "connected": true },
+            ])),
+        ));
+
+        let summary: Vec<_> = events
+            .iter()
+            .map(|e| (e.kind, env(e, "MAC_DEVICE_NAME")))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (HookEvent::BluetoothConnect, "Headphones".to_string()),
+                (HookEvent::BluetoothConnect, "Keyboard".to_string()),
+                (HookEvent::BluetoothDisconnect, "Speaker".to_string()),
+            ]
+        );
+        assert_eq!(env(&events[0], "MAC_DEVICE_BATTERY"), "80");
+        assert_eq!(events[2].data["device"]["address"], "bb");
+    }
+
+    #[test]
+    fn devices_without_an_address_are_matched_by_name() {
+        let before = devices(json!([
+            { "name": "Keyboard", "address": null, "connected": true },
+        ]));
+        let renamed = devices(json!([
+            { "name": "Keyboard", "address": null, "connected": true },
+            { "name": "Trackpad", "address": null, "connected": true },
+        ]));
+
+        let events = Event::from_change(&change(Subsystem::Bluetooth, before.clone(), renamed));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, HookEvent::BluetoothConnect);
+        assert_eq!(env(&events[0], "MAC_DEVICE_NAME"), "Trackpad");
+
+        let unchanged = change(Subsystem::Bluetooth, before.clone(), before);
+        assert_eq!(Event::from_change(&unchanged), []);
+    }
+
+    #[test]
+    fn subsystems_follow_the_declared_hooks() {
+        let hooks = Hooks {
+            on_bluetooth_disconnect: Some("true".to_string()),
+            on_track_change: Some("  ".to_string()),
+            ..Hooks::default()
+        };
+        assert_eq!(hooks.command(HookEvent::TrackChange), None);
+        assert_eq!(hooks.subsystems(), [Subsystem::Bluetooth]);
+    }
+}
@@ -0,0 +1,299 @@
+//! A local journal of changes, used to undo them.
+//!
+//! Before a mutating command runs, the settings it is about to change are
+//! captured as a [`Snapshot`] and appended to the journal together with the
+//! command. Undoing an entry restores that snapshot. The journal is a JSON
+//! Lines file keeping the most recent [`MAX_ENTRIES`] entries.
+
+use crate::backend::Backend;
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::music::MusicController;
+use crate::state::Snapshot;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Number of entries kept in the journal; older entries are dropped.
+pub const MAX_ENTRIES: usize = 100;
+
+/// One recorded change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// When the change was made.
+    pub time: DateTime<Local>,
+    /// The command that made the change, e.g. `volume 100`.
+    pub command: String,
+    /// The affected settings as they were before the change.
+    pub previous: Snapshot,
+}
+
+/// The journal file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    /// Uses the journal file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Journal { path: path.into() }
+    }
+
+    /// The default journal, `~/.config/mac-cli/journal.jsonl`.
+    pub fn default_path() -> PathBuf {
+        crate::paths::config_dir().join("journal.jsonl")
+    }
+
+    /// The path of the journal file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns every entry, oldest first. A missing journal is empty.
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(self.io_error("read", e)),
+        };
+
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line).map_err(|e| {
+                    MacCliError::new(
+                        Subsystem::Cli,
+                        ErrorKind::Parse,
+                        format!("Invalid journal entry in {}", self.path.display()),
+                    )
+                    .with_source(e)
+                })
+            })
+            .collect()
+    }
+
+    /// Appends an entry recording that `command` changed the settings in
+    /// `previous`. Nothing is recorded if `previous` is empty.
+    pub fn record(&self, command: impl Into<String>, previous: Snapshot) -> Result<()> {
+        if previous.is_empty() {
+            return Ok(());
+        }
+
+        let _lock = self.lock()?;
+        let mut entries = self.entries()?;
+        entries.push(Entry {
+            time: Local::now(),
+            command: command.into(),
+            previous,
+        });
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+        }
+        self.write(&entries)
+    }
+
+    /// Reverts the last `count` changes, newest first, and removes them from
+    /// the journal. Returns the reverted entries.
+    ///
+    /// An entry is only removed once it has been restored, so a failure
+    /// leaves it in place to be retried.
+    pub fn undo(
+        &self,
+        count: usize,
+        backend: &Backend,
+        music: &MusicController,
+    ) -> Result<Vec<Entry>> {
+        let _lock = self.lock()?;
+        let mut entries = self.entries()?;
+        if entries.is_empty() {
+            return Err(MacCliError::new(
+                Subsystem::Cli,
+                ErrorKind::NotFound,
+                "Nothing to undo",
+            ));
+        }
+
+        let mut undone = Vec::new();
+        while undone.len() < count {
+            let Some(entry) = entries.last() else { break };
+            entry.previous.restore(backend, music)?;
+            undone.extend(entries.pop());
+            self.write(&entries)?;
+        }
+        Ok(undone)
+    }
+
+    /// Takes an advisory lock on a sibling `.lock` file, so that commands
+    /// changing the journal at the same time do not lose each other's
+    /// entries. Released when the returned file is closed.
+    fn lock(&self) -> Result<File> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| self.io_error("write", e))?;
+        }
+        let lock = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("jsonl.lock"))
+            .map_err(|e| self.io_error("lock", e))?;
+        lock.lock().map_err(|e| self.io_error("lock", e))?;
+        Ok(lock)
+    }
+
+    fn write(&self, entries: &[Entry]) -> Result<()> {
+        let mut contents = String::new();
+        for entry in entries {
+            contents.push_str(&serde_json::to_string(entry).expect("Entry is always serializable"));
+            contents.push('\n');
+        }
+
+        // Write to a sibling file and rename so readers never see a partial journal
+        let tmp = self
+            .path
+            .with_extension(format!("jsonl.{}.tmp", std::process::id()));
+        std::fs::write(&tmp, contents).map_err(|e| self.io_error("write", e))?;
+        std::fs::rename(&tmp, &self.path).map_err(|e| self.io_error("write", e))
+    }
+
+    fn io_error(&self, action: &str, err: std::io::Error) -> MacCliError {
+        MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Other,
+            format!("Failed to {} journal {}", action, self.path.display()),
+        )
+        .with_source(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimStore;
+    use crate::state::Scope;
+    use crate::test_support::{remove_with_lock, temp_path};
+    use crate::volume::VolumeController;
+
+    fn volume(level: f32) -> Snapshot {
+        Snapshot {
+            volume: Some(level),
+            ..Snapshot::default()
+        }
+    }
+
+    #[test]
+    fn record_keeps_only_the_latest_entries() {
+        let journal = Journal::new(temp_path("journal", "trim", "jsonl"));
+        for i in 0..MAX_ENTRIES + 5 {
+            journal
+                .record(format!("volume {}", i), volume(0.5))
+                .unwrap();
+        }
+        journal.record("status", Snapshot::default()).unwrap();
+
+        let entries = journal.entries().unwrap();
+        std::fs::remove_file(journal.path()).unwrap();
+        std::fs::remove_file(journal.path().with_extension("jsonl.lock")).ok();
+
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0].command, "volume 5");
+        assert_eq!(
+            entries[MAX_ENTRIES - 1].command,
+            format!("volume {}", MAX_ENTRIES + 4)
+        );
+    }
+
+    #[test]
+    fn undo_reverts_the_newest_entries_first() {
+        let journal = Journal::new(temp_path("journal", "undo", "jsonl"));
+        let backend = Backend::Sim(SimStore::new(temp_path("journal", "undo", "json")));
+        let music = MusicController::with_backend(backend.clone());
+        let volume_controller = VolumeController::with_backend(backend.clone());
+
+        journal.record("volume 40", volume(0.2)).unwrap();
+        journal.record("volume 60", volume(0.4)).unwrap();
+
+        let undone = journal.undo(1, &backend, &music).unwrap();
+        assert_eq!(undone.len(), 1);
+        assert_eq!(undone[0].command, "volume 60");
+        assert_eq!(volume_controller.get().unwrap(), 0.4);
+
+        let undone = journal.undo(5, &backend, &music).unwrap();
+        assert_eq!(undone.len(), 1);
+        assert_eq!(undone[0].command, "volume 40");
+        assert_eq!(volume_controller.get().unwrap(), 0.2);
+
+        let err = journal.undo(1, &backend, &music).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        if let Backend::Sim(store) = &backend {
+            remove_with_lock(store.path());
+        }
+        std::fs::remove_file(journal.path()).unwrap();
+        std::fs::remove_file(journal.path().with_extension("jsonl.lock")).ok();
+    }
+
+    #[test]
+    fn entry_stays_when_its_restore_fails() {
+        let journal = Journal::new(temp_path("journal", "retry", "jsonl"));
+        let store = SimStore::new(temp_path("journal", "retry", "json"));
+        let backend = Backend::Sim(store.clone());
+        let music = MusicController::with_backend(backend.clone());
+
+        journal.record("volume 40", volume(0.2)).unwrap();
+        let player = Snapshot::capture_scope(&backend, &music, Scope::MUSIC).unwrap();
+        journal.record("pause", player).unwrap();
+
+        let set_running = |running: bool| {
+            store
+                .update(Subsystem::Music, |state| {
+                    state.music.running = running;
+                    Ok(())
+                })
+                .unwrap();
+        };
+
+        set_running(false);
+        let err = journal.undo(2, &backend, &music).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AppNotRunning);
+        assert_eq!(journal.entries().unwrap().len(), 2);
+
+        set_running(true);
+        let undone = journal.undo(2, &backend, &music).unwrap();
+        let commands: Vec<&str> = undone.iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(commands, ["pause", "volume 40"]);
+        assert!(journal.entries().unwrap().is_empty());
+
+        remove_with_lock(store.path());
+        std::fs::remove_file(journal.path()).unwrap();
+        std::fs::remove_file(journal.path().with_extension("jsonl.lock")).ok();
+    }
+
+    #[test]
+    fn concurrent_records_are_not_lost() {
+        let journal = Journal::new(temp_path("journal", "concurrent", "jsonl"));
+
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let journal = journal.clone();
+                std::thread::spawn(move || {
+                    for i in 0..10 {
+                        journal
+                            .record(format!("volume {}", thread * 10 + i), volume(0.5))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let entries = journal.entries().unwrap();
+        std::fs::remove_file(journal.path()).unwrap();
+        std::fs::remove_file(journal.path().with_extension("jsonl.lock")).ok();
+
+        assert_eq!(entries.len(), 40);
+    }
+}
@@ -0,0 +1,72 @@
+//! # mac-cli
+//!
+//! A library for controlling macOS system features including brightness,
+//! volume, Apple Music, Bluetooth, and weather information.
+//!
+//! The `mac` binary is a thin command-line front-end over this crate; the same
+//! controllers can be embedded directly in other Rust programs.
+//!
+//! ## Features
+//!
+//! - **Brightness**: Get and set screen brightness, down to the configurable
+//!   `brightness.min` (10% by default)
+//! - **Volume**: Control system volume (0-100%)
+//! - **Apple Music**: Play/pause, skip tracks, manage playlists
+//! - **Bluetooth**: List paired and connected devices
+//! - **Weather**: Get current weather for any location
+//! - **Scenes**: Apply named combinations of the settings above in one call
+//! - **Hooks**: Run shell commands when the state changes
+//! - **Rules**: Declarative conditions and the commands to run when they hold
+//! - **Scheduler**: Commands that run at set times, with cron-like recurrences
+//!
+//! Every controller can also run against a simulated machine backed by a JSON
+//! state file; see [`sim`] and [`Backend`].
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use mac_cli::{MusicController, VolumeController};
+//!
+//! let volume = VolumeController::new()?;
+//! volume.set(0.3)?;
+//!
+//! println!("{}", MusicController::new().current()?);
+//! # Ok::<(), mac_cli::MacCliError>(())
+//! ```
+
+pub mod backend;
+pub mod bluetooth;
+pub mod brightness;
+pub mod config;
+pub mod dry_run;
+pub mod error;
+pub mod hooks;
+pub mod journal;
+pub mod metrics;
+pub mod music;
+pub mod osc;
+pub mod output;
+pub mod paths;
+pub mod rpc;
+pub mod rules;
+pub mod runner;
+pub mod scene;
+pub mod schedule;
+pub mod script_worker;
+pub mod sim;
+pub mod state;
+pub mod status;
+#[cfg(test)]
+mod test_support;
+pub mod volume;
+pub mod watch;
+pub mod weather;
+
+pub use backend::Backend;
+pub use bluetooth::BluetoothController;
+pub use brightness::BrightnessController;
+pub use config::Config;
+pub use error::{ErrorKind, MacCliError, Result, Subsystem};
+pub use music::MusicController;
+pub use volume::VolumeController;
+pub use weather::WeatherController;
@@ -1,43 +1,178 @@
-//! # mac-cli
+//! # mac
 //!
-//! A command-line tool for controlling macOS system features including brightness,
-//! volume, Apple Music, Bluetooth, and weather information.
-//!
-//! ## Features
-//!
-//! - **Brightness**: Get and set screen brightness (10-100%)
-//! - **Volume**: Control system volume (0-100%)
-//! - **Apple Music**: Play/pause, skip tracks, manage playlists
-//! - **Bluetooth**: List paired and connected devices
-//! - **Weather**: Get current weather for any location
-
-mod brightness;
-mod bluetooth;
-mod music;
-mod volume;
-mod weather;
-
-use brightness::BrightnessController;
-use bluetooth::BluetoothController;
-use clap::{Parser, Subcommand};
-use music::MusicController;
-use volume::VolumeController;
-use weather::WeatherController;
+//! Command-line front-end for the `mac_cli` library. Parses arguments with clap
+//! and dispatches to the controllers exported by the library crate.
+
+use clap::builder::FalseyValueParser;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use mac_cli::backend::{BACKEND_ENV, SIM_STATE_ENV};
+use mac_cli::brightness;
+use mac_cli::config::{self, CONFIG_ENV, OutputKind};
+use mac_cli::dry_run::DryRun;
+use mac_cli::journal::Journal;
+use mac_cli::music::{NowPlaying, PlayerState};
+use mac_cli::output::{Output, OutputFormat, Template, percent};
+use mac_cli::rpc::{self, SOCKET_ENV};
+use mac_cli::rules::{RULES_ENV, RuleSet, TimeOfDay};
+use mac_cli::runner::CommandSpec;
+use mac_cli::scene::Scene;
+use mac_cli::schedule::{MissedRuns, Recurrence, SCHEDULE_ENV, Schedule};
+use mac_cli::sim::SimStore;
+use mac_cli::state::{Scope, Snapshot};
+use mac_cli::status::{Status, StatusQuery, Timeouts};
+use mac_cli::weather::{Units, Weather, WeatherCache};
+use mac_cli::{
+    Backend, BluetoothController, BrightnessController, Config, ErrorKind, MacCliError,
+    MusicController, Result, Subsystem, VolumeController, WeatherController,
+};
+use serde_json::json;
+use std::cell::OnceCell;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+
+mod batch;
+mod daemon;
+mod exporter;
+mod hook_runner;
+mod mqtt;
+mod osc_server;
+mod plugins;
+mod rule_runner;
+mod schedule_runner;
+mod serve;
+#[cfg(test)]
+mod test_support;
+
+/// Environment variable with a log filter such as `debug` or `mac_cli::runner=trace`.
+const LOG_ENV: &str = "MAC_CLI_LOG";
+
+/// Environment variable naming a file to append logs to instead of stderr.
+const LOG_FILE_ENV: &str = "MAC_CLI_LOG_FILE";
+
+/// Environment variable that keeps commands from using `mac daemon`.
+const NO_DAEMON_ENV: &str = "MAC_CLI_NO_DAEMON";
+
+/// Environment variable that makes batches run every command even if some fail.
+const BATCH_KEEP_GOING_ENV: &str = "MAC_CLI_BATCH_KEEP_GOING";
+
+/// Environment variables for the other global flags, which `mac` sets for plugins.
+const JSON_ENV: &str = "MAC_CLI_JSON";
+const FORMAT_ENV: &str = "MAC_CLI_FORMAT";
+const DRY_RUN_ENV: &str = "MAC_CLI_DRY_RUN";
+const ERROR_FORMAT_ENV: &str = "MAC_CLI_ERROR_FORMAT";
+
+const FORMAT_HELP: &str = "\
+Print results through a template instead of the default text.
+
+Placeholders are dotted paths into the command's JSON document (see --json):
+{a.b} selects a nested field, {list.0} indexes an array, {list.name} maps over
+an array and joins the results with \", \". Missing fields render as nothing;
+{{ and }} produce literal braces.
+
+Fields per command:
+  volume               {volume} {previous}
+  brightness           {brightness} {previous}
+  music play|pause|..  {action}
+  music current        {state} {track.name} {track.artist} {track.album}
+  music playlists -l   {playlists}
+  music playlists      {playlist} {now_playing.state} {now_playing.track.name} ...
+  bluetooth            {devices.name} {devices.connected} {devices.address} {devices.battery}
+  weather              {location} {condition} {icon} {temperature} {units} {temperature_c}
+  config get|set       {key} {value}
+  config list          {<section>.<key>} for every setting
+  config path          {path}
+  scene apply|save     {scene} {settings.volume} {settings.brightness} {settings.shuffle}
+                       {settings.playlist} {settings.music}
+  scene show           {scene} {settings.volume} ...
+  scene list           {scenes.name} {scenes.settings.volume} ...
+  state save|restore   {path} {snapshot.volume} {snapshot.displays.brightness}
+                       {snapshot.music.state} {snapshot.music.playlist} ...
+  --dry-run            {steps}
+  batch (summary)      {batch.succeeded} {batch.failed} {batch.skipped} {batch.failures.line} ...
+  status               {volume} {muted} {brightness} {music.state} {music.track.name}
+                       {bluetooth.name} {weather.location} {weather.temperature}
+                       {errors.<subsystem>.kind} {errors.<subsystem>.message}
+  undo|history         {entries.time} {entries.command} {entries.previous.volume} ...
+
+Example: mac music current --format '{track.name} by {track.artist}'";
 
 /// macOS system control utility - control brightness, volume, music, Bluetooth, and weather
 #[derive(Parser, Debug)]
 #[command(name = "mac")]
 #[command(about = "Control macOS system features and get weather info", long_about = None)]
 struct Cli {
+    /// Configuration file [default: ~/.config/mac-cli/config.toml]
+    #[arg(long, global = true, env = CONFIG_ENV, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// What to control: the real Mac, or a simulated one backed by a state file
+    #[arg(long, global = true, value_enum, env = BACKEND_ENV, default_value_t = BackendKind::Macos)]
+    backend: BackendKind,
+
+    /// State file for `--backend sim` [default: ~/.config/mac-cli/sim-state.json]
+    #[arg(long, global = true, env = SIM_STATE_ENV, value_name = "PATH")]
+    sim_state: Option<PathBuf>,
+
+    /// Print results (and errors) as JSON documents
+    #[arg(long, global = true, env = JSON_ENV, value_parser = FalseyValueParser::new(), conflicts_with = "format")]
+    json: bool,
+
+    /// Print results through a template, e.g. '{track.name} by {track.artist}'
+    #[arg(long, global = true, env = FORMAT_ENV, value_name = "TEMPLATE", long_help = FORMAT_HELP)]
+    format: Option<Template>,
+
+    /// Print the AppleScript, shell commands and DisplayServices calls that
+    /// would be executed instead of executing them. Only for the macOS backend
+    #[arg(long, global = true, env = DRY_RUN_ENV, value_parser = FalseyValueParser::new())]
+    dry_run: bool,
+
+    /// Log backend calls to stderr: -v for process spawns, DisplayServices
+    /// lookups and HTTP requests with timings, -vv to add stdin/stdout.
+    /// Overrides MAC_CLI_LOG
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Append logs to this file instead of stderr
+    #[arg(long, global = true, env = LOG_FILE_ENV, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Run commands in this process even if `mac daemon` is running
+    #[arg(long, global = true, env = NO_DAEMON_ENV, value_parser = FalseyValueParser::new())]
+    no_daemon: bool,
+
+    /// How to print errors on stderr
+    #[arg(long, global = true, value_enum, env = ERROR_FORMAT_ENV, default_value_t = ErrorFormat::Text)]
+    error_format: ErrorFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum BackendKind {
+    /// Control this Mac
+    Macos,
+    /// Serve everything from the simulation state file
+    Sim,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ErrorFormat {
+    /// `Error: <message>`
+    Text,
+    /// A JSON document with subsystem, kind, exit code and message
+    Json,
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
-    /// Control screen brightness (10-100%)
+    /// Control screen brightness (down to the brightness.min setting, 10% by default)
     Brightness {
-        /// Brightness percentage to set (10-100). If not provided, shows current brightness
+        /// Brightness percentage to set, from the brightness.min setting to 100.
+        /// If not provided, shows current brightness
         percentage: Option<f32>,
     },
 
@@ -56,9 +191,182 @@ enum Commands {
 
     /// Get current weather
     Weather {
-        /// Location (city, country). If not provided, auto-detects location
+        /// Location (city, country). Defaults to `weather.location` from the
+        /// config file, or auto-detects location
         location: Option<String>,
     },
+
+    /// Show volume, brightness, now playing, Bluetooth and weather at once
+    Status {
+        /// Print one row per subsystem instead of a single line
+        #[arg(long)]
+        table: bool,
+
+        /// Seconds every subsystem may take [default: `status.timeouts` from
+        /// the config file]
+        #[arg(long, value_name = "SECONDS")]
+        timeout: Option<f64>,
+    },
+
+    /// Show or change settings in the configuration file
+    #[command(subcommand)]
+    Config(ConfigCommands),
+
+    /// Apply and manage scenes: named combinations of settings
+    #[command(subcommand)]
+    Scene(SceneCommands),
+
+    /// Save the current settings to a file and restore them later
+    #[command(subcommand)]
+    State(StateCommands),
+
+    /// Run commands from a file (or `-` for stdin), one per line
+    Batch {
+        /// Script with one command per line, e.g. `volume 40`; blank lines
+        /// and lines starting with `#` are ignored
+        file: PathBuf,
+
+        /// Stop at the first failing command. This is the default; the flag
+        /// wins over --keep-going, which MAC_CLI_BATCH_KEEP_GOING also sets
+        #[arg(long)]
+        stop_on_error: bool,
+
+        /// Run every command even if some fail
+        #[arg(long, env = BATCH_KEEP_GOING_ENV, value_parser = FalseyValueParser::new())]
+        keep_going: bool,
+    },
+
+    /// Serve a JSON-RPC API on a Unix socket and run other mac commands faster
+    Daemon {
+        /// Socket to listen on [default: ~/.config/mac-cli/daemon.sock]
+        #[arg(long, env = SOCKET_ENV, value_name = "PATH")]
+        socket: Option<PathBuf>,
+
+        /// Seconds between polls for subscribers' change events
+        #[arg(long, value_name = "SECONDS", default_value_t = 2.0)]
+        interval: f64,
+    },
+
+    /// Serve a REST API over HTTP, e.g. for phones and web panels
+    Serve {
+        /// Address to listen on: HOST:PORT, or :PORT for every interface
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8765", value_parser = serve::listen_addr)]
+        listen: std::net::SocketAddr,
+
+        /// Require `Authorization: Bearer <TOKEN>` on every request
+        #[arg(long, env = serve::TOKEN_ENV, hide_env_values = true)]
+        token: Option<String>,
+
+        /// Let web pages from this origin (or `*` for any) call the API
+        #[arg(long, value_name = "ORIGIN", value_parser = serve::allow_origin)]
+        allow_origin: Option<String>,
+
+        /// Seconds between polls for `/events` streams
+        #[arg(long, value_name = "SECONDS", default_value_t = 2.0)]
+        interval: f64,
+    },
+
+    /// Serve Prometheus metrics of every subsystem over HTTP
+    Exporter {
+        /// Address to listen on: HOST:PORT, or :PORT for every interface
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:9877", value_parser = serve::listen_addr)]
+        listen: std::net::SocketAddr,
+    },
+
+    /// Bridge to an MQTT broker, with Home Assistant discovery
+    Mqtt {
+        /// Broker to connect to: HOST or HOST:PORT, [IPV6]:PORT for IPv6
+        #[arg(long, value_name = "ADDR", value_parser = mqtt::broker_addr)]
+        broker: (String, u16),
+
+        /// User name on the broker
+        #[arg(long, env = mqtt::USERNAME_ENV)]
+        username: Option<String>,
+
+        /// Password on the broker
+        #[arg(long, env = mqtt::PASSWORD_ENV, hide_env_values = true)]
+        password: Option<String>,
+
+        /// Name of this Mac in topics (mac-cli/NAME/...) and in Home Assistant
+        #[arg(long, default_value = "mac", value_parser = mqtt::node_name)]
+        name: String,
+
+        /// Topic prefix of Home Assistant discovery
+        #[arg(long, value_name = "PREFIX", default_value = "homeassistant")]
+        discovery_prefix: String,
+
+        /// Do not publish Home Assistant discovery messages
+        #[arg(long)]
+        no_discovery: bool,
+
+        /// Seconds between polls for changes
+        #[arg(long, value_name = "SECONDS", default_value_t = 2.0)]
+        interval: f64,
+    },
+
+    /// Serve Open Sound Control over UDP, e.g. for TouchOSC control surfaces
+    Osc {
+        /// UDP port to listen on
+        #[arg(long, default_value_t = 9000)]
+        port: u16,
+
+        /// Address to listen on; 0.0.0.0 accepts control surfaces on other devices
+        #[arg(long, value_name = "IP", default_value = "127.0.0.1")]
+        bind: std::net::IpAddr,
+
+        /// Send feedback to this port of each client instead of the one it sent from
+        #[arg(long, value_name = "PORT")]
+        feedback_port: Option<u16>,
+
+        /// Seconds between polls for changes made elsewhere
+        #[arg(long, value_name = "SECONDS", default_value_t = 2.0)]
+        interval: f64,
+    },
+
+    /// Run the hooks from the configuration file when the state changes
+    Hooks {
+        /// Seconds between polls for changes
+        #[arg(long, value_name = "SECONDS", default_value_t = 2.0)]
+        interval: f64,
+    },
+
+    /// Apply the automation rules from the rules file
+    Rules {
+        /// Rules file [default: ~/.config/mac-cli/rules.toml]
+        #[arg(long, global = true, env = RULES_ENV, value_name = "PATH")]
+        rules: Option<PathBuf>,
+
+        #[command(subcommand)]
+        command: RulesCommands,
+    },
+
+    /// Run commands at set times
+    Schedule {
+        /// Schedule file [default: ~/.config/mac-cli/schedule.json]
+        #[arg(long, global = true, env = SCHEDULE_ENV, value_name = "PATH")]
+        schedule: Option<PathBuf>,
+
+        #[command(subcommand)]
+        command: ScheduleCommands,
+    },
+
+    /// Revert the last changes made by mac
+    Undo {
+        /// Number of changes to revert
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+
+    /// List the changes that `mac undo` can revert, oldest first
+    History {
+        /// Only show the last N changes
+        #[arg(short = 'n', long, value_name = "N")]
+        limit: Option<usize>,
+    },
+
+    /// Any other command runs the plugin `mac-<command>` from PATH
+    #[command(external_subcommand)]
+    External(Vec<OsString>),
 }
 
 #[derive(Subcommand, Debug)]
@@ -84,155 +392,1118 @@ enum MusicCommands {
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// Print the effective value of a setting, e.g. `weather.location`
+    Get { key: String },
+    /// Change a setting; VALUE is parsed as TOML (`40`, `true`, `["a", "b"]`) or
+    /// taken as a string
+    Set { key: String, value: String },
+    /// Print every effective setting, including defaults
+    List,
+    /// Print the path of the configuration file
+    Path,
+}
+
+#[derive(Subcommand, Debug)]
+enum SceneCommands {
+    /// Apply a scene from the configuration file
+    Apply { name: String },
+    /// List the configured scenes
+    List,
+    /// Print the settings of a scene
+    Show { name: String },
+    /// Save the current volume, brightness and player state as a scene
+    Save { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum StateCommands {
+    /// Save volume, brightness of every display and the player state
+    Save {
+        /// Snapshot file [default: ~/.config/mac-cli/state.json]
+        file: Option<PathBuf>,
+    },
+    /// Reapply a snapshot taken with `mac state save`
+    Restore {
+        /// Snapshot file [default: ~/.config/mac-cli/state.json]
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum RulesCommands {
+    /// Apply the rules whenever their conditions start to hold
+    Run {
+        /// Seconds between evaluations
+        #[arg(long, value_name = "SECONDS", default_value_t = 10.0)]
+        interval: f64,
+
+        /// Apply the rules that hold now, then exit
+        #[arg(long)]
+        once: bool,
+    },
+    /// Show which rules would apply, without running anything
+    Test {
+        /// Time of day to evaluate at, e.g. 22:30 [default: now]
+        #[arg(long, value_name = "HH:MM", value_parser = rule_runner::time_of_day)]
+        at: Option<TimeOfDay>,
+
+        /// State to evaluate against, as printed by `mac status --json`
+        /// [default: the current state]
+        #[arg(long, value_name = "PATH")]
+        state: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ScheduleCommands {
+    /// Schedule a command, e.g. `mac schedule add "07:30 weekdays" -- music play`
+    Add {
+        /// When to run: HH:MM, optionally followed by `weekdays`, `weekends`
+        /// or days such as `mon,wed,fri`; or a cron expression such as
+        /// "*/15 9-17 * * mon-fri"
+        when: Recurrence,
+
+        /// What to do about runs missed while `mac schedule run` was not
+        /// running: `skip` them, or `run-once` as soon as possible
+        #[arg(long, value_name = "POLICY", default_value = "skip")]
+        missed: MissedRuns,
+
+        /// The command to run, after `--`
+        #[arg(last = true, required = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    /// List the scheduled jobs
+    List,
+    /// Remove a scheduled job
+    Remove { id: u32 },
+    /// Run the scheduled jobs when they are due, until interrupted
+    Run,
+}
+
+/// Everything a command handler needs besides its own arguments.
+struct Context {
+    backend: Backend,
+    config: Config,
+    config_path: PathBuf,
+    journal: Journal,
+    /// Opened on first use and reused by every command in a batch.
+    brightness: OnceCell<BrightnessController>,
+}
+
+impl Context {
+    fn brightness(&self) -> Result<&BrightnessController> {
+        if let Some(controller) = self.brightness.get() {
+            return Ok(controller);
+        }
+        let controller = BrightnessController::with_backend(&self.backend)?;
+        Ok(self.brightness.get_or_init(|| controller))
+    }
+
+    fn music(&self) -> MusicController {
+        MusicController::with_backend(self.backend.clone())
+            .player(&self.config.music.player)
+            .fzf_options(self.config.music.fzf_options.clone())
+    }
+
+    /// Reads the configuration file again, so that commands running until
+    /// they are stopped pick up edits. On error the previous configuration
+    /// stays in effect.
+    fn reload_config(&mut self) -> Result<()> {
+        self.config = Config::load(&self.config_path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Context {
+    /// A context on a fresh simulated machine, with its files named after
+    /// `prefix` and `name`.
+    fn sim(prefix: &str, name: &str) -> Context {
+        Context {
+            backend: Backend::Sim(SimStore::new(test_support::temp_path(prefix, name, "json"))),
+            config: Config::default(),
+            config_path: test_support::temp_path(prefix, name, "toml"),
+            journal: Journal::new(test_support::temp_path(prefix, name, "jsonl")),
+            brightness: OnceCell::new(),
+        }
+    }
+
+    /// Removes the state and journal files of a context made by [`Context::sim`].
+    fn remove_files(&self) {
+        if let Backend::Sim(store) = &self.backend {
+            test_support::remove_with_lock(store.path());
+        }
+        let _ = std::fs::remove_file(self.journal.path());
+        let _ = std::fs::remove_file(self.journal.path().with_extension("jsonl.lock"));
+    }
+}
+
 fn main() {
-    let cli = Cli::parse();
+    // Looking for plugins takes a scan of PATH, so only do it for the help
+    let mut command = Cli::command();
+    if plugins::help_requested(std::env::args_os().skip(1)) {
+        let plugins = plugins::discover();
+        if !plugins.is_empty() {
+            command = command.after_help(plugins::help(&plugins));
+        }
+    }
+    let cli = Cli::from_arg_matches(&command.get_matches()).unwrap_or_else(|e| e.exit());
+    let json_errors = cli.json || cli.error_format == ErrorFormat::Json;
 
-    let result = match cli.command {
-        Commands::Brightness { percentage } => handle_brightness(percentage),
-        Commands::Volume { percentage } => handle_volume(percentage),
-        Commands::Music(music_cmd) => handle_music(music_cmd),
-        Commands::Bluetooth => handle_bluetooth(),
-        Commands::Weather { location } => handle_weather(location),
-    };
+    if let Err(e) = run(cli, json_errors) {
+        report_error(&e, json_errors);
+        std::process::exit(e.exit_code());
+    }
+}
 
-    if let Err(e) = result {
+/// Prints an error on stderr as `Error: <message>` or as a JSON document.
+fn report_error(e: &MacCliError, json: bool) {
+    if json {
+        eprintln!("{}", e.to_json());
+    } else {
         eprintln!("Error: {}", e);
-        std::process::exit(1);
     }
 }
 
-fn handle_brightness(percentage: Option<f32>) -> Result<(), String> {
-    let controller = BrightnessController::new()?;
+fn run(cli: Cli, json_errors: bool) -> Result<()> {
+    // Plugins read the configuration and set up logging themselves
+    if let Commands::External(args) = &cli.command {
+        return plugins::run(&cli, args);
+    }
+    init_logging(cli.verbose, cli.log_file.as_deref())?;
+
+    let long_running = long_running(&cli.command);
+    if let Some(name) = long_running
+        && cli.dry_run
+    {
+        return Err(MacCliError::invalid_argument(
+            Subsystem::Cli,
+            format!("{} cannot run with --dry-run", name),
+        ));
+    }
+
+    // The plan lists macOS steps, which say nothing about the simulated machine
+    if cli.dry_run && cli.backend == BackendKind::Sim {
+        return Err(MacCliError::invalid_argument(
+            Subsystem::Cli,
+            "--dry-run cannot be combined with --backend sim",
+        ));
+    }
+
+    let backend = match cli.backend {
+        _ if cli.dry_run => Backend::DryRun(Arc::new(DryRun::new())),
+        // Batches run many scripts, so keep one interpreter for all of them
+        BackendKind::Macos
+            if long_running.is_some() || matches!(cli.command, Commands::Batch { .. }) =>
+        {
+            Backend::persistent()
+        }
+        BackendKind::Macos => Backend::system(),
+        BackendKind::Sim => Backend::sim(cli.sim_state.unwrap_or_else(SimStore::default_path)),
+    };
+    let config_path = cli.config.unwrap_or_else(Config::default_path);
+
+    // Changes to the simulated machine must not be undone on the real one
+    let journal = match &backend {
+        Backend::System(_) | Backend::DryRun(_) => Journal::new(Journal::default_path()),
+        Backend::Sim(store) => Journal::new(store.path().with_extension("journal.jsonl")),
+    };
+
+    // `mac config` must keep working when the file itself is invalid
+    let config = match &cli.command {
+        Commands::Config(_) => Config::default(),
+        _ => Config::load(&config_path)?,
+    };
+
+    // Flags take precedence over the configured defaults
+    let format = match (cli.json, cli.format) {
+        (true, _) => OutputFormat::Json,
+        (false, Some(template)) => OutputFormat::Template(template),
+        (false, None) => configured_format(&config)?,
+    };
+
+    let ctx = Context {
+        backend,
+        config,
+        config_path,
+        journal,
+        brightness: OnceCell::new(),
+    };
+
+    let no_daemon = cli.no_daemon;
+    match cli.command {
+        Commands::Batch {
+            file,
+            stop_on_error,
+            keep_going,
+        } => batch::run(
+            &ctx,
+            &file,
+            keep_going && !stop_on_error,
+            &format,
+            json_errors,
+        ),
+        Commands::Daemon { socket, interval } => daemon::serve(
+            ctx,
+            &socket.unwrap_or_else(rpc::default_socket_path),
+            poll_interval(interval)?,
+        ),
+        Commands::Serve {
+            listen,
+            token,
+            allow_origin,
+            interval,
+        } => serve::serve(
+            ctx,
+            serve::ServeOptions {
+                listen,
+                token: token.filter(|t| !t.is_empty()),
+                allow_origin,
+                interval: poll_interval(interval)?,
+            },
+        ),
+        Commands::Exporter { listen } => exporter::serve(ctx, listen),
+        Commands::Mqtt {
+            broker: (host, port),
+            username,
+            password,
+            name,
+            discovery_prefix,
+            no_discovery,
+            interval,
+        } => mqtt::run(
+            ctx,
+            mqtt::BridgeOptions {
+                host,
+                port,
+                username: username.filter(|u| !u.is_empty()),
+                password,
+                name,
+                discovery_prefix: (!no_discovery).then_some(discovery_prefix),
+                interval: poll_interval(interval)?,
+            },
+        ),
+        Commands::Osc {
+            port,
+            bind,
+            feedback_port,
+            interval,
+        } => osc_server::serve(
+            ctx,
+            osc_server::OscOptions {
+                listen: std::net::SocketAddr::new(bind, port),
+                feedback_port,
+                interval: poll_interval(interval)?,
+            },
+        ),
+        Commands::Hooks { interval } => hook_runner::run(ctx, poll_interval(interval)?),
+        Commands::Rules {
+            rules,
+            command: RulesCommands::Run { interval, once },
+        } => rule_runner::run(
+            ctx,
+            &rules.unwrap_or_else(RuleSet::default_path),
+            poll_interval(interval)?,
+            once,
+            &format,
+        ),
+        Commands::Schedule {
+            schedule,
+            command: ScheduleCommands::Run,
+        } => schedule_runner::run(
+            ctx,
+            &Schedule::new(schedule.unwrap_or_else(Schedule::default_path)),
+            &format,
+        ),
+        command => {
+            let args: Vec<String> = std::env::args().skip(1).collect();
+            let forwarded = match no_daemon {
+                true => None,
+                false => daemon::forward(&ctx, &args, &command)?,
+            };
+            let output = match forwarded {
+                Some(output) => output,
+                None => execute(
+                    &ctx,
+                    command,
+                    &CommandSpec::new("mac").args(&args).to_string(),
+                )?,
+            };
+            println!("{}", output.render(&format));
+            Ok(())
+        }
+    }
+}
+
+/// Validates the `--interval` of the servers.
+fn poll_interval(seconds: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|i| !i.is_zero())
+        .ok_or_else(|| {
+            MacCliError::invalid_argument(
+                Subsystem::Cli,
+                "Interval must be a positive number of seconds",
+            )
+        })
+}
+
+/// Runs one command, recording it in the undo journal if it changes anything.
+///
+/// `command_line` is the command as the user typed it, for `mac history`.
+fn execute(ctx: &Context, command: Commands, command_line: &str) -> Result<Output> {
+    if let Some(name) = long_running(&command) {
+        return Err(MacCliError::invalid_argument(
+            Subsystem::Cli,
+            format!("{} only runs as a top-level command", name),
+        ));
+    }
+
+    // Capture what the command is about to change so `mac undo` can revert it
+    let previous = match journal_scope(&command) {
+        Some(_) if ctx.backend.is_dry_run() => None,
+        Some(scope) => Some(Snapshot::capture_scope(&ctx.backend, &ctx.music(), scope)?),
+        None => None,
+    };
+
+    let output = match command {
+        Commands::Brightness { percentage } => handle_brightness(ctx, percentage),
+        Commands::Volume { percentage } => handle_volume(ctx, percentage),
+        Commands::Music(music_cmd) => handle_music(ctx, music_cmd),
+        Commands::Bluetooth => handle_bluetooth(ctx),
+        Commands::Weather { location } => handle_weather(ctx, location),
+        Commands::Status { table, timeout } => handle_status(ctx, table, timeout),
+        Commands::Config(config_cmd) => handle_config(&ctx.config_path, config_cmd),
+        Commands::Scene(scene_cmd) => handle_scene(ctx, scene_cmd),
+        Commands::State(state_cmd) => handle_state(ctx, state_cmd),
+        Commands::Undo { count } => handle_undo(ctx, count),
+        Commands::History { limit } => handle_history(ctx, limit),
+        Commands::Rules {
+            rules,
+            command: RulesCommands::Test { at, state },
+        } => rule_runner::test(
+            ctx,
+            &rules.unwrap_or_else(RuleSet::default_path),
+            at,
+            state.as_deref(),
+        ),
+        Commands::Batch { .. } => Err(MacCliError::invalid_argument(
+            Subsystem::Cli,
+            "mac batch only runs as a top-level command",
+        )),
+        Commands::External(_) => Err(MacCliError::invalid_argument(
+            Subsystem::Cli,
+            "Plugins only run as top-level commands",
+        )),
+        Commands::Schedule { schedule, command } => handle_schedule(
+            &Schedule::new(schedule.unwrap_or_else(Schedule::default_path)),
+            command,
+        ),
+        _ => unreachable!("long-running commands are rejected above"),
+    };
+
+    let output = match &ctx.backend {
+        Backend::DryRun(plan) => dry_run_output(plan, output)?,
+        _ => output?,
+    };
+
+    if let Some(previous) = previous {
+        ctx.journal.record(command_line, previous)?;
+    }
+
+    Ok(output)
+}
+
+/// Installs the log subscriber selected by `-v`/`-vv` or `MAC_CLI_LOG`.
+fn init_logging(verbose: u8, log_file: Option<&Path>) -> Result<()> {
+    let directives = match verbose {
+        0 => match std::env::var(LOG_ENV) {
+            Ok(directives) => directives,
+            Err(_) if log_file.is_some() => "debug".to_string(),
+            Err(_) => return Ok(()),
+        },
+        1 => "debug".to_string(),
+        _ => "trace".to_string(),
+    };
+
+    let filter = EnvFilter::try_new(&directives).map_err(|e| {
+        MacCliError::invalid_argument(
+            Subsystem::Cli,
+            format!("Invalid {}: {:?}", LOG_ENV, directives),
+        )
+        .with_source(e)
+    })?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match log_file {
+        Some(path) => {
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| {
+                    MacCliError::new(
+                        Subsystem::Cli,
+                        ErrorKind::Other,
+                        format!("Failed to open log file {}", path.display()),
+                    )
+                    .with_source(e)
+                })?;
+            builder
+                .with_ansi(false)
+                .with_writer(Mutex::new(file))
+                .init();
+        }
+        None => builder.with_writer(std::io::stderr).init(),
+    }
+    Ok(())
+}
+
+/// Replaces a command's output with the steps it would have executed.
+///
+/// Commands see empty output in a dry run, so an error after the first step
+/// only means the plan cannot continue without real results.
+fn dry_run_output(plan: &DryRun, result: Result<Output>) -> Result<Output> {
+    let steps = plan.take();
+    if steps.is_empty() {
+        result?;
+        return Ok(Output::new(
+            "Nothing would be executed",
+            json!({ "dry_run": true, "steps": steps }),
+        ));
+    }
+
+    Ok(Output::new(
+        steps.join("\n"),
+        json!({ "dry_run": true, "steps": steps }),
+    ))
+}
+
+/// Returns the name of a command that keeps running until it is stopped, such
+/// as a server, or `None` for a command that returns.
+///
+/// These only run as top-level commands, and never as a dry run.
+fn long_running(command: &Commands) -> Option<&'static str> {
+    match command {
+        Commands::Daemon { .. } => Some("mac daemon"),
+        Commands::Serve { .. } => Some("mac serve"),
+        Commands::Exporter { .. } => Some("mac exporter"),
+        Commands::Mqtt { .. } => Some("mac mqtt"),
+        Commands::Osc { .. } => Some("mac osc"),
+        Commands::Hooks { .. } => Some("mac hooks"),
+        Commands::Rules {
+            command: RulesCommands::Run { .. },
+            ..
+        } => Some("mac rules run"),
+        Commands::Schedule {
+            command: ScheduleCommands::Run,
+            ..
+        } => Some("mac schedule run"),
+        _ => None,
+    }
+}
+
+/// Returns the settings a command changes, or `None` for read-only commands.
+fn journal_scope(command: &Commands) -> Option<Scope> {
+    match command {
+        Commands::Brightness {
+            percentage: Some(_),
+        } => Some(Scope::BRIGHTNESS),
+        Commands::Volume {
+            percentage: Some(_),
+        } => Some(Scope::VOLUME),
+        Commands::Music(
+            MusicCommands::Play
+            | MusicCommands::Pause
+            | MusicCommands::Next
+            | MusicCommands::Previous
+            | MusicCommands::Playlists { list: false, .. },
+        ) => Some(Scope::MUSIC),
+        Commands::Scene(SceneCommands::Apply { .. })
+        | Commands::State(StateCommands::Restore { .. }) => Some(Scope::ALL),
+        _ => None,
+    }
+}
+
+/// Returns the output format selected by the `[output]` config section.
+fn configured_format(config: &Config) -> Result<OutputFormat> {
+    if let Some(template) = &config.output.template {
+        return Ok(OutputFormat::Template(Template::parse(template)?));
+    }
+
+    Ok(match config.output.format {
+        Some(OutputKind::Json) => OutputFormat::Json,
+        Some(OutputKind::Text) | None => OutputFormat::Text,
+    })
+}
+
+fn handle_brightness(ctx: &Context, percentage: Option<f32>) -> Result<Output> {
+    let controller = ctx.brightness()?;
+    let min = ctx.config.brightness.min;
 
     match percentage {
         Some(pct) => {
-            if pct == 0.0 {
-                return Err("Brightness cannot be 0".to_string());
-            }
-            if pct < 10.0 || pct > 100.0 {
-                return Err("Brightness must be between 10 and 100".to_string());
-            }
+            brightness::check_percentage(pct, min)?;
+            let previous = read_previous(ctx, || controller.get())?;
             controller.set(pct / 100.0)?;
-            println!("Brightness set to {:.0}%", pct);
+            Ok(Output::new(
+                format!("Brightness set to {:.0}%", pct),
+                json!({ "brightness": pct.round() as i64, "previous": previous.map(percent) }),
+            ))
         }
         None => {
             let brightness = controller.get()?;
-            println!("{:.0}%", brightness * 100.0);
+            Ok(Output::new(
+                format!("{:.0}%", brightness * 100.0),
+                json!({ "brightness": percent(brightness) }),
+            ))
         }
     }
+}
 
-    Ok(())
+/// Reads the value a command is about to replace, except in a dry run where
+/// the read would only produce a placeholder.
+fn read_previous(ctx: &Context, get: impl FnOnce() -> Result<f32>) -> Result<Option<f32>> {
+    if ctx.backend.is_dry_run() {
+        Ok(None)
+    } else {
+        get().map(Some)
+    }
 }
 
-fn handle_volume(percentage: Option<f32>) -> Result<(), String> {
-    let controller = VolumeController::new()?;
+fn handle_volume(ctx: &Context, percentage: Option<f32>) -> Result<Output> {
+    let controller = VolumeController::with_backend(ctx.backend.clone());
 
     match percentage {
         Some(pct) => {
-            if pct < 0.0 || pct > 100.0 {
-                return Err("Volume must be between 0 and 100".to_string());
+            if !(0.0..=100.0).contains(&pct) {
+                return Err(MacCliError::invalid_argument(
+                    Subsystem::Volume,
+                    "Volume must be between 0 and 100",
+                ));
             }
+            let previous = read_previous(ctx, || controller.get())?;
             controller.set(pct / 100.0)?;
-            println!("Volume set to {:.0}%", pct);
+            Ok(Output::new(
+                format!("Volume set to {:.0}%", pct),
+                json!({ "volume": pct.round() as i64, "previous": previous.map(percent) }),
+            ))
         }
         None => {
             let volume = controller.get()?;
-            println!("{:.0}%", volume * 100.0);
+            Ok(Output::new(
+                format!("{:.0}%", volume * 100.0),
+                json!({ "volume": percent(volume) }),
+            ))
         }
     }
-
-    Ok(())
 }
 
-fn handle_music(cmd: MusicCommands) -> Result<(), String> {
+fn handle_music(ctx: &Context, cmd: MusicCommands) -> Result<Output> {
+    let music = ctx.music();
+
+    let action = |name: &str, text: &str| Output::new(text, json!({ "action": name }));
+
     match cmd {
         MusicCommands::Play => {
-            MusicController::play()?;
-            println!("Playing");
+            music.play()?;
+            Ok(action("play", "Playing"))
         }
         MusicCommands::Pause => {
-            MusicController::pause()?;
-            println!("Paused");
+            music.pause()?;
+            Ok(action("pause", "Paused"))
         }
         MusicCommands::Next => {
-            MusicController::next()?;
-            println!("Next track");
+            music.next()?;
+            Ok(action("next", "Next track"))
         }
         MusicCommands::Previous => {
-            MusicController::previous()?;
-            println!("Previous track");
+            music.previous()?;
+            Ok(action("previous", "Previous track"))
         }
         MusicCommands::Current => {
-            let info = MusicController::current()?;
-            println!("{}", info);
+            let now = music.now_playing()?;
+            Ok(Output::new(now_playing_text(&now), json!(now)))
         }
         MusicCommands::Playlists { name, list } => {
             if list {
                 // Just list playlists
-                let playlists = MusicController::list_playlists()?;
-                if playlists.is_empty() {
-                    println!("No playlists found");
+                let playlists = music.list_playlists()?;
+                let text = if playlists.is_empty() {
+                    "No playlists found".to_string()
                 } else {
-                    println!("Playlists:");
-                    for playlist in playlists {
-                        println!("  - {}", playlist);
+                    let mut text = "Playlists:".to_string();
+                    for playlist in &playlists {
+                        text.push_str(&format!("\n  - {}", playlist));
                     }
+                    text
+                };
+                return Ok(Output::new(text, json!({ "playlists": playlists })));
+            }
+
+            let selected = match name {
+                // Play specific playlist
+                Some(playlist_name) => {
+                    music.play_playlist(&playlist_name)?;
+                    playlist_name
                 }
+                // Interactive mode with fzf
+                None => music.play_playlist_interactive()?,
+            };
+
+            // Show current track after a brief moment
+            let now = if ctx.backend.is_dry_run() {
+                None
             } else {
-                match name {
-                    Some(playlist_name) => {
-                        // Play specific playlist
-                        MusicController::play_playlist(&playlist_name)?;
-                        println!("Playing playlist: {}", playlist_name);
-
-                        // Show current track after a brief moment
-                        std::thread::sleep(std::time::Duration::from_millis(500));
-                        match MusicController::current() {
-                            Ok(info) => println!("Now playing: {}", info),
-                            Err(_) => {} // Ignore error if track info not available
-                        }
-                    }
-                    None => {
-                        // Interactive mode with fzf
-                        match MusicController::play_playlist_interactive() {
-                            Ok(selected) => {
-                                println!("Playing playlist: {}", selected);
-                                std::thread::sleep(std::time::Duration::from_millis(500));
-                                match MusicController::current() {
-                                    Ok(info) => println!("Now playing: {}", info),
-                                    Err(_) => {}
-                                }
-                            }
-                            Err(e) => return Err(e),
-                        }
-                    }
-                }
+                std::thread::sleep(std::time::Duration::from_millis(500));
+                // Ignore error if track info not available
+                music.now_playing().ok()
+            };
+
+            let mut text = format!("Playing playlist: {}", selected);
+            if let Some(now) = &now {
+                text.push_str(&format!("\nNow playing: {}", now_playing_text(now)));
             }
+            Ok(Output::new(
+                text,
+                json!({ "playlist": selected, "now_playing": now }),
+            ))
         }
     }
+}
 
-    Ok(())
+/// Formats the player state as "Track Name - Artist Name" or "Not playing".
+fn now_playing_text(now: &NowPlaying) -> String {
+    match &now.track {
+        Some(track) if now.state == PlayerState::Playing => {
+            format!("{} - {}", track.name, track.artist)
+        }
+        _ => "Not playing".to_string(),
+    }
 }
 
-fn handle_bluetooth() -> Result<(), String> {
-    let devices = BluetoothController::list_devices_simple()?;
+fn handle_bluetooth(ctx: &Context) -> Result<Output> {
+    let devices = BluetoothController::with_backend(ctx.backend.clone()).devices()?;
 
-    if devices.is_empty() {
-        println!("No Bluetooth devices found");
+    let text = if devices.is_empty() {
+        "No Bluetooth devices found".to_string()
     } else {
-        println!("Bluetooth Devices:");
-        for device in devices {
-            println!("  - {}", device);
+        let mut text = "Bluetooth Devices:".to_string();
+        for device in &devices {
+            text.push_str(&format!("\n  - {}", device.name));
         }
+        text
+    };
+
+    Ok(Output::new(text, json!({ "devices": devices })))
+}
+
+fn handle_weather(ctx: &Context, location: Option<String>) -> Result<Output> {
+    let location = location.or_else(|| ctx.config.weather.location.clone());
+    let units = ctx.config.weather.units;
+    let weather = WeatherController::with_backend(ctx.backend.clone())
+        .cache(WeatherCache::new(WeatherCache::default_path()))
+        .current(location.as_deref())?;
+
+    Ok(Output::new(
+        weather.summary_in(units),
+        weather_json(&weather, ctx.config.weather.units),
+    ))
+}
+
+/// The weather document, with the temperature in the configured units.
+fn weather_json(weather: &Weather, units: Units) -> serde_json::Value {
+    let mut data = json!(weather);
+    data["temperature"] = json!(weather.temperature_c.map(|t| units.convert(t)));
+    data["units"] = json!(units);
+    data
+}
+
+/// A status query with the configured player, location, weather cache and timeouts.
+fn status_query(ctx: &Context) -> StatusQuery {
+    StatusQuery::new(ctx.backend.clone())
+        .player(&ctx.config.music.player)
+        .location(ctx.config.weather.location.clone())
+        .weather_cache(
+            WeatherCache::new(WeatherCache::default_path()),
+            Duration::from_secs(ctx.config.status.weather_max_age),
+        )
+        .timeouts(ctx.config.status.timeouts)
+}
+
+fn handle_status(ctx: &Context, table: bool, timeout: Option<f64>) -> Result<Output> {
+    let timeouts = match timeout {
+        Some(seconds) if seconds > 0.0 && seconds.is_finite() => Timeouts::all(seconds),
+        Some(_) => {
+            return Err(MacCliError::invalid_argument(
+                Subsystem::Cli,
+                "Timeout must be a positive number of seconds",
+            ));
+        }
+        None => ctx.config.status.timeouts,
+    };
+
+    let status = status_query(ctx).timeouts(timeouts).run();
+
+    let units = ctx.config.weather.units;
+    let rows = status_rows(&status, units);
+    let text = if table {
+        rows.iter()
+            .map(|(label, value)| match value {
+                Ok(value) => format!("{:<12}{}", label, value),
+                Err(e) => format!("{:<12}error: {}", label, e),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    } else {
+        rows.iter()
+            .map(|(label, value)| match value {
+                // The weather summary already starts with the location
+                Ok(value) if *label == "Weather" => value.clone(),
+                Ok(value) => format!("{} {}", label, value),
+                Err(e) => format!("{}: {}", label, e.kind()),
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+    };
+
+    let errors: serde_json::Map<String, serde_json::Value> = status
+        .errors
+        .iter()
+        .map(|(subsystem, e)| (subsystem.to_string(), e.to_json()["error"].clone()))
+        .collect();
+
+    Ok(Output::new(
+        text,
+        json!({
+            "volume": status.volume.map(percent),
+            "muted": status.muted,
+            "brightness": status.brightness.map(percent),
+            "music": status.music,
+            "bluetooth": status.bluetooth,
+            "weather": status.weather.as_ref().map(|w| weather_json(w, units)),
+            "errors": errors,
+        }),
+    ))
+}
+
+/// One `(label, value or error)` row per subsystem that has something to show.
+fn status_rows(
+    status: &Status,
+    units: Units,
+) -> Vec<(&'static str, std::result::Result<String, &MacCliError>)> {
+    let mut rows = Vec::new();
+    let mut row = |label: &'static str, subsystem: Subsystem, value: Option<String>| match (
+        value,
+        status.errors.get(&subsystem),
+    ) {
+        (Some(value), _) => rows.push((label, Ok(value))),
+        (None, Some(e)) => rows.push((label, Err(e))),
+        (None, None) => {}
+    };
+
+    row(
+        "Volume",
+        Subsystem::Volume,
+        status.volume.map(|volume| match status.muted {
+            Some(true) => format!("{}% (muted)", percent(volume)),
+            _ => format!("{}%", percent(volume)),
+        }),
+    );
+    row(
+        "Brightness",
+        Subsystem::Brightness,
+        status.brightness.map(|b| format!("{}%", percent(b))),
+    );
+    row(
+        "Music",
+        Subsystem::Music,
+        status
+            .music
+            .as_ref()
+            .map(|now| match (&now.track, now.state) {
+                (Some(track), PlayerState::Playing) => {
+                    format!("playing {} - {}", track.name, track.artist)
+                }
+                (Some(track), PlayerState::Paused) => {
+                    format!("paused {} - {}", track.name, track.artist)
+                }
+                _ => "stopped".to_string(),
+            }),
+    );
+    row(
+        "Bluetooth",
+        Subsystem::Bluetooth,
+        status
+            .bluetooth
+            .as_ref()
+            .map(|devices| match devices.len() {
+                0 => "none connected".to_string(),
+                _ => devices
+                    .iter()
+                    .map(|d| d.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            }),
+    );
+    row(
+        "Weather",
+        Subsystem::Weather,
+        status.weather.as_ref().map(|w| w.summary_in(units)),
+    );
+
+    rows
+}
+
+fn handle_config(path: &Path, cmd: ConfigCommands) -> Result<Output> {
+    match cmd {
+        ConfigCommands::Get { key } => {
+            let config = Config::load(path)?;
+            let value = config.get(&key).ok_or_else(|| {
+                MacCliError::new(
+                    Subsystem::Cli,
+                    ErrorKind::NotFound,
+                    format!("Configuration key not set: {}", key),
+                )
+            })?;
+            Ok(Output::new(
+                toml_display(&value),
+                json!({ "key": key, "value": value }),
+            ))
+        }
+        ConfigCommands::Set { key, value } => {
+            let config = config::set(path, &key, &value)?;
+            let value = config.get(&key);
+            Ok(Output::new(
+                format!(
+                    "{} = {}",
+                    key,
+                    value.as_ref().map(toml_display).unwrap_or_default()
+                ),
+                json!({ "key": key, "value": value }),
+            ))
+        }
+        ConfigCommands::List => {
+            let entries = Config::load(path)?.entries();
+            let text = entries
+                .iter()
+                .map(|(k, v)| format!("{} = {}", k, v))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let data: serde_json::Map<String, serde_json::Value> =
+                entries.into_iter().map(|(k, v)| (k, json!(v))).collect();
+            Ok(Output::new(text, serde_json::Value::Object(data)))
+        }
+        ConfigCommands::Path => Ok(Output::new(
+            path.display().to_string(),
+            json!({ "path": path }),
+        )),
     }
+}
 
-    Ok(())
+fn handle_scene(ctx: &Context, cmd: SceneCommands) -> Result<Output> {
+    match cmd {
+        SceneCommands::Apply { name } => {
+            let scene = ctx.config.scene(&name)?;
+            scene.apply(&ctx.backend, &ctx.music(), ctx.config.brightness.min)?;
+            Ok(Output::new(
+                format!("Applied scene {}: {}", name, scene.summary()),
+                json!({ "scene": name, "settings": scene }),
+            ))
+        }
+        SceneCommands::List => {
+            let scenes = &ctx.config.scenes;
+            let text = if scenes.is_empty() {
+                "No scenes configured".to_string()
+            } else {
+                let mut text = "Scenes:".to_string();
+                for (name, scene) in scenes {
+                    text.push_str(&format!("\n  - {}: {}", name, scene.summary()));
+                }
+                text
+            };
+            let data: Vec<_> = scenes
+                .iter()
+                .map(|(name, scene)| json!({ "name": name, "settings": scene }))
+                .collect();
+            Ok(Output::new(text, json!({ "scenes": data })))
+        }
+        SceneCommands::Show { name } => {
+            let scene = ctx.config.scene(&name)?;
+            let text = toml::to_string(scene).expect("Scene is always serializable");
+            Ok(Output::new(
+                text.trim_end(),
+                json!({ "scene": name, "settings": scene }),
+            ))
+        }
+        SceneCommands::Save { name } => {
+            let scene = Scene::capture(&ctx.backend, &ctx.music())?;
+            config::save_scene(&ctx.config_path, &name, &scene)?;
+            Ok(Output::new(
+                format!("Saved scene {}: {}", name, scene.summary()),
+                json!({ "scene": name, "settings": scene }),
+            ))
+        }
+    }
 }
 
-fn handle_weather(location: Option<String>) -> Result<(), String> {
-    let location_ref = location.as_deref();
-    let weather = WeatherController::get_weather(location_ref)?;
-    println!("{}", weather);
+fn handle_state(ctx: &Context, cmd: StateCommands) -> Result<Output> {
+    match cmd {
+        StateCommands::Save { file } => {
+            let path = file.unwrap_or_else(Snapshot::default_path);
+            let snapshot = Snapshot::capture(&ctx.backend, &ctx.music())?;
+            snapshot.save(&path)?;
+            Ok(Output::new(
+                format!("Saved state to {}: {}", path.display(), snapshot.summary()),
+                json!({ "path": path, "snapshot": snapshot }),
+            ))
+        }
+        StateCommands::Restore { file } => {
+            let path = file.unwrap_or_else(Snapshot::default_path);
+            let snapshot = Snapshot::load(&path)?;
+            snapshot.restore(&ctx.backend, &ctx.music())?;
+            Ok(Output::new(
+                format!(
+                    "Restored state from {}: {}",
+                    path.display(),
+                    snapshot.summary()
+                ),
+                json!({ "path": path, "snapshot": snapshot }),
+            ))
+        }
+    }
+}
 
-    Ok(())
+fn handle_undo(ctx: &Context, count: usize) -> Result<Output> {
+    let undone = if ctx.backend.is_dry_run() {
+        // Show the restore steps but keep the journal intact
+        let mut entries = ctx.journal.entries()?;
+        entries.reverse();
+        entries.truncate(count);
+        for entry in &entries {
+            entry.previous.restore(&ctx.backend, &ctx.music())?;
+        }
+        entries
+    } else {
+        ctx.journal.undo(count, &ctx.backend, &ctx.music())?
+    };
+
+    let text = undone
+        .iter()
+        .map(|entry| {
+            format!(
+                "Undid `{}`: restored {}",
+                entry.command,
+                entry.previous.summary()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(Output::new(text, json!({ "entries": undone })))
+}
+
+fn handle_history(ctx: &Context, limit: Option<usize>) -> Result<Output> {
+    let mut entries = ctx.journal.entries()?;
+    if let Some(limit) = limit {
+        entries.drain(..entries.len().saturating_sub(limit));
+    }
+
+    let text = if entries.is_empty() {
+        "No changes recorded".to_string()
+    } else {
+        entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{}  {}  (was {})",
+                    entry.time.format("%Y-%m-%d %H:%M:%S"),
+                    entry.command,
+                    entry.previous.summary()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    Ok(Output::new(text, json!({ "entries": entries })))
+}
+
+fn handle_schedule(schedule: &Schedule, cmd: ScheduleCommands) -> Result<Output> {
+    let now = chrono::Local::now().naive_local();
+    let describe = |job: &mac_cli::schedule::Job| {
+        let next = job.when.next_after(now);
+        let mut data = serde_json::to_value(job).expect("Job is always serializable");
+        data["next_run"] = json!(next);
+        let next = next.map_or("never".to_string(), |next| {
+            format!("next {}", next.format("%a %Y-%m-%d %H:%M"))
+        });
+        let missed = match job.missed {
+            MissedRuns::Skip => String::new(),
+            policy => format!(", missed runs: {}", policy),
+        };
+        (
+            format!("{}  ({}, {}{})", job.command, job.when, next, missed),
+            data,
+        )
+    };
+
+    match cmd {
+        ScheduleCommands::Add {
+            when,
+            missed,
+            command,
+        } => {
+            let line = shlex::try_join(command.iter().map(String::as_str)).map_err(|e| {
+                MacCliError::invalid_argument(Subsystem::Cli, format!("Invalid command: {}", e))
+            })?;
+            batch::parse_line(&line).map_err(|message| {
+                MacCliError::invalid_argument(
+                    Subsystem::Cli,
+                    format!("Cannot schedule `{}`: {}", line, message),
+                )
+            })?;
+
+            let job = schedule.add(when, line, missed)?;
+            let (text, data) = describe(&job);
+            Ok(Output::new(
+                format!("Scheduled job {}: {}", job.id, text),
+                json!({ "job": data }),
+            ))
+        }
+        ScheduleCommands::List => {
+            let jobs = schedule.jobs()?;
+            let described: Vec<_> = jobs.iter().map(|job| (job.id, describe(job))).collect();
+            let text = if jobs.is_empty() {
+                "No scheduled jobs".to_string()
+            } else {
+                let mut text = "Scheduled jobs:".to_string();
+                for (id, (line, _)) in &described {
+                    text.push_str(&format!("\n  {}: {}", id, line));
+                }
+                text
+            };
+            let data: Vec<_> = described.into_iter().map(|(_, (_, data))| data).collect();
+            Ok(Output::new(text, json!({ "jobs": data })))
+        }
+        ScheduleCommands::Remove { id } => {
+            let job = schedule.remove(id)?;
+            Ok(Output::new(
+                format!("Removed job {}: {}", job.id, job.command),
+                json!({ "removed": job }),
+            ))
+        }
+        ScheduleCommands::Run => unreachable!("execute rejects long-running commands"),
+    }
+}
+
+/// Formats a TOML value for display, printing strings without quotes.
+fn toml_display(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
 }
@@ -0,0 +1,352 @@
+//! Prometheus metrics of a [`Status`].
+//!
+//! [`encode`] renders a status in the Prometheus text exposition format, as
+//! served by `mac exporter`:
+//!
+//! | Metric                                | Labels              | Meaning                                  |
+//! |---------------------------------------|---------------------|------------------------------------------|
+//! | `mac_volume_ratio`                    |                     | Output volume, 0 to 1                    |
+//! | `mac_muted`                           |                     | 1 if the output is muted                 |
+//! | `mac_display_brightness_ratio`        | `display`           | Brightness of each display, 0 to 1       |
+//! | `mac_music_playing`                   |                     | 1 if the player is playing               |
+//! | `mac_bluetooth_connected_devices`     |                     | Number of connected Bluetooth devices    |
+//! | `mac_bluetooth_device_battery_ratio`  | `device`, `address` | Battery of each connected device, 0 to 1 |
+//! | `mac_weather_temperature_celsius`     | `location`          | Current temperature                      |
+//! | `mac_scrape_success`                  | `subsystem`         | 0 if reading the subsystem failed        |
+//! | `mac_scrape_duration_seconds`         |                     | Time taken to read every subsystem       |
+//!
+//! Metrics of a subsystem that failed are left out, as are those that do not
+//! apply (no adjustable display, no battery reported).
+//!
+//! ```no_run
+//! use mac_cli::Backend;
+//! use mac_cli::status::StatusQuery;
+//! use std::time::Instant;
+//!
+//! let started = Instant::now();
+//! let status = StatusQuery::new(Backend::system()).run();
+//! print!("{}", mac_cli::metrics::encode(&status, started.elapsed()));
+//! ```
+
+use crate::error::Subsystem;
+use crate::music::PlayerState;
+use crate::status::Status;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// The `Content-Type` of [`encode`]'s output.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The subsystems reported by `mac_scrape_success`.
+const SUBSYSTEMS: [Subsystem; 5] = [
+    Subsystem::Volume,
+    Subsystem::Brightness,
+    Subsystem::Music,
+    Subsystem::Bluetooth,
+    Subsystem::Weather,
+];
+
+/// One metric and its samples.
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    samples: Vec<(Vec<(&'static str, String)>, f64)>,
+}
+
+/// Renders `status`, read in `duration`, as Prometheus gauges.
+pub fn encode(status: &Status, duration: Duration) -> String {
+    let families = [
+        Family::single(
+            "mac_volume_ratio",
+            "Output volume, 0 to 1.",
+            status.volume.map(float),
+        ),
+        Family::single(
+            "mac_muted",
+            "1 if the output is muted.",
+            status.muted.map(flag),
+        ),
+        Family {
+            name: "mac_display_brightness_ratio",
+            help: "Brightness of each display, 0 to 1.",
+            samples: status
+                .displays
+                .iter()
+                .map(|d| (vec![("display", d.id.to_string())], float(d.brightness)))
+                .collect(),
+        },
+        // Music is only missing when it failed; a player that is not running is not playing
+        Family::single(
+            "mac_music_playing",
+            "1 if the player is playing.",
+            (!status.errors.contains_key(&Subsystem::Music)).then(|| {
+                flag(
+                    status
+                        .music
+                        .as_ref()
+                        .is_some_and(|m| m.state == PlayerState::Playing),
+                )
+            }),
+        ),
+        Family::single(
+            "mac_bluetooth_connected_devices",
+            "Number of connected Bluetooth devices.",
+            status
+                .bluetooth
+                .as_ref()
+                .map(|devices| devices.len() as f64),
+        ),
+        Family {
+            name: "mac_bluetooth_device_battery_ratio",
+            help: "Battery level of each connected Bluetooth device, 0 to 1.",
+            samples: status
+                .bluetooth
+                .iter()
+                .flatten()
+                .filter_map(|device| {
+                    let labels = vec![
+                        ("device", device.name.clone()),
+                        ("address", device.address.clone().unwrap_or_default()),
+                    ];
+                    device.battery.map(|b| (labels, f64::from(b) / 100.0))
+                })
+                .collect(),
+        },
+        Family {
+            name: "mac_weather_temperature_celsius",
+            help: "Current temperature at the weather location.",
+            samples: status
+                .weather
+                .iter()
+                .filter_map(|w| {
+                    w.temperature_c
+                        .map(|t| (vec![("location", w.location.clone())], float(t)))
+                })
+                .collect(),
+        },
+        Family {
+            name: "mac_scrape_success",
+            help: "0 if reading the subsystem failed or timed out.",
+            samples: SUBSYSTEMS
+                .iter()
+                .map(|s| {
+                    let labels = vec![("subsystem", s.to_string())];
+                    (labels, flag(!status.errors.contains_key(s)))
+                })
+                .collect(),
+        },
+        Family::single(
+            "mac_scrape_duration_seconds",
+            "Time taken to read every subsystem.",
+            Some(duration.as_secs_f64()),
+        ),
+    ];
+
+    let mut out = String::new();
+    for family in families.iter().filter(|f| !f.samples.is_empty()) {
+        family.write(&mut out);
+    }
+    out
+}
+
+impl Family {
+    /// A metric without labels, left out when `value` is `None`.
+    fn single(name: &'static str, help: &'static str, value: Option<f64>) -> Self {
+        Family {
+            name,
+            help,
+            samples: value.map(|v| (Vec::new(), v)).into_iter().collect(),
+        }
+    }
+
+    fn write(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} gauge", self.name);
+        for (labels, value) in &self.samples {
+            out.push_str(self.name);
+            if !labels.is_empty() {
+                let labels: Vec<String> = labels
+                    .iter()
+                    .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+                    .collect();
+                let _ = write!(out, "{{{}}}", labels.join(","));
+            }
+            let _ = writeln!(out, " {}", value);
+        }
+    }
+}
+
+/// Widens a reading, dropping the digits f32 cannot represent (`0.33`, not
+/// `0.33000001311302185`).
+fn float(value: f32) -> f64 {
+    (f64::from(value) * 1e6).round() / 1e6
+}
+
+fn flag(value: bool) -> f64 {
+    if value { 1.0 } else { 0.0 }
+}
+
+/// Escapes a label value: backslashes, double quotes and newlines.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::Device;
+    use crate::error::{ErrorKind, MacCliError};
+    use crate::music::NowPlaying;
+    use crate::state::DisplayBrightness;
+    use crate::weather::Weather;
+
+    fn device(name: &str, address: Option<&str>, battery: Option<u8>) -> Device {
+        Device {
+            name: name.to_string(),
+            connected: true,
+            address: address.map(str::to_string),
+            battery,
+        }
+    }
+
+    #[test]
+    fn every_subsystem_is_a_gauge() {
+        let status = Status {
+            volume: Some(0.33),
+            muted: Some(false),
+            brightness: Some(0.75),
+            displays: vec![
+                DisplayBrightness {
+                    id: 1,
+                    brightness: 0.75,
+                },
+                DisplayBrightness {
+                    id: 2,
+                    brightness: 0.5,
+                },
+            ],
+            music: Some(NowPlaying {
+                state: PlayerState::Playing,
+                track: None,
+            }),
+            bluetooth: Some(vec![
+                device("AirPods", Some("aa-bb"), Some(80)),
+                device("Keyboard", None, None),
+            ]),
+            weather: Some(Weather {
+                location: "London".to_string(),
+                condition: "Rain".to_string(),
+                icon: "🌧".to_string(),
+                temperature_c: Some(11.0),
+            }),
+            errors: Default::default(),
+        };
+
+        assert_eq!(
+            encode(&status, Duration::from_millis(1500)),
+            "\
+# HELP mac_volume_ratio Output volume, 0 to 1.
+# TYPE mac_volume_ratio gauge
+mac_volume_ratio 0.33
+# HELP mac_muted 1 if the output is muted.
+# TYPE mac_muted gauge
+mac_muted 0
+# HELP mac_display_brightness_ratio Brightness of each display, 0 to 1.
+# TYPE mac_display_brightness_ratio gauge
+mac_display_brightness_ratio{display=\"1\"} 0.75
+mac_display_brightness_ratio{display=\"2\"} 0.5
+# HELP mac_music_playing 1 if the player is playing.
+# TYPE mac_music_playing gauge
+mac_music_playing 1
+# HELP mac_bluetooth_connected_devices Number of connected Bluetooth devices.
+# TYPE mac_bluetooth_connected_devices gauge
+mac_bluetooth_connected_devices 2
+# HELP mac_bluetooth_device_battery_ratio Battery level of each connected Bluetooth device, 0 to 1.
+# TYPE mac_bluetooth_device_battery_ratio gauge
+mac_bluetooth_device_battery_ratio{device=\"AirPods\",address=\"aa-bb\"} 0.8
+# HELP mac_weather_temperature_celsius Current temperature at the weather location.
+# TYPE mac_weather_temperature_celsius gauge
+mac_weather_temperature_celsius{location=\"London\"} 11
+# HELP mac_scrape_success 0 if reading the subsystem failed or timed out.
+# TYPE mac_scrape_success gauge
+mac_scrape_success{subsystem=\"volume\"} 1
+mac_scrape_success{subsystem=\"brightness\"} 1
+mac_scrape_success{subsystem=\"music\"} 1
+mac_scrape_success{subsystem=\"bluetooth\"} 1
+mac_scrape_success{subsystem=\"weather\"} 1
+# HELP mac_scrape_duration_seconds Time taken to read every subsystem.
+# TYPE mac_scrape_duration_seconds gauge
+mac_scrape_duration_seconds 1.5
+"
+        );
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let status = Status {
+            bluetooth: Some(vec![device(
+                "Joe's \"Pro\" Mouse\\2\nv2",
+                Some("cc-dd"),
+                Some(5),
+            )]),
+            ..Status::default()
+        };
+
+        let out = encode(&status, Duration::ZERO);
+        assert!(
+            out.contains(
+                "mac_bluetooth_device_battery_ratio{device=\"Joe's \\\"Pro\\\" Mouse\\\\2\\nv2\",address=\"cc-dd\"} 0.05\n"
+            ),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn failed_and_unavailable_subsystems_are_left_out() {
+        let mut errors = std::collections::BTreeMap::new();
+        for (subsystem, kind) in [
+            (Subsystem::Volume, ErrorKind::Timeout),
+            (Subsystem::Music, ErrorKind::CommandFailed),
+            (Subsystem::Weather, ErrorKind::Timeout),
+        ] {
+            errors.insert(subsystem, MacCliError::new(subsystem, kind, "failed"));
+        }
+        // Brightness is unavailable and no Bluetooth device is connected
+        let status = Status {
+            bluetooth: Some(Vec::new()),
+            errors,
+            ..Status::default()
+        };
+
+        let out = encode(&status, Duration::ZERO);
+        for family in [
+            "mac_volume_ratio",
+            "mac_muted",
+            "mac_display_brightness_ratio",
+            "mac_music_playing",
+            "mac_bluetooth_device_battery_ratio",
+            "mac_weather_temperature_celsius",
+        ] {
+            assert!(!out.contains(family), "{} in\n{}", family, out);
+        }
+        assert!(
+            out.contains("\nmac_bluetooth_connected_devices 0\n"),
+            "{}",
+            out
+        );
+        assert!(out.contains("mac_scrape_success{subsystem=\"brightness\"} 1\n"));
+        assert!(out.contains("mac_scrape_success{subsystem=\"volume\"} 0\n"));
+        assert!(out.contains("mac_scrape_success{subsystem=\"music\"} 0\n"));
+        assert!(out.contains("mac_scrape_success{subsystem=\"weather\"} 0\n"));
+    }
+
+    #[test]
+    fn player_that_is_not_running_is_not_playing() {
+        let out = encode(&Status::default(), Duration::ZERO);
+        assert!(out.contains("\nmac_music_playing 0\n"), "{}", out);
+    }
+}
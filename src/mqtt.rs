@@ -0,0 +1,675 @@
+//! `mac mqtt`: a bridge between this Mac and an MQTT broker, with Home
+//! Assistant discovery.
+//!
+//! States are published (retained) below `mac-cli/<name>/` whenever a
+//! [`Watcher`] sees them change, and commands received on the `set` and
+//! `command` topics run like the corresponding `mac` commands, so they are
+//! recorded for `mac undo`:
+//!
+//! | Topic                | Direction | Payload                                     |
+//! |----------------------|-----------|---------------------------------------------|
+//! | `availability`       | out       | `online` / `offline`                        |
+//! | `volume/state`       | out       | `40`                                        |
+//! | `volume/muted`       | out       | `ON` / `OFF`                                |
+//! | `volume/set`         | in        | `40`                                        |
+//! | `brightness/state`   | out       | `75`                                        |
+//! | `brightness/power`   | out       | `ON` / `OFF`                                |
+//! | `brightness/set`     | in        | `75`                                        |
+//! | `brightness/switch`  | in        | `ON` / `OFF` (dims to `brightness.min`)     |
+//! | `music/state`        | out       | `playing`, `paused`, `stopped`, `not_running` |
+//! | `music/track`        | out       | `{"name": ..., "artist": ..., "album": ...}`, or `{}` |
+//! | `music/command`      | in        | `play`, `pause`, `next`, `previous`         |
+//! | `bluetooth/state`    | out       | `{"connected": 1, "devices": [...]}`        |
+//!
+//! Home Assistant's MQTT integration has no media player platform, so the
+//! player shows up as two sensors and four buttons; the volume is a number
+//! and the display a dimmable light.
+
+use crate::{Commands, Context, MusicCommands, execute};
+use mac_cli::watch::{BLUETOOTH_POLL_EVERY, WATCHABLE, Watcher};
+use mac_cli::{ErrorKind, MacCliError, Result, Subsystem};
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS, RecvTimeoutError};
+use serde_json::{Value, json};
+use std::time::{Duration, Instant};
+
+/// Environment variable holding the broker user name.
+pub(crate) const USERNAME_ENV: &str = "MAC_CLI_MQTT_USERNAME";
+
+/// Environment variable holding the broker password.
+pub(crate) const PASSWORD_ENV: &str = "MAC_CLI_MQTT_PASSWORD";
+
+/// How long to wait before reconnecting after the broker went away.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Settings of `mac mqtt` besides the context.
+pub(crate) struct BridgeOptions {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) username: Option<String>,
+    pub(crate) password: Option<String>,
+    /// Identifies this Mac in topics and in Home Assistant.
+    pub(crate) name: String,
+    /// Topic prefix of Home Assistant discovery, or `None` to skip it.
+    pub(crate) discovery_prefix: Option<String>,
+    pub(crate) interval: Duration,
+}
+
+struct Bridge {
+    client: Client,
+    /// `mac-cli/<name>`.
+    base: String,
+    options: BridgeOptions,
+    watcher: Watcher,
+    online: bool,
+    ticks: u64,
+}
+
+/// What a message on a command topic asks for.
+struct Action {
+    subsystem: Subsystem,
+    /// `None` when only the display's power state is confirmed.
+    command: Option<Commands>,
+    /// The display's power state once the command has run.
+    power: Option<&'static str>,
+}
+
+/// Parses `--broker`: `HOST`, `HOST:PORT` or `mqtt://HOST:PORT`, where an
+/// IPv6 address is written `[::1]:1883`, or `::1` without a port.
+pub(crate) fn broker_addr(value: &str) -> std::result::Result<(String, u16), String> {
+    let value = value.strip_prefix("mqtt://").unwrap_or(value);
+    let (host, port) = match value.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, rest)) => match rest.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return Err(format!("Expected a port after ']': {}", value)),
+            },
+            None => return Err(format!("Missing ']': {}", value)),
+        },
+        None => match value.split_once(':') {
+            // More than one colon is a bare IPv6 address
+            Some((host, port)) if !port.contains(':') => (host, Some(port)),
+            _ => (value, None),
+        },
+    };
+
+    let port = match port {
+        Some(port) => port
+            .parse()
+            .map_err(|_| format!("Invalid port: {}", port))?,
+        None => 1883,
+    };
+    match host.is_empty() {
+        true => Err("Missing broker host".to_string()),
+        false => Ok((host.to_string(), port)),
+    }
+}
+
+/// Checks that `--name` can be used in topics and entity IDs.
+pub(crate) fn node_name(value: &str) -> std::result::Result<String, String> {
+    match !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        true => Ok(value.to_string()),
+        false => Err("Use letters, digits, '-' and '_' only".to_string()),
+    }
+}
+
+/// Connects to the broker and bridges until the process is killed.
+pub(crate) fn run(mut ctx: Context, options: BridgeOptions) -> Result<()> {
+    let base = format!("mac-cli/{}", options.name);
+
+    let mut mqtt = MqttOptions::new(
+        format!("mac-cli-{}", options.name),
+        &options.host,
+        options.port,
+    );
+    mqtt.set_keep_alive(Duration::from_secs(30));
+    mqtt.set_last_will(LastWill::new(
+        format!("{}/availability", base),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &options.username {
+        mqtt.set_credentials(username, options.password.clone().unwrap_or_default());
+    }
+    let (client, mut connection) = Client::new(mqtt, 100);
+
+    let mut bridge = Bridge {
+        client,
+        base,
+        watcher: Watcher::new(ctx.backend.clone(), ctx.music()),
+        options,
+        online: false,
+        ticks: 0,
+    };
+    let mut next_poll = Instant::now() + bridge.options.interval;
+
+    loop {
+        // Poll between messages, even when they keep coming
+        if Instant::now() >= next_poll {
+            bridge.poll();
+            next_poll = Instant::now() + bridge.options.interval;
+        }
+
+        match connection.recv_timeout(next_poll.saturating_duration_since(Instant::now())) {
+            Ok(Ok(Event::Incoming(Packet::ConnAck(_)))) => bridge.connected(),
+            Ok(Ok(Event::Incoming(Packet::Publish(message)))) => {
+                let payload = String::from_utf8_lossy(&message.payload);
+                bridge.received(&mut ctx, &message.topic, payload.trim());
+            }
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Err(e)) => {
+                if bridge.online {
+                    eprintln!("mac mqtt: lost connection to the broker: {}", e);
+                } else {
+                    eprintln!(
+                        "mac mqtt: cannot connect to {}:{}: {}",
+                        bridge.options.host, bridge.options.port, e
+                    );
+                }
+                bridge.online = false;
+                std::thread::sleep(RECONNECT_DELAY);
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(MacCliError::new(
+                    Subsystem::Cli,
+                    ErrorKind::Other,
+                    "mac mqtt stopped: the MQTT client closed",
+                ));
+            }
+        }
+    }
+}
+
+/// Maps a command topic below the base topic, and its payload, to an action.
+///
+/// `min_brightness` is the configured minimum, used for `OFF`.
+fn action(topic: &str, payload: &str, min_brightness: f32) -> Result<Option<Action>> {
+    let number = |subsystem| {
+        payload.parse::<f32>().map_err(|_| {
+            MacCliError::invalid_argument(subsystem, format!("Not a number: {:?}", payload))
+        })
+    };
+    let brightness = |percentage, power| Action {
+        subsystem: Subsystem::Brightness,
+        command: Some(Commands::Brightness {
+            percentage: Some(percentage),
+        }),
+        power: Some(power),
+    };
+
+    Ok(Some(match topic {
+        "volume/set" => Action {
+            subsystem: Subsystem::Volume,
+            command: Some(Commands::Volume {
+                percentage: Some(number(Subsystem::Volume)?),
+            }),
+            power: None,
+        },
+        // Home Assistant sends 0-100; the display never goes below the minimum
+        "brightness/set" => brightness(number(Subsystem::Brightness)?.max(min_brightness), "ON"),
+        "brightness/switch" => match payload {
+            // The display cannot be switched off, only dimmed; 0 would blank it
+            "OFF" => brightness(min_brightness.max(1.0), "OFF"),
+            // ON comes with a brightness
+            _ => Action {
+                subsystem: Subsystem::Brightness,
+                command: None,
+                power: Some("ON"),
+            },
+        },
+        "music/command" => {
+            let command = match payload {
+                "play" => MusicCommands::Play,
+                "pause" => MusicCommands::Pause,
+                "next" => MusicCommands::Next,
+                "previous" => MusicCommands::Previous,
+                _ => {
+                    return Err(MacCliError::invalid_argument(
+                        Subsystem::Music,
+                        format!(
+                            "Unknown command {:?}; use play, pause, next or previous",
+                            payload
+                        ),
+                    ));
+                }
+            };
+            Action {
+                subsystem: Subsystem::Music,
+                command: Some(Commands::Music(command)),
+                power: None,
+            }
+        }
+        _ => return Ok(None),
+    }))
+}
+
+impl Bridge {
+    /// Subscribes and publishes everything after every (re)connection: the
+    /// broker forgets subscriptions of a clean session.
+    fn connected(&mut self) {
+        eprintln!(
+            "mac mqtt: connected to {}:{}, publishing to {}/",
+            self.options.host, self.options.port, self.base
+        );
+        self.online = true;
+
+        for topic in [
+            format!("{}/+/set", self.base),
+            format!("{}/brightness/switch", self.base),
+            format!("{}/music/command", self.base),
+        ] {
+            self.subscribe(topic);
+        }
+        if let Some(prefix) = &self.options.discovery_prefix {
+            // Home Assistant announces restarts here and expects discovery again
+            self.subscribe(format!("{}/status", prefix));
+            self.discover();
+        }
+
+        self.publish("availability", "online");
+        self.publish("brightness/power", "ON");
+        self.watcher.reset();
+        self.watcher.poll(&WATCHABLE);
+        for subsystem in WATCHABLE {
+            self.publish_state(subsystem);
+        }
+    }
+
+    /// Publishes the subsystems that changed since the last poll.
+    fn poll(&mut self) {
+        if !self.online {
+            return;
+        }
+
+        let include_bluetooth = self.ticks.is_multiple_of(BLUETOOTH_POLL_EVERY);
+        self.ticks += 1;
+        let watched: Vec<Subsystem> = WATCHABLE
+            .into_iter()
+            .filter(|s| include_bluetooth || *s != Subsystem::Bluetooth)
+            .collect();
+        for change in self.watcher.poll(&watched) {
+            tracing::debug!(subsystem = %change.subsystem, value = %change.value, "mqtt: state changed");
+            self.publish_state(change.subsystem);
+        }
+    }
+
+    /// Handles a message on one of the subscribed topics.
+    fn received(&mut self, ctx: &mut Context, topic: &str, payload: &str) {
+        tracing::debug!(topic, payload, "mqtt: received");
+
+        if let Some(prefix) = &self.options.discovery_prefix
+            && topic == format!("{}/status", prefix)
+        {
+            if payload == "online" {
+                self.discover();
+                for subsystem in WATCHABLE {
+                    self.publish_state(subsystem);
+                }
+            }
+            return;
+        }
+
+        let Some(command) = topic
+            .strip_prefix(&self.base)
+            .and_then(|t| t.strip_prefix('/'))
+        else {
+            return;
+        };
+
+        let result = ctx
+            .reload_config()
+            .and_then(|_| action(command, payload, ctx.config.brightness.min));
+        let action = match result {
+            Ok(Some(action)) => action,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("mac mqtt: {}: {}", topic, e);
+                return;
+            }
+        };
+
+        let executed = match action.command {
+            Some(command) => execute(ctx, command, &format!("{} {}", topic, payload)).map(|_| ()),
+            None => Ok(()),
+        };
+        match executed {
+            // Report the power state only once the display is in it
+            Ok(()) => {
+                if let Some(power) = action.power {
+                    self.publish("brightness/power", power);
+                }
+            }
+            Err(e) => eprintln!("mac mqtt: {}: {}", topic, e),
+        }
+
+        // Confirm the new state right away, even if nothing changed
+        self.watcher.poll(&[action.subsystem]);
+        self.publish_state(action.subsystem);
+    }
+
+    /// Publishes the last known state of `subsystem`.
+    fn publish_state(&self, subsystem: Subsystem) {
+        let Some(value) = self.watcher.last(subsystem) else {
+            return;
+        };
+
+        match subsystem {
+            Subsystem::Volume => {
+                self.publish("volume/state", &value["volume"].to_string());
+                let muted = value["muted"].as_bool().unwrap_or(false);
+                self.publish("volume/muted", if muted { "ON" } else { "OFF" });
+            }
+            Subsystem::Brightness => {
+                self.publish("brightness/state", &value["brightness"].to_string())
+            }
+            Subsystem::Music => {
+                let state = value["state"].as_str().unwrap_or("not_running");
+                self.publish("music/state", state);
+                let track = match &value["track"] {
+                    Value::Null => json!({}),
+                    track => track.clone(),
+                };
+                self.publish("music/track", &track.to_string());
+            }
+            Subsystem::Bluetooth => {
+                let connected: Vec<&Value> = value["devices"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter(|d| d["connected"].as_bool().unwrap_or(false))
+                    .collect();
+                let state = json!({ "connected": connected.len(), "devices": connected });
+                self.publish("bluetooth/state", &state.to_string());
+            }
+            Subsystem::Weather | Subsystem::Cli => {}
+        }
+    }
+
+    /// Publishes the Home Assistant discovery documents.
+    fn discover(&self) {
+        let Some(prefix) = &self.options.discovery_prefix else {
+            return;
+        };
+        let name = &self.options.name;
+        let topic = |suffix: &str| format!("{}/{}", self.base, suffix);
+        let device = json!({
+            "identifiers": [format!("mac-cli-{}", name)],
+            "name": name,
+            "manufacturer": "Apple",
+            "model": "Mac",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+
+        let mut entities = vec![
+            (
+                "number",
+                "volume",
+                json!({
+                    "name": "Volume",
+                    "command_topic": topic("volume/set"),
+                    "state_topic": topic("volume/state"),
+                    "min": 0,
+                    "max": 100,
+                    "step": 1,
+                    "mode": "slider",
+                    "unit_of_measurement": "%",
+                    "icon": "mdi:volume-high",
+                }),
+            ),
+            (
+                "binary_sensor",
+                "muted",
+                json!({
+                    "name": "Muted",
+                    "state_topic": topic("volume/muted"),
+                    "icon": "mdi:volume-off",
+                }),
+            ),
+            (
+                "light",
+                "display",
+                json!({
+                    "name": "Display",
+                    "command_topic": topic("brightness/switch"),
+                    "state_topic": topic("brightness/power"),
+                    "brightness_command_topic": topic("brightness/set"),
+                    "brightness_state_topic": topic("brightness/state"),
+                    "brightness_scale": 100,
+                    "on_command_type": "brightness",
+                    "icon": "mdi:monitor",
+                }),
+            ),
+            (
+                "sensor",
+                "player",
+                json!({
+                    "name": "Player",
+                    "state_topic": topic("music/state"),
+                    "icon": "mdi:music",
+                }),
+            ),
+            (
+                "sensor",
+                "now_playing",
+                json!({
+                    "name": "Now playing",
+                    "state_topic": topic("music/track"),
+                    "value_template": "{{ value_json.name ~ ' - ' ~ value_json.artist if value_json.name is defined else 'Nothing' }}",
+                    "json_attributes_topic": topic("music/track"),
+                    "icon": "mdi:music-note",
+                }),
+            ),
+            (
+                "sensor",
+                "bluetooth",
+                json!({
+                    "name": "Bluetooth devices",
+                    "state_topic": topic("bluetooth/state"),
+                    "value_template": "{{ value_json.connected }}",
+                    "json_attributes_topic": topic("bluetooth/state"),
+                    "icon": "mdi:bluetooth",
+                }),
+            ),
+        ];
+        for (action, icon) in [
+            ("play", "mdi:play"),
+            ("pause", "mdi:pause"),
+            ("next", "mdi:skip-next"),
+            ("previous", "mdi:skip-previous"),
+        ] {
+            let mut label = action.to_string();
+            label[..1].make_ascii_uppercase();
+            entities.push((
+                "button",
+                action,
+                json!({
+                    "name": label,
+                    "command_topic": topic("music/command"),
+                    "payload_press": action,
+                    "icon": icon,
+                }),
+            ));
+        }
+
+        for (component, object, mut config) in entities {
+            config["unique_id"] = json!(format!("mac-cli-{}-{}", name, object));
+            config["availability_topic"] = json!(topic("availability"));
+            config["device"] = device.clone();
+            let topic = format!(
+                "{}/{}/mac-cli-{}/{}/config",
+                prefix, component, name, object
+            );
+            self.send(&topic, &config.to_string());
+        }
+    }
+
+    /// Publishes a retained state below the base topic.
+    fn publish(&self, suffix: &str, payload: &str) {
+        self.send(&format!("{}/{}", self.base, suffix), payload);
+    }
+
+    fn send(&self, topic: &str, payload: &str) {
+        // Never block the loop that keeps the connection alive
+        if let Err(e) =
+            self.client
+                .try_publish(topic, QoS::AtLeastOnce, true, payload.as_bytes().to_vec())
+        {
+            tracing::debug!(topic, error = %e, "mqtt: failed to publish");
+        }
+    }
+
+    fn subscribe(&self, topic: String) {
+        if let Err(e) = self.client.try_subscribe(topic.as_str(), QoS::AtLeastOnce) {
+            tracing::debug!(topic, error = %e, "mqtt: failed to subscribe");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action_of(topic: &str, payload: &str) -> Action {
+        action(topic, payload, 10.0)
+            .unwrap()
+            .unwrap_or_else(|| panic!("{} is not a command topic", topic))
+    }
+
+    #[test]
+    fn broker_addresses_accept_hosts_ports_and_ipv6() {
+        for (value, host, port) in [
+            ("broker.local", "broker.local", 1883),
+            ("broker.local:8883", "broker.local", 8883),
+            ("mqtt://10.0.0.2:1884", "10.0.0.2", 1884),
+            ("[::1]:1883", "::1", 1883),
+            ("mqtt://[fe80::1]:8883", "fe80::1", 8883),
+            ("[::1]", "::1", 1883),
+            ("::1", "::1", 1883),
+            ("fe80::1:2", "fe80::1:2", 1883),
+        ] {
+            assert_eq!(
+                broker_addr(value),
+                Ok((host.to_string(), port)),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn broker_addresses_reject_bad_ports_and_empty_hosts() {
+        for value in [
+            "",
+            ":1883",
+            "mqtt://",
+            "broker.local:",
+            "broker.local:mqtt",
+            "broker.local:70000",
+            "[::1]:",
+            "[::1]1883",
+            "[::1",
+            "[]:1883",
+        ] {
+            assert!(broker_addr(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn node_names_are_topic_safe() {
+        assert_eq!(node_name("studio-mac_2"), Ok("studio-mac_2".to_string()));
+        for value in ["", "studio mac", "studio/mac", "mac+", "mac#", "café"] {
+            assert!(node_name(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn volume_and_music_topics_map_to_commands() {
+        let volume = action_of("volume/set", "40");
+        assert_eq!(volume.subsystem, Subsystem::Volume);
+        assert!(matches!(
+            volume.command,
+            Some(Commands::Volume {
+                percentage: Some(40.0)
+            })
+        ));
+        assert_eq!(volume.power, None);
+
+        for (payload, expected) in [
+            ("play", MusicCommands::Play),
+            ("pause", MusicCommands::Pause),
+            ("next", MusicCommands::Next),
+            ("previous", MusicCommands::Previous),
+        ] {
+            let music = action_of("music/command", payload);
+            assert_eq!(music.subsystem, Subsystem::Music);
+            match music.command {
+                Some(Commands::Music(command)) => assert_eq!(
+                    std::mem::discriminant(&command),
+                    std::mem::discriminant(&expected)
+                ),
+                _ => panic!("{} is not a music command", payload),
+            }
+        }
+    }
+
+    #[test]
+    fn brightness_topics_never_go_below_the_minimum() {
+        let set = action_of("brightness/set", "3");
+        assert!(matches!(
+            set.command,
+            Some(Commands::Brightness {
+                percentage: Some(10.0)
+            })
+        ));
+        assert_eq!(set.power, Some("ON"));
+
+        let off = action_of("brightness/switch", "OFF");
+        assert!(matches!(
+            off.command,
+            Some(Commands::Brightness {
+                percentage: Some(10.0)
+            })
+        ));
+        assert_eq!(off.power, Some("OFF"));
+
+        // With no minimum, OFF still leaves the display lit
+        let off = action("brightness/switch", "OFF", 0.0).unwrap().unwrap();
+        assert!(matches!(
+            off.command,
+            Some(Commands::Brightness {
+                percentage: Some(1.0)
+            })
+        ));
+
+        let on = action_of("brightness/switch", "ON");
+        assert!(on.command.is_none());
+        assert_eq!(on.power, Some("ON"));
+    }
+
+    #[test]
+    fn bad_payloads_and_other_topics() {
+        for (topic, payload, subsystem) in [
+            ("volume/set", "loud", Subsystem::Volume),
+            ("brightness/set", "", Subsystem::Brightness),
+            ("music/command", "stop", Subsystem::Music),
+        ] {
+            let err = action(topic, payload, 10.0).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidArgument, "{}", topic);
+            assert_eq!(err.subsystem(), subsystem, "{}", topic);
+        }
+
+        for topic in [
+            "volume/state",
+            "brightness/power",
+            "bluetooth/set",
+            "availability",
+        ] {
+            assert!(action(topic, "40", 10.0).unwrap().is_none(), "{}", topic);
+        }
+    }
+}
@@ -3,53 +3,264 @@
 //! This module provides an interface to control Apple Music playback,
 //! including play/pause, track navigation, and playlist management.
 
-use std::process::Command;
+use crate::backend::Backend;
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::runner::{self, CommandRunner, CommandSpec};
+use crate::sim::SimMusic;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The player application controlled by default.
+pub const DEFAULT_PLAYER: &str = "Music";
+
+/// Arguments passed to fzf by [`MusicController::play_playlist_interactive`] by default.
+pub const DEFAULT_FZF_OPTIONS: &[&str] =
+    &["--prompt=Select playlist: ", "--height=40%", "--reverse"];
+
+/// Playback state of the Music app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+impl PlayerState {
+    /// Parses AppleScript's `player state as string`.
+    ///
+    /// Fast forwarding and rewinding count as playing.
+    fn from_applescript(state: &str) -> Self {
+        match state.trim() {
+            "paused" => PlayerState::Paused,
+            "stopped" => PlayerState::Stopped,
+            _ => PlayerState::Playing,
+        }
+    }
+
+    /// Returns the lowercase state name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlayerState::Playing => "playing",
+            PlayerState::Paused => "paused",
+            PlayerState::Stopped => "stopped",
+        }
+    }
+}
+
+/// A track in the Music library.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    pub name: String,
+    pub artist: String,
+    pub album: String,
+}
+
+/// The player state together with the current track, if any.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NowPlaying {
+    pub state: PlayerState,
+    /// `None` when the player is stopped.
+    pub track: Option<Track>,
+}
+
+/// Everything needed to put the player back where it was, see
+/// [`MusicController::snapshot`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    pub state: PlayerState,
+    pub shuffle: bool,
+    /// The playlist being played; `None` when stopped.
+    pub playlist: Option<String>,
+    /// Zero-based index of the current track within `playlist`.
+    pub track_index: Option<usize>,
+    /// Playback position within the current track, in seconds.
+    pub position: Option<f64>,
+}
 
 /// Controller for Apple Music on macOS.
 ///
 /// Uses AppleScript to control Apple Music playback and playlist management.
-pub struct MusicController;
+pub struct MusicController {
+    backend: Backend,
+    player: String,
+    fzf_options: Vec<String>,
+}
+
+impl Default for MusicController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl MusicController {
-    fn run_script(script: &str) -> Result<String, String> {
-        let output = Command::new("osascript")
-            .arg("-e")
-            .arg(script)
-            .output()
-            .map_err(|e| format!("Failed to execute osascript: {}", e))?;
-
-        if !output.status.success() {
-            return Err(format!(
-                "AppleScript error: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
+    /// Creates a new Apple Music controller.
+    pub fn new() -> Self {
+        Self::with_backend(Backend::system())
+    }
+
+    /// Creates a controller that executes commands through `runner`.
+    pub fn with_runner(runner: Arc<dyn CommandRunner>) -> Self {
+        Self::with_backend(Backend::System(runner))
+    }
+
+    /// Creates a controller for the given backend.
+    pub fn with_backend(backend: Backend) -> Self {
+        MusicController {
+            backend,
+            player: DEFAULT_PLAYER.to_string(),
+            fzf_options: DEFAULT_FZF_OPTIONS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Controls another AppleScript-compatible player application instead of Music.
+    pub fn player(mut self, name: impl Into<String>) -> Self {
+        self.player = name.into();
+        self
+    }
+
+    /// Replaces the arguments passed to fzf by [`Self::play_playlist_interactive`].
+    pub fn fzf_options(mut self, options: Vec<String>) -> Self {
+        self.fzf_options = options;
+        self
+    }
+
+    /// An AppleScript line that fails with error -600 when the player is not
+    /// running, so that queries don't launch it.
+    fn running_guard(&self) -> Result<String> {
+        let message = format!("{} isn't running", self.player);
+        Ok(format!(
+            "if application {} is not running then error {} number -600",
+            self.app()?,
+            runner::applescript_string(Subsystem::Music, &message)?
+        ))
+    }
+
+    /// The player as an AppleScript string literal.
+    fn app(&self) -> Result<String> {
+        runner::applescript_string(Subsystem::Music, &self.player)
+    }
+
+    /// Runs `script` on macOS, or applies `sim` to the simulated player and
+    /// saves the result.
+    fn dispatch<T>(
+        &self,
+        script: &str,
+        parse: impl FnOnce(String) -> Result<T>,
+        sim: impl FnOnce(&mut SimMusic) -> Result<T>,
+    ) -> Result<T> {
+        match &self.backend {
+            Backend::System(runner) => parse(runner::run_applescript(
+                runner.as_ref(),
+                Subsystem::Music,
+                script,
+            )?),
+            Backend::DryRun(plan) => parse(runner::run_applescript(
+                plan.as_ref(),
+                Subsystem::Music,
+                script,
+            )?),
+            Backend::Sim(store) => store.update(Subsystem::Music, |state| {
+                Self::ensure_running(&state.music)?;
+                sim(&mut state.music)
+            }),
         }
+    }
 
-        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    /// Like [`Self::dispatch`], but never modifies the simulated player.
+    fn query<T>(
+        &self,
+        script: &str,
+        parse: impl FnOnce(String) -> Result<T>,
+        sim: impl FnOnce(&SimMusic) -> Result<T>,
+    ) -> Result<T> {
+        match &self.backend {
+            Backend::System(runner) => parse(runner::run_applescript(
+                runner.as_ref(),
+                Subsystem::Music,
+                script,
+            )?),
+            Backend::DryRun(plan) => parse(runner::run_applescript(
+                plan.as_ref(),
+                Subsystem::Music,
+                script,
+            )?),
+            Backend::Sim(store) => {
+                let state = store.load(Subsystem::Music)?;
+                Self::ensure_running(&state.music)?;
+                sim(&state.music)
+            }
+        }
+    }
+
+    fn ensure_running(music: &SimMusic) -> Result<()> {
+        if music.running {
+            Ok(())
+        } else {
+            Err(MacCliError::new(
+                Subsystem::Music,
+                ErrorKind::AppNotRunning,
+                "Music isn't running",
+            ))
+        }
     }
 
     /// Plays the current track in Apple Music.
-    pub fn play() -> Result<(), String> {
-        Self::run_script("tell application \"Music\" to play")?;
-        Ok(())
+    pub fn play(&self) -> Result<()> {
+        let script = format!("tell application {} to play", self.app()?);
+        self.dispatch(
+            &script,
+            |_| Ok(()),
+            |music| {
+                if music.current_playlist.is_none() {
+                    music.current_playlist = music.playlists.first().map(|p| p.name.clone());
+                    music.track_index = 0;
+                }
+                music.state = PlayerState::Playing;
+                Ok(())
+            },
+        )
     }
 
     /// Pauses the current playback in Apple Music.
-    pub fn pause() -> Result<(), String> {
-        Self::run_script("tell application \"Music\" to pause")?;
-        Ok(())
+    pub fn pause(&self) -> Result<()> {
+        let script = format!("tell application {} to pause", self.app()?);
+        self.dispatch(
+            &script,
+            |_| Ok(()),
+            |music| {
+                if music.state == PlayerState::Playing {
+                    music.state = PlayerState::Paused;
+                }
+                Ok(())
+            },
+        )
     }
 
     /// Skips to the next track in Apple Music.
-    pub fn next() -> Result<(), String> {
-        Self::run_script("tell application \"Music\" to next track")?;
-        Ok(())
+    pub fn next(&self) -> Result<()> {
+        let script = format!("tell application {} to next track", self.app()?);
+        self.dispatch(
+            &script,
+            |_| Ok(()),
+            |music| {
+                music.skip(1);
+                Ok(())
+            },
+        )
     }
 
     /// Goes to the previous track in Apple Music.
-    pub fn previous() -> Result<(), String> {
-        Self::run_script("tell application \"Music\" to previous track")?;
-        Ok(())
+    pub fn previous(&self) -> Result<()> {
+        let script = format!("tell application {} to previous track", self.app()?);
+        self.dispatch(
+            &script,
+            |_| Ok(()),
+            |music| {
+                music.skip(-1);
+                Ok(())
+            },
+        )
     }
 
     /// Gets information about the currently playing track.
@@ -58,9 +269,11 @@ impl MusicController {
     ///
     /// Returns a string in the format "Track Name - Artist Name" if playing,
     /// or "Not playing" if nothing is currently playing.
-    pub fn current() -> Result<String, String> {
-        let script = r#"
-            tell application "Music"
+    pub fn current(&self) -> Result<String> {
+        let script = format!(
+            r#"
+            {}
+            tell application {}
                 if player state is playing then
                     set trackName to name of current track
                     set artistName to artist of current track
@@ -69,15 +282,271 @@ impl MusicController {
                     return "Not playing"
                 end if
             end tell
-        "#;
+        "#,
+            self.running_guard()?,
+            self.app()?
+        );
+
+        self.query(&script, Ok, |music| {
+            Ok(match music.current_track() {
+                Some(track) if music.state == PlayerState::Playing => {
+                    format!("{} - {}", track.name, track.artist)
+                }
+                _ => "Not playing".to_string(),
+            })
+        })
+    }
+
+    /// Gets the player state and the current track.
+    ///
+    /// Unlike [`Self::current`], the track is also reported while paused.
+    pub fn now_playing(&self) -> Result<NowPlaying> {
+        let script = format!(
+            r#"
+            {}
+            tell application {}
+                set playerState to player state as string
+                if playerState is "stopped" then return playerState
+                set t to current track
+                return playerState & tab & (name of t) & tab & (artist of t) & tab & (album of t)
+            end tell
+        "#,
+            self.running_guard()?,
+            self.app()?
+        );
+
+        self.query(
+            &script,
+            |result| {
+                let mut fields = result.split('\t');
+                let state = PlayerState::from_applescript(fields.next().unwrap_or_default());
+                let track = match (fields.next(), fields.next(), fields.next()) {
+                    (Some(name), Some(artist), Some(album)) => Some(Track {
+                        name: name.to_string(),
+                        artist: artist.to_string(),
+                        album: album.to_string(),
+                    }),
+                    _ => None,
+                };
+                Ok(NowPlaying { state, track })
+            },
+            |music| {
+                let track = match music.state {
+                    PlayerState::Stopped => None,
+                    _ => music.current_track().map(|t| Track {
+                        name: t.name.clone(),
+                        artist: t.artist.clone(),
+                        album: t.album.clone(),
+                    }),
+                };
+                Ok(NowPlaying {
+                    state: music.state,
+                    track,
+                })
+            },
+        )
+    }
+
+    /// Returns `true` if Apple Music is currently playing.
+    pub fn is_playing(&self) -> Result<bool> {
+        let script = format!(
+            r#"
+            {}
+            tell application {} to return player state as string
+        "#,
+            self.running_guard()?,
+            self.app()?
+        );
+
+        self.query(
+            &script,
+            |state| Ok(state == "playing"),
+            |music| Ok(music.state == PlayerState::Playing),
+        )
+    }
+
+    /// Returns the name of the playlist being played, or `None` when stopped.
+    pub fn current_playlist(&self) -> Result<Option<String>> {
+        let script = format!(
+            r#"
+            {}
+            tell application {}
+                if player state is stopped then return ""
+                return name of current playlist
+            end tell
+        "#,
+            self.running_guard()?,
+            self.app()?
+        );
+
+        self.query(
+            &script,
+            |name| Ok(Some(name).filter(|n| !n.is_empty())),
+            |music| {
+                Ok(match music.state {
+                    PlayerState::Stopped => None,
+                    _ => music.current_playlist.clone(),
+                })
+            },
+        )
+    }
+
+    /// Returns `true` if shuffle is enabled.
+    pub fn shuffle(&self) -> Result<bool> {
+        let script = format!(
+            r#"
+            {}
+            tell application {} to return shuffle enabled
+        "#,
+            self.running_guard()?,
+            self.app()?
+        );
 
-        Self::run_script(script)
+        self.query(
+            &script,
+            |enabled| Ok(enabled == "true"),
+            |music| Ok(music.shuffle),
+        )
     }
 
-    pub fn is_playing() -> Result<bool, String> {
-        let script = r#"tell application "Music" to return player state as string"#;
-        let state = Self::run_script(script)?;
-        Ok(state == "playing")
+    /// Enables or disables shuffle.
+    pub fn set_shuffle(&self, enabled: bool) -> Result<()> {
+        let script = format!(
+            r#"tell application {} to set shuffle enabled to {}"#,
+            self.app()?,
+            enabled
+        );
+        self.dispatch(
+            &script,
+            |_| Ok(()),
+            |music| {
+                music.shuffle = enabled;
+                Ok(())
+            },
+        )
+    }
+
+    /// Captures the player state, playlist, track, position and shuffle setting.
+    pub fn snapshot(&self) -> Result<PlayerSnapshot> {
+        let script = format!(
+            r#"
+            {}
+            tell application {}
+                set playerState to player state as string
+                set shuffleState to shuffle enabled as string
+                if playerState is "stopped" then return playerState & tab & shuffleState
+                set playlistName to name of current playlist
+                set trackIndex to index of current track
+                return playerState & tab & shuffleState & tab & playlistName & tab & trackIndex & tab & player position
+            end tell
+        "#,
+            self.running_guard()?,
+            self.app()?
+        );
+
+        self.query(
+            &script,
+            |result| {
+                let fields: Vec<&str> = result.split('\t').collect();
+                let state = PlayerState::from_applescript(fields[0]);
+                let shuffle = fields.get(1) == Some(&"true");
+                if fields.len() < 5 {
+                    return Ok(PlayerSnapshot {
+                        state,
+                        shuffle,
+                        playlist: None,
+                        track_index: None,
+                        position: None,
+                    });
+                }
+
+                let parse_error = || {
+                    MacCliError::new(
+                        Subsystem::Music,
+                        ErrorKind::Parse,
+                        format!("Unexpected player status: {}", result),
+                    )
+                };
+                // AppleScript indexes from 1; reals may use the locale's decimal comma
+                let track_index: usize = fields[3].trim().parse().map_err(|_| parse_error())?;
+                let position: f64 = fields[4]
+                    .trim()
+                    .replace(',', ".")
+                    .parse()
+                    .map_err(|_| parse_error())?;
+
+                Ok(PlayerSnapshot {
+                    state,
+                    shuffle,
+                    playlist: Some(fields[2].to_string()),
+                    track_index: Some(track_index.saturating_sub(1)),
+                    position: Some(position),
+                })
+            },
+            |music| {
+                let loaded =
+                    music.state != PlayerState::Stopped && music.current_playlist.is_some();
+                Ok(PlayerSnapshot {
+                    state: music.state,
+                    shuffle: music.shuffle,
+                    playlist: music.current_playlist.clone().filter(|_| loaded),
+                    track_index: Some(music.track_index).filter(|_| loaded),
+                    position: Some(music.position).filter(|_| loaded),
+                })
+            },
+        )
+    }
+
+    /// Puts the player back into the state captured by [`Self::snapshot`].
+    pub fn restore(&self, snapshot: &PlayerSnapshot) -> Result<()> {
+        // The playlist name comes from a snapshot file
+        let mut script = format!(
+            "tell application {}\nset shuffle enabled to {}\n",
+            self.app()?,
+            snapshot.shuffle
+        );
+        match (&snapshot.playlist, snapshot.state) {
+            (_, PlayerState::Stopped) => script.push_str("stop\n"),
+            (Some(playlist), state) => {
+                script.push_str(&format!(
+                    "play track {} of playlist named {}\n",
+                    snapshot.track_index.unwrap_or(0) + 1,
+                    runner::applescript_string(Subsystem::Music, playlist)?
+                ));
+                script.push_str(&format!(
+                    "set player position to {}\n",
+                    snapshot.position.unwrap_or(0.0)
+                ));
+                if state == PlayerState::Paused {
+                    script.push_str("pause\n");
+                }
+            }
+            (None, PlayerState::Playing) => script.push_str("play\n"),
+            (None, PlayerState::Paused) => script.push_str("pause\n"),
+        }
+        script.push_str("end tell");
+
+        self.dispatch(
+            &script,
+            |_| Ok(()),
+            |music| {
+                music.shuffle = snapshot.shuffle;
+                music.state = snapshot.state;
+                if let Some(playlist) = &snapshot.playlist {
+                    if !music.playlists.iter().any(|p| &p.name == playlist) {
+                        return Err(MacCliError::new(
+                            Subsystem::Music,
+                            ErrorKind::NotFound,
+                            format!("Playlist not found: {}", playlist),
+                        ));
+                    }
+                    music.current_playlist = Some(playlist.clone());
+                    music.track_index = snapshot.track_index.unwrap_or(0);
+                    music.position = snapshot.position.unwrap_or(0.0);
+                }
+                Ok(())
+            },
+        )
     }
 
     /// Lists all available playlists in Apple Music.
@@ -85,24 +554,29 @@ impl MusicController {
     /// # Returns
     ///
     /// Returns a vector of playlist names.
-    pub fn list_playlists() -> Result<Vec<String>, String> {
-        let script = r#"
-            tell application "Music"
+    pub fn list_playlists(&self) -> Result<Vec<String>> {
+        let script = format!(
+            r#"
+            tell application {}
                 set playlistNames to name of playlists
                 return playlistNames
             end tell
-        "#;
-
-        let result = Self::run_script(script)?;
-
-        // AppleScript returns comma-separated list
-        let playlists: Vec<String> = result
-            .split(", ")
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect();
+        "#,
+            self.app()?
+        );
 
-        Ok(playlists)
+        self.query(
+            &script,
+            |result| {
+                // AppleScript returns comma-separated list
+                Ok(result
+                    .split(", ")
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect())
+            },
+            |music| Ok(music.playlists.iter().map(|p| p.name.clone()).collect()),
+        )
     }
 
     /// Plays a specific playlist by name.
@@ -110,10 +584,30 @@ impl MusicController {
     /// # Arguments
     ///
     /// * `name` - The name of the playlist to play.
-    pub fn play_playlist(name: &str) -> Result<(), String> {
-        let script = format!(r#"tell application "Music" to play playlist named "{}""#, name);
-        Self::run_script(&script)?;
-        Ok(())
+    pub fn play_playlist(&self, name: &str) -> Result<()> {
+        let script = format!(
+            "tell application {} to play playlist named {}",
+            self.app()?,
+            runner::applescript_string(Subsystem::Music, name)?
+        );
+        self.dispatch(
+            &script,
+            |_| Ok(()),
+            |music| {
+                if !music.playlists.iter().any(|p| p.name == name) {
+                    return Err(MacCliError::new(
+                        Subsystem::Music,
+                        ErrorKind::NotFound,
+                        format!("Playlist not found: {}", name),
+                    ));
+                }
+                music.current_playlist = Some(name.to_string());
+                music.track_index = 0;
+                music.position = 0.0;
+                music.state = PlayerState::Playing;
+                Ok(())
+            },
+        )
     }
 
     /// Displays an interactive playlist picker using fzf and plays the selected playlist.
@@ -125,50 +619,232 @@ impl MusicController {
     /// # Errors
     ///
     /// Returns an error if fzf is not installed or if no playlist is selected.
-    pub fn play_playlist_interactive() -> Result<String, String> {
-        use std::io::Write;
-
-        let playlists = Self::list_playlists()?;
+    pub fn play_playlist_interactive(&self) -> Result<String> {
+        let playlists = self.list_playlists()?;
 
         if playlists.is_empty() {
-            return Err("No playlists found".to_string());
+            return Err(MacCliError::new(
+                Subsystem::Music,
+                ErrorKind::NotFound,
+                "No playlists found",
+            ));
         }
 
-        // Use fzf for interactive selection
-        let input = playlists.join("\n");
-
-        let mut child = Command::new("fzf")
-            .arg("--prompt=Select playlist: ")
-            .arg("--height=40%")
-            .arg("--reverse")
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::inherit())
-            .spawn()
-            .map_err(|e| format!("Failed to start fzf (is it installed?): {}", e))?;
-
-        // Write playlists to fzf stdin
-        if let Some(mut stdin) = child.stdin.take() {
-            stdin
-                .write_all(input.as_bytes())
-                .map_err(|e| format!("Failed to write to fzf: {}", e))?;
-        }
+        // Use fzf for interactive selection, feeding it the playlists on stdin
+        let command = CommandSpec::new("fzf")
+            .args(&self.fzf_options)
+            .stdin(playlists.join("\n"))
+            .interactive();
 
-        let output = child
-            .wait_with_output()
-            .map_err(|e| format!("Failed to read fzf output: {}", e))?;
+        // The picker runs on the local terminal even when the player is simulated
+        let picker: Arc<dyn CommandRunner> = match &self.backend {
+            Backend::System(runner) => runner.clone(),
+            Backend::DryRun(plan) => plan.clone(),
+            Backend::Sim(_) => runner::system(),
+        };
 
-        if !output.status.success() {
-            return Err("No playlist selected".to_string());
+        let output = picker
+            .run(&command)
+            .map_err(|e| MacCliError::spawn(Subsystem::Music, "fzf (is it installed?)", e))?;
+
+        if !output.is_success() {
+            return Err(MacCliError::new(
+                Subsystem::Music,
+                ErrorKind::Cancelled,
+                "No playlist selected",
+            ));
         }
 
-        let selected = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let selected = output.stdout.trim().to_string();
 
         if selected.is_empty() {
-            return Err("No playlist selected".to_string());
+            return Err(MacCliError::new(
+                Subsystem::Music,
+                ErrorKind::Cancelled,
+                "No playlist selected",
+            ));
         }
 
-        Self::play_playlist(&selected)?;
+        self.play_playlist(&selected)?;
         Ok(selected)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{CommandOutput, Expectation, FakeRunner};
+
+    fn controller(runner: &Arc<FakeRunner>) -> MusicController {
+        MusicController::with_runner(runner.clone())
+    }
+
+    fn replies(outputs: impl IntoIterator<Item = CommandOutput>) -> Arc<FakeRunner> {
+        let runner = outputs
+            .into_iter()
+            .fold(FakeRunner::new(), |runner, output| {
+                runner.expect(Expectation::new("osascript").returns(output))
+            });
+        Arc::new(runner)
+    }
+
+    #[test]
+    fn now_playing_parses_track_fields() {
+        let runner = replies([
+            CommandOutput::success("paused\tSo What\tMiles Davis\tKind of Blue\n"),
+            CommandOutput::success("stopped\n"),
+        ]);
+        let music = controller(&runner);
+
+        let playing = music.now_playing().unwrap();
+        assert_eq!(playing.state, PlayerState::Paused);
+        assert_eq!(
+            playing.track,
+            Some(Track {
+                name: "So What".into(),
+                artist: "Miles Davis".into(),
+                album: "Kind of Blue".into(),
+            })
+        );
+
+        let stopped = music.now_playing().unwrap();
+        assert_eq!(stopped.state, PlayerState::Stopped);
+        assert_eq!(stopped.track, None);
+        runner.assert_done();
+    }
+
+    #[test]
+    fn snapshot_parses_position_with_decimal_comma() {
+        let runner = replies([CommandOutput::success("playing\ttrue\tFocus\t3\t12,5")]);
+
+        let snapshot = controller(&runner).snapshot().unwrap();
+        assert_eq!(
+            snapshot,
+            PlayerSnapshot {
+                state: PlayerState::Playing,
+                shuffle: true,
+                playlist: Some("Focus".into()),
+                track_index: Some(2),
+                position: Some(12.5),
+            }
+        );
+    }
+
+    #[test]
+    fn snapshot_of_stopped_player_has_no_track() {
+        let runner = replies([CommandOutput::success("stopped\tfalse")]);
+
+        let snapshot = controller(&runner).snapshot().unwrap();
+        assert_eq!(snapshot.state, PlayerState::Stopped);
+        assert!(!snapshot.shuffle);
+        assert_eq!(snapshot.playlist, None);
+    }
+
+    #[test]
+    fn snapshot_rejects_unexpected_output() {
+        let runner = replies([CommandOutput::success("playing\ttrue\tFocus\tthird\t12.5")]);
+
+        let err = controller(&runner).snapshot().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Parse);
+    }
+
+    #[test]
+    fn list_playlists_splits_applescript_list() {
+        let runner = replies([CommandOutput::success("Library, Focus, Road Trip\n")]);
+
+        assert_eq!(
+            controller(&runner).list_playlists().unwrap(),
+            ["Library", "Focus", "Road Trip"]
+        );
+    }
+
+    #[test]
+    fn play_playlist_targets_the_player() {
+        let runner = Arc::new(
+            FakeRunner::new().expect(Expectation::new("osascript").args([
+                "-e",
+                r#"tell application "Spotify" to play playlist named "Focus""#,
+            ])),
+        );
+
+        controller(&runner)
+            .player("Spotify")
+            .play_playlist("Focus")
+            .unwrap();
+        runner.assert_done();
+    }
+
+    #[test]
+    fn restore_escapes_playlist_name() {
+        let runner = Arc::new(
+            FakeRunner::new().expect(Expectation::new("osascript").args([
+                "-e",
+                "tell application \"Music\"\nset shuffle enabled to false\n\
+             play track 2 of playlist named \"Say \\\"hi\\\" \\\\o/\"\n\
+             set player position to 0\nend tell",
+            ])),
+        );
+        let mut snapshot = PlayerSnapshot {
+            state: PlayerState::Playing,
+            shuffle: false,
+            playlist: Some(r#"Say "hi" \o/"#.into()),
+            track_index: Some(1),
+            position: Some(0.0),
+        };
+
+        controller(&runner).restore(&snapshot).unwrap();
+        runner.assert_done();
+
+        snapshot.playlist = Some("x\"\ndo shell script \"touch /tmp/pwned".into());
+        let err = controller(&runner).restore(&snapshot).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+    }
+
+    #[test]
+    fn play_playlist_escapes_name() {
+        let runner = Arc::new(FakeRunner::new().expect(Expectation::new("osascript").args([
+            "-e",
+            r#"tell application "Music" to play playlist named "x\" & (do shell script \"id\") & \"""#,
+        ])));
+        let music = controller(&runner);
+
+        music
+            .play_playlist(r#"x" & (do shell script "id") & ""#)
+            .unwrap();
+        runner.assert_done();
+
+        let err = music
+            .play_playlist("x\"\ndo shell script \"id")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+    }
+
+    #[test]
+    fn player_not_running_maps_to_app_not_running() {
+        let runner = replies([CommandOutput::failure(
+            1,
+            "execution error: Music isn't running (-600)",
+        )]);
+
+        let err = controller(&runner).is_playing().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AppNotRunning);
+        assert_eq!(err.subsystem(), Subsystem::Music);
+    }
+
+    #[test]
+    fn cancelled_picker_maps_to_cancelled() {
+        let runner = Arc::new(
+            FakeRunner::new()
+                .expect(Expectation::new("osascript").returns(CommandOutput::success("Focus")))
+                .expect(
+                    Expectation::new("fzf")
+                        .stdin("Focus")
+                        .returns(CommandOutput::failure(130, "")),
+                ),
+        );
+
+        let err = controller(&runner).play_playlist_interactive().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Cancelled);
+        runner.assert_done();
+    }
+}
@@ -0,0 +1,342 @@
+//! A minimal [Open Sound Control 1.0](https://opensoundcontrol.stanford.edu/spec-1_0.html)
+//! codec, as spoken by `mac osc`.
+//!
+//! A packet is a message or a bundle of packets; [`decode`] flattens bundles
+//! into their messages and ignores time tags. Arguments of types `i`, `f`, `s`,
+//! `b`, `h`, `d`, `T`, `F`, `N` and `I` are understood.
+//!
+//! ```
+//! use mac_cli::osc::{self, Arg, Message};
+//!
+//! let packet = Message::new("/mac/volume", vec![Arg::Float(0.5)]).encode();
+//! let messages = osc::decode(&packet)?;
+//! assert_eq!(messages[0].address, "/mac/volume");
+//! assert_eq!(messages[0].args[0].as_f32(), Some(0.5));
+//! # Ok::<(), mac_cli::MacCliError>(())
+//! ```
+
+use crate::error::{MacCliError, Result, Subsystem};
+
+/// The first element of a bundle.
+const BUNDLE: &[u8] = b"#bundle\0";
+
+/// An argument of a [`Message`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Blob(Vec<u8>),
+    Long(i64),
+    Double(f64),
+    Bool(bool),
+    Nil,
+    Impulse,
+}
+
+/// An OSC message: an address such as `/mac/volume` and its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub address: String,
+    pub args: Vec<Arg>,
+}
+
+impl Arg {
+    /// The argument as a number; booleans are 0 or 1.
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            Arg::Int(i) => Some(*i as f32),
+            Arg::Float(f) => Some(*f),
+            Arg::Long(l) => Some(*l as f32),
+            Arg::Double(d) => Some(*d as f32),
+            Arg::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            Arg::String(_) | Arg::Blob(_) | Arg::Nil | Arg::Impulse => None,
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            Arg::Int(_) => b'i',
+            Arg::Float(_) => b'f',
+            Arg::String(_) => b's',
+            Arg::Blob(_) => b'b',
+            Arg::Long(_) => b'h',
+            Arg::Double(_) => b'd',
+            Arg::Bool(true) => b'T',
+            Arg::Bool(false) => b'F',
+            Arg::Nil => b'N',
+            Arg::Impulse => b'I',
+        }
+    }
+}
+
+impl Message {
+    pub fn new(address: impl Into<String>, args: Vec<Arg>) -> Self {
+        Message {
+            address: address.into(),
+            args,
+        }
+    }
+
+    /// Encodes the message as a packet.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = Vec::new();
+        write_string(&mut packet, self.address.as_bytes());
+        let tags: Vec<u8> = std::iter::once(b',')
+            .chain(self.args.iter().map(Arg::tag))
+            .collect();
+        write_string(&mut packet, &tags);
+
+        for arg in &self.args {
+            match arg {
+                Arg::Int(i) => packet.extend(i.to_be_bytes()),
+                Arg::Float(f) => packet.extend(f.to_be_bytes()),
+                Arg::String(s) => write_string(&mut packet, s.as_bytes()),
+                Arg::Blob(blob) => {
+                    packet.extend((blob.len() as i32).to_be_bytes());
+                    packet.extend(blob);
+                    packet.resize(padded(packet.len()), 0);
+                }
+                Arg::Long(l) => packet.extend(l.to_be_bytes()),
+                Arg::Double(d) => packet.extend(d.to_be_bytes()),
+                Arg::Bool(_) | Arg::Nil | Arg::Impulse => {}
+            }
+        }
+        packet
+    }
+}
+
+/// Decodes a packet into its messages, in order.
+///
+/// # Errors
+///
+/// Returns an [`InvalidArgument`](crate::ErrorKind::InvalidArgument) error if
+/// the packet is truncated or malformed.
+pub fn decode(packet: &[u8]) -> Result<Vec<Message>> {
+    let mut messages = Vec::new();
+    decode_into(packet, &mut messages)?;
+    Ok(messages)
+}
+
+fn decode_into(packet: &[u8], messages: &mut Vec<Message>) -> Result<()> {
+    if let Some(elements) = packet.strip_prefix(BUNDLE) {
+        let mut reader = Reader(elements);
+        // Time tag: everything is applied on arrival
+        reader.take(8)?;
+        while !reader.0.is_empty() {
+            let size = reader.size()?;
+            decode_into(reader.take(size)?, messages)?;
+        }
+        return Ok(());
+    }
+
+    let mut reader = Reader(packet);
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return Err(malformed(format!(
+            "address {:?} does not start with '/'",
+            address
+        )));
+    }
+    // Very old senders leave out the type tags
+    let tags = match reader.0.is_empty() {
+        true => ",".to_string(),
+        false => reader.string()?,
+    };
+    let Some(tags) = tags.strip_prefix(',') else {
+        return Err(malformed(format!(
+            "type tags {:?} do not start with ','",
+            tags
+        )));
+    };
+
+    let mut args = Vec::new();
+    for tag in tags.chars() {
+        args.push(match tag {
+            'i' => Arg::Int(i32::from_be_bytes(reader.array()?)),
+            'f' => Arg::Float(f32::from_be_bytes(reader.array()?)),
+            's' | 'S' => Arg::String(reader.string()?),
+            'b' => {
+                let size = reader.size()?;
+                let blob = reader.take(size)?.to_vec();
+                reader.take(padded(size) - size)?;
+                Arg::Blob(blob)
+            }
+            'h' => Arg::Long(i64::from_be_bytes(reader.array()?)),
+            'd' => Arg::Double(f64::from_be_bytes(reader.array()?)),
+            'T' => Arg::Bool(true),
+            'F' => Arg::Bool(false),
+            'N' => Arg::Nil,
+            'I' => Arg::Impulse,
+            tag => return Err(malformed(format!("unsupported argument type '{}'", tag))),
+        });
+    }
+    messages.push(Message { address, args });
+    Ok(())
+}
+
+/// Reads the big-endian, 4-byte aligned fields of a packet.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.0.len() {
+            return Err(malformed("truncated packet"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    /// A size prefix, as of blobs and bundle elements.
+    fn size(&mut self) -> Result<usize> {
+        let size = i32::from_be_bytes(self.array()?);
+        usize::try_from(size).map_err(|_| malformed(format!("negative size {}", size)))
+    }
+
+    /// A NUL-terminated string, padded to a multiple of 4 bytes.
+    fn string(&mut self) -> Result<String> {
+        let Some(len) = self.0.iter().position(|b| *b == 0) else {
+            return Err(malformed("unterminated string"));
+        };
+        let bytes = self.take(padded(len + 1))?;
+        String::from_utf8(bytes[..len].to_vec()).map_err(|_| malformed("string is not UTF-8"))
+    }
+}
+
+fn write_string(packet: &mut Vec<u8>, bytes: &[u8]) {
+    packet.extend(bytes);
+    packet.resize(padded(packet.len() + 1), 0);
+}
+
+/// Rounds `len` up to a multiple of 4.
+fn padded(len: usize) -> usize {
+    len.div_ceil(4) * 4
+}
+
+fn malformed(message: impl Into<String>) -> MacCliError {
+    MacCliError::invalid_argument(
+        Subsystem::Cli,
+        format!("Malformed OSC packet: {}", message.into()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut packet = BUNDLE.to_vec();
+        packet.extend(1u64.to_be_bytes());
+        for element in elements {
+            packet.extend((element.len() as i32).to_be_bytes());
+            packet.extend(element);
+        }
+        packet
+    }
+
+    fn assert_malformed(packet: &[u8], reason: &str) {
+        let err = decode(packet).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+        assert!(err.message().contains(reason), "{}: {}", reason, err);
+    }
+
+    #[test]
+    fn every_argument_type_round_trips() {
+        let message = Message::new(
+            "/mac/test",
+            vec![
+                Arg::Int(-7),
+                Arg::Float(0.25),
+                Arg::String("café".to_string()),
+                Arg::Blob(vec![1, 2, 3, 4, 5]),
+                Arg::Long(1 << 40),
+                Arg::Double(-1.5),
+                Arg::Bool(true),
+                Arg::Bool(false),
+                Arg::Nil,
+                Arg::Impulse,
+                Arg::String(String::new()),
+            ],
+        );
+
+        let packet = message.encode();
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(decode(&packet).unwrap(), [message]);
+    }
+
+    #[test]
+    fn strings_are_padded_to_four_bytes() {
+        assert_eq!(
+            Message::new("/abc", vec![]).encode(),
+            b"/abc\0\0\0\0,\0\0\0".to_vec()
+        );
+        assert_eq!(
+            Message::new("/ab", vec![Arg::Int(1)]).encode(),
+            b"/ab\0,i\0\0\0\0\0\x01".to_vec()
+        );
+    }
+
+    #[test]
+    fn bundles_are_flattened_in_order() {
+        let volume = Message::new("/mac/volume", vec![Arg::Float(0.5)]);
+        let play = Message::new("/mac/music/play", vec![]);
+        let sync = Message::new("/mac/sync", vec![]);
+        let packet = bundle(&[volume.encode(), bundle(&[play.encode(), sync.encode()])]);
+
+        assert_eq!(decode(&packet).unwrap(), [volume, play, sync]);
+        assert_eq!(decode(&bundle(&[])).unwrap(), []);
+    }
+
+    #[test]
+    fn messages_without_type_tags_have_no_arguments() {
+        assert_eq!(
+            decode(b"/mac/sync\0\0\0").unwrap(),
+            [Message::new("/mac/sync", vec![])]
+        );
+    }
+
+    #[test]
+    fn capital_s_is_a_string() {
+        assert_eq!(
+            decode(b"/a\0\0,S\0\0on\0\0").unwrap(),
+            [Message::new("/a", vec![Arg::String("on".to_string())])]
+        );
+    }
+
+    #[test]
+    fn truncated_and_unpadded_packets_are_rejected() {
+        let packet = Message::new("/mac/volume", vec![Arg::Float(0.5)]).encode();
+        for len in [0, 3, packet.len() - 4, packet.len() - 1] {
+            assert!(decode(&packet[..len]).is_err(), "{} bytes", len);
+        }
+
+        // The string argument is terminated, but its padding is missing
+        assert_malformed(b"/abc\0\0\0\0,s\0\0on\0", "truncated");
+        assert_malformed(b"/abc\0,f\0\0\0\0\0\0", "do not start with ','");
+        assert_malformed(b"/abc", "unterminated");
+        assert_malformed(b"/a\0\0,b\0\0\0\0\0\x05abcd", "truncated");
+        assert_malformed(b"/a\0\0,b\0\0\xff\xff\xff\xff", "negative size");
+
+        let mut element = bundle(std::slice::from_ref(&packet));
+        element.truncate(element.len() - 4);
+        assert_malformed(&element, "truncated");
+        assert_malformed(&BUNDLE[..8], "truncated");
+    }
+
+    #[test]
+    fn unknown_types_and_bad_addresses_are_rejected() {
+        assert_malformed(
+            b"/a\0\0,ic\0\0\0\0\x01x\0\0\0",
+            "unsupported argument type 'c'",
+        );
+        assert_malformed(b"/a\0\0if\0\0", "do not start with ','");
+        assert_malformed(b"mac\0,\0\0\0", "does not start with '/'");
+        assert_malformed(b"/a\0\0,s\0\0\xff\xfe\0\0", "not UTF-8");
+    }
+}
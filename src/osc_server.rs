@@ -0,0 +1,530 @@
+//! `mac osc`: Open Sound Control over UDP, for control surfaces such as
+//! TouchOSC.
+//!
+//! | Address            | Arguments | Effect                                                |
+//! |--------------------|-----------|-------------------------------------------------------|
+//! | `/mac/volume`      | `f`, 0-1  | Sets the volume                                       |
+//! | `/mac/brightness`  | `f`, 0-1  | Sets the brightness, down to `brightness.min`         |
+//! | `/mac/music/play`  |           | Same for `pause`, `next` and `previous`               |
+//! | `/mac/sync`        |           | Sends every state back                                |
+//!
+//! Without an argument, `/mac/volume` and `/mac/brightness` only ask for the
+//! current value. Buttons send 1 when pressed and 0 when released; only the
+//! press counts. Messages with a NaN argument are ignored.
+//!
+//! Feedback goes to every client heard from in the last hour, up to
+//! [`MAX_CLIENTS`] of them, whenever a state changes, whoever changed it:
+//! `/mac/volume f`, `/mac/muted f`, `/mac/brightness f`,
+//! `/mac/music/playing f`, `/mac/music/state s`, `/mac/music/name s`,
+//! `/mac/music/artist s` and `/mac/music/album s`.
+//!
+//! The controllers are called directly, without recording anything for
+//! `mac undo`: a fader sends dozens of values a second.
+
+use crate::Context;
+use mac_cli::osc::{self, Arg, Message};
+use mac_cli::watch::Watcher;
+use mac_cli::{ErrorKind, MacCliError, Result, Subsystem, VolumeController};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// The subsystems with feedback; Bluetooth is not controlled over OSC.
+const FEEDBACK: [Subsystem; 3] = [Subsystem::Volume, Subsystem::Brightness, Subsystem::Music];
+
+/// How long a client keeps getting feedback after its last message.
+const CLIENT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Most clients getting feedback at once. UDP senders are easily forged, and
+/// each one would otherwise be remembered, and sent feedback, for an hour.
+const MAX_CLIENTS: usize = 64;
+
+/// Settings of `mac osc` besides the context.
+pub(crate) struct OscOptions {
+    pub(crate) listen: SocketAddr,
+    /// Port to send feedback to, instead of the port each client sent from.
+    pub(crate) feedback_port: Option<u16>,
+    pub(crate) interval: Duration,
+}
+
+/// What a message calls for.
+enum Reply {
+    Nothing,
+    /// A state changed; every client hears about it.
+    Changed(Subsystem),
+    /// The client asked for these states.
+    Asked(&'static [Subsystem]),
+}
+
+struct Surface {
+    socket: UdpSocket,
+    feedback_port: Option<u16>,
+    watcher: Watcher,
+    /// Where feedback goes, with the time each client was last heard from.
+    clients: HashMap<SocketAddr, Instant>,
+}
+
+/// Listens on `options.listen` and applies messages until the process is killed.
+pub(crate) fn serve(mut ctx: Context, options: OscOptions) -> Result<()> {
+    let socket = UdpSocket::bind(options.listen).map_err(|e| {
+        MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Other,
+            format!("Failed to listen on {}", options.listen),
+        )
+        .with_source(e)
+    })?;
+    if !options.listen.ip().is_loopback() {
+        eprintln!(
+            "warning: anyone who can reach {} can control this Mac; use --bind 127.0.0.1 to accept local clients only",
+            options.listen
+        );
+    }
+    eprintln!("mac osc listening on udp://{}", options.listen);
+
+    let mut surface = Surface {
+        socket,
+        feedback_port: options.feedback_port,
+        watcher: Watcher::new(ctx.backend.clone(), ctx.music()),
+        clients: HashMap::new(),
+    };
+    let mut buf = vec![0; 65536];
+    let mut next_poll = Instant::now();
+
+    loop {
+        if Instant::now() >= next_poll {
+            surface.poll();
+            next_poll = Instant::now() + options.interval;
+        }
+
+        // A zero timeout means no timeout at all
+        let timeout = next_poll.saturating_duration_since(Instant::now());
+        surface.set_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let mut packets = match surface.socket.recv_from(&mut buf) {
+            Ok((len, from)) => vec![(from, buf[..len].to_vec())],
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => {
+                tracing::debug!(error = %e, "osc: failed to receive");
+                continue;
+            }
+        };
+
+        // Faders send values faster than they can be applied: take whatever
+        // else is queued too, so that only the latest value of each is applied
+        surface.set_timeout(None)?;
+        surface.socket.set_nonblocking(true).map_err(socket_error)?;
+        while let Ok((len, from)) = surface.socket.recv_from(&mut buf) {
+            packets.push((from, buf[..len].to_vec()));
+        }
+        surface
+            .socket
+            .set_nonblocking(false)
+            .map_err(socket_error)?;
+
+        if let Err(e) = ctx.reload_config() {
+            eprintln!("mac osc: {}", e);
+        }
+        surface.received(&ctx, packets);
+    }
+}
+
+impl Surface {
+    fn set_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.socket.set_read_timeout(timeout).map_err(socket_error)
+    }
+
+    /// Sends feedback on whatever changed since the last poll.
+    fn poll(&mut self) {
+        self.clients.retain(|_, seen| seen.elapsed() < CLIENT_TTL);
+        // Nobody would hear about changes
+        if self.clients.is_empty() {
+            self.watcher.reset();
+            return;
+        }
+
+        for change in self.watcher.poll(&FEEDBACK) {
+            self.broadcast(change.subsystem);
+        }
+    }
+
+    fn received(&mut self, ctx: &Context, packets: Vec<(SocketAddr, Vec<u8>)>) {
+        let mut messages = Vec::new();
+        for (from, packet) in packets {
+            match osc::decode(&packet) {
+                Ok(decoded) => messages.extend(decoded.into_iter().map(|m| (from, m))),
+                Err(e) => tracing::debug!(%from, error = %e, "osc: ignoring packet"),
+            }
+        }
+
+        for (i, (from, message)) in messages.iter().enumerate() {
+            let (client, new) = self.client(*from);
+            if new {
+                self.watcher.poll(&FEEDBACK);
+                self.sync(client, &FEEDBACK);
+            }
+
+            // Only the latest position of a fader matters; every button press does
+            let fader = matches!(message.address.as_str(), "/mac/volume" | "/mac/brightness");
+            let superseded = messages[i + 1..]
+                .iter()
+                .any(|(_, later)| later.address == message.address && !later.args.is_empty());
+            if fader && superseded {
+                continue;
+            }
+            tracing::debug!(%from, address = message.address, args = ?message.args, "osc: received");
+
+            match self.apply(ctx, message) {
+                Ok(Reply::Changed(subsystem)) => {
+                    // Confirm the new state right away, even if nothing changed
+                    self.watcher.poll(&[subsystem]);
+                    self.broadcast(subsystem);
+                }
+                Ok(Reply::Asked(subsystems)) => {
+                    self.watcher.poll(subsystems);
+                    self.sync(client, subsystems);
+                }
+                Ok(Reply::Nothing) => {}
+                Err(e) => eprintln!("mac osc: {}: {}", message.address, e),
+            }
+        }
+    }
+
+    /// Remembers where to send feedback for a message from `from`, and whether
+    /// that is a new client.
+    fn client(&mut self, from: SocketAddr) -> (SocketAddr, bool) {
+        let mut client = from;
+        if let Some(port) = self.feedback_port {
+            client.set_port(port);
+        }
+        if !self.clients.contains_key(&client) && self.clients.len() >= MAX_CLIENTS {
+            // Make room by forgetting the client heard from least recently
+            let oldest = self
+                .clients
+                .iter()
+                .min_by_key(|(_, seen)| **seen)
+                .map(|(client, _)| *client);
+            if let Some(oldest) = oldest {
+                self.clients.remove(&oldest);
+            }
+        }
+        let new = self.clients.insert(client, Instant::now()).is_none();
+        (client, new)
+    }
+
+    /// Applies a message.
+    fn apply(&self, ctx: &Context, message: &Message) -> Result<Reply> {
+        let value = message.args.first().and_then(Arg::as_f32);
+        // Nothing sensible to set, and no question asked either
+        if value.is_some_and(f32::is_nan) {
+            tracing::debug!(address = message.address, "osc: ignoring NaN");
+            return Ok(Reply::Nothing);
+        }
+
+        match message.address.as_str() {
+            "/mac/volume" => {
+                let Some(value) = value else {
+                    return Ok(Reply::Asked(&[Subsystem::Volume]));
+                };
+                VolumeController::with_backend(ctx.backend.clone()).set(value.clamp(0.0, 1.0))?;
+                Ok(Reply::Changed(Subsystem::Volume))
+            }
+            "/mac/brightness" => {
+                let Some(value) = value else {
+                    return Ok(Reply::Asked(&[Subsystem::Brightness]));
+                };
+                // Faders go down to 0, the display only to the minimum and
+                // never off; unlike clamp, max and min never panic
+                let min = (ctx.config.brightness.min / 100.0).max(0.01);
+                ctx.brightness()?.set(value.max(min).min(1.0))?;
+                Ok(Reply::Changed(Subsystem::Brightness))
+            }
+            "/mac/music/play" | "/mac/music/pause" | "/mac/music/next" | "/mac/music/previous" => {
+                // The release of a button
+                if value == Some(0.0) {
+                    return Ok(Reply::Nothing);
+                }
+                let music = ctx.music();
+                match message.address.as_str() {
+                    "/mac/music/play" => music.play()?,
+                    "/mac/music/pause" => music.pause()?,
+                    "/mac/music/next" => music.next()?,
+                    _ => music.previous()?,
+                }
+                Ok(Reply::Changed(Subsystem::Music))
+            }
+            "/mac/sync" => Ok(Reply::Asked(&FEEDBACK)),
+            address => {
+                tracing::debug!(address, "osc: unknown address");
+                Ok(Reply::Nothing)
+            }
+        }
+    }
+
+    /// Sends the last known state of `subsystem` to every client.
+    fn broadcast(&self, subsystem: Subsystem) {
+        for client in self.clients.keys() {
+            self.send(*client, subsystem);
+        }
+    }
+
+    /// Sends the last known states of `subsystems` to one client.
+    fn sync(&self, client: SocketAddr, subsystems: &[Subsystem]) {
+        for subsystem in subsystems {
+            self.send(client, *subsystem);
+        }
+    }
+
+    fn send(&self, client: SocketAddr, subsystem: Subsystem) {
+        let Some(value) = self.watcher.last(subsystem) else {
+            return;
+        };
+        for message in feedback(subsystem, value) {
+            if let Err(e) = self.socket.send_to(&message.encode(), client) {
+                tracing::debug!(%client, error = %e, "osc: failed to send feedback");
+            }
+        }
+    }
+}
+
+/// The feedback messages for a state read by the [`Watcher`].
+fn feedback(subsystem: Subsystem, value: &Value) -> Vec<Message> {
+    let ratio = |percent: &Value| Arg::Float(percent.as_f64().unwrap_or_default() as f32 / 100.0);
+    let flag = |on: bool| Arg::Float(if on { 1.0 } else { 0.0 });
+    let text = |value: &Value| Arg::String(value.as_str().unwrap_or_default().to_string());
+
+    match subsystem {
+        Subsystem::Volume => vec![
+            Message::new("/mac/volume", vec![ratio(&value["volume"])]),
+            Message::new(
+                "/mac/muted",
+                vec![flag(value["muted"].as_bool().unwrap_or(false))],
+            ),
+        ],
+        Subsystem::Brightness => vec![Message::new(
+            "/mac/brightness",
+            vec![ratio(&value["brightness"])],
+        )],
+        Subsystem::Music => {
+            // The player is not running when the value is null
+            let state = value["state"].as_str().unwrap_or("not_running");
+            let track = &value["track"];
+            vec![
+                Message::new("/mac/music/playing", vec![flag(state == "playing")]),
+                Message::new("/mac/music/state", vec![Arg::String(state.to_string())]),
+                Message::new("/mac/music/name", vec![text(&track["name"])]),
+                Message::new("/mac/music/artist", vec![text(&track["artist"])]),
+                Message::new("/mac/music/album", vec![text(&track["album"])]),
+            ]
+        }
+        Subsystem::Bluetooth | Subsystem::Weather | Subsystem::Cli => Vec::new(),
+    }
+}
+
+fn socket_error(e: io::Error) -> MacCliError {
+    MacCliError::new(Subsystem::Cli, ErrorKind::Other, "OSC socket failed").with_source(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{remove_with_lock, temp_path};
+    use mac_cli::Backend;
+    use mac_cli::music::PlayerState;
+    use mac_cli::sim::SimStore;
+
+    fn surface(backend: Backend) -> Surface {
+        let music = mac_cli::MusicController::with_backend(backend.clone());
+        Surface {
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            feedback_port: None,
+            watcher: Watcher::new(backend, music),
+            clients: HashMap::new(),
+        }
+    }
+
+    fn store(ctx: &Context) -> &SimStore {
+        match &ctx.backend {
+            Backend::Sim(store) => store,
+            _ => unreachable!("Context::sim is simulated"),
+        }
+    }
+
+    #[test]
+    fn brightness_fader_stops_at_the_configured_minimum() {
+        let mut ctx = Context::sim("osc", "brightness-min");
+        ctx.config.brightness.min = 20.0;
+        let surface = surface(ctx.backend.clone());
+
+        let reply = surface
+            .apply(
+                &ctx,
+                &Message::new("/mac/brightness", vec![Arg::Float(0.0)]),
+            )
+            .unwrap();
+        assert!(matches!(reply, Reply::Changed(Subsystem::Brightness)));
+        let brightness = store(&ctx).load(Subsystem::Brightness).unwrap().brightness;
+        assert!((brightness - 0.2).abs() < 1e-6, "{}", brightness);
+
+        surface
+            .apply(
+                &ctx,
+                &Message::new("/mac/brightness", vec![Arg::Float(1.5)]),
+            )
+            .unwrap();
+        assert_eq!(
+            store(&ctx).load(Subsystem::Brightness).unwrap().brightness,
+            1.0
+        );
+        ctx.remove_files();
+    }
+
+    #[test]
+    fn button_release_is_ignored() {
+        let ctx = Context::sim("osc", "release");
+        let surface = surface(ctx.backend.clone());
+
+        let reply = surface
+            .apply(
+                &ctx,
+                &Message::new("/mac/music/play", vec![Arg::Float(0.0)]),
+            )
+            .unwrap();
+        assert!(matches!(reply, Reply::Nothing));
+        let music = store(&ctx).load(Subsystem::Music).unwrap().music;
+        assert_eq!(music.state, PlayerState::Stopped);
+
+        let reply = surface
+            .apply(
+                &ctx,
+                &Message::new("/mac/music/play", vec![Arg::Float(1.0)]),
+            )
+            .unwrap();
+        assert!(matches!(reply, Reply::Changed(Subsystem::Music)));
+        let music = store(&ctx).load(Subsystem::Music).unwrap().music;
+        assert_eq!(music.state, PlayerState::Playing);
+        ctx.remove_files();
+    }
+
+    #[test]
+    fn nan_is_ignored() {
+        let ctx = Context::sim("osc", "nan");
+        let surface = surface(ctx.backend.clone());
+
+        for address in ["/mac/volume", "/mac/brightness", "/mac/music/play"] {
+            let reply = surface
+                .apply(&ctx, &Message::new(address, vec![Arg::Float(f32::NAN)]))
+                .unwrap();
+            assert!(matches!(reply, Reply::Nothing), "{}", address);
+        }
+        assert_eq!(
+            store(&ctx).load(Subsystem::Cli).unwrap(),
+            Default::default()
+        );
+        ctx.remove_files();
+    }
+
+    #[test]
+    fn fader_without_an_argument_asks_for_the_value() {
+        let ctx = Context::sim("osc", "asked");
+        let surface = surface(ctx.backend.clone());
+
+        let reply = surface
+            .apply(&ctx, &Message::new("/mac/volume", Vec::new()))
+            .unwrap();
+        assert!(matches!(reply, Reply::Asked([Subsystem::Volume])));
+        assert_eq!(store(&ctx).load(Subsystem::Volume).unwrap().volume, 0.5);
+        ctx.remove_files();
+    }
+
+    #[test]
+    fn feedback_reports_the_simulated_state() {
+        let ctx = Context::sim("osc", "feedback");
+        let mut surface = surface(ctx.backend.clone());
+        surface.watcher.poll(&FEEDBACK);
+
+        let volume = surface.watcher.last(Subsystem::Volume).unwrap();
+        assert_eq!(
+            feedback(Subsystem::Volume, volume),
+            vec![
+                Message::new("/mac/volume", vec![Arg::Float(0.5)]),
+                Message::new("/mac/muted", vec![Arg::Float(0.0)]),
+            ]
+        );
+        let brightness = surface.watcher.last(Subsystem::Brightness).unwrap();
+        assert_eq!(
+            feedback(Subsystem::Brightness, brightness),
+            vec![Message::new("/mac/brightness", vec![Arg::Float(0.75)])]
+        );
+        ctx.remove_files();
+    }
+
+    #[test]
+    fn feedback_on_music_that_is_not_running() {
+        let ctx = Context::sim("osc", "not-running");
+        store(&ctx)
+            .update(Subsystem::Music, |state| {
+                state.music.running = false;
+                Ok(())
+            })
+            .unwrap();
+        let mut surface = surface(ctx.backend.clone());
+        surface.watcher.poll(&FEEDBACK);
+
+        let music = surface.watcher.last(Subsystem::Music).unwrap();
+        assert_eq!(*music, Value::Null);
+        let empty = || vec![Arg::String(String::new())];
+        assert_eq!(
+            feedback(Subsystem::Music, music),
+            vec![
+                Message::new("/mac/music/playing", vec![Arg::Float(0.0)]),
+                Message::new(
+                    "/mac/music/state",
+                    vec![Arg::String("not_running".to_string())]
+                ),
+                Message::new("/mac/music/name", empty()),
+                Message::new("/mac/music/artist", empty()),
+                Message::new("/mac/music/album", empty()),
+            ]
+        );
+        ctx.remove_files();
+    }
+
+    #[test]
+    fn client_table_forgets_the_least_recent_client_when_full() {
+        let path = temp_path("osc", "clients", "json");
+        let mut surface = surface(Backend::Sim(SimStore::new(path.clone())));
+        let addr = |i: usize| SocketAddr::from(([10, 0, 0, i as u8], 9000));
+
+        // Client 1 was heard from least recently, client 0 most recently
+        let now = Instant::now();
+        for i in 0..MAX_CLIENTS {
+            let ago = Duration::from_secs(if i == 0 { 1 } else { 1000 - i as u64 });
+            surface
+                .clients
+                .insert(addr(i), now.checked_sub(ago).unwrap());
+        }
+
+        assert_eq!(surface.client(addr(0)), (addr(0), false));
+        assert_eq!(surface.client(addr(200)), (addr(200), true));
+        assert_eq!(surface.clients.len(), MAX_CLIENTS);
+        assert!(!surface.clients.contains_key(&addr(1)));
+        assert!(surface.clients.contains_key(&addr(2)));
+
+        surface.feedback_port = Some(9001);
+        let (client, new) = surface.client(addr(201));
+        assert_eq!(client, SocketAddr::from(([10, 0, 0, 201], 9001)));
+        assert!(new);
+        assert_eq!(surface.clients.len(), MAX_CLIENTS);
+        assert!(!surface.clients.contains_key(&addr(2)));
+        remove_with_lock(&path);
+    }
+}
@@ -0,0 +1,273 @@
+//! Rendering of command results for humans and scripts.
+//!
+//! Every `mac` command produces an [`Output`]: the text shown in a terminal,
+//! and a JSON document carrying the same information for scripts. The document
+//! can also be rendered through a user-supplied [`Template`].
+
+use crate::error::{MacCliError, Result, Subsystem};
+use serde_json::Value;
+
+/// How command results are printed.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// One JSON document per command.
+    Json,
+    /// The JSON document rendered through a template.
+    Template(Template),
+}
+
+/// The result of a command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    text: String,
+    data: Value,
+}
+
+impl Output {
+    /// Creates an output from its human-readable text and its JSON document.
+    pub fn new(text: impl Into<String>, data: Value) -> Self {
+        Output {
+            text: text.into(),
+            data,
+        }
+    }
+
+    /// The human-readable text.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The JSON document.
+    pub fn data(&self) -> &Value {
+        &self.data
+    }
+
+    /// Renders the output in the given format.
+    pub fn render(&self, format: &OutputFormat) -> String {
+        match format {
+            OutputFormat::Text => self.text.clone(),
+            OutputFormat::Json => self.data.to_string(),
+            OutputFormat::Template(template) => template.render(&self.data),
+        }
+    }
+}
+
+/// A `--format` template such as `{track.name} by {track.artist}`.
+///
+/// Placeholders are dotted paths into a command's JSON document:
+///
+/// - `{volume}` selects a top-level field, `{track.name}` a nested one.
+/// - `{devices.0.name}` indexes into an array.
+/// - `{devices.name}` maps over an array, joining the results with `", "`.
+/// - Missing and `null` fields render as an empty string.
+/// - `{{` and `}}` produce literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Vec<String>),
+}
+
+impl Template {
+    /// Parses a template, rejecting unbalanced or empty placeholders.
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') | None => {
+                                return Err(Self::error(template, "unclosed '{'"));
+                            }
+                            Some(c) => field.push(c),
+                        }
+                    }
+
+                    let field = field.trim();
+                    if field.is_empty() {
+                        return Err(Self::error(template, "empty placeholder '{}'"));
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(field.split('.').map(str::to_string).collect()));
+                }
+                '}' => return Err(Self::error(template, "unmatched '}'")),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Template { parts })
+    }
+
+    /// Renders the template against a JSON document.
+    pub fn render(&self, data: &Value) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Field(path) => out.push_str(&format_value(&lookup(data, path))),
+            }
+        }
+        out
+    }
+
+    fn error(template: &str, reason: &str) -> MacCliError {
+        MacCliError::invalid_argument(
+            Subsystem::Cli,
+            format!("Invalid format template {:?}: {}", template, reason),
+        )
+    }
+}
+
+/// Writes the template back in the syntax [`Template::parse`] accepts.
+impl std::fmt::Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => f.write_str(&s.replace('{', "{{").replace('}', "}}"))?,
+                Part::Field(path) => write!(f, "{{{}}}", path.join("."))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = MacCliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Template::parse(s)
+    }
+}
+
+/// Resolves a dotted path, mapping over arrays when the segment is not an index.
+fn lookup(value: &Value, path: &[String]) -> Value {
+    let Some((first, rest)) = path.split_first() else {
+        return value.clone();
+    };
+
+    match value {
+        Value::Object(map) => map
+            .get(first)
+            .map(|v| lookup(v, rest))
+            .unwrap_or(Value::Null),
+        Value::Array(items) => match first.parse::<usize>() {
+            Ok(index) => items
+                .get(index)
+                .map(|v| lookup(v, rest))
+                .unwrap_or(Value::Null),
+            Err(_) => Value::Array(items.iter().map(|v| lookup(v, path)).collect()),
+        },
+        _ => Value::Null,
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Number(n) => match n.as_f64() {
+            // Print whole numbers without a trailing ".0"
+            Some(f) if f.fract() == 0.0 && f.abs() < 1e15 => format!("{}", f as i64),
+            _ => n.to_string(),
+        },
+        Value::Bool(b) => b.to_string(),
+        Value::Array(items) => items
+            .iter()
+            .map(format_value)
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+        Value::Object(_) => value.to_string(),
+    }
+}
+
+/// Converts a 0.0-1.0 level to a whole percentage, as shown to users.
+pub fn percent(level: f32) -> i64 {
+    (level * 100.0).round() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use serde_json::json;
+
+    fn render(template: &str, data: &Value) -> String {
+        Template::parse(template).unwrap().render(data)
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let data = json!({ "volume": 40 });
+        assert_eq!(render("{{{volume}}}", &data), "{40}");
+        assert_eq!(render("{{volume}}", &data), "{volume}");
+
+        let template = Template::parse("{{ {volume} }}").unwrap();
+        assert_eq!(template.to_string(), "{{ {volume} }}");
+    }
+
+    #[test]
+    fn unbalanced_and_empty_placeholders_are_rejected() {
+        for template in ["{volume", "{track.{name}}", "volume}", "{}", "{ }"] {
+            let err = Template::parse(template).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidArgument, "{}", template);
+        }
+    }
+
+    #[test]
+    fn missing_and_null_fields_render_empty() {
+        let data = json!({ "track": null, "volume": 40 });
+        assert_eq!(render("[{track.name}]", &data), "[]");
+        assert_eq!(render("[{muted}]", &data), "[]");
+        assert_eq!(render("[{volume.level}]", &data), "[]");
+    }
+
+    #[test]
+    fn arrays_are_indexed_or_mapped() {
+        let data = json!({
+            "devices": [
+                { "name": "AirPods", "battery": 80 },
+                { "name": "Keyboard", "battery": null },
+            ]
+        });
+        assert_eq!(render("{devices.0.name}", &data), "AirPods");
+        assert_eq!(render("{devices.1.name}", &data), "Keyboard");
+        assert_eq!(render("[{devices.2.name}]", &data), "[]");
+        assert_eq!(render("{devices.name}", &data), "AirPods, Keyboard");
+        assert_eq!(render("{devices.battery}", &data), "80");
+    }
+
+    #[test]
+    fn whole_numbers_print_without_a_fraction() {
+        let data = json!({ "a": 40.0, "b": 0.25, "c": true });
+        assert_eq!(render("{a} {b} {c}", &data), "40 0.25 true");
+    }
+}
@@ -0,0 +1,21 @@
+//! Well-known file locations.
+
+use std::path::PathBuf;
+
+/// Returns the mac-cli configuration directory.
+///
+/// This is `$XDG_CONFIG_HOME/mac-cli` if set, otherwise `~/.config/mac-cli`.
+pub fn config_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir).join("mac-cli");
+    }
+
+    home_dir().join(".config").join("mac-cli")
+}
+
+/// Returns the user's home directory, falling back to the current directory.
+pub fn home_dir() -> PathBuf {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
@@ -0,0 +1,393 @@
+//! External subcommands: `mac foo` runs an executable named `mac-foo` found on
+//! `PATH`, the way git and cargo do.
+//!
+//! Built-in commands always win. The plugin replaces this process and gets
+//! the global flags through the environment variables `mac` itself reads, so
+//! a plugin that runs `mac` again inherits them.
+
+use crate::{
+    BackendKind, Cli, DRY_RUN_ENV, ERROR_FORMAT_ENV, ErrorFormat, FORMAT_ENV, JSON_ENV, LOG_ENV,
+    LOG_FILE_ENV, NO_DAEMON_ENV,
+};
+use clap::{CommandFactory, ValueEnum};
+use mac_cli::backend::{BACKEND_ENV, SIM_STATE_ENV};
+use mac_cli::config::CONFIG_ENV;
+use mac_cli::sim::SimStore;
+use mac_cli::{Config, ErrorKind, MacCliError, Result, Subsystem};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+
+/// Prefix of plugin executables.
+const PREFIX: &str = "mac-";
+
+/// An executable `mac-<name>` on `PATH`.
+pub(crate) struct Plugin {
+    name: String,
+    path: PathBuf,
+}
+
+/// Whether `args`, the arguments of `mac`, ask for the help of `mac` itself,
+/// which lists the plugins. Arguments after the subcommand are left alone:
+/// `mac foo --help` is for the plugin.
+pub(crate) fn help_requested(args: impl IntoIterator<Item = OsString>) -> bool {
+    let cli = Cli::command();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(arg) = arg.to_str() else {
+            return false;
+        };
+        match arg {
+            "-h" | "--help" | "help" => return true,
+            "--" => return false,
+            arg if !arg.starts_with('-') => return false,
+            _ => {}
+        }
+        // The value of `--config PATH` is not the subcommand
+        if takes_value(&cli, arg) {
+            args.next();
+        }
+    }
+    false
+}
+
+/// Whether the global option `arg` takes its value from the next argument.
+fn takes_value(cli: &clap::Command, arg: &str) -> bool {
+    let option = match arg.strip_prefix("--") {
+        Some(long) if !long.contains('=') => {
+            cli.get_arguments().find(|a| a.get_long() == Some(long))
+        }
+        Some(_) => None,
+        None => {
+            let mut shorts = arg.chars().skip(1);
+            match (shorts.next(), shorts.next()) {
+                (Some(short), None) => cli.get_arguments().find(|a| a.get_short() == Some(short)),
+                _ => None,
+            }
+        }
+    };
+    option.is_some_and(|a| a.get_action().takes_values())
+}
+
+/// Returns the plugins on `PATH`, sorted by name. When several directories
+/// hold the same plugin, the first one wins, as it would in a shell.
+pub(crate) fn discover() -> Vec<Plugin> {
+    discover_in(&path_dirs())
+}
+
+fn discover_in(dirs: &[PathBuf]) -> Vec<Plugin> {
+    let builtin = Cli::command();
+    let mut plugins: Vec<Plugin> = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(name) = file_name.to_str().and_then(|n| n.strip_prefix(PREFIX)) else {
+                continue;
+            };
+            if name.is_empty()
+                || builtin.find_subcommand(name).is_some()
+                || plugins.iter().any(|p| p.name == name)
+                || !is_executable(&entry.path())
+            {
+                continue;
+            }
+            plugins.push(Plugin {
+                name: name.to_string(),
+                path: entry.path(),
+            });
+        }
+    }
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+    plugins
+}
+
+/// Lists `plugins` for `mac --help`.
+pub(crate) fn help(plugins: &[Plugin]) -> String {
+    let width = plugins.iter().map(|p| p.name.len()).max().unwrap_or(0);
+    let mut text = "Plugins (mac-<name> on PATH):".to_string();
+    for plugin in plugins {
+        text.push_str(&format!(
+            "\n  {:width$}  {}",
+            plugin.name,
+            plugin.path.display(),
+            width = width
+        ));
+    }
+    text
+}
+
+/// Replaces this process with the plugin named by the first of `args`,
+/// passing it the other arguments. Only returns on failure.
+pub(crate) fn run(cli: &Cli, args: &[OsString]) -> Result<()> {
+    let Some((name, args)) = args.split_first() else {
+        return Err(MacCliError::invalid_argument(
+            Subsystem::Cli,
+            "Missing command",
+        ));
+    };
+    let Some(path) = find(name, &path_dirs()) else {
+        unknown_command(std::env::args_os()).exit();
+    };
+
+    tracing::debug!(plugin = %path.display(), "running plugin");
+    let e = std::process::Command::new(&path)
+        .args(args)
+        .envs(environment(cli))
+        .exec();
+    Err(MacCliError::new(
+        Subsystem::Cli,
+        ErrorKind::CommandFailed,
+        format!("Failed to run plugin {}", path.display()),
+    )
+    .with_source(e))
+}
+
+/// The environment variables passing the global flags of `cli` on to a
+/// plugin.
+fn environment(cli: &Cli) -> Vec<(&'static str, OsString)> {
+    // Resolved values, so plugins don't need to know the defaults
+    let config = cli.config.clone().unwrap_or_else(Config::default_path);
+    let backend = cli
+        .backend
+        .to_possible_value()
+        .expect("no backend is skipped");
+    let mut env = vec![
+        (CONFIG_ENV, config.into_os_string()),
+        (BACKEND_ENV, backend.get_name().into()),
+    ];
+    if cli.backend == BackendKind::Sim {
+        let state = cli.sim_state.clone().unwrap_or_else(SimStore::default_path);
+        env.push((SIM_STATE_ENV, state.into_os_string()));
+    }
+    if cli.json {
+        env.push((JSON_ENV, "1".into()));
+    }
+    if let Some(template) = &cli.format {
+        env.push((FORMAT_ENV, template.to_string().into()));
+    }
+    if cli.dry_run {
+        env.push((DRY_RUN_ENV, "1".into()));
+    }
+    // Same levels as `-v` and `-vv`
+    let log = match cli.verbose {
+        0 => None,
+        1 => Some("debug"),
+        _ => Some("trace"),
+    };
+    if let Some(directives) = log {
+        env.push((LOG_ENV, directives.into()));
+    }
+    if let Some(path) = &cli.log_file {
+        env.push((LOG_FILE_ENV, path.clone().into_os_string()));
+    }
+    if cli.no_daemon {
+        env.push((NO_DAEMON_ENV, "1".into()));
+    }
+    if cli.error_format == ErrorFormat::Json {
+        env.push((ERROR_FORMAT_ENV, "json".into()));
+    }
+    env
+}
+
+/// The error clap reports for the unknown subcommand in `argv` when plugins
+/// are not considered, suggesting a similar built-in command if there is one.
+fn unknown_command(argv: impl IntoIterator<Item = OsString>) -> clap::Error {
+    let mut command = Cli::command()
+        .external_subcommand_value_parser(clap::builder::Resettable::Reset)
+        .allow_external_subcommands(false);
+    match command.try_get_matches_from_mut(argv) {
+        Err(e) => e,
+        Ok(_) => command.error(
+            clap::error::ErrorKind::InvalidSubcommand,
+            "unrecognized subcommand",
+        ),
+    }
+}
+
+/// Finds the plugin `name` in `dirs`, the directories of `PATH`.
+fn find(name: &OsStr, dirs: &[PathBuf]) -> Option<PathBuf> {
+    let name = name.to_str()?;
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    dirs.iter()
+        .map(|dir| dir.join(format!("{}{}", PREFIX, name)))
+        .find(|path| is_executable(path))
+}
+
+fn path_dirs() -> Vec<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .collect()
+}
+
+fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use clap::Parser;
+
+    fn file(dir: &Path, name: &str, mode: u32) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    fn help(args: &[&str]) -> bool {
+        help_requested(args.iter().map(OsString::from))
+    }
+
+    fn env(args: &[&str]) -> Vec<(&'static str, String)> {
+        let cli = Cli::try_parse_from(args).unwrap();
+        environment(&cli)
+            .into_iter()
+            .map(|(name, value)| (name, value.to_string_lossy().into_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn help_is_only_looked_for_before_the_subcommand() {
+        assert!(help(&["--help"]));
+        assert!(help(&["-h"]));
+        assert!(help(&["help"]));
+        assert!(help(&["-vv", "--json", "--help"]));
+        assert!(help(&["--config", "/tmp/config.toml", "help", "volume"]));
+        assert!(help(&["--backend=sim", "-h"]));
+
+        assert!(!help(&[]));
+        assert!(!help(&["foo", "--help"]));
+        assert!(!help(&["volume", "-h"]));
+        assert!(!help(&["--config", "help", "foo"]));
+        assert!(!help(&["--", "--help"]));
+        assert!(!help(&["-v", "foo", "help"]));
+    }
+
+    #[test]
+    fn find_takes_the_first_executable_on_path() {
+        let (first, second) = (temp_dir("plugins", "first"), temp_dir("plugins", "second"));
+        file(&first, "mac-skipped", 0o644);
+        let skipped = file(&second, "mac-skipped", 0o755);
+        let shadowed = file(&first, "mac-foo", 0o755);
+        file(&second, "mac-foo", 0o755);
+        let dirs = [first.clone(), second.clone()];
+
+        assert_eq!(find(OsStr::new("foo"), &dirs), Some(shadowed.clone()));
+        assert_eq!(find(OsStr::new("skipped"), &dirs), Some(skipped.clone()));
+        assert_eq!(find(OsStr::new("bar"), &dirs), None);
+        assert_eq!(find(OsStr::new(""), &dirs), None);
+
+        let found: Vec<(String, PathBuf)> = discover_in(&dirs)
+            .into_iter()
+            .map(|p| (p.name, p.path))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("foo".to_string(), shadowed),
+                ("skipped".to_string(), skipped)
+            ]
+        );
+
+        std::fs::remove_dir_all(&first).ok();
+        std::fs::remove_dir_all(&second).ok();
+    }
+
+    #[test]
+    fn find_rejects_paths() {
+        let dir = temp_dir("plugins", "paths");
+        std::fs::create_dir_all(dir.join("mac-sub")).unwrap();
+        file(&dir.join("mac-sub"), "tool", 0o755);
+        file(&dir, "mac-", 0o755);
+        let dirs = [dir.clone()];
+
+        assert_eq!(find(OsStr::new("sub/tool"), &dirs), None);
+        assert_eq!(find(OsStr::new("/bin/sh"), &dirs), None);
+        assert_eq!(find(OsStr::new("/"), &dirs), None);
+        assert_eq!(find(OsStr::new("sub"), &dirs), None);
+        assert!(discover_in(&dirs).is_empty());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn unknown_commands_get_the_clap_error_and_suggestion() {
+        let err = unknown_command(["mac", "--json", "volum", "40"].map(OsString::from));
+        assert_eq!(err.kind(), clap::error::ErrorKind::InvalidSubcommand);
+        let message = err.to_string();
+        assert!(message.contains("'volum'"), "{}", message);
+        assert!(message.contains("'volume'"), "{}", message);
+
+        let err = unknown_command(["mac", "frobnicate"].map(OsString::from));
+        assert_eq!(err.kind(), clap::error::ErrorKind::InvalidSubcommand);
+    }
+
+    #[test]
+    fn builtin_commands_are_not_plugins() {
+        let dir = temp_dir("plugins", "builtin");
+        file(&dir, "mac-volume", 0o755);
+        assert!(discover_in(std::slice::from_ref(&dir)).is_empty());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn global_flags_are_passed_in_the_environment() {
+        let passed = env(&[
+            "mac",
+            "--config",
+            "/tmp/config.toml",
+            "--backend",
+            "sim",
+            "--sim-state",
+            "/tmp/sim.json",
+            "--format",
+            "{volume}",
+            "--dry-run",
+            "-vv",
+            "--log-file",
+            "/tmp/mac.log",
+            "--no-daemon",
+            "--error-format",
+            "json",
+            "foo",
+            "--bar",
+        ]);
+        assert_eq!(
+            passed,
+            [
+                (CONFIG_ENV, "/tmp/config.toml".to_string()),
+                (BACKEND_ENV, "sim".to_string()),
+                (SIM_STATE_ENV, "/tmp/sim.json".to_string()),
+                (FORMAT_ENV, "{volume}".to_string()),
+                (DRY_RUN_ENV, "1".to_string()),
+                (LOG_ENV, "trace".to_string()),
+                (LOG_FILE_ENV, "/tmp/mac.log".to_string()),
+                (NO_DAEMON_ENV, "1".to_string()),
+                (ERROR_FORMAT_ENV, "json".to_string()),
+            ]
+        );
+
+        let defaults = env(&["mac", "--json", "-v", "foo"]);
+        assert_eq!(
+            defaults,
+            [
+                (
+                    CONFIG_ENV,
+                    Config::default_path().to_string_lossy().into_owned()
+                ),
+                (BACKEND_ENV, "macos".to_string()),
+                (JSON_ENV, "1".to_string()),
+                (LOG_ENV, "debug".to_string()),
+            ]
+        );
+    }
+}
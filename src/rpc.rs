@@ -0,0 +1,446 @@
+//! The JSON-RPC protocol spoken by `mac daemon`, and a client for it.
+//!
+//! The daemon listens on a Unix domain socket ([`default_socket_path`]).
+//! Messages are [JSON-RPC 2.0](https://www.jsonrpc.org/specification) objects,
+//! one per line:
+//!
+//! ```text
+//! → {"jsonrpc": "2.0", "id": 1, "method": "volume.set", "params": {"volume": 40}}
+//! ← {"jsonrpc": "2.0", "id": 1, "result": {"volume": 40, "previous": 55}}
+//! → {"jsonrpc": "2.0", "id": 2, "method": "subscribe", "params": {"subsystems": ["volume"]}}
+//! ← {"jsonrpc": "2.0", "id": 2, "result": {"subscribed": ["volume"]}}
+//! ← {"jsonrpc": "2.0", "method": "event", "params": {"subsystem": "volume", "value": {...}, "previous": {...}}}
+//! ```
+//!
+//! Failures of `mac` itself are reported with code [`COMMAND_FAILED`] and the
+//! error document of [`MacCliError::to_json`] as `data`.
+//!
+//! ```no_run
+//! use mac_cli::rpc::{self, Client};
+//! use serde_json::json;
+//!
+//! let mut client = Client::connect(&rpc::default_socket_path())?;
+//! client.call("volume.set", json!({ "volume": 40 }))?;
+//! client.call("subscribe", json!({ "subsystems": ["volume", "music"] }))?;
+//! while let Some(change) = client.next_event()? {
+//!     println!("{} changed to {}", change.subsystem, change.value);
+//! }
+//! # Ok::<(), mac_cli::MacCliError>(())
+//! ```
+
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::watch::Change;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+/// Environment variable overriding the daemon socket path.
+pub const SOCKET_ENV: &str = "MAC_CLI_SOCKET";
+
+/// The request was not valid JSON.
+pub const PARSE_ERROR: i64 = -32700;
+/// The request was not a JSON-RPC request.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// The parameters are missing or of the wrong type.
+pub const INVALID_PARAMS: i64 = -32602;
+/// The command failed; `data` holds the error document.
+pub const COMMAND_FAILED: i64 = -32000;
+
+/// The socket of the daemon: [`SOCKET_ENV`] if set, otherwise
+/// `~/.config/mac-cli/daemon.sock`.
+pub fn default_socket_path() -> PathBuf {
+    std::env::var_os(SOCKET_ENV)
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| crate::paths::config_dir().join("daemon.sock"))
+}
+
+/// A request, or a notification when it has no `id`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// The answer to a request: either `result` or `error` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+/// A message sent by the server without a request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: Value,
+}
+
+/// The `error` member of a response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl Request {
+    pub fn new(id: u64, method: impl Into<String>, params: Value) -> Self {
+        Request {
+            jsonrpc: "2.0".to_string(),
+            id: Some(id.into()),
+            method: method.into(),
+            params,
+        }
+    }
+}
+
+impl Response {
+    /// A successful response.
+    pub fn ok(id: Value, result: Value) -> Self {
+        Response {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    /// A failed response.
+    pub fn err(id: Value, error: RpcError) -> Self {
+        Response {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(error),
+        }
+    }
+}
+
+impl Notification {
+    /// An `event` notification for a change seen by the daemon.
+    pub fn event(change: &Change) -> Self {
+        Notification {
+            jsonrpc: "2.0".to_string(),
+            method: "event".to_string(),
+            params: serde_json::to_value(change).expect("Change is always serializable"),
+        }
+    }
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Converts the error back into the [`MacCliError`] the server reported,
+    /// or describes a protocol error.
+    pub fn into_error(self) -> MacCliError {
+        if let Some(error) = self.data.as_ref().and_then(MacCliError::from_json) {
+            return error;
+        }
+
+        let kind = match self.code {
+            METHOD_NOT_FOUND | INVALID_PARAMS => ErrorKind::InvalidArgument,
+            _ => ErrorKind::Other,
+        };
+        MacCliError::new(
+            Subsystem::Cli,
+            kind,
+            format!("mac daemon error {}: {}", self.code, self.message),
+        )
+    }
+}
+
+impl From<&MacCliError> for RpcError {
+    fn from(error: &MacCliError) -> Self {
+        RpcError {
+            code: COMMAND_FAILED,
+            message: error.to_string(),
+            data: Some(error.to_json()["error"].clone()),
+        }
+    }
+}
+
+/// A connection to `mac daemon`.
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+    /// Events received while waiting for a response.
+    events: VecDeque<Change>,
+}
+
+impl Client {
+    /// Connects to the daemon listening on `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ErrorKind::Unavailable`] if no daemon is listening.
+    pub fn connect(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path).map_err(|e| {
+            MacCliError::new(
+                Subsystem::Cli,
+                ErrorKind::Unavailable,
+                format!("mac daemon is not running at {}", path.display()),
+            )
+            .with_source(e)
+        })?;
+        let writer = stream.try_clone().map_err(Self::io_error)?;
+
+        Ok(Client {
+            reader: BufReader::new(stream),
+            writer,
+            next_id: 1,
+            events: VecDeque::new(),
+        })
+    }
+
+    /// Calls `method` and waits for its result.
+    pub fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut line = serde_json::to_string(&Request::new(id, method, params))
+            .expect("Request is always serializable");
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .map_err(Self::io_error)?;
+
+        loop {
+            let message = self.read()?.ok_or_else(|| {
+                MacCliError::new(
+                    Subsystem::Cli,
+                    ErrorKind::Other,
+                    "mac daemon closed the connection",
+                )
+            })?;
+
+            if message.get("id").is_none() {
+                self.queue_event(message);
+                continue;
+            }
+
+            let response: Response = serde_json::from_value(message).map_err(Self::parse_error)?;
+            if response.id != id {
+                continue;
+            }
+            return match (response.result, response.error) {
+                (_, Some(error)) => Err(error.into_error()),
+                (Some(result), None) => Ok(result),
+                (None, None) => Ok(Value::Null),
+            };
+        }
+    }
+
+    /// Waits for the next event of a subscription, or returns `None` once the
+    /// daemon closes the connection.
+    pub fn next_event(&mut self) -> Result<Option<Change>> {
+        while self.events.is_empty() {
+            match self.read()? {
+                Some(message) => self.queue_event(message),
+                None => return Ok(None),
+            }
+        }
+        Ok(self.events.pop_front())
+    }
+
+    fn queue_event(&mut self, message: Value) {
+        match serde_json::from_value::<Notification>(message) {
+            Ok(notification) if notification.method == "event" => {
+                match serde_json::from_value(notification.params) {
+                    Ok(change) => self.events.push_back(change),
+                    Err(e) => tracing::debug!(error = %e, "ignoring malformed event"),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Reads one message, or `None` at the end of the stream.
+    fn read(&mut self) -> Result<Option<Value>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line).map_err(Self::io_error)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim().is_empty() {
+                return serde_json::from_str(&line)
+                    .map(Some)
+                    .map_err(Self::parse_error);
+            }
+        }
+    }
+
+    fn io_error(e: std::io::Error) -> MacCliError {
+        MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Other,
+            "Failed to talk to mac daemon",
+        )
+        .with_source(e)
+    }
+
+    fn parse_error(e: serde_json::Error) -> MacCliError {
+        MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Parse,
+            "Invalid message from mac daemon",
+        )
+        .with_source(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_path;
+    use serde_json::json;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn requests_without_id_or_params_parse() {
+        let request: Request =
+            serde_json::from_str(r#"{"jsonrpc": "2.0", "method": "music.play"}"#).unwrap();
+        assert_eq!(request.id, None);
+        assert_eq!(request.params, Value::Null);
+
+        let request = Request::new(7, "volume.set", json!({ "volume": 40 }));
+        let line = serde_json::to_string(&request).unwrap();
+        assert_eq!(serde_json::from_str::<Request>(&line).unwrap(), request);
+    }
+
+    #[test]
+    fn responses_carry_either_result_or_error() {
+        let ok = serde_json::to_value(Response::ok(json!(1), json!({ "volume": 40 }))).unwrap();
+        assert_eq!(
+            ok,
+            json!({ "jsonrpc": "2.0", "id": 1, "result": { "volume": 40 } })
+        );
+
+        let err = Response::err(Value::Null, RpcError::new(PARSE_ERROR, "Invalid JSON"));
+        assert_eq!(
+            serde_json::to_value(err).unwrap(),
+            json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": PARSE_ERROR, "message": "Invalid JSON" },
+            })
+        );
+    }
+
+    #[test]
+    fn command_failures_come_back_as_the_original_error() {
+        let error = MacCliError::new(
+            Subsystem::Music,
+            ErrorKind::AppNotRunning,
+            "Music is not running",
+        );
+
+        let rpc_error = RpcError::from(&error);
+        assert_eq!(rpc_error.code, COMMAND_FAILED);
+
+        // Through the wire and back
+        let line = serde_json::to_string(&rpc_error).unwrap();
+        let back = serde_json::from_str::<RpcError>(&line)
+            .unwrap()
+            .into_error();
+        assert_eq!(back.subsystem(), Subsystem::Music);
+        assert_eq!(back.kind(), ErrorKind::AppNotRunning);
+        assert_eq!(back.message(), "Music is not running");
+        assert_eq!(back.exit_code(), error.exit_code());
+    }
+
+    #[test]
+    fn protocol_errors_map_to_cli_errors() {
+        for (code, kind, exit_code) in [
+            (METHOD_NOT_FOUND, ErrorKind::InvalidArgument, 2),
+            (INVALID_PARAMS, ErrorKind::InvalidArgument, 2),
+            (INVALID_REQUEST, ErrorKind::Other, 1),
+            (PARSE_ERROR, ErrorKind::Other, 1),
+            (COMMAND_FAILED, ErrorKind::Other, 1),
+        ] {
+            let error = RpcError::new(code, "nope").into_error();
+            assert_eq!(error.subsystem(), Subsystem::Cli);
+            assert_eq!(error.kind(), kind, "code {}", code);
+            assert_eq!(error.exit_code(), exit_code, "code {}", code);
+            assert_eq!(error.message(), format!("mac daemon error {}: nope", code));
+        }
+    }
+
+    #[test]
+    fn client_keeps_events_that_arrive_before_a_response() {
+        let path = temp_path("rpc", "events", "sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let request: Request = serde_json::from_str(&line).unwrap();
+
+            let event = Notification::event(&Change {
+                subsystem: Subsystem::Volume,
+                value: json!({ "volume": 40 }),
+                previous: json!({ "volume": 30 }),
+            });
+            for message in [
+                serde_json::to_value(event).unwrap(),
+                serde_json::to_value(Response::ok(json!(99), json!("stale"))).unwrap(),
+                serde_json::to_value(Response::ok(
+                    request.id.clone().unwrap(),
+                    json!({ "ok": 1 }),
+                ))
+                .unwrap(),
+            ] {
+                // The blank lines in between are skipped
+                writeln!(writer, "{}\n", message).unwrap();
+            }
+            request
+        });
+
+        let mut client = Client::connect(&path).unwrap();
+        let result = client.call("volume.get", Value::Null).unwrap();
+        let request = server.join().unwrap();
+        let event = client.next_event().unwrap().unwrap();
+        let end = client.next_event().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(request.method, "volume.get");
+        assert_eq!(result, json!({ "ok": 1 }));
+        assert_eq!(event.subsystem, Subsystem::Volume);
+        assert_eq!(event.value, json!({ "volume": 40 }));
+        assert_eq!(end, None);
+    }
+
+    #[test]
+    fn connecting_without_a_daemon_is_unavailable() {
+        let path = temp_path("rpc", "nonexistent", "sock");
+        let err = Client::connect(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Unavailable);
+        assert_eq!(err.exit_code(), 6);
+    }
+}
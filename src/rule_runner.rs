@@ -0,0 +1,334 @@
+//! `mac rules`: applies the rules of the rules file; see [`mac_cli::rules`].
+//!
+//! `mac rules run` evaluates the rules every `--interval` and runs the
+//! actions of a rule when its conditions start to hold, not again while they
+//! keep holding. Rules that hold when it starts apply right away. A rule
+//! whose conditions cannot be checked, because a subsystem could not be
+//! read, neither applies nor counts as no longer holding.
+
+use crate::batch::parse_line;
+use crate::{Context, execute, status_query};
+use chrono::{Local, NaiveTime};
+use mac_cli::output::{Output, OutputFormat};
+use mac_cli::rules::{Facts, Outcome, Rule, RuleSet, TimeOfDay};
+use mac_cli::watch::BLUETOOTH_POLL_EVERY;
+use mac_cli::{ErrorKind, MacCliError, Result, Subsystem};
+use serde_json::json;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+/// Parses `--at`.
+pub(crate) fn time_of_day(value: &str) -> std::result::Result<TimeOfDay, String> {
+    TimeOfDay::try_from(value.to_string())
+}
+
+/// Applies the rules at `path` until the process is killed, or once.
+pub(crate) fn run(
+    mut ctx: Context,
+    path: &Path,
+    interval: Duration,
+    once: bool,
+    format: &OutputFormat,
+) -> Result<()> {
+    let mut rules = load(path)?;
+    if !once {
+        eprintln!(
+            "mac rules: watching {} rules from {}",
+            rules.rules.len(),
+            path.display()
+        );
+    }
+
+    // Rules that held at the previous evaluation
+    let mut holding: HashSet<String> = HashSet::new();
+    // Between Bluetooth reads, the last one stands
+    let mut bluetooth = None;
+    for tick in 0u64.. {
+        let mut subsystems = rules.subsystems();
+        if !tick.is_multiple_of(BLUETOOTH_POLL_EVERY) {
+            subsystems.retain(|s| *s != Subsystem::Bluetooth);
+        }
+        let mut facts = facts(&ctx, &subsystems);
+        match subsystems.contains(&Subsystem::Bluetooth) {
+            true => bluetooth = facts.bluetooth.clone(),
+            false => facts.bluetooth = bluetooth.clone(),
+        }
+        let now = Local::now().time();
+
+        let mut first_error = None;
+        for rule in due(&rules, &facts, now, &mut holding, once) {
+            // A failed rule is not retried until its conditions hold again
+            if let Err(e) = apply(&ctx, rule, format) {
+                eprintln!("mac rules: {}", e);
+                first_error.get_or_insert(e);
+            }
+        }
+
+        if once {
+            return first_error.map_or(Ok(()), Err);
+        }
+        std::thread::sleep(interval);
+
+        if let Err(e) = ctx.reload_config() {
+            eprintln!("mac rules: {}", e);
+        }
+        // Pick up edits of the rules without restarting, too
+        match load(path) {
+            Ok(reloaded) => rules = reloaded,
+            Err(e) => eprintln!("mac rules: {}; keeping the previous rules", e),
+        }
+    }
+    Ok(())
+}
+
+/// Evaluates `rules` against `facts` at the time of day `now` and returns
+/// those to apply: the rules whose conditions started to hold since the
+/// previous evaluation or, with `once`, every rule that holds.
+///
+/// `holding` carries the rules that held from one evaluation to the next.
+/// Rules that cannot be checked leave it as it was.
+fn due<'a>(
+    rules: &'a RuleSet,
+    facts: &Facts,
+    now: NaiveTime,
+    holding: &mut HashSet<String>,
+    once: bool,
+) -> Vec<&'a Rule> {
+    let mut due = Vec::new();
+    for rule in &rules.rules {
+        match rule.evaluate(facts, now).outcome() {
+            Outcome::Holds => {}
+            Outcome::Unmet => {
+                holding.remove(&rule.name);
+                continue;
+            }
+            // Whether it still holds is not known; wait for a good read
+            Outcome::Unknown => continue,
+        }
+        if holding.insert(rule.name.clone()) || once {
+            due.push(rule);
+        }
+    }
+    due
+}
+
+/// Evaluates the rules at `path` against the current state or the state in
+/// `fixture`, at the time of day `at` or now, and runs nothing.
+pub(crate) fn test(
+    ctx: &Context,
+    path: &Path,
+    at: Option<TimeOfDay>,
+    fixture: Option<&Path>,
+) -> Result<Output> {
+    let rules = load(path)?;
+    let facts = match fixture {
+        Some(fixture) => read_fixture(fixture)?,
+        None => facts(ctx, &rules.subsystems()),
+    };
+    let now = at.map_or_else(|| Local::now().time(), |at| at.0);
+
+    let mut lines = Vec::new();
+    let mut documents = Vec::new();
+    for rule in &rules.rules {
+        let evaluation = rule.evaluate(&facts, now);
+        lines.push(match evaluation.outcome() {
+            Outcome::Holds => format!("{}: would run {}", rule.name, rule.actions.join("; ")),
+            Outcome::Unmet => format!(
+                "{}: would not run ({})",
+                rule.name,
+                evaluation.unmet.join("; ")
+            ),
+            Outcome::Unknown => format!(
+                "{}: cannot tell ({})",
+                rule.name,
+                evaluation.unknown.join("; ")
+            ),
+        });
+        documents.push(json!({
+            "name": rule.name,
+            "applies": evaluation.applies(),
+            "actions": rule.actions,
+            "unmet": evaluation.unmet,
+            "unknown": evaluation.unknown,
+        }));
+    }
+    if lines.is_empty() {
+        lines.push(format!("No rules in {}", path.display()));
+    }
+
+    Ok(Output::new(
+        lines.join("\n"),
+        json!({ "at": now.format("%H:%M").to_string(), "rules": documents }),
+    ))
+}
+
+/// Loads the rules at `path` and checks that every action is a command `mac`
+/// can run, reporting every invalid action at once.
+fn load(path: &Path) -> Result<RuleSet> {
+    let rules = RuleSet::load(path)?;
+
+    let errors: Vec<String> = rules
+        .rules
+        .iter()
+        .flat_map(|rule| rule.actions.iter().map(move |action| (rule, action)))
+        .filter_map(|(rule, action)| {
+            parse_line(action)
+                .err()
+                .map(|message| format!("rule {:?}, `{}`: {}", rule.name, action, message))
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Parse,
+            format!(
+                "Invalid rules in {}:\n  {}",
+                path.display(),
+                errors.join("\n  ")
+            ),
+        ));
+    }
+    Ok(rules)
+}
+
+/// Reads `subsystems`, those the rules depend on.
+fn facts(ctx: &Context, subsystems: &[Subsystem]) -> Facts {
+    Facts::from_status(&status_query(ctx).only(subsystems).run())
+}
+
+/// Reads a `mac status --json` document.
+fn read_fixture(path: &Path) -> Result<Facts> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        let kind = match e.kind() {
+            std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+            _ => ErrorKind::Other,
+        };
+        MacCliError::new(
+            Subsystem::Cli,
+            kind,
+            format!("Failed to read state {}", path.display()),
+        )
+        .with_source(e)
+    })?;
+
+    serde_json::from_str(&contents).map_err(|e| {
+        MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Parse,
+            format!(
+                "Invalid state in {}, expected a `mac status --json` document",
+                path.display()
+            ),
+        )
+        .with_source(e)
+    })
+}
+
+/// Runs the actions of `rule` in order, stopping at the first failure.
+fn apply(ctx: &Context, rule: &Rule, format: &OutputFormat) -> Result<()> {
+    for action in &rule.actions {
+        let command = parse_line(action)
+            .map_err(|message| MacCliError::invalid_argument(Subsystem::Cli, message))?;
+        let output = execute(ctx, command, &format!("mac {}", action)).map_err(|e| {
+            MacCliError::new(
+                e.subsystem(),
+                e.kind(),
+                format!("rule {:?}, `{}`: {}", rule.name, action, e),
+            )
+        })?;
+
+        match format {
+            OutputFormat::Json => println!(
+                "{}",
+                json!({ "rule": rule.name, "action": action, "result": output.data() })
+            ),
+            _ => println!("{}: {}", rule.name, output.text()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> RuleSet {
+        toml::from_str(
+            r#"
+            [[rules]]
+            name = "loud"
+            volume = ">= 50"
+            actions = ["volume 30"]
+
+            [[rules]]
+            name = "evening"
+            after = "18:00"
+            actions = ["brightness 40"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn at(time: &str) -> NaiveTime {
+        time_of_day(time).unwrap().0
+    }
+
+    fn volume(volume: Option<i64>) -> Facts {
+        Facts {
+            volume,
+            ..Facts::default()
+        }
+    }
+
+    fn names(rules: Vec<&Rule>) -> Vec<&str> {
+        rules.iter().map(|rule| rule.name.as_str()).collect()
+    }
+
+    #[test]
+    fn rules_apply_when_they_start_to_hold() {
+        let rules = rules();
+        let mut holding = HashSet::new();
+        let mut evaluate =
+            |facts: &Facts, now: &str| names(due(&rules, facts, at(now), &mut holding, false));
+
+        assert_eq!(evaluate(&volume(Some(60)), "17:00"), ["loud"]);
+        assert!(evaluate(&volume(Some(70)), "17:30").is_empty());
+        assert_eq!(evaluate(&volume(Some(70)), "18:00"), ["evening"]);
+        assert!(evaluate(&volume(Some(30)), "18:30").is_empty());
+        assert_eq!(evaluate(&volume(Some(50)), "19:00"), ["loud"]);
+    }
+
+    #[test]
+    fn unknown_rules_keep_their_previous_state() {
+        let rules = rules();
+        let mut holding = HashSet::new();
+
+        let evaluate = |facts: &Facts, now: &str, holding: &mut HashSet<String>| {
+            names(due(&rules, facts, at(now), holding, false))
+        };
+
+        assert_eq!(evaluate(&volume(Some(60)), "12:00", &mut holding), ["loud"]);
+        // A failed read neither applies the rule again nor resets it
+        assert!(evaluate(&volume(None), "12:01", &mut holding).is_empty());
+        assert!(holding.contains("loud"));
+        assert!(evaluate(&volume(Some(60)), "12:02", &mut holding).is_empty());
+
+        // Nor does it count as starting to hold
+        holding.clear();
+        assert!(evaluate(&volume(None), "12:03", &mut holding).is_empty());
+        assert!(holding.is_empty());
+    }
+
+    #[test]
+    fn once_applies_every_rule_that_holds() {
+        let rules = rules();
+        let mut holding = HashSet::from(["loud".to_string(), "evening".to_string()]);
+
+        let applied = due(&rules, &volume(Some(60)), at("20:00"), &mut holding, true);
+        assert_eq!(names(applied), ["loud", "evening"]);
+
+        let applied = due(&rules, &volume(Some(10)), at("08:00"), &mut holding, true);
+        assert!(applied.is_empty());
+    }
+}
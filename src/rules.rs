@@ -0,0 +1,726 @@
+//! Automation rules: conditions on the state of the Mac, and the `mac`
+//! commands to run when they hold.
+//!
+//! Rules live in `~/.config/mac-cli/rules.toml`, applied by `mac rules run`
+//! and tried out by `mac rules test`:
+//!
+//! ```toml
+//! [[rules]]
+//! name = "Quiet nights"
+//! after = "22:00"            # time window, may wrap around midnight
+//! before = "07:00"
+//! bluetooth = "AirPods"      # a connected device whose name contains this
+//! volume = "> 50"            # >, >=, <, <=, == or != and a percentage
+//! actions = ["volume 30"]
+//!
+//! [[rules]]
+//! name = "Rainy music"
+//! weather = "rain"           # the weather condition contains this
+//! music = "stopped"          # playing, paused, stopped or not_running
+//! actions = ["music playlists Rainy"]
+//! ```
+//!
+//! Every condition is optional, and a rule applies when all of its conditions
+//! hold. Other conditions are `muted = true` and `brightness = "< 30"`.
+//! Actions are `mac` command lines without the leading `mac`.
+//!
+//! ```
+//! use chrono::NaiveTime;
+//! use mac_cli::rules::{Facts, RuleSet};
+//!
+//! let rules: RuleSet = toml::from_str(r#"
+//!     [[rules]]
+//!     name = "Quiet nights"
+//!     after = "22:00"
+//!     volume = "> 50"
+//!     actions = ["volume 30"]
+//! "#).unwrap();
+//! let facts: Facts = serde_json::from_str(r#"{"volume": 70}"#).unwrap();
+//!
+//! let at = NaiveTime::from_hms_opt(22, 30, 0).unwrap();
+//! assert!(rules.rules[0].evaluate(&facts, at).applies());
+//! ```
+
+use crate::bluetooth::Device;
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::music::NowPlaying;
+use crate::output::percent;
+use crate::status::Status;
+use crate::weather::Weather;
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Environment variable overriding the rules file path.
+pub const RULES_ENV: &str = "MAC_CLI_RULES";
+
+/// The states a `music` condition can name.
+const MUSIC_STATES: [&str; 4] = ["playing", "paused", "stopped", "not_running"];
+
+/// The rules of a rules file, in order.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+/// Conditions and the commands to run when all of them hold.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Identifies the rule in output and logs.
+    pub name: String,
+    /// The rule applies from this time of day on.
+    pub after: Option<TimeOfDay>,
+    /// The rule applies until this time of day.
+    pub before: Option<TimeOfDay>,
+    /// Part of the name of a connected Bluetooth device, ignoring case.
+    pub bluetooth: Option<String>,
+    /// Player state: `playing`, `paused`, `stopped` or `not_running`.
+    pub music: Option<String>,
+    pub volume: Option<Comparison>,
+    pub muted: Option<bool>,
+    pub brightness: Option<Comparison>,
+    /// Part of the weather condition, ignoring case.
+    pub weather: Option<String>,
+    /// `mac` command lines, without `mac`.
+    pub actions: Vec<String>,
+}
+
+/// A time of day written `HH:MM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay(pub NaiveTime);
+
+/// A condition on a number, such as `> 50`. A bare number means `==`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "ComparisonSpec")]
+pub struct Comparison {
+    pub operator: Operator,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ComparisonSpec {
+    Number(f64),
+    Text(String),
+}
+
+/// The state rules are evaluated against, in the shape of the document
+/// printed by `mac status --json`, which can therefore serve as a fixture.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Facts {
+    /// Volume percentage.
+    pub volume: Option<i64>,
+    pub muted: Option<bool>,
+    /// Brightness percentage of the main display.
+    pub brightness: Option<i64>,
+    /// `None` when the player is not running.
+    pub music: Option<NowPlaying>,
+    /// Bluetooth devices; only connected ones count.
+    pub bluetooth: Option<Vec<Device>>,
+    pub weather: Option<Weather>,
+    /// Why subsystems could not be read, by subsystem.
+    pub errors: BTreeMap<Subsystem, Value>,
+}
+
+/// Whether a rule applies, and why not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    /// The conditions that do not hold, described.
+    pub unmet: Vec<String>,
+    /// The conditions on subsystems that could not be read, described.
+    pub unknown: Vec<String>,
+}
+
+/// What an [`Evaluation`] amounts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every condition holds.
+    Holds,
+    /// A condition does not hold.
+    Unmet,
+    /// No condition is known not to hold, but some could not be checked.
+    Unknown,
+}
+
+impl RuleSet {
+    /// The default rules file, `~/.config/mac-cli/rules.toml`.
+    pub fn default_path() -> PathBuf {
+        crate::paths::config_dir().join("rules.toml")
+    }
+
+    /// Loads and checks the rules file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`NotFound`](ErrorKind::NotFound) error if the file does not
+    /// exist, and a [`Parse`](ErrorKind::Parse) error if it is invalid, names
+    /// two rules alike or names an unknown player state.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            let kind = match e.kind() {
+                std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+                _ => ErrorKind::Other,
+            };
+            MacCliError::new(
+                Subsystem::Cli,
+                kind,
+                format!("Failed to read rules {}", path.display()),
+            )
+            .with_source(e)
+        })?;
+
+        let invalid = |message: String| {
+            MacCliError::new(
+                Subsystem::Cli,
+                ErrorKind::Parse,
+                format!("Invalid rules in {}: {}", path.display(), message),
+            )
+        };
+        let rules: RuleSet =
+            toml::from_str(&contents).map_err(|e| invalid(e.message().to_string()))?;
+        for (i, rule) in rules.rules.iter().enumerate() {
+            if rules.rules[..i].iter().any(|r| r.name == rule.name) {
+                return Err(invalid(format!("two rules are named {:?}", rule.name)));
+            }
+            if rule.actions.is_empty() {
+                return Err(invalid(format!("rule {:?} has no actions", rule.name)));
+            }
+            if let Some(music) = &rule.music
+                && !MUSIC_STATES.iter().any(|s| s.eq_ignore_ascii_case(music))
+            {
+                return Err(invalid(format!(
+                    "rule {:?} has an unknown music state {:?}, expected one of {}",
+                    rule.name,
+                    music,
+                    MUSIC_STATES.join(", ")
+                )));
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Returns the subsystems the conditions of the rules depend on.
+    pub fn subsystems(&self) -> Vec<Subsystem> {
+        let mut subsystems: Vec<Subsystem> = self.rules.iter().flat_map(Rule::subsystems).collect();
+        subsystems.sort();
+        subsystems.dedup();
+        subsystems
+    }
+}
+
+impl Rule {
+    /// Evaluates the conditions against `facts` at the time of day `now`.
+    ///
+    /// A condition on a subsystem missing from `facts` is unknown, and so is
+    /// a `music` condition when `facts` has an error for the player.
+    pub fn evaluate(&self, facts: &Facts, now: NaiveTime) -> Evaluation {
+        let mut unmet = Vec::new();
+        let mut unknown = Vec::new();
+
+        let time = now.format("%H:%M");
+        let outside = match (self.after, self.before) {
+            (Some(after), Some(before)) => {
+                // A window that ends before it starts wraps around midnight
+                let inside = match after <= before {
+                    true => after.0 <= now && now < before.0,
+                    false => now >= after.0 || now < before.0,
+                };
+                (!inside).then(|| format!("{} is not between {} and {}", time, after, before))
+            }
+            (Some(after), None) => (now < after.0).then(|| format!("{} is before {}", time, after)),
+            (None, Some(before)) => {
+                (now >= before.0).then(|| format!("{} is not before {}", time, before))
+            }
+            (None, None) => None,
+        };
+        unmet.extend(outside);
+
+        if let Some(pattern) = &self.bluetooth {
+            match &facts.bluetooth {
+                None => unknown.push("Bluetooth devices are unknown".to_string()),
+                Some(devices) => {
+                    let found = devices
+                        .iter()
+                        .any(|d| d.connected && contains(&d.name, pattern));
+                    if !found {
+                        unmet.push(format!(
+                            "no connected Bluetooth device matches {:?}",
+                            pattern
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Some(expected) = &self.music {
+            // Without music, the player is not running unless reading it failed
+            let state = match &facts.music {
+                Some(music) => Some(music.state.as_str()),
+                None if facts.errors.contains_key(&Subsystem::Music) => None,
+                None => Some("not_running"),
+            };
+            match state {
+                None => unknown.push("the player state is unknown".to_string()),
+                Some(state) if !state.eq_ignore_ascii_case(expected) => {
+                    unmet.push(format!("the player is {}, not {}", state, expected));
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (label, comparison, value) in [
+            ("volume", &self.volume, facts.volume),
+            ("brightness", &self.brightness, facts.brightness),
+        ] {
+            match (comparison, value) {
+                (None, _) => {}
+                (Some(_), None) => unknown.push(format!("{} is unknown", label)),
+                (Some(comparison), Some(value)) if !comparison.matches(value as f64) => {
+                    unmet.push(format!("{} {} is not {}", label, value, comparison));
+                }
+                (Some(_), Some(_)) => {}
+            }
+        }
+
+        if let Some(expected) = self.muted {
+            match facts.muted {
+                None => unknown.push("muting is unknown".to_string()),
+                Some(true) if !expected => unmet.push("the output is muted".to_string()),
+                Some(false) if expected => unmet.push("the output is not muted".to_string()),
+                Some(_) => {}
+            }
+        }
+
+        if let Some(pattern) = &self.weather {
+            match &facts.weather {
+                None => unknown.push("the weather is unknown".to_string()),
+                Some(weather) if !contains(&weather.condition, pattern) => {
+                    unmet.push(format!(
+                        "the weather is {:?}, not {:?}",
+                        weather.condition, pattern
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+
+        Evaluation { unmet, unknown }
+    }
+
+    /// Returns the subsystems the conditions depend on.
+    pub fn subsystems(&self) -> Vec<Subsystem> {
+        let mut subsystems = Vec::new();
+        if self.volume.is_some() || self.muted.is_some() {
+            subsystems.push(Subsystem::Volume);
+        }
+        if self.brightness.is_some() {
+            subsystems.push(Subsystem::Brightness);
+        }
+        if self.music.is_some() {
+            subsystems.push(Subsystem::Music);
+        }
+        if self.bluetooth.is_some() {
+            subsystems.push(Subsystem::Bluetooth);
+        }
+        if self.weather.is_some() {
+            subsystems.push(Subsystem::Weather);
+        }
+        subsystems
+    }
+}
+
+impl Evaluation {
+    /// Whether every condition holds.
+    pub fn applies(&self) -> bool {
+        self.outcome() == Outcome::Holds
+    }
+
+    /// A condition that does not hold wins over one that is unknown.
+    pub fn outcome(&self) -> Outcome {
+        match (self.unmet.is_empty(), self.unknown.is_empty()) {
+            (false, _) => Outcome::Unmet,
+            (true, false) => Outcome::Unknown,
+            (true, true) => Outcome::Holds,
+        }
+    }
+}
+
+impl Facts {
+    /// The facts of a status read by [`StatusQuery`](crate::status::StatusQuery).
+    pub fn from_status(status: &Status) -> Self {
+        Facts {
+            volume: status.volume.map(percent),
+            muted: status.muted,
+            brightness: status.brightness.map(percent),
+            music: status.music.clone(),
+            bluetooth: status.bluetooth.clone(),
+            weather: status.weather.clone(),
+            errors: status
+                .errors
+                .iter()
+                .map(|(subsystem, e)| (*subsystem, e.to_json()["error"].clone()))
+                .collect(),
+        }
+    }
+}
+
+impl Comparison {
+    pub fn matches(&self, value: f64) -> bool {
+        match self.operator {
+            Operator::Greater => value > self.value,
+            Operator::GreaterOrEqual => value >= self.value,
+            Operator::Less => value < self.value,
+            Operator::LessOrEqual => value <= self.value,
+            Operator::Equal => value == self.value,
+            Operator::NotEqual => value != self.value,
+        }
+    }
+}
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.operator.as_str(), self.value)
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.format("%H:%M"))
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, String> {
+        NaiveTime::parse_from_str(value.trim(), "%H:%M")
+            .map(TimeOfDay)
+            .map_err(|_| format!("invalid time {:?}, expected HH:MM", value))
+    }
+}
+
+impl TryFrom<ComparisonSpec> for Comparison {
+    type Error = String;
+
+    fn try_from(spec: ComparisonSpec) -> std::result::Result<Self, String> {
+        let text = match spec {
+            ComparisonSpec::Number(value) => {
+                return Ok(Comparison {
+                    operator: Operator::Equal,
+                    value,
+                });
+            }
+            ComparisonSpec::Text(text) => text,
+        };
+
+        // Two-character operators first, so `>=` is not read as `>`
+        let operators = [
+            (">=", Operator::GreaterOrEqual),
+            ("<=", Operator::LessOrEqual),
+            ("==", Operator::Equal),
+            ("!=", Operator::NotEqual),
+            (">", Operator::Greater),
+            ("<", Operator::Less),
+            ("=", Operator::Equal),
+        ];
+        let trimmed = text.trim();
+        let (operator, rest) = operators
+            .iter()
+            .find_map(|(symbol, operator)| {
+                trimmed.strip_prefix(symbol).map(|rest| (*operator, rest))
+            })
+            .unwrap_or((Operator::Equal, trimmed));
+        let value = rest
+            .trim()
+            .trim_end_matches('%')
+            .parse()
+            .map_err(|_| format!("invalid comparison {:?}, expected e.g. \"> 50\"", text))?;
+        Ok(Comparison { operator, value })
+    }
+}
+
+fn contains(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::music::PlayerState;
+    use crate::test_support::temp_path;
+    use serde_json::json;
+
+    fn rule(conditions: &str) -> Rule {
+        let rules: RuleSet = toml::from_str(&format!(
+            "[[rules]]\nname = \"test\"\nactions = [\"volume 30\"]\n{}",
+            conditions
+        ))
+        .unwrap();
+        rules.rules.into_iter().next().unwrap()
+    }
+
+    fn at(time: &str) -> NaiveTime {
+        TimeOfDay::try_from(time.to_string()).unwrap().0
+    }
+
+    fn comparison(text: &str) -> std::result::Result<Comparison, String> {
+        Comparison::try_from(ComparisonSpec::Text(text.to_string()))
+    }
+
+    fn device(name: &str, connected: bool) -> Device {
+        Device {
+            name: name.to_string(),
+            connected,
+            address: None,
+            battery: None,
+        }
+    }
+
+    fn load(name: &str, contents: &str) -> Result<RuleSet> {
+        let path = temp_path("rules", name, "toml");
+        std::fs::write(&path, contents).unwrap();
+        let rules = RuleSet::load(&path);
+        std::fs::remove_file(&path).ok();
+        rules
+    }
+
+    #[test]
+    fn comparisons_read_two_character_operators_first() {
+        let cases = [
+            (">= 50", Operator::GreaterOrEqual, 50.0),
+            (">50", Operator::Greater, 50.0),
+            ("<= 5%", Operator::LessOrEqual, 5.0),
+            ("< 30", Operator::Less, 30.0),
+            ("!= 0", Operator::NotEqual, 0.0),
+            ("== 40", Operator::Equal, 40.0),
+            ("= 40", Operator::Equal, 40.0),
+            (" 75% ", Operator::Equal, 75.0),
+            ("12.5", Operator::Equal, 12.5),
+        ];
+        for (text, operator, value) in cases {
+            assert_eq!(
+                comparison(text),
+                Ok(Comparison { operator, value }),
+                "{:?}",
+                text
+            );
+        }
+
+        assert!(comparison(">= 50").unwrap().matches(50.0));
+        assert!(!comparison("> 50").unwrap().matches(50.0));
+        assert_eq!(rule("volume = 50").volume, comparison("== 50").ok());
+    }
+
+    #[test]
+    fn invalid_comparisons_are_rejected() {
+        for text in ["loud", ">", "=> 5", "50 %%x", ""] {
+            let err = comparison(text).unwrap_err();
+            assert!(err.contains("invalid comparison"), "{:?}: {}", text, err);
+        }
+        assert!(
+            toml::from_str::<RuleSet>(
+                "[[rules]]\nname = \"a\"\nactions = [\"volume 30\"]\nvolume = \"high\""
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn time_windows() {
+        let facts = Facts::default();
+        let applies = |rule: &Rule, time: &str| rule.evaluate(&facts, at(time)).applies();
+
+        let night = rule("after = \"22:00\"\nbefore = \"07:00\"");
+        for (time, expected) in [
+            ("22:00", true),
+            ("23:59", true),
+            ("00:00", true),
+            ("06:59", true),
+            ("07:00", false),
+            ("12:00", false),
+            ("21:59", false),
+        ] {
+            assert_eq!(applies(&night, time), expected, "night at {}", time);
+        }
+
+        let day = rule("after = \"09:00\"\nbefore = \"17:00\"");
+        assert!(applies(&day, "09:00"));
+        assert!(!applies(&day, "17:00"));
+        assert!(!applies(&day, "08:59"));
+
+        // An empty window
+        let never = rule("after = \"12:00\"\nbefore = \"12:00\"");
+        for time in ["00:00", "11:59", "12:00", "12:01"] {
+            assert!(!applies(&never, time), "empty window at {}", time);
+        }
+
+        let evening = rule("after = \"18:00\"");
+        assert!(!applies(&evening, "17:59"));
+        assert!(applies(&evening, "18:00"));
+        assert!(applies(&evening, "23:59"));
+
+        let morning = rule("before = \"09:00\"");
+        assert!(applies(&morning, "00:00"));
+        assert!(applies(&morning, "08:59"));
+        assert!(!applies(&morning, "09:00"));
+        assert_eq!(
+            morning.evaluate(&facts, at("10:00")).unmet,
+            ["10:00 is not before 09:00"]
+        );
+    }
+
+    #[test]
+    fn bluetooth_matches_connected_devices_ignoring_case() {
+        let facts = Facts {
+            bluetooth: Some(vec![
+                device("AirPods Pro", false),
+                device("Magic Keyboard", true),
+            ]),
+            ..Facts::default()
+        };
+        let now = at("12:00");
+
+        assert!(
+            rule("bluetooth = \"KEYBOARD\"")
+                .evaluate(&facts, now)
+                .applies()
+        );
+        assert_eq!(
+            rule("bluetooth = \"airpods\"").evaluate(&facts, now).unmet,
+            ["no connected Bluetooth device matches \"airpods\""]
+        );
+        assert_eq!(
+            rule("bluetooth = \"airpods\"")
+                .evaluate(&Facts::default(), now)
+                .outcome(),
+            Outcome::Unknown
+        );
+    }
+
+    #[test]
+    fn a_missing_player_is_not_running_unless_reading_it_failed() {
+        let now = at("12:00");
+        let quit = Facts::default();
+        assert!(
+            rule("music = \"not_running\"")
+                .evaluate(&quit, now)
+                .applies()
+        );
+        assert_eq!(
+            rule("music = \"stopped\"").evaluate(&quit, now).unmet,
+            ["the player is not_running, not stopped"]
+        );
+
+        let failed = Facts {
+            errors: BTreeMap::from([(Subsystem::Music, json!({ "kind": "timeout" }))]),
+            ..Facts::default()
+        };
+        let evaluation = rule("music = \"not_running\"").evaluate(&failed, now);
+        assert_eq!(evaluation.outcome(), Outcome::Unknown);
+        assert_eq!(evaluation.unknown, ["the player state is unknown"]);
+
+        let playing = Facts {
+            music: Some(NowPlaying {
+                state: PlayerState::Playing,
+                track: None,
+            }),
+            ..Facts::default()
+        };
+        assert!(
+            rule("music = \"Playing\"")
+                .evaluate(&playing, now)
+                .applies()
+        );
+    }
+
+    #[test]
+    fn unmet_conditions_win_over_unknown_ones() {
+        let rule = rule("volume = \"> 50\"\nweather = \"rain\"");
+        let now = at("12:00");
+
+        let quiet = Facts {
+            volume: Some(40),
+            ..Facts::default()
+        };
+        assert_eq!(rule.evaluate(&quiet, now).outcome(), Outcome::Unmet);
+
+        let loud = Facts {
+            volume: Some(70),
+            ..Facts::default()
+        };
+        let evaluation = rule.evaluate(&loud, now);
+        assert_eq!(evaluation.outcome(), Outcome::Unknown);
+        assert!(!evaluation.applies());
+        assert_eq!(evaluation.unknown, ["the weather is unknown"]);
+    }
+
+    #[test]
+    fn status_documents_carry_their_errors() {
+        let facts: Facts = serde_json::from_str(
+            r#"{"volume": 40, "music": null, "errors": {"music": {"kind": "timeout"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(facts.volume, Some(40));
+        assert!(facts.errors.contains_key(&Subsystem::Music));
+    }
+
+    #[test]
+    fn load_checks_names_actions_and_player_states() {
+        let valid = "[[rules]]\nname = \"a\"\nmusic = \"not_running\"\nactions = [\"volume 30\"]\n";
+        assert_eq!(load("valid", valid).unwrap().rules.len(), 1);
+
+        let duplicate = format!("{}{}", valid, valid);
+        let no_actions = "[[rules]]\nname = \"a\"\nactions = []\n";
+        let typo = "[[rules]]\nname = \"a\"\nmusic = \"playng\"\nactions = [\"volume 30\"]\n";
+        for (name, contents, reason) in [
+            ("duplicate", duplicate.as_str(), "two rules are named \"a\""),
+            ("no-actions", no_actions, "rule \"a\" has no actions"),
+            ("typo", typo, "unknown music state \"playng\""),
+            (
+                "unknown-field",
+                "[[rules]]\nname = \"a\"\nvolumes = 5\n",
+                "volumes",
+            ),
+        ] {
+            let err = load(name, contents).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Parse, "{}", name);
+            assert!(err.message().contains(reason), "{}: {}", name, err);
+        }
+
+        let missing = temp_path("rules", "missing", "toml");
+        assert_eq!(
+            RuleSet::load(&missing).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,509 @@
+//! Execution of external commands.
+//!
+//! Every controller that shells out (`osascript`, `system_profiler`, `curl`, `fzf`)
+//! goes through a [`CommandRunner`]. The default [`SystemRunner`] spawns real
+//! processes; [`FakeRunner`] replays scripted responses so that parsing and error
+//! handling can be exercised on machines without those tools.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use mac_cli::VolumeController;
+//! use mac_cli::runner::{CommandOutput, Expectation, FakeRunner};
+//!
+//! let fake = Arc::new(FakeRunner::new().expect(
+//!     Expectation::new("osascript")
+//!         .args(["-e", "output volume of (get volume settings)"])
+//!         .returns(CommandOutput::success("42\n")),
+//! ));
+//!
+//! let volume = VolumeController::with_runner(fake.clone());
+//! assert_eq!(volume.get().unwrap(), 0.42);
+//! fake.assert_done();
+//! ```
+
+use crate::error::{MacCliError, Result, Subsystem};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// A command to execute: program, arguments and optional stdin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSpec {
+    program: String,
+    args: Vec<String>,
+    stdin: Option<String>,
+    interactive: bool,
+}
+
+impl CommandSpec {
+    /// Creates a command that runs `program` with no arguments.
+    pub fn new(program: impl Into<String>) -> Self {
+        CommandSpec {
+            program: program.into(),
+            args: Vec::new(),
+            stdin: None,
+            interactive: false,
+        }
+    }
+
+    /// Appends an argument.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Appends several arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets the data written to the command's stdin.
+    pub fn stdin(mut self, input: impl Into<String>) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    /// Marks the command as interactive: its stderr is inherited from the
+    /// terminal instead of captured (used for fzf's UI).
+    pub fn interactive(mut self) -> Self {
+        self.interactive = true;
+        self
+    }
+
+    /// The program to execute.
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    /// The arguments passed to the program.
+    pub fn get_args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// The data written to stdin, if any.
+    pub fn get_stdin(&self) -> Option<&str> {
+        self.stdin.as_deref()
+    }
+
+    /// Whether stderr is inherited from the terminal.
+    pub fn is_interactive(&self) -> bool {
+        self.interactive
+    }
+}
+
+impl fmt::Display for CommandSpec {
+    /// Formats the command as a shell-quoted command line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&shell_quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", shell_quote(arg))?;
+        }
+        Ok(())
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    let safe = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c));
+    if safe {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+/// The result of running a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// Exit code, or `None` if the process was terminated by a signal.
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    /// A successful exit with the given stdout.
+    pub fn success(stdout: impl Into<String>) -> Self {
+        CommandOutput {
+            status: Some(0),
+            stdout: stdout.into(),
+            stderr: String::new(),
+        }
+    }
+
+    /// A failed exit with the given status code and stderr.
+    pub fn failure(status: i32, stderr: impl Into<String>) -> Self {
+        CommandOutput {
+            status: Some(status),
+            stdout: String::new(),
+            stderr: stderr.into(),
+        }
+    }
+
+    /// Returns `true` if the command exited with status 0.
+    pub fn is_success(&self) -> bool {
+        self.status == Some(0)
+    }
+}
+
+/// Executes commands on behalf of the controllers.
+pub trait CommandRunner: Send + Sync {
+    /// Runs `command` to completion.
+    ///
+    /// Returns an `io::Error` only if the command could not be started or
+    /// communicated with; a non-zero exit is reported through [`CommandOutput::status`].
+    fn run(&self, command: &CommandSpec) -> io::Result<CommandOutput>;
+}
+
+/// Runs commands as real child processes.
+///
+/// Every spawn is logged at `debug` level with its command line, exit status,
+/// stderr and duration; stdin and stdout are logged at `trace` level.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, command: &CommandSpec) -> io::Result<CommandOutput> {
+        let started = Instant::now();
+        tracing::debug!(command = %command, "spawning process");
+        if let Some(input) = &command.stdin {
+            tracing::trace!(stdin = %input, "process stdin");
+        }
+
+        let result = Self::spawn(command);
+        let elapsed = started.elapsed();
+
+        match &result {
+            Ok(output) => {
+                tracing::debug!(
+                    program = %command.program,
+                    status = ?output.status,
+                    stderr = %output.stderr.trim(),
+                    elapsed = ?elapsed,
+                    "process exited"
+                );
+                tracing::trace!(stdout = %output.stdout.trim(), "process stdout");
+            }
+            Err(e) => {
+                tracing::debug!(program = %command.program, error = %e, elapsed = ?elapsed, "spawn failed");
+            }
+        }
+
+        result
+    }
+}
+
+impl SystemRunner {
+    fn spawn(command: &CommandSpec) -> io::Result<CommandOutput> {
+        let mut cmd = Command::new(&command.program);
+        cmd.args(&command.args)
+            .stdout(Stdio::piped())
+            .stderr(if command.interactive {
+                Stdio::inherit()
+            } else {
+                Stdio::piped()
+            })
+            .stdin(if command.stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            });
+
+        let mut child = cmd.spawn()?;
+
+        if let (Some(input), Some(mut stdin)) = (&command.stdin, child.stdin.take()) {
+            stdin.write_all(input.as_bytes())?;
+        }
+
+        let output = child.wait_with_output()?;
+
+        Ok(CommandOutput {
+            status: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+}
+
+/// Returns the runner used when none is specified.
+pub fn system() -> Arc<dyn CommandRunner> {
+    Arc::new(SystemRunner)
+}
+
+/// A scripted response for [`FakeRunner`].
+#[derive(Debug)]
+pub struct Expectation {
+    program: String,
+    args: Option<Vec<String>>,
+    stdin: Option<String>,
+    response: Response,
+}
+
+#[derive(Debug)]
+enum Response {
+    Output(CommandOutput),
+    SpawnError(io::ErrorKind),
+}
+
+impl Expectation {
+    /// Expects a call to `program`. By default any arguments and stdin match
+    /// and the command succeeds with empty output.
+    pub fn new(program: impl Into<String>) -> Self {
+        Expectation {
+            program: program.into(),
+            args: None,
+            stdin: None,
+            response: Response::Output(CommandOutput::success("")),
+        }
+    }
+
+    /// Requires the arguments to equal `args` exactly.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = Some(args.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Requires stdin to equal `input` exactly.
+    pub fn stdin(mut self, input: impl Into<String>) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    /// Responds with `output`.
+    pub fn returns(mut self, output: CommandOutput) -> Self {
+        self.response = Response::Output(output);
+        self
+    }
+
+    /// Fails to spawn with an I/O error of the given kind, e.g.
+    /// [`io::ErrorKind::NotFound`] to simulate a missing executable.
+    pub fn spawn_error(mut self, kind: io::ErrorKind) -> Self {
+        self.response = Response::SpawnError(kind);
+        self
+    }
+
+    fn matches(&self, command: &CommandSpec) -> bool {
+        self.program == command.program
+            && self.args.as_ref().is_none_or(|a| *a == command.args)
+            && self
+                .stdin
+                .as_ref()
+                .is_none_or(|s| Some(s) == command.stdin.as_ref())
+    }
+}
+
+/// A [`CommandRunner`] that replays a fixed sequence of [`Expectation`]s.
+///
+/// Commands must arrive in the order the expectations were added. An
+/// unexpected command panics with a description of what was expected.
+#[derive(Debug, Default)]
+pub struct FakeRunner {
+    expectations: Mutex<VecDeque<Expectation>>,
+    calls: Mutex<Vec<CommandSpec>>,
+}
+
+impl FakeRunner {
+    /// Creates a runner with no expectations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an expectation to the end of the script.
+    pub fn expect(self, expectation: Expectation) -> Self {
+        self.expectations.lock().unwrap().push_back(expectation);
+        self
+    }
+
+    /// Returns every command run so far.
+    pub fn calls(&self) -> Vec<CommandSpec> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Panics if any expectation has not been consumed.
+    pub fn assert_done(&self) {
+        let remaining = self.expectations.lock().unwrap();
+        if let Some(next) = remaining.front() {
+            panic!(
+                "FakeRunner: {} expected command(s) were never run, next: {:?}",
+                remaining.len(),
+                next
+            );
+        }
+    }
+}
+
+impl CommandRunner for FakeRunner {
+    fn run(&self, command: &CommandSpec) -> io::Result<CommandOutput> {
+        self.calls.lock().unwrap().push(command.clone());
+
+        let expectation = self.expectations.lock().unwrap().pop_front();
+        let expectation = match expectation {
+            Some(e) if e.matches(command) => e,
+            Some(e) => panic!(
+                "FakeRunner: unexpected command {}\nexpected: {:?}",
+                command, e
+            ),
+            None => panic!(
+                "FakeRunner: unexpected command {} (no expectations left)",
+                command
+            ),
+        };
+
+        match expectation.response {
+            Response::Output(output) => Ok(output),
+            Response::SpawnError(kind) => Err(io::Error::new(kind, "simulated spawn failure")),
+        }
+    }
+}
+
+/// Runs `command`, mapping spawn failures and non-zero exits to [`MacCliError`].
+///
+/// `describe` turns the stderr of a failed command into an error.
+pub(crate) fn run_checked(
+    runner: &dyn CommandRunner,
+    subsystem: Subsystem,
+    command: &CommandSpec,
+    describe: impl FnOnce(&CommandOutput) -> MacCliError,
+) -> Result<CommandOutput> {
+    let output = runner
+        .run(command)
+        .map_err(|e| MacCliError::spawn(subsystem, command.program(), e))?;
+
+    if !output.is_success() {
+        return Err(describe(&output));
+    }
+
+    Ok(output)
+}
+
+/// Runs an AppleScript through `osascript -e` and returns its trimmed stdout.
+pub(crate) fn run_applescript(
+    runner: &dyn CommandRunner,
+    subsystem: Subsystem,
+    script: &str,
+) -> Result<String> {
+    let command = CommandSpec::new("osascript").arg("-e").arg(script);
+    let output = run_checked(runner, subsystem, &command, |o| {
+        MacCliError::script(subsystem, &o.stderr)
+    })?;
+
+    Ok(output.stdout.trim().to_string())
+}
+
+/// Quotes `value` as an AppleScript string literal, escaping backslashes and
+/// double quotes.
+///
+/// Control characters, which no application or playlist name contains, are
+/// rejected.
+pub(crate) fn applescript_string(subsystem: Subsystem, value: &str) -> Result<String> {
+    if let Some(c) = value.chars().find(|c| c.is_control()) {
+        return Err(MacCliError::invalid_argument(
+            subsystem,
+            format!(
+                "Invalid name {:?}: contains the control character {:?}",
+                value, c
+            ),
+        ));
+    }
+
+    Ok(format!(
+        "\"{}\"",
+        value.replace('\\', "\\\\").replace('"', "\\\"")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn failed(o: &CommandOutput) -> MacCliError {
+        MacCliError::new(Subsystem::Weather, ErrorKind::Network, o.stderr.trim())
+    }
+
+    #[test]
+    fn command_line_quotes_only_unsafe_words() {
+        let command =
+            CommandSpec::new("curl").args(["-s", "--", "https://wttr.in/Paris?format=3&m"]);
+        assert_eq!(
+            command.to_string(),
+            "curl -s -- 'https://wttr.in/Paris?format=3&m'"
+        );
+
+        let command = CommandSpec::new("say").args(["", "it's", "a b"]);
+        assert_eq!(command.to_string(), r"say '' 'it'\''s' 'a b'");
+    }
+
+    #[test]
+    fn run_checked_maps_spawn_errors_and_failures() {
+        let runner = FakeRunner::new()
+            .expect(Expectation::new("curl").spawn_error(io::ErrorKind::NotFound))
+            .expect(Expectation::new("curl").returns(CommandOutput::failure(6, "no route\n")))
+            .expect(Expectation::new("curl").returns(CommandOutput::success("ok")));
+        let command = CommandSpec::new("curl");
+
+        let err = run_checked(&runner, Subsystem::Weather, &command, failed).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::CommandNotFound);
+        assert_eq!(err.subsystem(), Subsystem::Weather);
+
+        let err = run_checked(&runner, Subsystem::Weather, &command, failed).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Network);
+        assert_eq!(err.message(), "no route");
+
+        let output = run_checked(&runner, Subsystem::Weather, &command, failed).unwrap();
+        assert_eq!(output.stdout, "ok");
+        runner.assert_done();
+    }
+
+    #[test]
+    fn run_applescript_trims_output() {
+        let runner = FakeRunner::new().expect(
+            Expectation::new("osascript")
+                .args(["-e", "get volume settings"])
+                .returns(CommandOutput::success("  42\n")),
+        );
+
+        let output = run_applescript(&runner, Subsystem::Volume, "get volume settings").unwrap();
+        assert_eq!(output, "42");
+    }
+
+    #[test]
+    fn applescript_string_escapes_quotes_and_backslashes() {
+        assert_eq!(
+            applescript_string(Subsystem::Music, "Focus").unwrap(),
+            r#""Focus""#
+        );
+        assert_eq!(
+            applescript_string(Subsystem::Music, r#"Say "hi" \o/"#).unwrap(),
+            r#""Say \"hi\" \\o/""#
+        );
+    }
+
+    #[test]
+    fn applescript_string_rejects_control_characters() {
+        for name in ["line\nbreak", "tab\there", "nul\0"] {
+            let err = applescript_string(Subsystem::Music, name).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected command osascript")]
+    fn fake_runner_rejects_unexpected_commands() {
+        let runner = FakeRunner::new().expect(Expectation::new("curl"));
+        let _ = runner.run(&CommandSpec::new("osascript"));
+    }
+}
@@ -0,0 +1,257 @@
+//! Scenes: named combinations of settings applied in one command.
+//!
+//! Scenes are stored in the `[scenes]` section of the configuration file. Every
+//! setting is optional; only the ones present are applied:
+//!
+//! ```toml
+//! [scenes.focus]
+//! volume = 30              # percent
+//! brightness = 60          # percent
+//! shuffle = true
+//! playlist = "Focus"       # starts playing this playlist
+//! music = "play"           # "play" or "pause"
+//!
+//! [scenes.meeting]
+//! volume = 60
+//! music = "pause"
+//! ```
+
+use crate::backend::Backend;
+use crate::brightness::{self, BrightnessController};
+use crate::error::{ErrorKind, Result};
+use crate::music::{MusicController, PlayerState};
+use crate::output::percent;
+use crate::volume::VolumeController;
+use serde::{Deserialize, Serialize};
+
+/// A named set of settings. Fields left as `None` are not touched.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scene {
+    /// Output volume in percent (0-100).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u8>,
+    /// Display brightness in percent (0-100).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<u8>,
+    /// Whether the player shuffles.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shuffle: Option<bool>,
+    /// Playlist to start playing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub playlist: Option<String>,
+    /// Whether playback should be running afterwards.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub music: Option<Playback>,
+}
+
+/// The playback state a scene leaves the player in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Playback {
+    Play,
+    Pause,
+}
+
+impl Scene {
+    /// Captures the current volume, brightness and player state.
+    ///
+    /// Brightness is skipped where it is unavailable, and the music settings
+    /// are skipped when the player is not running.
+    pub fn capture(backend: &Backend, music: &MusicController) -> Result<Self> {
+        let volume = VolumeController::with_backend(backend.clone()).get()?;
+        let brightness =
+            optional(BrightnessController::with_backend(backend).and_then(|c| c.get()))?;
+
+        let mut scene = Scene {
+            volume: Some(percent(volume) as u8),
+            brightness: brightness.map(|b| percent(b) as u8),
+            ..Scene::default()
+        };
+
+        if let Some(now) = optional(music.now_playing())? {
+            scene.shuffle = Some(music.shuffle()?);
+            scene.playlist = music.current_playlist()?;
+            scene.music = Some(match now.state {
+                PlayerState::Playing => Playback::Play,
+                PlayerState::Paused | PlayerState::Stopped => Playback::Pause,
+            });
+        }
+
+        Ok(scene)
+    }
+
+    /// Applies every setting present in the scene, stopping at the first error.
+    ///
+    /// The brightness is checked like `mac brightness N` checks it, against
+    /// `min_brightness` in percent.
+    pub fn apply(
+        &self,
+        backend: &Backend,
+        music: &MusicController,
+        min_brightness: f32,
+    ) -> Result<()> {
+        if let Some(brightness) = self.brightness {
+            brightness::check_percentage(f32::from(brightness), min_brightness)?;
+        }
+        if let Some(volume) = self.volume {
+            VolumeController::with_backend(backend.clone()).set(f32::from(volume) / 100.0)?;
+        }
+        if let Some(brightness) = self.brightness {
+            BrightnessController::with_backend(backend)?.set(f32::from(brightness) / 100.0)?;
+        }
+        if let Some(shuffle) = self.shuffle {
+            music.set_shuffle(shuffle)?;
+        }
+        if let Some(playlist) = &self.playlist {
+            music.play_playlist(playlist)?;
+        }
+        match self.music {
+            // Starting a playlist already started playback
+            Some(Playback::Play) if self.playlist.is_none() => music.play()?,
+            Some(Playback::Pause) => music.pause()?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Describes the settings, e.g. `volume 30%, shuffle on, playlist Focus`.
+    pub fn summary(&self) -> String {
+        let on_off = |b: bool| if b { "on" } else { "off" };
+
+        let mut parts = Vec::new();
+        if let Some(volume) = self.volume {
+            parts.push(format!("volume {}%", volume));
+        }
+        if let Some(brightness) = self.brightness {
+            parts.push(format!("brightness {}%", brightness));
+        }
+        if let Some(shuffle) = self.shuffle {
+            parts.push(format!("shuffle {}", on_off(shuffle)));
+        }
+        if let Some(playlist) = &self.playlist {
+            parts.push(format!("playlist {}", playlist));
+        }
+        match self.music {
+            Some(Playback::Play) => parts.push("music playing".to_string()),
+            Some(Playback::Pause) => parts.push("music paused".to_string()),
+            None => {}
+        }
+
+        if parts.is_empty() {
+            "no settings".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+/// Turns "not available here" errors into `None`.
+fn optional<T>(result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if matches!(e.kind(), ErrorKind::Unavailable | ErrorKind::AppNotRunning) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Subsystem;
+    use crate::sim::{SimState, SimStore};
+    use crate::test_support::{remove_with_lock, temp_path};
+
+    fn sim(name: &str) -> (SimStore, Backend, MusicController) {
+        let store = SimStore::new(temp_path("scene", name, "json"));
+        let backend = Backend::Sim(store.clone());
+        let music = MusicController::with_backend(backend.clone());
+        (store, backend, music)
+    }
+
+    fn finish(store: &SimStore) -> SimState {
+        let state = store.load(Subsystem::Cli).unwrap();
+        remove_with_lock(store.path());
+        state
+    }
+
+    #[test]
+    fn apply_starts_the_playlist_before_pausing() {
+        let (store, backend, music) = sim("pause");
+        let scene = Scene {
+            volume: Some(30),
+            brightness: Some(60),
+            shuffle: Some(true),
+            playlist: Some("Focus".to_string()),
+            music: Some(Playback::Pause),
+        };
+
+        scene.apply(&backend, &music, 10.0).unwrap();
+        let captured = Scene::capture(&backend, &music).unwrap();
+        let state = finish(&store);
+
+        assert_eq!(captured, scene);
+        assert_eq!(state.music.state, PlayerState::Paused);
+        assert_eq!(state.music.current_playlist.as_deref(), Some("Focus"));
+        assert_eq!(state.volume, 0.3);
+        assert_eq!(state.brightness, 0.6);
+    }
+
+    #[test]
+    fn apply_plays_without_a_playlist() {
+        let (store, backend, music) = sim("play");
+        let scene = Scene {
+            music: Some(Playback::Play),
+            ..Scene::default()
+        };
+
+        scene.apply(&backend, &music, 10.0).unwrap();
+        let state = finish(&store);
+
+        assert_eq!(state.music.state, PlayerState::Playing);
+        assert_eq!(state.volume, SimState::default().volume);
+    }
+
+    #[test]
+    fn brightness_below_the_minimum_changes_nothing() {
+        let (store, backend, music) = sim("min");
+        let scene = Scene {
+            volume: Some(80),
+            brightness: Some(5),
+            music: Some(Playback::Play),
+            ..Scene::default()
+        };
+
+        let err = scene.apply(&backend, &music, 10.0).unwrap_err();
+        let state = finish(&store);
+
+        assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+        assert_eq!(err.subsystem(), Subsystem::Brightness);
+        assert_eq!(state, SimState::default());
+    }
+
+    #[test]
+    fn capture_leaves_out_a_player_that_is_not_running() {
+        let (store, backend, music) = sim("not-running");
+        store
+            .update(Subsystem::Music, |state| {
+                state.music.running = false;
+                state.volume = 0.42;
+                Ok(())
+            })
+            .unwrap();
+
+        let scene = Scene::capture(&backend, &music).unwrap();
+        finish(&store);
+
+        assert_eq!(
+            scene,
+            Scene {
+                volume: Some(42),
+                brightness: Some(75),
+                ..Scene::default()
+            }
+        );
+        assert_eq!(scene.summary(), "volume 42%, brightness 75%");
+    }
+}
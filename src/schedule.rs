@@ -0,0 +1,688 @@
+//! Scheduled jobs: `mac` commands that run at set times.
+//!
+//! Jobs are kept in `~/.config/mac-cli/schedule.json`, managed with
+//! `mac schedule add`, `list` and `remove`, and run by `mac schedule run`.
+//! When a job is due is a [`Recurrence`]: a time of day followed by days, such
+//! as `07:30 weekdays`, or a five-field cron expression.
+//!
+//! A run is missed when `mac schedule run` was not running, or the Mac was
+//! asleep, at the time. What happens then is the job's [`MissedRuns`] policy.
+//!
+//! ```
+//! use chrono::NaiveDate;
+//! use mac_cli::schedule::Recurrence;
+//!
+//! let when: Recurrence = "07:30 weekdays".parse().unwrap();
+//! // Friday evening
+//! let after = NaiveDate::from_ymd_opt(2026, 10, 16).unwrap().and_hms_opt(20, 0, 0).unwrap();
+//! let next = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap().and_hms_opt(7, 30, 0).unwrap();
+//! assert_eq!(when.next_after(after), Some(next));
+//!
+//! let every_quarter: Recurrence = "*/15 9-17 * * mon-fri".parse().unwrap();
+//! assert_eq!(every_quarter.next_after(next), next.checked_add_signed(chrono::TimeDelta::minutes(90)));
+//! ```
+
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Timelike};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Environment variable overriding the schedule file path.
+pub const SCHEDULE_ENV: &str = "MAC_CLI_SCHEDULE";
+
+/// Days searched for the next run. February 29th can be eight years apart.
+const SEARCH_DAYS: usize = 8 * 366;
+
+const WEEKDAYS: [&str; 7] = [
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+];
+
+/// When a job runs, at minute resolution in local time.
+///
+/// Written either as `HH:MM` followed by `daily` (the default), `weekdays`,
+/// `weekends` or days such as `mon,wed,fri` or `mon-thu`, or as a cron
+/// expression: minute, hour, day of the month, month and day of the week,
+/// each `*`, a number, a range `a-b` or a list of those, optionally with a
+/// step such as `*/15`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Recurrence {
+    spec: String,
+    minutes: u64,
+    hours: u64,
+    /// Days of the month, bits 1 to 31.
+    days: u64,
+    /// Bits 1 to 12.
+    months: u64,
+    /// Bits 0 to 6, Sunday first.
+    weekdays: u64,
+    /// Like cron, a day matches either field when both days and weekdays are
+    /// restricted.
+    either_day: bool,
+}
+
+/// What to do about runs missed while `mac schedule run` was not running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissedRuns {
+    /// Wait for the next run.
+    #[default]
+    Skip,
+    /// Run once as soon as possible, however many runs were missed.
+    RunOnce,
+}
+
+/// A scheduled command.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u32,
+    pub when: Recurrence,
+    /// A `mac` command line, without `mac`.
+    pub command: String,
+    #[serde(default)]
+    pub missed: MissedRuns,
+    pub created: DateTime<Local>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<DateTime<Local>>,
+    /// Runs due until this time have been run or skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checked: Option<DateTime<Local>>,
+}
+
+/// The contents of the schedule file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Jobs {
+    jobs: Vec<Job>,
+}
+
+/// The schedule file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    path: PathBuf,
+}
+
+impl Recurrence {
+    /// Returns the first run strictly after `after`, or `None` if there is
+    /// none in the next eight years.
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + TimeDelta::minutes(1);
+        let mut date = start.date();
+        for _ in 0..SEARCH_DAYS {
+            if self.matches_day(date) {
+                let first = match date == start.date() {
+                    true => start.time(),
+                    false => NaiveTime::MIN,
+                };
+                for hour in (first.hour()..24).filter(|h| self.hours & 1 << h != 0) {
+                    let from = if hour == first.hour() {
+                        first.minute()
+                    } else {
+                        0
+                    };
+                    if let Some(minute) = (from..60).find(|m| self.minutes & 1 << m != 0) {
+                        return date.and_hms_opt(hour, minute, 0);
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & 1 << date.day() != 0;
+        let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+        self.months & 1 << date.month() != 0
+            && match self.either_day {
+                true => day || weekday,
+                false => day && weekday,
+            }
+    }
+
+    fn daily(spec: &str, time: &str, days: &str) -> std::result::Result<Self, String> {
+        let time = NaiveTime::parse_from_str(time, "%H:%M")
+            .map_err(|_| format!("invalid time {:?}, expected HH:MM", time))?;
+        let weekdays = match days.to_lowercase().as_str() {
+            "" | "daily" => range(0, 6, 1),
+            "weekdays" => range(1, 5, 1),
+            "weekends" => 1 << 0 | 1 << 6,
+            days => field(days, 0, 7, &WEEKDAYS)
+                .map(sunday_once)
+                .map_err(|e| format!("invalid days {:?}: {}", days, e))?,
+        };
+        Ok(Recurrence {
+            spec: spec.to_string(),
+            minutes: 1 << time.minute(),
+            hours: 1 << time.hour(),
+            days: range(1, 31, 1),
+            months: range(1, 12, 1),
+            weekdays,
+            either_day: false,
+        })
+    }
+
+    fn cron(spec: &str, fields: [&str; 5]) -> std::result::Result<Self, String> {
+        let [minute, hour, day, month, weekday] = fields;
+        let parse = |name: &str, text: &str, min, max, names: &[&str]| {
+            field(text, min, max, names).map_err(|e| format!("invalid {} {:?}: {}", name, text, e))
+        };
+        Ok(Recurrence {
+            spec: spec.to_string(),
+            minutes: parse("minute", minute, 0, 59, &[])?,
+            hours: parse("hour", hour, 0, 23, &[])?,
+            days: parse("day of the month", day, 1, 31, &[])?,
+            months: parse("month", month, 1, 12, &[])?,
+            weekdays: parse("day of the week", weekday, 0, 7, &WEEKDAYS).map(sunday_once)?,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    fn from_str(spec: &str) -> std::result::Result<Self, String> {
+        let spec = spec.split_whitespace().collect::<Vec<_>>().join(" ");
+        let words: Vec<&str> = spec.split(' ').collect();
+        let recurrence = match words.as_slice() {
+            // `mon, wed` is `mon,wed`
+            [time, days @ ..] if time.contains(':') => Self::daily(&spec, time, &days.concat()),
+            [minute, hour, day, month, weekday] => {
+                Self::cron(&spec, [minute, hour, day, month, weekday])
+            }
+            _ => Err(format!(
+                "invalid schedule {:?}, expected e.g. \"07:30 weekdays\" or a cron expression",
+                spec
+            )),
+        }?;
+
+        let epoch = NaiveDate::from_ymd_opt(2000, 1, 1).and_then(|d| d.and_hms_opt(0, 0, 0));
+        match epoch.and_then(|epoch| recurrence.next_after(epoch)) {
+            Some(_) => Ok(recurrence),
+            None => Err(format!("schedule {:?} never runs", spec)),
+        }
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = String;
+
+    fn try_from(spec: String) -> std::result::Result<Self, String> {
+        spec.parse()
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(recurrence: Recurrence) -> String {
+        recurrence.spec
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+impl MissedRuns {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MissedRuns::Skip => "skip",
+            MissedRuns::RunOnce => "run-once",
+        }
+    }
+}
+
+impl FromStr for MissedRuns {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, String> {
+        match value {
+            "skip" => Ok(MissedRuns::Skip),
+            "run-once" => Ok(MissedRuns::RunOnce),
+            _ => Err(format!(
+                "invalid policy {:?}, expected skip or run-once",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MissedRuns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Job {
+    /// The first run after those already run or skipped. It is in the past
+    /// when the job is due, or missed.
+    pub fn due(&self) -> Option<NaiveDateTime> {
+        self.when
+            .next_after(self.checked.unwrap_or(self.created).naive_local())
+    }
+}
+
+impl Schedule {
+    /// Uses the schedule file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Schedule { path: path.into() }
+    }
+
+    /// The default schedule, `~/.config/mac-cli/schedule.json`.
+    pub fn default_path() -> PathBuf {
+        crate::paths::config_dir().join("schedule.json")
+    }
+
+    /// The path of the schedule file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns every job, oldest first. A missing schedule is empty.
+    pub fn jobs(&self) -> Result<Vec<Job>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(self.io_error("read", e)),
+        };
+
+        serde_json::from_str::<Jobs>(&contents)
+            .map(|file| file.jobs)
+            .map_err(|e| {
+                MacCliError::new(
+                    Subsystem::Cli,
+                    ErrorKind::Parse,
+                    format!("Invalid schedule in {}", self.path.display()),
+                )
+                .with_source(e)
+            })
+    }
+
+    /// Schedules `command` and returns the new job.
+    pub fn add(
+        &self,
+        when: Recurrence,
+        command: impl Into<String>,
+        missed: MissedRuns,
+    ) -> Result<Job> {
+        self.update(|jobs| {
+            let job = Job {
+                id: jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1,
+                when,
+                command: command.into(),
+                missed,
+                created: Local::now(),
+                last_run: None,
+                checked: None,
+            };
+            jobs.push(job.clone());
+            Ok(job)
+        })
+    }
+
+    /// Removes the job `id` and returns it.
+    pub fn remove(&self, id: u32) -> Result<Job> {
+        self.update(|jobs| {
+            let Some(index) = jobs.iter().position(|j| j.id == id) else {
+                return Err(MacCliError::new(
+                    Subsystem::Cli,
+                    ErrorKind::NotFound,
+                    format!("No scheduled job {}", id),
+                ));
+            };
+            Ok(jobs.remove(index))
+        })
+    }
+
+    /// Records that the runs of job `id` due until `checked` were handled,
+    /// and whether it ran. A job removed in the meantime stays removed.
+    pub fn record(&self, id: u32, checked: DateTime<Local>, ran: bool) -> Result<()> {
+        self.update(|jobs| {
+            if let Some(job) = jobs.iter_mut().find(|j| j.id == id) {
+                job.checked = Some(checked);
+                if ran {
+                    job.last_run = Some(checked);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Reads the jobs, changes them with `f` and writes them back unless `f`
+    /// fails. An advisory lock on a sibling `.lock` file keeps `mac schedule
+    /// run` and `mac schedule add` or `remove` from losing each other's
+    /// changes.
+    fn update<T>(&self, f: impl FnOnce(&mut Vec<Job>) -> Result<T>) -> Result<T> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| self.io_error("write", e))?;
+        }
+        // Released when the file is closed
+        let lock = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("json.lock"))
+            .map_err(|e| self.io_error("lock", e))?;
+        lock.lock().map_err(|e| self.io_error("lock", e))?;
+
+        let mut jobs = self.jobs()?;
+        let value = f(&mut jobs)?;
+        self.write(jobs)?;
+        Ok(value)
+    }
+
+    fn write(&self, jobs: Vec<Job>) -> Result<()> {
+        let json =
+            serde_json::to_string_pretty(&Jobs { jobs }).expect("Jobs are always serializable");
+        // Write to a sibling file and rename so readers never see a partial schedule
+        let tmp = self
+            .path
+            .with_extension(format!("json.{}.tmp", std::process::id()));
+        std::fs::write(&tmp, json + "\n").map_err(|e| self.io_error("write", e))?;
+        std::fs::rename(&tmp, &self.path).map_err(|e| self.io_error("write", e))
+    }
+
+    fn io_error(&self, action: &str, err: std::io::Error) -> MacCliError {
+        MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Other,
+            format!("Failed to {} schedule {}", action, self.path.display()),
+        )
+        .with_source(err)
+    }
+}
+
+/// Parses one field of a recurrence into a bit set of the values from `min`
+/// to `max`. `names` are the names of the values from 0 on, matched by their
+/// first three letters or more.
+fn field(text: &str, min: u32, max: u32, names: &[&str]) -> std::result::Result<u64, String> {
+    let value = |word: &str| -> std::result::Result<u32, String> {
+        let word = word.to_lowercase();
+        let named = names
+            .iter()
+            .position(|name| word.len() >= 3 && name.starts_with(&word));
+        let value = match named {
+            Some(index) => index as u32,
+            None => word
+                .parse()
+                .map_err(|_| format!("invalid value {:?}", word))?,
+        };
+        match (min..=max).contains(&value) {
+            true => Ok(value),
+            false => Err(format!("{} is not between {} and {}", value, min, max)),
+        }
+    };
+
+    let mut bits = 0;
+    for part in text.split(',').filter(|p| !p.is_empty()) {
+        let (span, step) = match part.split_once('/') {
+            Some((span, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (span, Some(step)),
+                _ => return Err(format!("invalid step {:?}", step)),
+            },
+            None => (part, None),
+        };
+        let (first, last) = match span.split_once('-') {
+            _ if span == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            // `5/15` is `5-59/15`, as in most crons
+            None if step.is_some() => (value(span)?, max),
+            None => {
+                let value = value(span)?;
+                (value, value)
+            }
+        };
+        if first > last {
+            return Err(format!("invalid range {:?}", span));
+        }
+        bits |= range(first, last, step.unwrap_or(1));
+    }
+    match bits {
+        0 => Err(format!("empty field {:?}", text)),
+        bits => Ok(bits),
+    }
+}
+
+fn range(first: u32, last: u32, step: u32) -> u64 {
+    (first..=last)
+        .step_by(step as usize)
+        .fold(0, |bits, v| bits | 1 << v)
+}
+
+/// Cron allows 7 for Sunday as well as 0.
+fn sunday_once(weekdays: u64) -> u64 {
+    match weekdays & 1 << 7 {
+        0 => weekdays,
+        _ => (weekdays & !(1 << 7)) | 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_path;
+    use chrono::TimeZone;
+
+    /// Friday evening.
+    fn friday() -> NaiveDateTime {
+        time("2026-10-16 20:00")
+    }
+
+    fn time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    /// The next `count` runs of `spec` after `after`.
+    fn runs(spec: &str, after: NaiveDateTime, count: usize) -> Vec<String> {
+        let when: Recurrence = spec.parse().unwrap();
+        let mut runs = Vec::new();
+        let mut at = after;
+        for _ in 0..count {
+            at = when.next_after(at).unwrap();
+            runs.push(at.format("%a %d %H:%M").to_string());
+        }
+        runs
+    }
+
+    fn days(spec: &str) -> Vec<String> {
+        runs(spec, friday(), 7)
+            .iter()
+            .map(|run| run[..3].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn times_of_day_with_days() {
+        assert_eq!(
+            days("07:30 weekdays"),
+            ["Mon", "Tue", "Wed", "Thu", "Fri", "Mon", "Tue"]
+        );
+        assert_eq!(
+            days("07:30 weekends"),
+            ["Sat", "Sun", "Sat", "Sun", "Sat", "Sun", "Sat"]
+        );
+        assert_eq!(
+            days("07:30 mon-thu"),
+            ["Mon", "Tue", "Wed", "Thu", "Mon", "Tue", "Wed"]
+        );
+        assert_eq!(
+            days("07:30 mon, wed"),
+            ["Mon", "Wed", "Mon", "Wed", "Mon", "Wed", "Mon"]
+        );
+        assert_eq!(days("07:30 mon, wed"), days("07:30 Monday,WED"));
+        assert_eq!(days("07:30"), days("07:30 daily"));
+        assert_eq!(runs("21:15", friday(), 2), ["Fri 16 21:15", "Sat 17 21:15"]);
+        assert_eq!(
+            "07:30  mon,   wed"
+                .parse::<Recurrence>()
+                .unwrap()
+                .to_string(),
+            "07:30 mon, wed"
+        );
+    }
+
+    #[test]
+    fn cron_lists_ranges_and_steps() {
+        assert_eq!(
+            runs("0,30 * * * *", friday(), 3),
+            ["Fri 16 20:30", "Fri 16 21:00", "Fri 16 21:30"]
+        );
+        assert_eq!(
+            runs("*/15 9-17 * * *", time("2026-10-16 17:40"), 2),
+            ["Fri 16 17:45", "Sat 17 09:00"]
+        );
+        // `5/15` starts at 5
+        assert_eq!(
+            runs("5/15 20 * * *", friday(), 5),
+            [
+                "Fri 16 20:05",
+                "Fri 16 20:20",
+                "Fri 16 20:35",
+                "Fri 16 20:50",
+                "Sat 17 20:05"
+            ]
+        );
+        assert_eq!(
+            runs("0 9-13/2,20 * * *", friday(), 4),
+            [
+                "Sat 17 09:00",
+                "Sat 17 11:00",
+                "Sat 17 13:00",
+                "Sat 17 20:00"
+            ]
+        );
+        assert_eq!(runs("0 12 1 1 *", friday(), 1), ["Fri 01 12:00"]);
+    }
+
+    #[test]
+    fn weekday_seven_is_sunday() {
+        assert_eq!(
+            runs("0 12 * * 7", friday(), 2),
+            ["Sun 18 12:00", "Sun 25 12:00"]
+        );
+        assert_eq!(
+            runs("0 12 * * 7", friday(), 3),
+            runs("0 12 * * 0", friday(), 3)
+        );
+        assert_eq!(
+            runs("0 12 * * 5-7", friday(), 3),
+            ["Sat 17 12:00", "Sun 18 12:00", "Fri 23 12:00"]
+        );
+    }
+
+    #[test]
+    fn restricted_days_and_weekdays_match_either() {
+        // The 13th of any month, and every Friday
+        assert_eq!(
+            runs("0 12 13 * fri", time("2026-12-01 00:00"), 4),
+            [
+                "Fri 04 12:00",
+                "Fri 11 12:00",
+                "Sun 13 12:00",
+                "Fri 18 12:00"
+            ]
+        );
+        assert_eq!(
+            runs("0 12 13 * *", time("2026-12-01 00:00"), 2),
+            ["Sun 13 12:00", "Wed 13 12:00"]
+        );
+        assert_eq!(
+            runs("0 12 * * fri", time("2026-12-01 00:00"), 3),
+            ["Fri 04 12:00", "Fri 11 12:00", "Fri 18 12:00"]
+        );
+    }
+
+    #[test]
+    fn invalid_and_impossible_schedules_are_rejected() {
+        let err = "0 0 31 2 *".parse::<Recurrence>().unwrap_err();
+        assert_eq!(err, "schedule \"0 0 31 2 *\" never runs");
+        assert!("0 0 30 2 *".parse::<Recurrence>().is_err());
+        assert!("0 0 29 2 *".parse::<Recurrence>().is_ok());
+
+        for spec in [
+            "",
+            "* * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "mo * * * *",
+            "25:00",
+            "07:30 someday",
+            "07:30 fri-mon",
+        ] {
+            assert!(spec.parse::<Recurrence>().is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn jobs_are_due_after_their_last_check() {
+        let local = |text| Local.from_local_datetime(&time(text)).earliest().unwrap();
+        let mut job = Job {
+            id: 1,
+            when: "07:30".parse().unwrap(),
+            command: "volume 30".to_string(),
+            missed: MissedRuns::Skip,
+            created: local("2026-10-16 08:00"),
+            last_run: None,
+            checked: None,
+        };
+        assert_eq!(job.due(), Some(time("2026-10-17 07:30")));
+
+        job.checked = Some(local("2026-10-17 07:30"));
+        assert_eq!(job.due(), Some(time("2026-10-18 07:30")));
+
+        // Runs at the minute the job was created are not due
+        job.checked = None;
+        job.created = local("2026-10-16 07:30") + TimeDelta::seconds(30);
+        assert_eq!(job.due(), Some(time("2026-10-17 07:30")));
+    }
+
+    #[test]
+    fn concurrent_changes_are_not_lost() {
+        let path = temp_path("schedule", "concurrent", "json");
+        let schedule = Schedule::new(&path);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let schedule = schedule.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..5 {
+                        let job = schedule
+                            .add("07:30".parse().unwrap(), "volume 30", MissedRuns::Skip)
+                            .unwrap();
+                        schedule.record(job.id, Local::now(), true).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let jobs = schedule.jobs().unwrap();
+        let ids: Vec<u32> = jobs.iter().map(|j| j.id).collect();
+        assert_eq!(ids, (1..=20).collect::<Vec<_>>());
+        assert!(jobs.iter().all(|j| j.last_run.is_some()));
+
+        assert_eq!(schedule.remove(3).unwrap().id, 3);
+        assert_eq!(schedule.remove(3).unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(schedule.jobs().unwrap().len(), 19);
+
+        std::fs::remove_file(&path).ok();
+        std::fs::remove_file(path.with_extension("json.lock")).ok();
+    }
+}
@@ -0,0 +1,256 @@
+//! `mac schedule run`: runs the scheduled jobs when they are due; see
+//! [`mac_cli::schedule`].
+//!
+//! The schedule file is read again before every check, so jobs added or
+//! removed with `mac schedule` apply without restarting.
+
+use crate::batch::parse_line;
+use crate::{Context, execute};
+use chrono::{DateTime, Local, NaiveDateTime, TimeDelta, TimeZone};
+use mac_cli::output::OutputFormat;
+use mac_cli::schedule::{Job, MissedRuns, Schedule};
+use mac_cli::{ErrorKind, MacCliError, Result, Subsystem};
+use serde_json::json;
+use std::time::Duration;
+
+/// Runs this late are still on time rather than missed, e.g. after a slow job.
+const LATE_MINUTES: i64 = 5;
+
+/// The schedule file is checked for changes at least this often.
+const MAX_SLEEP: Duration = Duration::from_secs(30);
+
+/// Clocks go forward by an hour, by two at most.
+const MAX_GAP_MINUTES: i64 = 2 * 60;
+
+/// Runs the jobs of `schedule` until the process is killed.
+pub(crate) fn run(mut ctx: Context, schedule: &Schedule, format: &OutputFormat) -> Result<()> {
+    let jobs = load(schedule)?;
+    eprintln!(
+        "mac schedule: running {} jobs from {}",
+        jobs.len(),
+        schedule.path().display()
+    );
+
+    loop {
+        let jobs = match schedule.jobs() {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                eprintln!("mac schedule: {}", e);
+                std::thread::sleep(MAX_SLEEP);
+                continue;
+            }
+        };
+
+        let now = Local::now();
+        for job in &jobs {
+            let Some(due) = latest_due(&Local, job, &now) else {
+                continue;
+            };
+            let missed = now - due > TimeDelta::minutes(LATE_MINUTES);
+            let ran = match (missed, job.missed) {
+                (true, MissedRuns::Skip) => {
+                    eprintln!(
+                        "mac schedule: job {} missed its run at {}, skipped",
+                        job.id,
+                        due.format("%Y-%m-%d %H:%M")
+                    );
+                    false
+                }
+                _ => {
+                    if let Err(e) = run_job(&ctx, job, format) {
+                        eprintln!("mac schedule: job {} (`{}`): {}", job.id, job.command, e);
+                    }
+                    true
+                }
+            };
+            if let Err(e) = schedule.record(job.id, now, ran) {
+                eprintln!("mac schedule: {}", e);
+            }
+        }
+
+        // Sleep until the next run, waking up now and then for edits
+        let next = jobs
+            .iter()
+            .filter_map(|job| job.when.next_after(now.naive_local()))
+            .filter_map(|next| happens(&Local, next))
+            .min();
+        let sleep = next
+            .and_then(|next| (next - Local::now()).to_std().ok())
+            .map_or(MAX_SLEEP, |until| until.min(MAX_SLEEP));
+        std::thread::sleep(sleep);
+
+        if let Err(e) = ctx.reload_config() {
+            eprintln!("mac schedule: {}", e);
+        }
+    }
+}
+
+/// The latest run of `job` due at `now`, or `None` if no run is due since
+/// the last check. Earlier runs are covered by it, so after a long pause a
+/// run a few minutes ago is on time even though older ones were missed.
+fn latest_due<Tz: TimeZone>(zone: &Tz, job: &Job, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+    let mut latest = None;
+    let mut next = job.due();
+    while let Some(run) = next {
+        match happens(zone, run) {
+            Some(at) if at <= *now => latest = Some(at),
+            _ => break,
+        }
+        next = job.when.next_after(run);
+    }
+    latest
+}
+
+/// When the local time `time` happens in `zone`. A time skipped when the
+/// clocks go forward, such as 02:30, happens at the first valid minute after
+/// it, and a time repeated when they go back happens the first time.
+fn happens<Tz: TimeZone>(zone: &Tz, time: NaiveDateTime) -> Option<DateTime<Tz>> {
+    (0..=MAX_GAP_MINUTES).find_map(|minutes| {
+        zone.from_local_datetime(&(time + TimeDelta::minutes(minutes)))
+            .earliest()
+    })
+}
+
+/// Reads the jobs of `schedule` and checks that every command is one `mac`
+/// can run, reporting every invalid command at once.
+///
+/// `mac schedule add` only accepts valid commands, but the file may have been
+/// edited by hand.
+fn load(schedule: &Schedule) -> Result<Vec<Job>> {
+    let jobs = schedule.jobs()?;
+
+    let errors: Vec<String> = jobs
+        .iter()
+        .filter_map(|job| {
+            parse_line(&job.command)
+                .err()
+                .map(|message| format!("job {}, `{}`: {}", job.id, job.command, message))
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Parse,
+            format!(
+                "Invalid jobs in {}:\n  {}",
+                schedule.path().display(),
+                errors.join("\n  ")
+            ),
+        ));
+    }
+    Ok(jobs)
+}
+
+fn run_job(ctx: &Context, job: &Job, format: &OutputFormat) -> Result<()> {
+    let command = parse_line(&job.command)
+        .map_err(|message| MacCliError::invalid_argument(Subsystem::Cli, message))?;
+    let output = execute(ctx, command, &format!("mac {}", job.command))?;
+
+    match format {
+        OutputFormat::Json => println!(
+            "{}",
+            json!({ "job": job.id, "command": job.command, "result": output.data() })
+        ),
+        _ => println!("job {}: {}", job.id, output.text()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, MappedLocalTime, NaiveDate};
+
+    /// Central European time around 2026-03-29, when the clocks go from 02:00
+    /// to 03:00.
+    #[derive(Debug, Clone)]
+    struct SpringForward;
+
+    fn local(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 3, 29)
+            .and_then(|d| d.and_hms_opt(hour, minute, 0))
+            .unwrap()
+    }
+
+    fn offset(hours: i32) -> FixedOffset {
+        FixedOffset::east_opt(hours * 3600).unwrap()
+    }
+
+    impl TimeZone for SpringForward {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            SpringForward
+        }
+
+        fn offset_from_local_date(&self, _: &NaiveDate) -> MappedLocalTime<FixedOffset> {
+            MappedLocalTime::None
+        }
+
+        fn offset_from_local_datetime(&self, time: &NaiveDateTime) -> MappedLocalTime<FixedOffset> {
+            match *time {
+                time if time < local(2, 0) => MappedLocalTime::Single(offset(1)),
+                time if time < local(3, 0) => MappedLocalTime::None,
+                _ => MappedLocalTime::Single(offset(2)),
+            }
+        }
+
+        fn offset_from_utc_date(&self, _: &NaiveDate) -> FixedOffset {
+            offset(1)
+        }
+
+        fn offset_from_utc_datetime(&self, time: &NaiveDateTime) -> FixedOffset {
+            match *time < local(1, 0) {
+                true => offset(1),
+                false => offset(2),
+            }
+        }
+    }
+
+    #[test]
+    fn the_latest_due_run_counts() {
+        let time = |text| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+        let local = |text| Local.from_local_datetime(&time(text)).earliest().unwrap();
+        let mut job = Job {
+            id: 1,
+            when: "07:30".parse().unwrap(),
+            command: "volume 30".to_string(),
+            missed: MissedRuns::Skip,
+            created: local("2026-10-12 08:00"),
+            last_run: None,
+            checked: None,
+        };
+        let due = |job: &Job, now| latest_due(&Local, job, &local(now));
+
+        // Days off, then started two minutes after today's run: it is on time
+        assert_eq!(
+            due(&job, "2026-10-16 07:32"),
+            Some(local("2026-10-16 07:30"))
+        );
+        assert_eq!(
+            due(&job, "2026-10-16 07:00"),
+            Some(local("2026-10-15 07:30"))
+        );
+
+        job.checked = Some(local("2026-10-16 07:32"));
+        assert_eq!(due(&job, "2026-10-16 20:00"), None);
+        assert_eq!(
+            due(&job, "2026-10-17 07:30"),
+            Some(local("2026-10-17 07:30"))
+        );
+    }
+
+    #[test]
+    fn times_the_clocks_skip_happen_when_they_resume() {
+        let at = |hour, minute| happens(&SpringForward, local(hour, minute)).unwrap();
+
+        assert_eq!(at(1, 30).naive_local(), local(1, 30));
+        assert_eq!(at(2, 0).naive_local(), local(3, 0));
+        assert_eq!(at(2, 30).naive_local(), local(3, 0));
+        assert_eq!(at(3, 15).naive_local(), local(3, 15));
+
+        // A job due at 02:30 is on time at 03:02
+        assert_eq!(at(3, 2) - at(2, 30), TimeDelta::minutes(2));
+        assert_eq!(at(3, 0) - at(1, 59), TimeDelta::minutes(1));
+    }
+}
@@ -0,0 +1,456 @@
+//! A long-lived AppleScript interpreter.
+//!
+//! Spawning `osascript` costs tens to hundreds of milliseconds per script. A
+//! [`ScriptWorker`] instead keeps one `osascript -l JavaScript` process open
+//! and feeds it scripts over a line protocol; the JavaScript side compiles and
+//! runs each one with `NSAppleScript`. Each request is one JSON line,
+//!
+//! ```json
+//! {"script": "output volume of (get volume settings)"}
+//! ```
+//!
+//! and each response is one JSON line, mirroring what `osascript -e` would
+//! have printed:
+//!
+//! ```json
+//! {"ok": true, "output": "42"}
+//! {"ok": false, "error": "Music got an error: ...", "number": -600}
+//! ```
+//!
+//! The worker is a [`CommandRunner`], so controllers use it transparently:
+//! `osascript -e <script>` commands go to the worker and everything else, or
+//! any script the worker cannot accept, falls back to one-shot execution. An
+//! interpreter that does not answer within 30 seconds is killed and
+//! started again for the next script.
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use mac_cli::VolumeController;
+//! use mac_cli::runner;
+//! use mac_cli::script_worker::ScriptWorker;
+//!
+//! let worker = Arc::new(ScriptWorker::new(runner::system()));
+//! let volume = VolumeController::with_runner(worker);
+//! for _ in 0..10 {
+//!     println!("{}", volume.get()?);
+//! }
+//! # Ok::<(), mac_cli::MacCliError>(())
+//! ```
+
+use crate::runner::{CommandOutput, CommandRunner, CommandSpec};
+use serde::Deserialize;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long the interpreter may take to answer a script before it is
+/// considered hung, for example on an Automation permission prompt, and
+/// killed.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The JavaScript for Automation program run by the worker process.
+const WORKER_SOURCE: &str = r#"
+ObjC.import('Foundation');
+
+const input = $.NSFileHandle.fileHandleWithStandardInput;
+const output = $.NSFileHandle.fileHandleWithStandardOutput;
+
+// Formats a result the way `osascript -e` prints it
+function text(d) {
+    if (d.isNil()) return '';
+    const type = d.descriptorType;
+    if (type === 0x6c697374 /* list */) {
+        const items = [];
+        for (let i = 1; i <= d.numberOfItems; i++) items.push(text(d.descriptorAtIndex(i)));
+        return items.join(', ');
+    }
+    if (type === 0x74727565 /* true */ || type === 0x66616c73 /* fals */ || type === 0x626f6f6c /* bool */) {
+        return d.booleanValue ? 'true' : 'false';
+    }
+    const s = d.stringValue;
+    if (!s.isNil()) return ObjC.unwrap(s);
+    const coerced = d.coerceToDescriptorType(0x75747874 /* utxt */);
+    return coerced.isNil() ? '' : ObjC.unwrap(coerced.stringValue);
+}
+
+function respond(response) {
+    const line = $(JSON.stringify(response) + '\n');
+    output.writeData(line.dataUsingEncoding($.NSUTF8StringEncoding));
+}
+
+let buffer = '';
+for (;;) {
+    const data = input.availableData;
+    if (data.length === 0) break;
+    buffer += ObjC.unwrap($.NSString.alloc.initWithDataEncoding(data, $.NSUTF8StringEncoding));
+
+    let newline;
+    while ((newline = buffer.indexOf('\n')) >= 0) {
+        const request = JSON.parse(buffer.slice(0, newline));
+        buffer = buffer.slice(newline + 1);
+
+        const error = Ref();
+        const script = $.NSAppleScript.alloc.initWithSource(request.script);
+        const result = script.executeAndReturnError(error);
+        if (result.isNil()) {
+            respond({
+                ok: false,
+                error: ObjC.unwrap(error[0].objectForKey('NSAppleScriptErrorMessage')) || 'AppleScript error',
+                number: ObjC.unwrap(error[0].objectForKey('NSAppleScriptErrorNumber')) || 0,
+            });
+        } else {
+            respond({ ok: true, output: text(result) });
+        }
+    }
+}
+"#;
+
+/// Runs AppleScript in a persistent interpreter, falling back to another
+/// runner for other commands and when the interpreter is unavailable.
+pub struct ScriptWorker {
+    fallback: Arc<dyn CommandRunner>,
+    /// Builds the command that starts the interpreter process.
+    interpreter: fn() -> Command,
+    /// How long to wait for each answer.
+    timeout: Duration,
+    state: Mutex<State>,
+}
+
+enum State {
+    /// Started on the first script, and again after the process dies.
+    Idle,
+    Running(Process),
+    /// The interpreter could not be started, or never answered; every
+    /// script falls back.
+    Unavailable,
+}
+
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    /// The lines of stdout, read on a separate thread so that waiting for
+    /// them can time out. Disconnected once stdout is closed.
+    replies: Receiver<io::Result<String>>,
+    /// Whether the process has answered a request yet.
+    answered: bool,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    ok: bool,
+    #[serde(default)]
+    output: String,
+    #[serde(default)]
+    error: String,
+    #[serde(default)]
+    number: i64,
+}
+
+/// Why a script was not run by the worker.
+enum Failure {
+    /// The script was never delivered, so running it elsewhere is safe.
+    NotSent(io::Error),
+    /// The script may have run; running it again could repeat its effects.
+    Lost(io::Error),
+}
+
+impl ScriptWorker {
+    /// Creates a worker that hands everything it cannot run to `fallback`.
+    ///
+    /// The interpreter process is started lazily by the first script.
+    pub fn new(fallback: Arc<dyn CommandRunner>) -> Self {
+        ScriptWorker {
+            fallback,
+            interpreter,
+            timeout: REPLY_TIMEOUT,
+            state: Mutex::new(State::Idle),
+        }
+    }
+
+    /// Returns the script of an `osascript -e <script>` command.
+    fn script_of(command: &CommandSpec) -> Option<&str> {
+        match command.get_args() {
+            [flag, script]
+                if command.program() == "osascript"
+                    && flag == "-e"
+                    && command.get_stdin().is_none()
+                    && !command.is_interactive() =>
+            {
+                Some(script)
+            }
+            _ => None,
+        }
+    }
+
+    fn eval(&self, script: &str) -> Result<CommandOutput, Failure> {
+        let mut state = self.state.lock().unwrap();
+
+        if let State::Idle = *state {
+            *state = match Process::spawn((self.interpreter)()) {
+                Ok(process) => State::Running(process),
+                Err(e) => {
+                    tracing::debug!(error = %e, "script worker unavailable");
+                    State::Unavailable
+                }
+            };
+        }
+
+        let State::Running(process) = &mut *state else {
+            return Err(Failure::NotSent(io::Error::other(
+                "script worker unavailable",
+            )));
+        };
+
+        let answered = process.answered;
+        let result = process.eval(script, self.timeout);
+        if result.is_err() {
+            // Restart on the next script rather than reusing a broken pipe
+            if let State::Running(mut process) = std::mem::replace(&mut *state, State::Idle) {
+                process.kill();
+            }
+
+            // An interpreter that fails its first request most likely cannot
+            // run scripts here at all, so use one-shot execution from now on
+            if !answered {
+                tracing::debug!("script worker never answered");
+                *state = State::Unavailable;
+                return result
+                    .map_err(|(Failure::NotSent(e) | Failure::Lost(e))| Failure::NotSent(e));
+            }
+        }
+        result
+    }
+}
+
+impl CommandRunner for ScriptWorker {
+    fn run(&self, command: &CommandSpec) -> io::Result<CommandOutput> {
+        let Some(script) = Self::script_of(command) else {
+            return self.fallback.run(command);
+        };
+
+        match self.eval(script) {
+            Ok(output) => Ok(output),
+            Err(Failure::NotSent(e)) => {
+                tracing::debug!(error = %e, "running script with one-shot osascript");
+                self.fallback.run(command)
+            }
+            Err(Failure::Lost(e)) => Err(e),
+        }
+    }
+}
+
+impl Drop for ScriptWorker {
+    fn drop(&mut self) {
+        if let Ok(State::Running(process)) = self.state.get_mut() {
+            process.kill();
+        }
+    }
+}
+
+/// The command that starts the real interpreter.
+fn interpreter() -> Command {
+    let mut command = Command::new("osascript");
+    command.args(["-l", "JavaScript", "-e", WORKER_SOURCE]);
+    command
+}
+
+impl Process {
+    fn spawn(mut command: Command) -> io::Result<Self> {
+        let started = Instant::now();
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        tracing::debug!(pid = child.id(), elapsed = ?started.elapsed(), "started script worker");
+
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let (lines, replies) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in stdout.lines() {
+                let failed = line.is_err();
+                if lines.send(line).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Process {
+            child,
+            stdin,
+            replies,
+            answered: false,
+        })
+    }
+
+    fn eval(&mut self, script: &str, timeout: Duration) -> Result<CommandOutput, Failure> {
+        let started = Instant::now();
+        let request = serde_json::json!({ "script": script }).to_string() + "\n";
+        self.stdin
+            .write_all(request.as_bytes())
+            .and_then(|_| self.stdin.flush())
+            .map_err(Failure::NotSent)?;
+
+        let line = match self.replies.recv_timeout(timeout) {
+            Ok(line) => line.map_err(Failure::Lost)?,
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(Failure::Lost(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("script worker did not answer within {:?}", timeout),
+                )));
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(Failure::Lost(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "script worker exited",
+                )));
+            }
+        };
+
+        let response: Response = serde_json::from_str(&line)
+            .map_err(|e| Failure::Lost(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+        tracing::debug!(ok = response.ok, elapsed = ?started.elapsed(), "script worker response");
+        self.answered = true;
+
+        Ok(if response.ok {
+            CommandOutput::success(response.output + "\n")
+        } else {
+            // Same shape as osascript's stderr, so error classification is unchanged
+            CommandOutput::failure(
+                1,
+                format!(
+                    "execution error: {} ({})\n",
+                    response.error, response.number
+                ),
+            )
+        })
+    }
+
+    fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{Expectation, FakeRunner};
+
+    const SCRIPT: &str = "output volume of (get volume settings)";
+
+    fn worker(fallback: &Arc<FakeRunner>, interpreter: fn() -> Command) -> ScriptWorker {
+        ScriptWorker {
+            fallback: fallback.clone(),
+            interpreter,
+            timeout: Duration::from_secs(5),
+            state: Mutex::new(State::Idle),
+        }
+    }
+
+    fn shell(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    fn one_shot(output: &str) -> Expectation {
+        Expectation::new("osascript")
+            .args(["-e", SCRIPT])
+            .returns(CommandOutput::success(output))
+    }
+
+    fn is_unavailable(worker: &ScriptWorker) -> bool {
+        matches!(*worker.state.lock().unwrap(), State::Unavailable)
+    }
+
+    #[test]
+    fn interpreter_that_cannot_start_falls_back() {
+        let fallback = Arc::new(
+            FakeRunner::new()
+                .expect(one_shot("42\n"))
+                .expect(one_shot("43\n")),
+        );
+        let worker = worker(&fallback, || Command::new("/nonexistent/osascript"));
+
+        let command = CommandSpec::new("osascript").args(["-e", SCRIPT]);
+        assert_eq!(worker.run(&command).unwrap().stdout, "42\n");
+        assert!(is_unavailable(&worker));
+        assert_eq!(worker.run(&command).unwrap().stdout, "43\n");
+        fallback.assert_done();
+    }
+
+    #[test]
+    fn interpreter_that_never_answers_falls_back() {
+        let fallback = Arc::new(
+            FakeRunner::new()
+                .expect(one_shot("42\n"))
+                .expect(one_shot("43\n")),
+        );
+        // Reads the request, then exits without a response
+        let worker = worker(&fallback, || shell("read -r request"));
+
+        let command = CommandSpec::new("osascript").args(["-e", SCRIPT]);
+        assert_eq!(worker.run(&command).unwrap().stdout, "42\n");
+        assert!(is_unavailable(&worker));
+        assert_eq!(worker.run(&command).unwrap().stdout, "43\n");
+        fallback.assert_done();
+    }
+
+    #[test]
+    fn lost_response_after_an_answer_is_an_error() {
+        let fallback = Arc::new(FakeRunner::new());
+        let worker = worker(&fallback, || {
+            shell(r#"read -r request; echo '{"ok": true, "output": "42"}'; read -r request"#)
+        });
+
+        let command = CommandSpec::new("osascript").args(["-e", SCRIPT]);
+        assert_eq!(worker.run(&command).unwrap().stdout, "42\n");
+        let err = worker.run(&command).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(!is_unavailable(&worker));
+        fallback.assert_done();
+    }
+
+    #[test]
+    fn interpreter_that_hangs_is_killed_and_restarted() {
+        let fallback = Arc::new(FakeRunner::new());
+        let mut worker = worker(&fallback, || {
+            shell(
+                r#"read -r request; echo '{"ok": true, "output": "42"}'; read -r request; exec sleep 60"#,
+            )
+        });
+        worker.timeout = Duration::from_millis(200);
+
+        let command = CommandSpec::new("osascript").args(["-e", SCRIPT]);
+        assert_eq!(worker.run(&command).unwrap().stdout, "42\n");
+        let started = Instant::now();
+        let err = worker.run(&command).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(matches!(*worker.state.lock().unwrap(), State::Idle));
+
+        // The next script starts a new interpreter
+        assert_eq!(worker.run(&command).unwrap().stdout, "42\n");
+        fallback.assert_done();
+    }
+
+    #[test]
+    fn other_commands_go_to_the_fallback() {
+        let fallback = Arc::new(
+            FakeRunner::new().expect(
+                Expectation::new("osascript")
+                    .args(["-l", "JavaScript", "-e", "1"])
+                    .returns(CommandOutput::success("1\n")),
+            ),
+        );
+        let worker = worker(&fallback, || shell("exit 1"));
+
+        let command = CommandSpec::new("osascript").args(["-l", "JavaScript", "-e", "1"]);
+        assert_eq!(worker.run(&command).unwrap().stdout, "1\n");
+        assert!(matches!(*worker.state.lock().unwrap(), State::Idle));
+        fallback.assert_done();
+    }
+}
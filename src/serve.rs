@@ -0,0 +1,1054 @@
+//! `mac serve`: a REST API over HTTP, for phones, web panels and other
+//! machines on the network.
+//!
+//! Every endpoint maps to a regular command and answers with the document
+//! that command prints with `--json`. Failures answer with the `--json` error
+//! document and an HTTP status derived from its [`ErrorKind`].
+//!
+//! `GET /events` streams changes of volume, brightness, the player and
+//! Bluetooth devices, found by polling with a [`Watcher`] like `mac daemon`
+//! does: as Server-Sent Events, or as WebSocket text messages when the client
+//! asks for an upgrade. Each stream is written by its own thread.
+//!
+//! Like `mac daemon`, the server owns a single [`Context`] and runs one
+//! request at a time on it; connections are accepted and read on
+//! tiny_http's threads.
+
+use crate::{Commands, Context, MusicCommands, SceneCommands, execute};
+use mac_cli::watch::{BLUETOOTH_POLL_EVERY, Change, WATCHABLE, Watcher};
+use mac_cli::{ErrorKind, MacCliError, Result, Subsystem};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::protocol::{Role, WebSocket};
+use tungstenite::{Message, handshake::derive_accept_key};
+use url::Url;
+
+/// Environment variable holding the bearer token of `mac serve`.
+pub(crate) const TOKEN_ENV: &str = "MAC_CLI_TOKEN";
+
+/// Request bodies are a few bytes of JSON; anything longer is refused.
+const MAX_BODY: u64 = 64 * 1024;
+
+/// How often idle event streams send a keep-alive, to notice closed connections.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// What the server answers, before it is turned into a tiny_http response.
+struct Reply {
+    status: u16,
+    body: Value,
+    /// The methods a path supports, for 405 and preflight answers.
+    allow: Option<&'static str>,
+}
+
+/// What to do with a request.
+enum Outcome {
+    Reply(Reply),
+    /// Stream changes of these subsystems.
+    Events(BTreeSet<Subsystem>),
+}
+
+/// A request once its path and method are known.
+enum Route {
+    Command(Commands),
+    Events(BTreeSet<Subsystem>),
+}
+
+/// The open event streams and the watcher feeding them.
+struct Events {
+    watcher: Watcher,
+    streams: Vec<EventStream>,
+    ticks: u64,
+}
+
+struct EventStream {
+    subsystems: BTreeSet<Subsystem>,
+    sender: mpsc::Sender<Change>,
+    /// Set by the stream's thread once the client is gone.
+    closed: Arc<AtomicBool>,
+}
+
+/// Settings of `mac serve` besides the context.
+pub(crate) struct ServeOptions {
+    pub(crate) listen: SocketAddr,
+    /// Requests must carry `Authorization: Bearer <token>` if set.
+    pub(crate) token: Option<String>,
+    /// Origin allowed to call the API from a browser, or `*`.
+    pub(crate) allow_origin: Option<String>,
+    /// Time between polls for event streams.
+    pub(crate) interval: Duration,
+}
+
+/// Listens on `options.listen` and serves requests until the process is killed.
+pub(crate) fn serve(mut ctx: Context, options: ServeOptions) -> Result<()> {
+    let server = Server::http(options.listen).map_err(|e| {
+        MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::Other,
+            format!("Failed to listen on {}", options.listen),
+        )
+        .with_source(e)
+    })?;
+
+    if options.token.is_none() && !options.listen.ip().is_loopback() {
+        eprintln!(
+            "warning: anyone who can reach {} can control this Mac; set a token with --token or {}",
+            options.listen, TOKEN_ENV
+        );
+    }
+    eprintln!("mac serve listening on http://{}", options.listen);
+
+    let mut events = Events {
+        watcher: Watcher::new(ctx.backend.clone(), ctx.music()),
+        streams: Vec::new(),
+        ticks: 0,
+    };
+    let mut next_poll = Instant::now() + options.interval;
+
+    loop {
+        // Poll between requests, even when they keep coming
+        if Instant::now() >= next_poll {
+            events.poll();
+            next_poll = Instant::now() + options.interval;
+        }
+
+        let mut request =
+            match server.recv_timeout(next_poll.saturating_duration_since(Instant::now())) {
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(e) => {
+                    return Err(MacCliError::new(
+                        Subsystem::Cli,
+                        ErrorKind::Other,
+                        "mac serve stopped accepting connections",
+                    )
+                    .with_source(e));
+                }
+            };
+
+        let started = Instant::now();
+        match handle(&mut ctx, &options, &mut request) {
+            Outcome::Reply(reply) => {
+                tracing::debug!(
+                    method = %request.method(),
+                    url = request.url(),
+                    status = reply.status,
+                    elapsed = ?started.elapsed(),
+                    "http request"
+                );
+                if let Err(e) = request.respond(response(reply, &options)) {
+                    tracing::debug!(error = %e, "failed to send response");
+                }
+            }
+            Outcome::Events(subsystems) => {
+                tracing::debug!(url = request.url(), ?subsystems, "event stream opened");
+                events.subscribe(request, subsystems, &options);
+            }
+        }
+    }
+}
+
+/// Parses `--listen`: `HOST:PORT`, or `:PORT` for every interface.
+pub(crate) fn listen_addr(value: &str) -> std::result::Result<SocketAddr, String> {
+    let value = match value.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => value.to_string(),
+    };
+    value
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("{} does not resolve to an address", value))
+}
+
+/// Parses `--allow-origin`, which is sent back in a header.
+pub(crate) fn allow_origin(value: &str) -> std::result::Result<String, String> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err("expected an origin such as https://panel.example, or *".to_string());
+    }
+    Ok(value.to_string())
+}
+
+fn handle(ctx: &mut Context, options: &ServeOptions, request: &mut Request) -> Outcome {
+    // A page whose domain resolves to this machine is same-origin with the
+    // server, so without a token only trust the names it listens under
+    if options.token.is_none() && !allowed_host(request, options.listen) {
+        let error = MacCliError::invalid_argument(
+            Subsystem::Cli,
+            format!(
+                "Host {} is not allowed; use localhost or an IP address, or a bearer token",
+                header(request, "Host").unwrap_or_default()
+            ),
+        );
+        return Outcome::Reply(Reply::error(403, &error));
+    }
+
+    // Browsers ask before sending PUT or Authorization, without credentials
+    if *request.method() == Method::Options {
+        return Outcome::Reply(Reply {
+            status: 204,
+            body: Value::Null,
+            allow: Some("GET, PUT, POST, OPTIONS"),
+        });
+    }
+
+    // Only the path and query matter; the base is never used
+    let url = match Url::parse("http://localhost").and_then(|base| base.join(request.url())) {
+        Ok(url) => url,
+        Err(e) => {
+            let error =
+                MacCliError::invalid_argument(Subsystem::Cli, format!("Invalid URL: {}", e));
+            return Outcome::Reply(Reply::error(400, &error));
+        }
+    };
+
+    if let Some(token) = &options.token
+        && !authorized(request, &url, token)
+    {
+        let error = MacCliError::new(
+            Subsystem::Cli,
+            ErrorKind::InvalidArgument,
+            "Missing or invalid bearer token",
+        );
+        return Outcome::Reply(Reply::error(401, &error));
+    }
+
+    // Any web page can post a form to localhost, but browsers ask before
+    // sending JSON to another origin
+    if options.token.is_none()
+        && matches!(request.method(), Method::Put | Method::Post)
+        && !json_content(request)
+    {
+        let error = MacCliError::invalid_argument(
+            Subsystem::Cli,
+            "Requests that change anything need Content-Type: application/json, or a bearer token",
+        );
+        return Outcome::Reply(Reply::error(415, &error));
+    }
+
+    // The body of an upgrade request is the rest of the connection
+    let body = match request.method() {
+        Method::Put | Method::Post => match read_body(request) {
+            Ok(body) => body,
+            Err(e) => return Outcome::Reply(Reply::error(400, &e)),
+        },
+        _ => Value::Null,
+    };
+
+    let command = match route(request.method(), &url, &body) {
+        Ok(Route::Command(command)) => command,
+        Ok(Route::Events(subsystems)) => {
+            // Browsers let any page open a WebSocket to localhost, and send
+            // its origin; only --allow-origin may listen without a token
+            if options.token.is_none() && !allowed_origin(request, options) {
+                let error = MacCliError::invalid_argument(
+                    Subsystem::Cli,
+                    format!(
+                        "Origin {} is not allowed; use --allow-origin or a bearer token",
+                        header(request, "Origin").unwrap_or_default()
+                    ),
+                );
+                return Outcome::Reply(Reply::error(403, &error));
+            }
+            return Outcome::Events(subsystems);
+        }
+        Err(reply) => return Outcome::Reply(reply),
+    };
+
+    if let Err(e) = ctx.reload_config() {
+        return Outcome::Reply(Reply::error(500, &e));
+    }
+
+    let command_line = match &body {
+        Value::Null => format!("{} {}", request.method(), url.path()),
+        body => format!("{} {} {}", request.method(), url.path(), body),
+    };
+    Outcome::Reply(match execute(ctx, command, &command_line) {
+        Ok(output) => Reply {
+            status: 200,
+            body: output.data().clone(),
+            allow: None,
+        },
+        Err(e) => Reply::error(status_of(e.kind()), &e),
+    })
+}
+
+/// Maps a request to the command it runs.
+fn route(method: &Method, url: &Url, body: &Value) -> std::result::Result<Route, Reply> {
+    let segments: Vec<String> = url
+        .path_segments()
+        .into_iter()
+        .flatten()
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            percent_encoding::percent_decode_str(s)
+                .decode_utf8_lossy()
+                .into_owned()
+        })
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    let command = match (segments.as_slice(), method) {
+        (["volume"], Method::Get) => Commands::Volume { percentage: None },
+        (["volume"], Method::Put) => Commands::Volume {
+            percentage: Some(number(body, "volume")?),
+        },
+        (["volume"], _) => return Err(Reply::not_allowed("GET, PUT")),
+
+        (["brightness"], Method::Get) => Commands::Brightness { percentage: None },
+        (["brightness"], Method::Put) => Commands::Brightness {
+            percentage: Some(number(body, "brightness")?),
+        },
+        (["brightness"], _) => return Err(Reply::not_allowed("GET, PUT")),
+
+        (["music", "current"], Method::Get) => Commands::Music(MusicCommands::Current),
+        (["music", "current"], _) => return Err(Reply::not_allowed("GET")),
+        (["music", action @ ("play" | "pause" | "next" | "previous")], Method::Post) => {
+            let action = match *action {
+                "play" => MusicCommands::Play,
+                "pause" => MusicCommands::Pause,
+                "next" => MusicCommands::Next,
+                _ => MusicCommands::Previous,
+            };
+            Commands::Music(action)
+        }
+        (["music", "play" | "pause" | "next" | "previous"], _) => {
+            return Err(Reply::not_allowed("POST"));
+        }
+        (["music", "playlists"], Method::Get) => Commands::Music(MusicCommands::Playlists {
+            name: None,
+            list: true,
+        }),
+        (["music", "playlists"], _) => return Err(Reply::not_allowed("GET")),
+        (["music", "playlists", name], Method::Post) => Commands::Music(MusicCommands::Playlists {
+            name: Some(name.to_string()),
+            list: false,
+        }),
+        (["music", "playlists", _], _) => return Err(Reply::not_allowed("POST")),
+
+        (["bluetooth", "devices"], Method::Get) => Commands::Bluetooth,
+        (["bluetooth", "devices"], _) => return Err(Reply::not_allowed("GET")),
+
+        (["weather"], Method::Get) => Commands::Weather {
+            location: query("location").filter(|l| !l.is_empty()),
+        },
+        (["weather"], _) => return Err(Reply::not_allowed("GET")),
+
+        (["status"], Method::Get) => {
+            let timeout = match query("timeout") {
+                Some(timeout) => Some(timeout.parse().map_err(|_| {
+                    Reply::error(
+                        400,
+                        &MacCliError::invalid_argument(
+                            Subsystem::Cli,
+                            format!("Invalid timeout: {}", timeout),
+                        ),
+                    )
+                })?),
+                None => None,
+            };
+            Commands::Status {
+                table: false,
+                timeout,
+            }
+        }
+        (["status"], _) => return Err(Reply::not_allowed("GET")),
+
+        (["scenes", name, "apply"], Method::Post) => Commands::Scene(SceneCommands::Apply {
+            name: name.to_string(),
+        }),
+        (["scenes", _, "apply"], _) => return Err(Reply::not_allowed("POST")),
+
+        (["undo"], Method::Post) => {
+            let count = match body.get("count") {
+                None | Some(Value::Null) => 1,
+                Some(count) => count.as_u64().ok_or_else(|| {
+                    Reply::error(
+                        400,
+                        &MacCliError::invalid_argument(
+                            Subsystem::Cli,
+                            "count must be a positive integer",
+                        ),
+                    )
+                })? as usize,
+            };
+            Commands::Undo { count }
+        }
+        (["undo"], _) => return Err(Reply::not_allowed("POST")),
+
+        (["events"], Method::Get) => return subsystems(query("subsystems")).map(Route::Events),
+        (["events"], _) => return Err(Reply::not_allowed("GET")),
+
+        _ => {
+            let error = MacCliError::new(
+                Subsystem::Cli,
+                ErrorKind::NotFound,
+                format!("No such endpoint: {} {}", method, url.path()),
+            );
+            return Err(Reply::error(404, &error));
+        }
+    };
+    Ok(Route::Command(command))
+}
+
+/// Parses `?subsystems=volume,music`; every watchable subsystem if omitted.
+fn subsystems(list: Option<String>) -> std::result::Result<BTreeSet<Subsystem>, Reply> {
+    let Some(list) = list.filter(|l| !l.is_empty()) else {
+        return Ok(WATCHABLE.into_iter().collect());
+    };
+
+    list.split(',')
+        .map(|name| {
+            serde_json::from_value(Value::String(name.trim().to_string()))
+                .ok()
+                .filter(|s| WATCHABLE.contains(s))
+                .ok_or_else(|| {
+                    let message = format!(
+                        "Cannot watch {:?}; use volume, brightness, music or bluetooth",
+                        name
+                    );
+                    Reply::error(400, &MacCliError::invalid_argument(Subsystem::Cli, message))
+                })
+        })
+        .collect()
+}
+
+/// Reads a JSON request body; an empty body is `null`.
+fn read_body(request: &mut Request) -> Result<Value> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_string(&mut body)
+        .map_err(|e| {
+            MacCliError::invalid_argument(Subsystem::Cli, "Failed to read request body")
+                .with_source(e)
+        })?;
+
+    if body.len() as u64 > MAX_BODY {
+        return Err(MacCliError::invalid_argument(
+            Subsystem::Cli,
+            "Request body is too large",
+        ));
+    }
+    if body.trim().is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_json::from_str(&body).map_err(|e| {
+        MacCliError::invalid_argument(Subsystem::Cli, "Request body is not valid JSON")
+            .with_source(e)
+    })
+}
+
+/// Reads `{"<name>": 40}`, or a bare `40`, from a request body.
+fn number(body: &Value, name: &str) -> std::result::Result<f32, Reply> {
+    body.get(name)
+        .unwrap_or(body)
+        .as_f64()
+        .map(|n| n as f32)
+        .ok_or_else(|| {
+            let message = format!("Expected a body like {{\"{}\": 40}}", name);
+            Reply::error(400, &MacCliError::invalid_argument(Subsystem::Cli, message))
+        })
+}
+
+/// Checks for `Content-Type: application/json`, with or without parameters.
+fn json_content(request: &Request) -> bool {
+    request
+        .headers()
+        .iter()
+        .filter(|h| h.field.equiv("Content-Type"))
+        .filter_map(|h| h.value.as_str().split(';').next())
+        .any(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Checks the `Host` header: `localhost`, a loopback address or the address
+/// the server listens on. When it listens on every interface, any IP address
+/// is accepted; DNS rebinding needs a domain name. Only browsers are a risk,
+/// and they always send the header, so requests without one pass.
+fn allowed_host(request: &Request, listen: SocketAddr) -> bool {
+    let Some(host) = header(request, "Host") else {
+        return true;
+    };
+    let Ok(url) = Url::parse(&format!("http://{}", host)) else {
+        return false;
+    };
+
+    let ip = match url.host() {
+        Some(url::Host::Domain(domain)) => return domain == "localhost",
+        Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+        None => return false,
+    };
+    ip.is_loopback() || ip == listen.ip() || listen.ip().is_unspecified()
+}
+
+/// Checks the `Origin` header of a browser request against `--allow-origin`.
+/// Requests from other programs carry no origin and pass.
+fn allowed_origin(request: &Request, options: &ServeOptions) -> bool {
+    match (header(request, "Origin"), options.allow_origin.as_deref()) {
+        (None, _) | (Some(_), Some("*")) => true,
+        (Some(origin), Some(allowed)) => origin.eq_ignore_ascii_case(allowed),
+        (Some(_), None) => false,
+    }
+}
+
+/// The value of the first header called `name`.
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+/// Checks the bearer token, or the `token` query parameter of `/events`:
+/// browsers cannot set headers on EventSource and WebSocket connections.
+fn authorized(request: &Request, url: &Url, token: &str) -> bool {
+    let header = request
+        .headers()
+        .iter()
+        .filter(|h| h.field.equiv("Authorization"))
+        .filter_map(|h| h.value.as_str().strip_prefix("Bearer "))
+        .map(|given| given.trim().to_string());
+    let query = url
+        .query_pairs()
+        .filter(|(key, _)| url.path() == "/events" && key == "token")
+        .map(|(_, value)| value.into_owned());
+
+    header
+        .chain(query)
+        .any(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+/// Compares without returning early, so timing does not reveal the token.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The HTTP status for a failed command.
+fn status_of(kind: ErrorKind) -> u16 {
+    match kind {
+        ErrorKind::InvalidArgument => 400,
+        ErrorKind::NotFound => 404,
+        ErrorKind::AppNotRunning | ErrorKind::Cancelled => 409,
+        ErrorKind::Network => 502,
+        ErrorKind::Unavailable => 503,
+        ErrorKind::Timeout => 504,
+        ErrorKind::Other
+        | ErrorKind::CommandNotFound
+        | ErrorKind::CommandFailed
+        | ErrorKind::Parse => 500,
+    }
+}
+
+impl Events {
+    /// Starts streaming changes of `subsystems` to the client of `request`,
+    /// beginning with their current state.
+    fn subscribe(
+        &mut self,
+        request: Request,
+        subsystems: BTreeSet<Subsystem>,
+        options: &ServeOptions,
+    ) {
+        // Read the current state now rather than at the next tick
+        let wanted: Vec<Subsystem> = subsystems.iter().copied().collect();
+        for change in self.watcher.poll(&wanted) {
+            self.publish(&change);
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        for &subsystem in &subsystems {
+            if let Some(value) = self.watcher.last(subsystem) {
+                let _ = sender.send(Change {
+                    subsystem,
+                    value: value.clone(),
+                    previous: Value::Null,
+                });
+            }
+        }
+
+        let closed = Arc::new(AtomicBool::new(false));
+        let websocket_key = websocket_key(&request);
+        let allow_origin = options.allow_origin.clone();
+        {
+            let closed = closed.clone();
+            std::thread::spawn(move || {
+                let result = match websocket_key {
+                    Some(key) => stream_websocket(request, &key, receiver),
+                    None => stream_sse(request, allow_origin.as_deref(), receiver),
+                };
+                match result {
+                    Ok(()) => tracing::debug!("event stream closed"),
+                    Err(e) => tracing::debug!(error = %e, "event stream closed"),
+                }
+                closed.store(true, Ordering::Relaxed);
+            });
+        }
+
+        self.streams.push(EventStream {
+            subsystems,
+            sender,
+            closed,
+        });
+    }
+
+    /// Polls the subsystems of the open streams and sends them the changes.
+    fn poll(&mut self) {
+        self.streams.retain(|s| !s.closed.load(Ordering::Relaxed));
+        if self.streams.is_empty() {
+            self.watcher.reset();
+            return;
+        }
+
+        let include_bluetooth = self.ticks.is_multiple_of(BLUETOOTH_POLL_EVERY);
+        self.ticks += 1;
+        let watched: Vec<Subsystem> = WATCHABLE
+            .into_iter()
+            .filter(|s| include_bluetooth || *s != Subsystem::Bluetooth)
+            .filter(|s| {
+                self.streams
+                    .iter()
+                    .any(|stream| stream.subsystems.contains(s))
+            })
+            .collect();
+        for change in self.watcher.poll(&watched) {
+            self.publish(&change);
+        }
+    }
+
+    fn publish(&mut self, change: &Change) {
+        self.streams.retain(|stream| {
+            !stream.subsystems.contains(&change.subsystem)
+                || stream.sender.send(change.clone()).is_ok()
+        });
+    }
+}
+
+/// The `Sec-WebSocket-Key` of a WebSocket upgrade request.
+fn websocket_key(request: &Request) -> Option<String> {
+    header(request, "Upgrade")
+        .filter(|u| u.eq_ignore_ascii_case("websocket"))
+        .and(header(request, "Sec-WebSocket-Key"))
+        .map(str::to_string)
+}
+
+/// Writes changes as Server-Sent Events until the client disconnects.
+fn stream_sse(
+    request: Request,
+    allow_origin: Option<&str>,
+    events: mpsc::Receiver<Change>,
+) -> std::io::Result<()> {
+    let mut writer = request.into_writer();
+    // The stream has no length; it ends when the connection closes
+    let mut head = String::from(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n",
+    );
+    if let Some(origin) = allow_origin {
+        head.push_str(&format!("Access-Control-Allow-Origin: {}\r\n", origin));
+    }
+    head.push_str("\r\n");
+    writer.write_all(head.as_bytes())?;
+    writer.flush()?;
+
+    pump(events, |change| {
+        match change {
+            Some(change) => {
+                let data = serde_json::to_string(change).expect("Change is always serializable");
+                write!(writer, "event: change\ndata: {}\n\n", data)?;
+            }
+            None => writer.write_all(b": keepalive\n\n")?,
+        }
+        writer.flush()
+    })
+}
+
+/// Completes the WebSocket handshake and sends changes as text messages.
+fn stream_websocket(
+    request: Request,
+    key: &str,
+    events: mpsc::Receiver<Change>,
+) -> std::io::Result<()> {
+    let accept = Header::from_bytes("Sec-WebSocket-Accept", derive_accept_key(key.as_bytes()))
+        .expect("accept keys are ASCII");
+    let stream = request.upgrade("websocket", Response::empty(101).with_header(accept));
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+    let result = pump(events, |change| {
+        let message = match change {
+            Some(change) => {
+                Message::Text(serde_json::to_string(change).expect("Change is always serializable"))
+            }
+            None => Message::Ping(Vec::new()),
+        };
+        socket.send(message).map_err(std::io::Error::other)
+    });
+    let _ = socket.close(None);
+    result
+}
+
+/// Calls `send` with each change, or with `None` after [`KEEPALIVE`] without
+/// one, until it fails or the server drops the stream.
+fn pump(
+    events: mpsc::Receiver<Change>,
+    mut send: impl FnMut(Option<&Change>) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    loop {
+        match events.recv_timeout(KEEPALIVE) {
+            Ok(change) => send(Some(&change))?,
+            Err(mpsc::RecvTimeoutError::Timeout) => send(None)?,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+impl Reply {
+    fn error(status: u16, error: &MacCliError) -> Self {
+        Reply {
+            status,
+            body: error.to_json(),
+            allow: None,
+        }
+    }
+
+    fn not_allowed(allow: &'static str) -> Self {
+        let error = MacCliError::invalid_argument(
+            Subsystem::Cli,
+            format!("Method not allowed; use {}", allow),
+        );
+        Reply {
+            allow: Some(allow),
+            ..Reply::error(405, &error)
+        }
+    }
+}
+
+fn response(reply: Reply, options: &ServeOptions) -> Response<std::io::Cursor<Vec<u8>>> {
+    let body = match reply.body {
+        Value::Null if reply.status == 204 => Vec::new(),
+        body => serde_json::to_vec(&body).expect("JSON values are always serializable"),
+    };
+
+    let mut response = Response::from_data(body).with_status_code(reply.status);
+    let mut headers = vec![("Content-Type", "application/json".to_string())];
+    if let Some(allow) = reply.allow {
+        headers.push(("Allow", allow.to_string()));
+    }
+    if let Some(origin) = &options.allow_origin {
+        headers.push(("Access-Control-Allow-Origin", origin.clone()));
+        headers.push((
+            "Access-Control-Allow-Methods",
+            "GET, PUT, POST, OPTIONS".to_string(),
+        ));
+        headers.push((
+            "Access-Control-Allow-Headers",
+            "Authorization, Content-Type".to_string(),
+        ));
+    }
+    if reply.status == 401 {
+        headers.push(("WWW-Authenticate", "Bearer".to_string()));
+    }
+    for (name, value) in headers {
+        let header = Header::from_bytes(name.as_bytes(), value.as_bytes())
+            .expect("header names and values are ASCII");
+        response.add_header(header);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::net::TcpStream;
+
+    fn options(listen: &str, token: Option<&str>) -> ServeOptions {
+        ServeOptions {
+            listen: listen.parse().unwrap(),
+            token: token.map(str::to_string),
+            allow_origin: None,
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Sends `head` and `body` over a real connection and handles the request.
+    fn send(ctx: &mut Context, options: &ServeOptions, head: &str, body: &str) -> Outcome {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let raw = format!(
+            "{}\r\nContent-Length: {}\r\n\r\n{}",
+            head.trim_end(),
+            body.len(),
+            body
+        );
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(raw.as_bytes()).unwrap();
+            stream
+        });
+
+        let mut request = server.recv().unwrap();
+        let outcome = handle(ctx, options, &mut request);
+        drop(client.join().unwrap());
+        outcome
+    }
+
+    fn status(ctx: &mut Context, options: &ServeOptions, head: &str, body: &str) -> u16 {
+        match send(ctx, options, head, body) {
+            Outcome::Reply(reply) => reply.status,
+            Outcome::Events(_) => 101,
+        }
+    }
+
+    fn route_of(method: Method, path: &str, body: Value) -> std::result::Result<Route, Reply> {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        route(&method, &url, &body)
+    }
+
+    fn command_of(method: Method, path: &str, body: Value) -> Commands {
+        match route_of(method, path, body) {
+            Ok(Route::Command(command)) => command,
+            Ok(Route::Events(_)) => panic!("{} is an event stream", path),
+            Err(reply) => panic!("{} failed with {}: {}", path, reply.status, reply.body),
+        }
+    }
+
+    fn error_status(method: Method, path: &str, body: Value) -> u16 {
+        match route_of(method, path, body) {
+            Err(reply) => reply.status,
+            Ok(_) => panic!("{} should fail", path),
+        }
+    }
+
+    #[test]
+    fn paths_and_methods_map_to_commands() {
+        assert!(matches!(
+            command_of(Method::Get, "/volume", Value::Null),
+            Commands::Volume { percentage: None }
+        ));
+        assert!(matches!(
+            command_of(Method::Put, "/volume", json!({ "volume": 40 })),
+            Commands::Volume {
+                percentage: Some(40.0)
+            }
+        ));
+        assert!(matches!(
+            command_of(Method::Put, "/brightness", json!(70)),
+            Commands::Brightness {
+                percentage: Some(70.0)
+            }
+        ));
+        assert!(matches!(
+            command_of(Method::Post, "/music/playlists/Deep%20Focus", Value::Null),
+            Commands::Music(MusicCommands::Playlists { name: Some(name), list: false })
+                if name == "Deep Focus"
+        ));
+        assert!(matches!(
+            command_of(Method::Get, "/weather?location=", Value::Null),
+            Commands::Weather { location: None }
+        ));
+        assert!(matches!(
+            command_of(Method::Post, "/undo", json!({ "count": 3 })),
+            Commands::Undo { count: 3 }
+        ));
+
+        match route_of(Method::Get, "/events?subsystems=music,volume", Value::Null) {
+            Ok(Route::Events(subsystems)) => assert_eq!(
+                subsystems,
+                BTreeSet::from([Subsystem::Volume, Subsystem::Music])
+            ),
+            _ => panic!("/events is an event stream"),
+        }
+    }
+
+    #[test]
+    fn bad_requests_are_refused_before_running() {
+        assert_eq!(error_status(Method::Delete, "/volume", Value::Null), 405);
+        assert_eq!(error_status(Method::Get, "/music/play", Value::Null), 405);
+        assert_eq!(error_status(Method::Get, "/nope", Value::Null), 404);
+        assert_eq!(
+            error_status(Method::Put, "/volume", json!({ "level": 40 })),
+            400
+        );
+        assert_eq!(
+            error_status(Method::Get, "/status?timeout=soon", Value::Null),
+            400
+        );
+        assert_eq!(
+            error_status(Method::Post, "/undo", json!({ "count": -1 })),
+            400
+        );
+        assert_eq!(
+            error_status(Method::Get, "/events?subsystems=weather", Value::Null),
+            400
+        );
+
+        match route_of(Method::Delete, "/volume", Value::Null) {
+            Err(reply) => assert_eq!(reply.allow, Some("GET, PUT")),
+            Ok(_) => panic!("DELETE /volume should fail"),
+        }
+    }
+
+    #[test]
+    fn token_is_required_in_the_header_or_on_events() {
+        let mut ctx = Context::sim("serve", "token");
+        let options = options("127.0.0.1:8765", Some("s3cret"));
+        let get = |ctx: &mut Context, path: &str, auth: &str| {
+            let head = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n{}", path, auth);
+            status(ctx, &options, &head, "")
+        };
+
+        let missing = get(&mut ctx, "/volume", "");
+        let wrong = get(&mut ctx, "/volume", "Authorization: Bearer nope\r\n");
+        let right = get(&mut ctx, "/volume", "Authorization: Bearer s3cret\r\n");
+        let query = get(&mut ctx, "/volume?token=s3cret", "");
+        let events = get(&mut ctx, "/events?token=s3cret", "");
+        ctx.remove_files();
+
+        assert_eq!(missing, 401);
+        assert_eq!(wrong, 401);
+        assert_eq!(right, 200);
+        assert_eq!(query, 401);
+        assert_eq!(events, 101);
+    }
+
+    #[test]
+    fn changes_without_a_token_need_json() {
+        let mut ctx = Context::sim("serve", "content-type");
+        let put = |ctx: &mut Context, options: &ServeOptions, content_type: &str| {
+            let head = format!(
+                "PUT /volume HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer s3cret\r\n{}",
+                content_type
+            );
+            status(ctx, options, &head, r#"{"volume": 40}"#)
+        };
+
+        let open = options("127.0.0.1:8765", None);
+        let form = put(
+            &mut ctx,
+            &open,
+            "Content-Type: application/x-www-form-urlencoded\r\n",
+        );
+        let missing = put(&mut ctx, &open, "");
+        let json = put(
+            &mut ctx,
+            &open,
+            "Content-Type: Application/JSON; charset=utf-8\r\n",
+        );
+        let with_token = put(&mut ctx, &options("127.0.0.1:8765", Some("s3cret")), "");
+        ctx.remove_files();
+
+        assert_eq!(form, 415);
+        assert_eq!(missing, 415);
+        assert_eq!(json, 200);
+        assert_eq!(with_token, 200);
+    }
+
+    #[test]
+    fn host_must_name_this_machine_without_a_token() {
+        let mut ctx = Context::sim("serve", "host");
+        let get = |ctx: &mut Context, options: &ServeOptions, method: &str, host: &str| {
+            let head = format!("{} /volume HTTP/1.1\r\nHost: {}\r\n", method, host);
+            status(ctx, options, &head, "")
+        };
+
+        let local = options("127.0.0.1:8765", None);
+        let all = options("0.0.0.0:8765", None);
+        let lan = options("192.168.1.5:8765", None);
+        let token = options("127.0.0.1:8765", Some("s3cret"));
+
+        let results = [
+            get(&mut ctx, &local, "GET", "localhost:8765"),
+            get(&mut ctx, &local, "GET", "LOCALHOST"),
+            get(&mut ctx, &local, "GET", "127.0.0.1:8765"),
+            get(&mut ctx, &local, "GET", "[::1]:8765"),
+            get(&mut ctx, &local, "GET", "rebind.example:8765"),
+            get(&mut ctx, &local, "OPTIONS", "rebind.example:8765"),
+            get(&mut ctx, &local, "GET", "localhost.rebind.example"),
+            get(&mut ctx, &local, "GET", "192.168.1.5:8765"),
+            get(&mut ctx, &lan, "GET", "192.168.1.5:8765"),
+            get(&mut ctx, &all, "GET", "192.168.1.5:8765"),
+            get(&mut ctx, &all, "GET", "mac.local:8765"),
+        ];
+        let with_token = status(
+            &mut ctx,
+            &token,
+            "GET /volume HTTP/1.1\r\nHost: mac.local\r\nAuthorization: Bearer s3cret\r\n",
+            "",
+        );
+        ctx.remove_files();
+
+        assert_eq!(
+            results,
+            [200, 200, 200, 200, 403, 403, 403, 403, 200, 200, 403]
+        );
+        assert_eq!(with_token, 200);
+    }
+
+    #[test]
+    fn event_streams_without_a_token_check_origin_and_host() {
+        let mut ctx = Context::sim("serve", "origin");
+        let events = |ctx: &mut Context, options: &ServeOptions, headers: &str| {
+            let head = format!(
+                "GET /events HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n{}",
+                headers
+            );
+            status(ctx, options, &head, "")
+        };
+
+        let open = options("127.0.0.1:8765", None);
+        let panel = ServeOptions {
+            allow_origin: Some("https://panel.example".to_string()),
+            ..options("127.0.0.1:8765", None)
+        };
+        let any = ServeOptions {
+            allow_origin: Some("*".to_string()),
+            ..options("127.0.0.1:8765", None)
+        };
+        let token = options("127.0.0.1:8765", Some("s3cret"));
+
+        let results = [
+            events(&mut ctx, &open, "Host: localhost\r\n"),
+            events(
+                &mut ctx,
+                &open,
+                "Host: localhost\r\nOrigin: https://evil.example\r\n",
+            ),
+            events(
+                &mut ctx,
+                &panel,
+                "Host: localhost\r\nOrigin: https://panel.example\r\n",
+            ),
+            events(
+                &mut ctx,
+                &panel,
+                "Host: localhost\r\nOrigin: https://evil.example\r\n",
+            ),
+            events(
+                &mut ctx,
+                &any,
+                "Host: localhost\r\nOrigin: https://evil.example\r\n",
+            ),
+            events(
+                &mut ctx,
+                &token,
+                "Host: localhost\r\nOrigin: https://evil.example\r\nAuthorization: Bearer s3cret\r\n",
+            ),
+            events(&mut ctx, &open, "Host: rebind.example\r\n"),
+        ];
+        ctx.remove_files();
+
+        assert_eq!(results, [101, 403, 101, 403, 101, 101, 403]);
+    }
+}
@@ -0,0 +1,418 @@
+//! A simulated "virtual Mac" backed by a JSON state file.
+//!
+//! With the simulation backend, every controller reads from and writes to a
+//! [`SimState`] stored on disk instead of talking to macOS. Mutating commands
+//! such as `mac volume 40` or `mac music next` update the file, so shell
+//! automation can be exercised end-to-end on machines without macOS.
+//!
+//! The file is created with [`SimState::default`] the first time it is written.
+//! A minimal hand-written file only needs the fields it wants to override:
+//!
+//! ```json
+//! {
+//!   "volume": 0.3,
+//!   "music": { "running": true, "state": "paused" },
+//!   "bluetooth": [{ "name": "AirPods Pro", "connected": true, "battery": 80 }]
+//! }
+//! ```
+
+use crate::bluetooth::Device;
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::music::PlayerState;
+use crate::weather::Weather;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// The complete state of the simulated machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimState {
+    /// Output volume, 0.0 (mute) to 1.0 (maximum).
+    pub volume: f32,
+    /// Whether the output is muted.
+    pub muted: bool,
+    /// Display brightness, 0.0 to 1.0.
+    pub brightness: f32,
+    pub music: SimMusic,
+    pub bluetooth: Vec<Device>,
+    /// Known locations; the first entry is used when no location is given.
+    pub weather: Vec<Weather>,
+}
+
+/// Simulated Apple Music player.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimMusic {
+    /// Whether the Music app is running. Queries fail with
+    /// [`ErrorKind::AppNotRunning`] when it is not.
+    pub running: bool,
+    pub state: PlayerState,
+    pub shuffle: bool,
+    pub playlists: Vec<SimPlaylist>,
+    /// Name of the playlist being played, if any.
+    pub current_playlist: Option<String>,
+    /// Index of the current track within the current playlist.
+    pub track_index: usize,
+    /// Playback position within the current track, in seconds.
+    pub position: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimPlaylist {
+    pub name: String,
+    #[serde(default)]
+    pub tracks: Vec<SimTrack>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimTrack {
+    pub name: String,
+    pub artist: String,
+    #[serde(default)]
+    pub album: String,
+    /// Length of the track in seconds.
+    #[serde(default)]
+    pub duration: f64,
+}
+
+impl Default for SimState {
+    fn default() -> Self {
+        SimState {
+            volume: 0.5,
+            muted: false,
+            brightness: 0.75,
+            music: SimMusic::default(),
+            bluetooth: vec![
+                Device {
+                    name: "AirPods Pro".to_string(),
+                    connected: true,
+                    address: Some("A0:B1:C2:D3:E4:F5".to_string()),
+                    battery: Some(80),
+                },
+                Device {
+                    name: "Magic Keyboard".to_string(),
+                    connected: false,
+                    address: Some("10:20:30:40:50:60".to_string()),
+                    battery: Some(55),
+                },
+            ],
+            weather: vec![
+                Weather {
+                    location: "Cupertino".to_string(),
+                    condition: "Sunny".to_string(),
+                    icon: "☀️".to_string(),
+                    temperature_c: Some(18.0),
+                },
+                Weather {
+                    location: "London".to_string(),
+                    condition: "Light rain".to_string(),
+                    icon: "🌧".to_string(),
+                    temperature_c: Some(11.0),
+                },
+            ],
+        }
+    }
+}
+
+impl Default for SimMusic {
+    fn default() -> Self {
+        let track = |name: &str, artist: &str, album: &str, duration: f64| SimTrack {
+            name: name.to_string(),
+            artist: artist.to_string(),
+            album: album.to_string(),
+            duration,
+        };
+
+        SimMusic {
+            running: true,
+            state: PlayerState::Stopped,
+            shuffle: false,
+            playlists: vec![
+                SimPlaylist {
+                    name: "Focus".to_string(),
+                    tracks: vec![
+                        track("Weightless", "Marconi Union", "Weightless", 480.0),
+                        track("An Ending (Ascent)", "Brian Eno", "Apollo", 266.0),
+                        track("Avril 14th", "Aphex Twin", "Drukqs", 125.0),
+                    ],
+                },
+                SimPlaylist {
+                    name: "Morning".to_string(),
+                    tracks: vec![
+                        track("Here Comes the Sun", "The Beatles", "Abbey Road", 185.0),
+                        track("Lovely Day", "Bill Withers", "Menagerie", 254.0),
+                    ],
+                },
+            ],
+            current_playlist: None,
+            track_index: 0,
+            position: 0.0,
+        }
+    }
+}
+
+impl SimMusic {
+    /// Returns the current track, if a playlist is loaded.
+    pub fn current_track(&self) -> Option<&SimTrack> {
+        let name = self.current_playlist.as_ref()?;
+        let playlist = self.playlists.iter().find(|p| &p.name == name)?;
+        playlist.tracks.get(self.track_index)
+    }
+
+    /// Moves `delta` tracks forward or backward, wrapping around the playlist.
+    pub fn skip(&mut self, delta: isize) {
+        let len = self
+            .current_playlist
+            .as_ref()
+            .and_then(|name| self.playlists.iter().find(|p| &p.name == name))
+            .map(|p| p.tracks.len())
+            .unwrap_or(0);
+
+        if len > 0 {
+            self.track_index =
+                (self.track_index as isize + delta).rem_euclid(len as isize) as usize;
+            self.position = 0.0;
+        }
+    }
+}
+
+/// A [`SimState`] persisted at a path on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimStore {
+    path: PathBuf,
+}
+
+impl SimStore {
+    /// Uses the state file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        SimStore { path: path.into() }
+    }
+
+    /// The default state file, `~/.config/mac-cli/sim-state.json`.
+    pub fn default_path() -> PathBuf {
+        crate::paths::config_dir().join("sim-state.json")
+    }
+
+    /// The path of the state file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the state file, returning the default state if it does not exist.
+    pub fn load(&self, subsystem: Subsystem) -> Result<SimState> {
+        tracing::debug!(path = %self.path.display(), subsystem = subsystem.as_str(), "loading simulation state");
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(SimState::default()),
+            Err(e) => return Err(self.io_error(subsystem, "read", e)),
+        };
+
+        serde_json::from_str(&contents).map_err(|e| {
+            MacCliError::new(
+                subsystem,
+                ErrorKind::Parse,
+                format!("Invalid simulation state in {}", self.path.display()),
+            )
+            .with_source(e)
+        })
+    }
+
+    /// Writes `state` to the state file, creating parent directories as needed.
+    pub fn save(&self, subsystem: Subsystem, state: &SimState) -> Result<()> {
+        let _lock = self.lock(subsystem)?;
+        self.write(subsystem, state)
+    }
+
+    /// Loads the state, applies `f`, and saves the result if `f` succeeds.
+    ///
+    /// The state file stays locked throughout, so that processes updating
+    /// it at the same time do not lose each other's changes.
+    pub fn update<T>(
+        &self,
+        subsystem: Subsystem,
+        f: impl FnOnce(&mut SimState) -> Result<T>,
+    ) -> Result<T> {
+        let _lock = self.lock(subsystem)?;
+        let mut state = self.load(subsystem)?;
+        let value = f(&mut state)?;
+        self.write(subsystem, &state)?;
+        Ok(value)
+    }
+
+    /// Takes an advisory lock on a sibling `.lock` file, creating parent
+    /// directories as needed. Released when the returned file is closed.
+    fn lock(&self, subsystem: Subsystem) -> Result<File> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| self.io_error(subsystem, "write", e))?;
+        }
+        let lock = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("json.lock"))
+            .map_err(|e| self.io_error(subsystem, "lock", e))?;
+        lock.lock()
+            .map_err(|e| self.io_error(subsystem, "lock", e))?;
+        Ok(lock)
+    }
+
+    fn write(&self, subsystem: Subsystem, state: &SimState) -> Result<()> {
+        tracing::debug!(path = %self.path.display(), subsystem = subsystem.as_str(), "saving simulation state");
+        let json = serde_json::to_string_pretty(state).expect("SimState is always serializable");
+
+        // Write to a sibling file and rename so readers never see a partial state
+        let tmp = self
+            .path
+            .with_extension(format!("json.{}.tmp", std::process::id()));
+        std::fs::write(&tmp, json + "\n").map_err(|e| self.io_error(subsystem, "write", e))?;
+        std::fs::rename(&tmp, &self.path).map_err(|e| self.io_error(subsystem, "write", e))
+    }
+
+    fn io_error(&self, subsystem: Subsystem, action: &str, err: std::io::Error) -> MacCliError {
+        MacCliError::new(
+            subsystem,
+            ErrorKind::Other,
+            format!(
+                "Failed to {} simulation state {}",
+                action,
+                self.path.display()
+            ),
+        )
+        .with_source(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{remove_with_lock, temp_path};
+
+    fn temp_store(name: &str) -> SimStore {
+        SimStore::new(temp_path("sim", name, "json"))
+    }
+
+    #[test]
+    fn missing_file_is_the_default_state() {
+        let store = temp_store("missing");
+
+        assert_eq!(store.load(Subsystem::Volume).unwrap(), SimState::default());
+        assert!(!store.path().exists());
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let store = temp_store("round-trip");
+        let mut state = SimState {
+            volume: 0.3,
+            ..SimState::default()
+        };
+        state.music.current_playlist = Some("Morning".into());
+        state.music.track_index = 1;
+        state.bluetooth.truncate(1);
+
+        store.save(Subsystem::Volume, &state).unwrap();
+        let loaded = store.load(Subsystem::Volume).unwrap();
+        remove_with_lock(store.path());
+
+        assert_eq!(loaded, state);
+        assert_eq!(loaded.music.current_track().unwrap().name, "Lovely Day");
+    }
+
+    #[test]
+    fn partial_file_keeps_the_other_defaults() {
+        let store = temp_store("partial");
+        std::fs::write(
+            store.path(),
+            r#"{ "volume": 0.3, "music": { "state": "paused" } }"#,
+        )
+        .unwrap();
+
+        let state = store.load(Subsystem::Music).unwrap();
+        remove_with_lock(store.path());
+
+        assert_eq!(state.volume, 0.3);
+        assert_eq!(state.music.state, PlayerState::Paused);
+        assert_eq!(state.brightness, SimState::default().brightness);
+        assert_eq!(state.music.playlists, SimMusic::default().playlists);
+    }
+
+    #[test]
+    fn invalid_file_is_a_parse_error() {
+        let store = temp_store("invalid");
+        std::fs::write(store.path(), "{ volume: 0.3 }").unwrap();
+
+        let err = store.load(Subsystem::Volume).unwrap_err();
+        remove_with_lock(store.path());
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        assert_eq!(err.subsystem(), Subsystem::Volume);
+    }
+
+    #[test]
+    fn failed_update_saves_nothing() {
+        let store = temp_store("failed-update");
+
+        let err = store
+            .update(Subsystem::Volume, |state| {
+                state.volume = 1.0;
+                Err::<(), _>(MacCliError::invalid_argument(Subsystem::Volume, "no"))
+            })
+            .unwrap_err();
+
+        let saved = store.path().exists();
+        remove_with_lock(store.path());
+
+        assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+        assert!(!saved);
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        let store = temp_store("concurrent");
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        store
+                            .update(Subsystem::Music, |state| {
+                                state.music.track_index += 1;
+                                Ok(())
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let state = store.load(Subsystem::Music).unwrap();
+        remove_with_lock(store.path());
+
+        assert_eq!(state.music.track_index, 40);
+    }
+
+    #[test]
+    fn skip_wraps_around_the_playlist() {
+        let mut music = SimMusic {
+            current_playlist: Some("Morning".into()),
+            position: 42.0,
+            ..SimMusic::default()
+        };
+
+        music.skip(-1);
+        assert_eq!(music.track_index, 1);
+        assert_eq!(music.position, 0.0);
+        music.skip(3);
+        assert_eq!(music.track_index, 0);
+
+        music.current_playlist = None;
+        music.skip(1);
+        assert_eq!(music.track_index, 0);
+        assert_eq!(music.current_track(), None);
+    }
+}
@@ -0,0 +1,356 @@
+//! Snapshots of the machine's settings that can be restored later.
+//!
+//! A [`Snapshot`] records the volume, the brightness of every active display
+//! and the Music player's state, or a subset of them selected by a [`Scope`],
+//! and is stored as JSON:
+//!
+//! ```json
+//! {
+//!   "volume": 0.4,
+//!   "displays": [{ "id": 1, "brightness": 0.75 }],
+//!   "music": { "state": "paused", "shuffle": false, "playlist": "Focus", "track_index": 1, "position": 42.5 }
+//! }
+//! ```
+
+use crate::backend::Backend;
+use crate::brightness::BrightnessController;
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::music::{MusicController, PlayerSnapshot};
+use crate::volume::VolumeController;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The restorable settings of the machine.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Output volume, 0.0 to 1.0, or `None` if not captured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<f32>,
+    /// Brightness of each active display. Empty where brightness is unavailable.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub displays: Vec<DisplayBrightness>,
+    /// The player state, or `None` if the player was not running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub music: Option<PlayerSnapshot>,
+}
+
+/// Which settings a snapshot captures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scope {
+    pub volume: bool,
+    pub brightness: bool,
+    pub music: bool,
+}
+
+impl Scope {
+    /// Every supported setting.
+    pub const ALL: Scope = Scope {
+        volume: true,
+        brightness: true,
+        music: true,
+    };
+    /// Only the volume.
+    pub const VOLUME: Scope = Scope {
+        volume: true,
+        brightness: false,
+        music: false,
+    };
+    /// Only the brightness of every display.
+    pub const BRIGHTNESS: Scope = Scope {
+        volume: false,
+        brightness: true,
+        music: false,
+    };
+    /// Only the player state.
+    pub const MUSIC: Scope = Scope {
+        volume: false,
+        brightness: false,
+        music: true,
+    };
+}
+
+/// The brightness of one display.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisplayBrightness {
+    /// Display ID, as returned by [`BrightnessController::displays`].
+    pub id: u32,
+    /// Brightness, 0.0 to 1.0.
+    pub brightness: f32,
+}
+
+impl Snapshot {
+    /// The default snapshot file, `~/.config/mac-cli/state.json`.
+    pub fn default_path() -> PathBuf {
+        crate::paths::config_dir().join("state.json")
+    }
+
+    /// Captures the current settings.
+    ///
+    /// Displays are skipped where brightness is unavailable, and the player
+    /// when it is not running.
+    pub fn capture(backend: &Backend, music: &MusicController) -> Result<Self> {
+        Self::capture_scope(backend, music, Scope::ALL)
+    }
+
+    /// Captures the settings selected by `scope`, see [`Self::capture`].
+    pub fn capture_scope(backend: &Backend, music: &MusicController, scope: Scope) -> Result<Self> {
+        let mut snapshot = Snapshot::default();
+
+        if scope.volume {
+            snapshot.volume = Some(VolumeController::with_backend(backend.clone()).get()?);
+        }
+        if scope.brightness {
+            snapshot.displays = Self::capture_displays(backend)?;
+        }
+        if scope.music {
+            snapshot.music = match music.snapshot() {
+                Ok(snapshot) => Some(snapshot),
+                Err(e) if e.kind() == ErrorKind::AppNotRunning => None,
+                Err(e) => return Err(e),
+            };
+        }
+
+        Ok(snapshot)
+    }
+
+    fn capture_displays(backend: &Backend) -> Result<Vec<DisplayBrightness>> {
+        Ok(match BrightnessController::displays(backend) {
+            Ok(ids) => ids
+                .into_iter()
+                .map(|id| {
+                    let brightness = BrightnessController::for_display(backend, id)?.get()?;
+                    Ok(DisplayBrightness { id, brightness })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Err(e) if e.kind() == ErrorKind::Unavailable => Vec::new(),
+            Err(e) => return Err(e),
+        })
+    }
+
+    /// Returns `true` if nothing was captured.
+    pub fn is_empty(&self) -> bool {
+        self.volume.is_none() && self.displays.is_empty() && self.music.is_none()
+    }
+
+    /// Reapplies the captured settings, stopping at the first error.
+    ///
+    /// Displays that are no longer connected are skipped, and the player is
+    /// left alone if it was not running when the snapshot was taken.
+    pub fn restore(&self, backend: &Backend, music: &MusicController) -> Result<()> {
+        if let Some(volume) = self.volume {
+            VolumeController::with_backend(backend.clone()).set(volume)?;
+        }
+
+        for display in &self.displays {
+            match BrightnessController::for_display(backend, display.id) {
+                Ok(controller) => controller.set(display.brightness)?,
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        if let Some(snapshot) = &self.music {
+            music.restore(snapshot)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a snapshot file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            let kind = if e.kind() == std::io::ErrorKind::NotFound {
+                ErrorKind::NotFound
+            } else {
+                ErrorKind::Other
+            };
+            MacCliError::new(
+                Subsystem::Cli,
+                kind,
+                format!("Failed to read snapshot {}", path.display()),
+            )
+            .with_source(e)
+        })?;
+
+        serde_json::from_str(&contents).map_err(|e| {
+            MacCliError::new(
+                Subsystem::Cli,
+                ErrorKind::Parse,
+                format!("Invalid snapshot in {}", path.display()),
+            )
+            .with_source(e)
+        })
+    }
+
+    /// Writes the snapshot to `path`, creating parent directories as needed.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let io_error = |e: std::io::Error| {
+            MacCliError::new(
+                Subsystem::Cli,
+                ErrorKind::Other,
+                format!("Failed to write snapshot {}", path.display()),
+            )
+            .with_source(e)
+        };
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(io_error)?;
+        }
+
+        let json = serde_json::to_string_pretty(self).expect("Snapshot is always serializable");
+        std::fs::write(path, json + "\n").map_err(io_error)
+    }
+
+    /// Describes the snapshot, e.g. `volume 40%, display 1 at 75%, music paused`.
+    ///
+    /// Settings outside the captured scope are left out.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(volume) = self.volume {
+            parts.push(format!("volume {}%", crate::output::percent(volume)));
+        }
+        for display in &self.displays {
+            parts.push(format!(
+                "display {} at {}%",
+                display.id,
+                crate::output::percent(display.brightness)
+            ));
+        }
+        if let Some(music) = &self.music {
+            match &music.playlist {
+                Some(playlist) => {
+                    parts.push(format!("music {} ({})", music.state.as_str(), playlist))
+                }
+                None => parts.push(format!("music {}", music.state.as_str())),
+            }
+        }
+
+        if parts.is_empty() {
+            "nothing".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::music::PlayerState;
+    use crate::sim::SimStore;
+    use crate::test_support::{remove_with_lock, temp_path};
+
+    fn sim(name: &str) -> (SimStore, Backend, MusicController) {
+        let store = SimStore::new(temp_path("state", name, "json"));
+        let backend = Backend::Sim(store.clone());
+        let music = MusicController::with_backend(backend.clone());
+        (store, backend, music)
+    }
+
+    #[test]
+    fn restore_puts_back_what_capture_saw() {
+        let (store, backend, music) = sim("restore");
+        VolumeController::with_backend(backend.clone())
+            .set(0.3)
+            .unwrap();
+        BrightnessController::with_backend(&backend)
+            .unwrap()
+            .set(0.6)
+            .unwrap();
+        music.set_shuffle(true).unwrap();
+        music.play_playlist("Focus").unwrap();
+        music.next().unwrap();
+        music.pause().unwrap();
+
+        let saved = Snapshot::capture(&backend, &music).unwrap();
+        VolumeController::with_backend(backend.clone())
+            .set(0.9)
+            .unwrap();
+        BrightnessController::with_backend(&backend)
+            .unwrap()
+            .set(0.2)
+            .unwrap();
+        music.set_shuffle(false).unwrap();
+        music.play_playlist("Morning").unwrap();
+
+        saved.restore(&backend, &music).unwrap();
+        let restored = Snapshot::capture(&backend, &music).unwrap();
+        remove_with_lock(store.path());
+
+        assert_eq!(restored, saved);
+        assert_eq!(saved.volume, Some(0.3));
+        assert_eq!(
+            saved.displays,
+            [DisplayBrightness {
+                id: 1,
+                brightness: 0.6
+            }]
+        );
+        let player = saved.music.unwrap();
+        assert_eq!(player.state, PlayerState::Paused);
+        assert!(player.shuffle);
+        assert_eq!(player.playlist.as_deref(), Some("Focus"));
+        assert_eq!(player.track_index, Some(1));
+    }
+
+    #[test]
+    fn capture_scope_only_reads_the_selected_settings() {
+        let (_store, backend, music) = sim("scope");
+
+        let snapshot = Snapshot::capture_scope(&backend, &music, Scope::VOLUME).unwrap();
+        assert_eq!(snapshot.volume, Some(0.5));
+        assert!(snapshot.displays.is_empty());
+        assert_eq!(snapshot.music, None);
+        assert!(!snapshot.is_empty());
+    }
+
+    #[test]
+    fn player_that_is_not_running_is_left_out() {
+        let (store, backend, music) = sim("not-running");
+        store
+            .update(Subsystem::Music, |state| {
+                state.music.running = false;
+                Ok(())
+            })
+            .unwrap();
+
+        let snapshot = Snapshot::capture_scope(&backend, &music, Scope::MUSIC).unwrap();
+        remove_with_lock(store.path());
+
+        assert!(snapshot.is_empty());
+        assert_eq!(snapshot.summary(), "nothing");
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = temp_path("state", "file", "json");
+        let snapshot = Snapshot {
+            volume: Some(0.4),
+            displays: vec![DisplayBrightness {
+                id: 1,
+                brightness: 0.75,
+            }],
+            music: None,
+        };
+
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, snapshot);
+        assert_eq!(loaded.summary(), "volume 40%, display 1 at 75%");
+    }
+
+    #[test]
+    fn load_reports_missing_and_invalid_files() {
+        let path = temp_path("state", "missing", "json");
+        assert_eq!(
+            Snapshot::load(&path).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+
+        std::fs::write(&path, "volume = 0.4").unwrap();
+        let err = Snapshot::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), ErrorKind::Parse);
+    }
+}
@@ -0,0 +1,433 @@
+//! A summary of every subsystem gathered in one call.
+//!
+//! [`StatusQuery::run`] reads the volume, brightness, player, Bluetooth
+//! devices and weather concurrently, each on its own thread with its own
+//! timeout, so one slow subsystem (typically `system_profiler` or the
+//! network) delays the summary by at most its timeout:
+//!
+//! ```no_run
+//! use mac_cli::Backend;
+//! use mac_cli::status::StatusQuery;
+//!
+//! let status = StatusQuery::new(Backend::system()).run();
+//! if let Some(volume) = status.volume {
+//!     println!("volume {:.0}%", volume * 100.0);
+//! }
+//! for (part, error) in &status.errors {
+//!     eprintln!("{}: {}", part, error);
+//! }
+//! ```
+//!
+//! A subsystem that times out is abandoned, not cancelled: its thread keeps
+//! running until the underlying command returns.
+
+use crate::backend::Backend;
+use crate::bluetooth::{BluetoothController, Device};
+use crate::brightness::BrightnessController;
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::music::{MusicController, NowPlaying};
+use crate::state::DisplayBrightness;
+use crate::volume::VolumeController;
+use crate::weather::{Weather, WeatherCache, WeatherController};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// The subsystems a [`StatusQuery`] reads.
+const SUBSYSTEMS: [Subsystem; 5] = [
+    Subsystem::Volume,
+    Subsystem::Brightness,
+    Subsystem::Music,
+    Subsystem::Bluetooth,
+    Subsystem::Weather,
+];
+
+/// How long each subsystem may take, in seconds. Every timeout must be
+/// positive and finite.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    pub volume: f64,
+    pub brightness: f64,
+    pub music: f64,
+    pub bluetooth: f64,
+    pub weather: f64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            volume: 2.0,
+            brightness: 2.0,
+            music: 2.0,
+            bluetooth: 5.0,
+            weather: 5.0,
+        }
+    }
+}
+
+impl Timeouts {
+    /// The same timeout for every subsystem.
+    pub fn all(seconds: f64) -> Self {
+        Timeouts {
+            volume: seconds,
+            brightness: seconds,
+            music: seconds,
+            bluetooth: seconds,
+            weather: seconds,
+        }
+    }
+
+    fn of(&self, subsystem: Subsystem) -> Duration {
+        let seconds = match subsystem {
+            Subsystem::Volume => self.volume,
+            Subsystem::Brightness => self.brightness,
+            Subsystem::Music => self.music,
+            Subsystem::Bluetooth => self.bluetooth,
+            Subsystem::Weather => self.weather,
+            Subsystem::Cli => unreachable!("mac status does not query the CLI"),
+        };
+        Duration::try_from_secs_f64(seconds).expect(
+            "timeouts are positive and finite, as checked by Config::validate and --timeout",
+        )
+    }
+}
+
+/// The state of every subsystem.
+///
+/// A field is `None` when its subsystem failed, timed out or does not apply
+/// (no adjustable display, player not running); only failures and timeouts
+/// are listed in `errors`.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// Output volume, 0.0 to 1.0.
+    pub volume: Option<f32>,
+    pub muted: Option<bool>,
+    /// Brightness of the main display, 0.0 to 1.0.
+    pub brightness: Option<f32>,
+    /// Brightness of every active display, main display first.
+    pub displays: Vec<DisplayBrightness>,
+    pub music: Option<NowPlaying>,
+    /// Connected Bluetooth devices.
+    pub bluetooth: Option<Vec<Device>>,
+    pub weather: Option<Weather>,
+    /// Why subsystems are missing, by subsystem.
+    pub errors: BTreeMap<Subsystem, MacCliError>,
+}
+
+/// What a subsystem's thread reports back.
+enum Reading {
+    Volume { volume: f32, muted: bool },
+    Brightness(Vec<DisplayBrightness>),
+    Music(NowPlaying),
+    Bluetooth(Vec<Device>),
+    Weather(Weather),
+}
+
+/// The parameters of a status query, built like the controllers.
+pub struct StatusQuery {
+    backend: Backend,
+    player: String,
+    location: Option<String>,
+    weather_cache: Option<(WeatherCache, Duration)>,
+    timeouts: Timeouts,
+    subsystems: Vec<Subsystem>,
+}
+
+impl StatusQuery {
+    /// Queries every subsystem of `backend` with the default player, the
+    /// auto-detected location and [`Timeouts::default`].
+    pub fn new(backend: Backend) -> Self {
+        StatusQuery {
+            backend,
+            player: crate::music::DEFAULT_PLAYER.to_string(),
+            location: None,
+            weather_cache: None,
+            timeouts: Timeouts::default(),
+            subsystems: SUBSYSTEMS.to_vec(),
+        }
+    }
+
+    /// Sets the player application.
+    pub fn player(mut self, name: impl Into<String>) -> Self {
+        self.player = name.into();
+        self
+    }
+
+    /// Sets the weather location.
+    pub fn location(mut self, location: Option<String>) -> Self {
+        self.location = location;
+        self
+    }
+
+    /// Reuses weather reports from `cache` that are younger than `max_age`.
+    pub fn weather_cache(mut self, cache: WeatherCache, max_age: Duration) -> Self {
+        self.weather_cache = Some((cache, max_age));
+        self
+    }
+
+    /// Sets the per-subsystem timeouts.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Only reads `subsystems`; the fields of the others stay `None`.
+    pub fn only(mut self, subsystems: &[Subsystem]) -> Self {
+        self.subsystems = SUBSYSTEMS
+            .into_iter()
+            .filter(|s| subsystems.contains(s))
+            .collect();
+        self
+    }
+
+    /// Queries every subsystem concurrently and waits for each one until its
+    /// timeout.
+    pub fn run(&self) -> Status {
+        let started = Instant::now();
+        let (tx, rx) = mpsc::channel();
+
+        let subsystems = &self.subsystems;
+        for &subsystem in subsystems {
+            let tx = tx.clone();
+            let task = self.task(subsystem);
+            std::thread::spawn(move || {
+                // The receiver is gone once the subsystem has timed out
+                let _ = tx.send((subsystem, task()));
+            });
+        }
+        drop(tx);
+
+        let mut status = Status::default();
+        let mut pending: Vec<(Subsystem, Instant)> = subsystems
+            .iter()
+            .map(|&s| (s, started + self.timeouts.of(s)))
+            .collect();
+
+        while let Some(deadline) = pending.iter().map(|(_, d)| *d).min() {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(wait) {
+                Ok((subsystem, result)) => {
+                    // Ignore late answers from subsystems already reported as timed out
+                    let waiting = pending.len();
+                    pending.retain(|(s, _)| *s != subsystem);
+                    if pending.len() == waiting {
+                        continue;
+                    }
+                    tracing::debug!(%subsystem, elapsed = ?started.elapsed(), ok = result.is_ok(), "status reading");
+                    status.record(subsystem, result);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    let now = Instant::now();
+                    pending.retain(|&(subsystem, deadline)| {
+                        if deadline > now {
+                            return true;
+                        }
+                        let timeout = self.timeouts.of(subsystem);
+                        tracing::debug!(%subsystem, ?timeout, "status reading timed out");
+                        status.errors.insert(
+                            subsystem,
+                            MacCliError::new(
+                                subsystem,
+                                ErrorKind::Timeout,
+                                format!("No answer within {:?}", timeout),
+                            ),
+                        );
+                        false
+                    });
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        status
+    }
+
+    /// Returns the work of one subsystem, to run on its own thread.
+    fn task(&self, subsystem: Subsystem) -> Box<dyn FnOnce() -> Result<Reading> + Send> {
+        let backend = self.backend.clone();
+        match subsystem {
+            Subsystem::Volume => Box::new(move || {
+                let controller = VolumeController::with_backend(backend);
+                Ok(Reading::Volume {
+                    volume: controller.get()?,
+                    muted: controller.muted()?,
+                })
+            }),
+            Subsystem::Brightness => Box::new(move || {
+                let mut displays = Vec::new();
+                for (index, id) in BrightnessController::displays(&backend)?
+                    .into_iter()
+                    .enumerate()
+                {
+                    match BrightnessController::for_display(&backend, id).and_then(|c| c.get()) {
+                        Ok(brightness) => displays.push(DisplayBrightness { id, brightness }),
+                        Err(e) if index == 0 => return Err(e),
+                        // External displays often cannot report their brightness
+                        Err(e) => tracing::debug!(display_id = id, error = %e, "skipping display"),
+                    }
+                }
+                Ok(Reading::Brightness(displays))
+            }),
+            Subsystem::Music => {
+                let music = MusicController::with_backend(backend).player(&self.player);
+                Box::new(move || Ok(Reading::Music(music.now_playing()?)))
+            }
+            Subsystem::Bluetooth => Box::new(move || {
+                let devices = BluetoothController::with_backend(backend).devices()?;
+                Ok(Reading::Bluetooth(
+                    devices.into_iter().filter(|d| d.connected).collect(),
+                ))
+            }),
+            Subsystem::Cli => unreachable!("the CLI is not a status subsystem"),
+            Subsystem::Weather => {
+                let location = self.location.clone();
+                let cache = self.weather_cache.clone();
+                Box::new(move || {
+                    let controller = WeatherController::with_backend(backend);
+                    let weather = match cache {
+                        Some((cache, max_age)) => controller
+                            .cache(cache)
+                            .cached(location.as_deref(), max_age)?,
+                        None => controller.current(location.as_deref())?,
+                    };
+                    Ok(Reading::Weather(weather))
+                })
+            }
+        }
+    }
+}
+
+impl Status {
+    fn record(&mut self, subsystem: Subsystem, result: Result<Reading>) {
+        match result {
+            Ok(Reading::Volume { volume, muted }) => {
+                self.volume = Some(volume);
+                self.muted = Some(muted);
+            }
+            Ok(Reading::Brightness(displays)) => {
+                self.brightness = displays.first().map(|d| d.brightness);
+                self.displays = displays;
+            }
+            Ok(Reading::Music(now)) => self.music = Some(now),
+            Ok(Reading::Bluetooth(devices)) => self.bluetooth = Some(devices),
+            Ok(Reading::Weather(weather)) => self.weather = Some(weather),
+            // Nothing to report rather than a failure
+            Err(e) if matches!(e.kind(), ErrorKind::Unavailable | ErrorKind::AppNotRunning) => {}
+            Err(e) => {
+                self.errors.insert(subsystem, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{CommandOutput, CommandRunner, CommandSpec};
+    use std::sync::Arc;
+
+    /// Answers like a Mac whose network hangs.
+    struct SlowNetwork;
+
+    impl CommandRunner for SlowNetwork {
+        fn run(&self, command: &CommandSpec) -> std::io::Result<CommandOutput> {
+            let script = command.get_args().last().map(String::as_str);
+            Ok(match (command.program(), script) {
+                ("osascript", Some("output volume of (get volume settings)")) => {
+                    CommandOutput::success("40\n")
+                }
+                ("osascript", Some("output muted of (get volume settings)")) => {
+                    CommandOutput::success("false\n")
+                }
+                ("system_profiler", _) => CommandOutput::success(
+                    r#"{ "SPBluetoothDataType": [{
+                        "device_connected": [{ "AirPods": {} }],
+                        "device_not_connected": [{ "Keyboard": {} }]
+                    }] }"#,
+                ),
+                ("curl", _) => {
+                    std::thread::sleep(Duration::from_secs(2));
+                    CommandOutput::success("Paris | Sunny | ☀️ | +20°C")
+                }
+                _ => panic!("unexpected command {}", command),
+            })
+        }
+    }
+
+    #[test]
+    fn slow_subsystem_times_out_while_the_others_report() {
+        let started = Instant::now();
+        let status = StatusQuery::new(Backend::System(Arc::new(SlowNetwork)))
+            .only(&[Subsystem::Volume, Subsystem::Bluetooth, Subsystem::Weather])
+            .timeouts(Timeouts {
+                weather: 0.2,
+                ..Timeouts::default()
+            })
+            .run();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(status.volume, Some(0.4));
+        assert_eq!(status.muted, Some(false));
+        let names: Vec<&str> = status
+            .bluetooth
+            .iter()
+            .flatten()
+            .map(|d| d.name.as_str())
+            .collect();
+        assert_eq!(names, ["AirPods"]);
+        assert_eq!(status.weather, None);
+        assert_eq!(status.errors.len(), 1);
+        assert_eq!(
+            status.errors[&Subsystem::Weather].kind(),
+            ErrorKind::Timeout
+        );
+    }
+
+    #[test]
+    fn only_skips_the_other_subsystems() {
+        let status = StatusQuery::new(Backend::System(Arc::new(SlowNetwork)))
+            .only(&[Subsystem::Volume])
+            .run();
+
+        assert_eq!(status.volume, Some(0.4));
+        assert_eq!(status.bluetooth, None);
+        assert_eq!(status.weather, None);
+        assert!(status.errors.is_empty());
+    }
+
+    #[test]
+    fn unavailable_and_stopped_subsystems_are_not_errors() {
+        let mut status = Status::default();
+        status.record(
+            Subsystem::Brightness,
+            Err(MacCliError::new(
+                Subsystem::Brightness,
+                ErrorKind::Unavailable,
+                "No display",
+            )),
+        );
+        status.record(
+            Subsystem::Music,
+            Err(MacCliError::new(
+                Subsystem::Music,
+                ErrorKind::AppNotRunning,
+                "Music isn't running",
+            )),
+        );
+        status.record(
+            Subsystem::Volume,
+            Err(MacCliError::new(
+                Subsystem::Volume,
+                ErrorKind::Parse,
+                "Failed to parse volume",
+            )),
+        );
+
+        assert_eq!(
+            status.errors.keys().collect::<Vec<_>>(),
+            [&Subsystem::Volume]
+        );
+    }
+}
@@ -0,0 +1,43 @@
+//! Helpers shared by the unit tests of the library and of the `mac` binary.
+
+use std::path::{Path, PathBuf};
+
+/// Returns `mac-cli-<prefix>-<name>-<pid>.<extension>` in the temporary
+/// directory, removing whatever a previous run left there.
+pub fn temp_path(prefix: &str, name: &str, extension: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "mac-cli-{}-{}-{}.{}",
+        prefix,
+        name,
+        std::process::id(),
+        extension
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Returns the empty directory `mac-cli-<prefix>-<name>-<pid>` in the
+/// temporary directory, removing whatever a previous run left in it.
+// Only the tests of the binary use directories so far
+#[allow(dead_code)]
+pub fn temp_dir(prefix: &str, name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "mac-cli-{}-{}-{}",
+        prefix,
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Removes `path` and the `.lock` file that serializes its updates, if any.
+pub fn remove_with_lock(path: &Path) {
+    let _ = std::fs::remove_file(path);
+    if let Some(extension) = path.extension() {
+        let mut lock = extension.to_os_string();
+        lock.push(".lock");
+        let _ = std::fs::remove_file(path.with_extension(lock));
+    }
+}
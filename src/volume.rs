@@ -1,36 +1,38 @@
 //! Volume control for macOS using AppleScript.
 //!
 //! This module provides an interface to get and set system volume on macOS
-//! by executing AppleScript commands.
+//! by executing AppleScript commands, or on the simulated machine.
 
-use std::process::Command;
+use crate::backend::Backend;
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::runner::{self, CommandRunner};
+use std::sync::Arc;
 
 /// Controller for managing system volume on macOS.
 ///
 /// Uses AppleScript to control the system volume output.
-pub struct VolumeController;
+pub struct VolumeController {
+    backend: Backend,
+}
 
 impl VolumeController {
     /// Creates a new volume controller.
-    pub fn new() -> Result<Self, String> {
-        Ok(VolumeController)
+    pub fn new() -> Result<Self> {
+        Ok(Self::with_backend(Backend::system()))
     }
 
-    fn run_script(script: &str) -> Result<String, String> {
-        let output = Command::new("osascript")
-            .arg("-e")
-            .arg(script)
-            .output()
-            .map_err(|e| format!("Failed to execute osascript: {}", e))?;
-
-        if !output.status.success() {
-            return Err(format!(
-                "AppleScript error: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
-        }
+    /// Creates a volume controller that executes commands through `runner`.
+    pub fn with_runner(runner: Arc<dyn CommandRunner>) -> Self {
+        Self::with_backend(Backend::System(runner))
+    }
 
-        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    /// Creates a volume controller for the given backend.
+    pub fn with_backend(backend: Backend) -> Self {
+        VolumeController { backend }
+    }
+
+    fn run_script(runner: &dyn CommandRunner, script: &str) -> Result<String> {
+        runner::run_applescript(runner, Subsystem::Volume, script)
     }
 
     /// Gets the current volume level.
@@ -38,18 +40,50 @@ impl VolumeController {
     /// # Returns
     ///
     /// Returns a value between 0.0 (mute) and 1.0 (maximum).
-    pub fn get(&self) -> Result<f32, String> {
+    pub fn get(&self) -> Result<f32> {
+        let runner: &dyn CommandRunner = match &self.backend {
+            Backend::System(runner) => runner.as_ref(),
+            Backend::DryRun(plan) => plan.as_ref(),
+            Backend::Sim(sim) => return Ok(sim.load(Subsystem::Volume)?.volume),
+        };
+
         let script = "output volume of (get volume settings)";
-        let result = Self::run_script(script)?;
+        let result = Self::run_script(runner, script)?;
 
-        let volume = result
-            .parse::<f32>()
-            .map_err(|_| "Failed to parse volume".to_string())?;
+        let volume = result.parse::<f32>().map_err(|e| {
+            MacCliError::new(
+                Subsystem::Volume,
+                ErrorKind::Parse,
+                "Failed to parse volume",
+            )
+            .with_source(e)
+        })?;
 
         // AppleScript returns 0-100, convert to 0.0-1.0
         Ok(volume / 100.0)
     }
 
+    /// Returns `true` if the output is muted.
+    pub fn muted(&self) -> Result<bool> {
+        let runner: &dyn CommandRunner = match &self.backend {
+            Backend::System(runner) => runner.as_ref(),
+            Backend::DryRun(plan) => plan.as_ref(),
+            Backend::Sim(sim) => return Ok(sim.load(Subsystem::Volume)?.muted),
+        };
+
+        let result = Self::run_script(runner, "output muted of (get volume settings)")?;
+        match result.as_str() {
+            "true" => Ok(true),
+            // Outputs that cannot be muted report `missing value`
+            "false" | "missing value" => Ok(false),
+            other => Err(MacCliError::new(
+                Subsystem::Volume,
+                ErrorKind::Parse,
+                format!("Failed to parse mute state: {}", other),
+            )),
+        }
+    }
+
     /// Sets the volume level.
     ///
     /// # Arguments
@@ -59,16 +93,121 @@ impl VolumeController {
     /// # Errors
     ///
     /// Returns an error if the volume value is out of range or if the AppleScript fails.
-    pub fn set(&self, volume: f32) -> Result<(), String> {
+    pub fn set(&self, volume: f32) -> Result<()> {
         if !(0.0..=1.0).contains(&volume) {
-            return Err("Volume must be between 0.0 and 1.0".to_string());
+            return Err(MacCliError::invalid_argument(
+                Subsystem::Volume,
+                "Volume must be between 0.0 and 1.0",
+            ));
         }
 
         // Convert to 0-100 for AppleScript
         let volume_pct = (volume * 100.0) as i32;
+
         let script = format!("set volume output volume {}", volume_pct);
-        Self::run_script(&script)?;
+        match &self.backend {
+            Backend::System(runner) => {
+                Self::run_script(runner.as_ref(), &script)?;
+            }
+            Backend::DryRun(plan) => {
+                Self::run_script(plan.as_ref(), &script)?;
+            }
+            Backend::Sim(sim) => sim.update(Subsystem::Volume, |state| {
+                state.volume = volume_pct as f32 / 100.0;
+                Ok(())
+            })?,
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{CommandOutput, Expectation, FakeRunner};
+
+    fn osascript(script: &str, output: CommandOutput) -> Expectation {
+        Expectation::new("osascript")
+            .args(["-e", script])
+            .returns(output)
+    }
+
+    #[test]
+    fn get_converts_percent_to_level() {
+        let runner = Arc::new(FakeRunner::new().expect(osascript(
+            "output volume of (get volume settings)",
+            CommandOutput::success("40\n"),
+        )));
+        let volume = VolumeController::with_runner(runner.clone());
+
+        assert_eq!(volume.get().unwrap(), 0.4);
+        runner.assert_done();
+    }
+
+    #[test]
+    fn get_rejects_unexpected_output() {
+        let runner = Arc::new(FakeRunner::new().expect(osascript(
+            "output volume of (get volume settings)",
+            CommandOutput::success("missing value"),
+        )));
+
+        let err = VolumeController::with_runner(runner).get().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Parse);
+    }
+
+    #[test]
+    fn muted_treats_missing_value_as_unmuted() {
+        let script = "output muted of (get volume settings)";
+        let runner = Arc::new(
+            FakeRunner::new()
+                .expect(osascript(script, CommandOutput::success("true")))
+                .expect(osascript(script, CommandOutput::success("missing value")))
+                .expect(osascript(script, CommandOutput::success("maybe"))),
+        );
+        let volume = VolumeController::with_runner(runner);
+
+        assert!(volume.muted().unwrap());
+        assert!(!volume.muted().unwrap());
+        assert_eq!(volume.muted().unwrap_err().kind(), ErrorKind::Parse);
+    }
+
+    #[test]
+    fn set_runs_script_and_validates_range() {
+        let runner = Arc::new(FakeRunner::new().expect(osascript(
+            "set volume output volume 25",
+            CommandOutput::success(""),
+        )));
+        let volume = VolumeController::with_runner(runner.clone());
+
+        volume.set(0.25).unwrap();
+        assert_eq!(
+            volume.set(1.5).unwrap_err().kind(),
+            ErrorKind::InvalidArgument
+        );
+        runner.assert_done();
+    }
+
+    #[test]
+    fn script_failure_maps_to_command_failed() {
+        let runner = Arc::new(FakeRunner::new().expect(osascript(
+            "output volume of (get volume settings)",
+            CommandOutput::failure(1, "execution error: Not authorized (-1743)"),
+        )));
+
+        let err = VolumeController::with_runner(runner).get().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::CommandFailed);
+        assert_eq!(err.subsystem(), Subsystem::Volume);
+    }
+
+    #[test]
+    fn missing_osascript_maps_to_command_not_found() {
+        let runner = Arc::new(
+            FakeRunner::new()
+                .expect(Expectation::new("osascript").spawn_error(std::io::ErrorKind::NotFound)),
+        );
+
+        let err = VolumeController::with_runner(runner).get().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::CommandNotFound);
+    }
+}
@@ -0,0 +1,251 @@
+//! Detecting changes by polling.
+//!
+//! macOS has no single notification for volume, brightness, player and
+//! Bluetooth changes, so a [`Watcher`] reads the selected subsystems on every
+//! [`Watcher::poll`] and reports the ones whose state differs from the previous
+//! poll. States are the JSON documents printed by the corresponding `mac`
+//! commands:
+//!
+//! | Subsystem    | State                                              |
+//! |--------------|----------------------------------------------------|
+//! | `volume`     | `{"volume": 40, "muted": false}`                   |
+//! | `brightness` | `{"brightness": 75}`                               |
+//! | `music`      | `{"state": "playing", "track": {...}}`, or `null` when Music is not running |
+//! | `bluetooth`  | `{"devices": [...]}`                               |
+//!
+//! ```no_run
+//! use mac_cli::{Backend, MusicController, Subsystem};
+//! use mac_cli::watch::Watcher;
+//!
+//! let mut watcher = Watcher::new(Backend::system(), MusicController::new());
+//! loop {
+//!     for change in watcher.poll(&[Subsystem::Volume, Subsystem::Music]) {
+//!         println!("{} is now {}", change.subsystem, change.value);
+//!     }
+//!     std::thread::sleep(std::time::Duration::from_secs(1));
+//! }
+//! ```
+
+use crate::backend::Backend;
+use crate::bluetooth::BluetoothController;
+use crate::brightness::BrightnessController;
+use crate::error::{ErrorKind, Result, Subsystem};
+use crate::music::MusicController;
+use crate::output::percent;
+use crate::volume::VolumeController;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+
+/// The subsystems a [`Watcher`] can observe.
+pub const WATCHABLE: [Subsystem; 4] = [
+    Subsystem::Volume,
+    Subsystem::Brightness,
+    Subsystem::Music,
+    Subsystem::Bluetooth,
+];
+
+/// Commands polling on a timer read Bluetooth on every Nth tick only:
+/// `system_profiler` takes about a second.
+pub const BLUETOOTH_POLL_EVERY: u64 = 10;
+
+/// A subsystem whose state changed between two polls.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    pub subsystem: Subsystem,
+    /// The new state.
+    pub value: Value,
+    /// The state at the previous poll.
+    pub previous: Value,
+}
+
+/// Polls subsystems and remembers their last state.
+pub struct Watcher {
+    backend: Backend,
+    music: MusicController,
+    /// Opened on first use.
+    brightness: Option<BrightnessController>,
+    last: BTreeMap<Subsystem, Value>,
+}
+
+impl Watcher {
+    /// Creates a watcher that has not seen any state yet.
+    pub fn new(backend: Backend, music: MusicController) -> Self {
+        Watcher {
+            backend,
+            music,
+            brightness: None,
+            last: BTreeMap::new(),
+        }
+    }
+
+    /// Reads each of `subsystems` and returns those that changed since the
+    /// last poll, in the given order.
+    ///
+    /// The first reading of a subsystem only records its state. Subsystems
+    /// that cannot be read are skipped and keep their last state; those that
+    /// are not in [`WATCHABLE`] are ignored.
+    pub fn poll(&mut self, subsystems: &[Subsystem]) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for &subsystem in subsystems {
+            let value = match self.read(subsystem) {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::debug!(%subsystem, error = %e, "watch: reading failed");
+                    continue;
+                }
+            };
+
+            match self.last.insert(subsystem, value.clone()) {
+                Some(previous) if previous != value => changes.push(Change {
+                    subsystem,
+                    value,
+                    previous,
+                }),
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    /// Forgets every state, so the next poll only records them again.
+    pub fn reset(&mut self) {
+        self.last.clear();
+    }
+
+    /// Returns the last state seen for `subsystem`.
+    pub fn last(&self, subsystem: Subsystem) -> Option<&Value> {
+        self.last.get(&subsystem)
+    }
+
+    fn read(&mut self, subsystem: Subsystem) -> Result<Option<Value>> {
+        let value = match subsystem {
+            Subsystem::Volume => {
+                let volume = VolumeController::with_backend(self.backend.clone());
+                json!({ "volume": percent(volume.get()?), "muted": volume.muted()? })
+            }
+            Subsystem::Brightness => {
+                if self.brightness.is_none() {
+                    match BrightnessController::with_backend(&self.backend) {
+                        Ok(controller) => self.brightness = Some(controller),
+                        // No adjustable display: nothing to watch
+                        Err(e) if e.kind() == ErrorKind::Unavailable => return Ok(None),
+                        Err(e) => return Err(e),
+                    }
+                }
+                let controller = self.brightness.as_ref().expect("opened above");
+                json!({ "brightness": percent(controller.get()?) })
+            }
+            Subsystem::Music => match self.music.now_playing() {
+                Ok(now) => json!(now),
+                Err(e) if e.kind() == ErrorKind::AppNotRunning => Value::Null,
+                Err(e) => return Err(e),
+            },
+            Subsystem::Bluetooth => {
+                let devices = BluetoothController::with_backend(self.backend.clone()).devices()?;
+                json!({ "devices": devices })
+            }
+            Subsystem::Weather | Subsystem::Cli => return Ok(None),
+        };
+        Ok(Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimStore;
+    use crate::test_support::{remove_with_lock, temp_path};
+
+    fn sim(name: &str) -> (SimStore, Watcher) {
+        let store = SimStore::new(temp_path("watch", name, "json"));
+        let backend = Backend::Sim(store.clone());
+        let watcher = Watcher::new(backend.clone(), MusicController::with_backend(backend));
+        (store, watcher)
+    }
+
+    fn set_volume(store: &SimStore, volume: f32) {
+        store
+            .update(Subsystem::Volume, |state| {
+                state.volume = volume;
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn first_poll_only_records_the_state() {
+        let (store, mut watcher) = sim("first");
+        let changes = watcher.poll(&[Subsystem::Volume, Subsystem::Brightness]);
+        remove_with_lock(store.path());
+
+        assert!(changes.is_empty());
+        assert_eq!(
+            watcher.last(Subsystem::Volume),
+            Some(&json!({ "volume": 50, "muted": false }))
+        );
+        assert_eq!(
+            watcher.last(Subsystem::Brightness),
+            Some(&json!({ "brightness": 75 }))
+        );
+    }
+
+    #[test]
+    fn changes_carry_the_previous_state() {
+        let (store, mut watcher) = sim("change");
+        watcher.poll(&[Subsystem::Volume, Subsystem::Brightness]);
+        set_volume(&store, 0.3);
+        let changes = watcher.poll(&[Subsystem::Volume, Subsystem::Brightness]);
+        let unchanged = watcher.poll(&[Subsystem::Volume, Subsystem::Brightness]);
+        remove_with_lock(store.path());
+
+        assert_eq!(
+            changes,
+            [Change {
+                subsystem: Subsystem::Volume,
+                value: json!({ "volume": 30, "muted": false }),
+                previous: json!({ "volume": 50, "muted": false }),
+            }]
+        );
+        assert!(unchanged.is_empty());
+    }
+
+    #[test]
+    fn failed_reads_keep_the_last_state() {
+        let (store, mut watcher) = sim("failed");
+        set_volume(&store, 0.3);
+        watcher.poll(&[Subsystem::Volume]);
+
+        let saved = std::fs::read_to_string(store.path()).unwrap();
+        std::fs::write(store.path(), "not json").unwrap();
+        let failed = watcher.poll(&[Subsystem::Volume]);
+        let during = watcher.last(Subsystem::Volume).cloned();
+
+        std::fs::write(store.path(), saved).unwrap();
+        let after = watcher.poll(&[Subsystem::Volume]);
+        remove_with_lock(store.path());
+
+        assert!(failed.is_empty());
+        assert_eq!(during, Some(json!({ "volume": 30, "muted": false })));
+        assert!(after.is_empty());
+    }
+
+    #[test]
+    fn reset_forgets_every_state() {
+        let (store, mut watcher) = sim("reset");
+        watcher.poll(&[Subsystem::Volume]);
+        watcher.reset();
+        assert_eq!(watcher.last(Subsystem::Volume), None);
+
+        set_volume(&store, 0.3);
+        let changes = watcher.poll(&[Subsystem::Volume]);
+        remove_with_lock(store.path());
+
+        assert!(changes.is_empty());
+        assert_eq!(
+            watcher.last(Subsystem::Volume),
+            Some(&json!({ "volume": 30, "muted": false }))
+        );
+    }
+}
@@ -2,55 +2,477 @@
 //!
 //! This module provides an interface to fetch current weather information
 //! for a given location or auto-detected location using the wttr.in API.
+//! Reports can be kept in a [`WeatherCache`] so that frequent callers, such as
+//! prompts running `mac status`, don't query wttr.in on every refresh.
 
-use std::process::Command;
+use crate::backend::Backend;
+use crate::error::{ErrorKind, MacCliError, Result, Subsystem};
+use crate::runner::{self, CommandOutput, CommandRunner, CommandSpec};
+use crate::sim::SimState;
+use chrono::{DateTime, Local};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Characters escaped in the location part of a wttr.in URL: everything but
+/// letters, digits and the punctuation of place names, such as `Saint-Malo`,
+/// `London, UK` and `~Eiffel Tower`.
+const LOCATION: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b',');
+
+/// Longest a request to wttr.in may take, in seconds. Callers such as
+/// `mac hooks` fetch in the same loop as everything else.
+const FETCH_TIMEOUT_SECS: u64 = 10;
+
+/// Units used when presenting temperatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    /// Degrees Celsius.
+    #[default]
+    Metric,
+    /// Degrees Fahrenheit.
+    Imperial,
+}
+
+impl Units {
+    /// Converts a Celsius temperature to these units.
+    pub fn convert(&self, celsius: f32) -> f32 {
+        match self {
+            Units::Metric => celsius,
+            Units::Imperial => celsius * 9.0 / 5.0 + 32.0,
+        }
+    }
+
+    /// The temperature suffix, `°C` or `°F`.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Units::Metric => "°C",
+            Units::Imperial => "°F",
+        }
+    }
+}
+
+/// Current weather at a location.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Weather {
+    pub location: String,
+    /// Textual description, e.g. "Partly cloudy".
+    pub condition: String,
+    /// Weather symbol, e.g. "⛅️".
+    pub icon: String,
+    /// Temperature in degrees Celsius, if reported.
+    pub temperature_c: Option<f32>,
+}
+
+impl Weather {
+    /// Formats the weather the way `wttr.in/?format=3` does,
+    /// e.g. `London: 🌧 +11°C`.
+    pub fn summary(&self) -> String {
+        self.summary_in(Units::Metric)
+    }
+
+    /// Like [`Self::summary`], with the temperature in the given units.
+    pub fn summary_in(&self, units: Units) -> String {
+        match self.temperature_c {
+            Some(t) => format!(
+                "{}: {} {:+.0}{}",
+                self.location,
+                self.icon,
+                units.convert(t),
+                units.symbol()
+            ),
+            None => format!("{}: {}", self.location, self.icon),
+        }
+    }
+}
 
 /// Controller for fetching weather information.
 ///
 /// Uses the wttr.in service to retrieve weather data without requiring API keys.
-pub struct WeatherController;
+pub struct WeatherController {
+    backend: Backend,
+    cache: Option<WeatherCache>,
+}
+
+impl Default for WeatherController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl WeatherController {
+    /// Creates a new weather controller.
+    pub fn new() -> Self {
+        Self::with_backend(Backend::system())
+    }
+
+    /// Creates a controller that executes commands through `runner`.
+    pub fn with_runner(runner: Arc<dyn CommandRunner>) -> Self {
+        Self::with_backend(Backend::System(runner))
+    }
+
+    /// Creates a controller for the given backend.
+    pub fn with_backend(backend: Backend) -> Self {
+        WeatherController {
+            backend,
+            cache: None,
+        }
+    }
+
+    /// Stores every report fetched by [`Self::current`] in `cache`, and lets
+    /// [`Self::cached`] answer from it.
+    ///
+    /// Only reports fetched from wttr.in are cached, not simulated ones.
+    pub fn cache(mut self, cache: WeatherCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
     /// Gets current weather information for a location.
     ///
     /// # Arguments
     ///
     /// * `location` - Optional location string (e.g., "San Francisco" or "London, UK").
-    ///                If None, the location is auto-detected based on IP address.
+    ///   If None, the location is auto-detected based on IP address.
     ///
     /// # Returns
     ///
     /// Returns a formatted weather string including location, conditions, and temperature in Celsius.
-    pub fn get_weather(location: Option<&str>) -> Result<String, String> {
+    pub fn get_weather(&self, location: Option<&str>) -> Result<String> {
+        let runner: &dyn CommandRunner = match &self.backend {
+            Backend::System(runner) => runner.as_ref(),
+            Backend::DryRun(plan) => plan.as_ref(),
+            Backend::Sim(sim) => {
+                return Self::sim_weather(&sim.load(Subsystem::Weather)?, location)
+                    .map(|w| w.summary());
+            }
+        };
+
+        self.fetch(runner, location, "3")
+    }
+
+    /// Gets structured weather information for a location.
+    ///
+    /// # Arguments
+    ///
+    /// * `location` - Optional location string. If None, the location is
+    ///   auto-detected based on IP address.
+    pub fn current(&self, location: Option<&str>) -> Result<Weather> {
+        let runner: &dyn CommandRunner = match &self.backend {
+            Backend::System(runner) => runner.as_ref(),
+            Backend::DryRun(plan) => plan.as_ref(),
+            Backend::Sim(sim) => {
+                return Self::sim_weather(&sim.load(Subsystem::Weather)?, location);
+            }
+        };
+
+        // location | condition | symbol | temperature
+        let raw = self.fetch(runner, location, "%l|%C|%c|%t")?;
+        let fields: Vec<&str> = raw.split('|').map(str::trim).collect();
+
+        if fields.len() != 4 {
+            return Err(MacCliError::new(
+                Subsystem::Weather,
+                ErrorKind::Parse,
+                format!("Unexpected weather data: {}", raw),
+            ));
+        }
+
+        let weather = Weather {
+            location: fields[0].to_string(),
+            condition: fields[1].to_string(),
+            icon: fields[2].to_string(),
+            temperature_c: fields[3].trim_end_matches("°C").parse().ok(),
+        };
+
+        if let Some(Err(e)) = self.live_cache().map(|cache| cache.put(location, &weather)) {
+            tracing::debug!(error = %e, "failed to update weather cache");
+        }
+        Ok(weather)
+    }
+
+    /// Like [`Self::current`], but returns the cached report for `location`
+    /// if it is younger than `max_age`.
+    pub fn cached(&self, location: Option<&str>, max_age: Duration) -> Result<Weather> {
+        if let Some(weather) = self
+            .live_cache()
+            .and_then(|cache| cache.get(location, max_age))
+        {
+            return Ok(weather);
+        }
+        self.current(location)
+    }
+
+    /// The cache, unless reports come from the simulation or a dry run.
+    fn live_cache(&self) -> Option<&WeatherCache> {
+        match self.backend {
+            Backend::System(_) => self.cache.as_ref(),
+            Backend::Sim(_) | Backend::DryRun(_) => None,
+        }
+    }
+
+    /// Fetches `wttr.in` with the given `format` parameter.
+    fn fetch(
+        &self,
+        runner: &dyn CommandRunner,
+        location: Option<&str>,
+        format: &str,
+    ) -> Result<String> {
         // Use wttr.in service which provides weather info without API keys
         // The 'm' parameter ensures metric units (Celsius)
         let url = if let Some(loc) = location {
-            format!("https://wttr.in/{}?format=3&m", loc.replace(' ', "+"))
+            // Locations come from remote callers too; wttr.in reads `+` as a space
+            let loc = utf8_percent_encode(loc, LOCATION)
+                .to_string()
+                .replace("%20", "+");
+            format!("https://wttr.in/{}?format={}&m", loc, format)
         } else {
             // Auto-detect location
-            "https://wttr.in/?format=3&m".to_string()
+            format!("https://wttr.in/?format={}&m", format)
         };
 
-        // Use curl to fetch weather data
-        let output = Command::new("curl")
-            .arg("-s") // silent mode
-            .arg(&url)
-            .output()
-            .map_err(|e| format!("Failed to execute curl: {}", e))?;
-
-        if !output.status.success() {
-            return Err(format!(
-                "Failed to fetch weather data: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
+        // Use curl to fetch weather data; curl exits non-zero for DNS,
+        // connection and timeout failures
+        let command = CommandSpec::new("curl")
+            .arg("-s")
+            .arg("--max-time")
+            .arg(FETCH_TIMEOUT_SECS.to_string())
+            .arg("--")
+            .arg(&url);
+        let started = Instant::now();
+        tracing::debug!(url = %url, "HTTP GET");
+        let output =
+            runner::run_checked(runner, Subsystem::Weather, &command, |o: &CommandOutput| {
+                MacCliError::new(
+                    Subsystem::Weather,
+                    ErrorKind::Network,
+                    format!(
+                        "Failed to fetch weather data (curl exit status {}): {}",
+                        o.status.unwrap_or(-1),
+                        o.stderr.trim()
+                    ),
+                )
+            });
+
+        let elapsed = started.elapsed();
+        match &output {
+            Ok(o) => {
+                tracing::debug!(url = %url, bytes = o.stdout.len(), elapsed = ?elapsed, "HTTP response")
+            }
+            Err(e) => {
+                tracing::debug!(url = %url, error = %e, elapsed = ?elapsed, "HTTP request failed")
+            }
         }
 
-        let weather = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let weather = output?.stdout.trim().to_string();
 
         if weather.is_empty() {
-            return Err("No weather data received".to_string());
+            return Err(MacCliError::new(
+                Subsystem::Weather,
+                ErrorKind::Network,
+                "No weather data received",
+            ));
         }
 
         Ok(weather)
     }
+
+    fn sim_weather(state: &SimState, location: Option<&str>) -> Result<Weather> {
+        let found = match location {
+            Some(loc) => state
+                .weather
+                .iter()
+                .find(|w| w.location.eq_ignore_ascii_case(loc.trim())),
+            None => state.weather.first(),
+        };
+
+        found.cloned().ok_or_else(|| {
+            MacCliError::new(
+                Subsystem::Weather,
+                ErrorKind::NotFound,
+                format!("Unknown location: {}", location.unwrap_or("(auto)")),
+            )
+        })
+    }
+}
+
+/// Weather reports by location, stored as JSON with the time they were fetched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeatherCache {
+    path: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    fetched: DateTime<Local>,
+    weather: Weather,
+}
+
+impl WeatherCache {
+    /// Uses the cache file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        WeatherCache { path: path.into() }
+    }
+
+    /// The default cache file, `~/.config/mac-cli/weather-cache.json`.
+    pub fn default_path() -> PathBuf {
+        crate::paths::config_dir().join("weather-cache.json")
+    }
+
+    /// The path of the cache file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the report for `location` if it was fetched less than
+    /// `max_age` ago. A missing or unreadable cache is empty.
+    pub fn get(&self, location: Option<&str>, max_age: Duration) -> Option<Weather> {
+        let entry = self.entries().remove(&Self::key(location))?;
+        let age = (Local::now() - entry.fetched).to_std().ok()?;
+        (age < max_age).then_some(entry.weather)
+    }
+
+    /// Stores the report for `location`, fetched now.
+    pub fn put(&self, location: Option<&str>, weather: &Weather) -> Result<()> {
+        let mut entries = self.entries();
+        entries.insert(
+            Self::key(location),
+            CacheEntry {
+                fetched: Local::now(),
+                weather: weather.clone(),
+            },
+        );
+
+        let io_error = |e: std::io::Error| {
+            MacCliError::new(
+                Subsystem::Weather,
+                ErrorKind::Other,
+                format!("Failed to write weather cache {}", self.path.display()),
+            )
+            .with_source(e)
+        };
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(io_error)?;
+        }
+
+        // Concurrent callers may refresh the cache; never leave a partial file
+        let json = serde_json::to_string_pretty(&entries).expect("cache is always serializable");
+        let tmp = self
+            .path
+            .with_extension(format!("json.{}.tmp", std::process::id()));
+        std::fs::write(&tmp, json + "\n").map_err(io_error)?;
+        std::fs::rename(&tmp, &self.path).map_err(io_error)
+    }
+
+    fn entries(&self) -> BTreeMap<String, CacheEntry> {
+        std::fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Locations are matched case-insensitively; the empty key is the
+    /// auto-detected location.
+    fn key(location: Option<&str>) -> String {
+        location
+            .map(|l| l.trim().to_lowercase())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{Expectation, FakeRunner};
+
+    fn curl(url: &str, output: CommandOutput) -> Arc<FakeRunner> {
+        Arc::new(
+            FakeRunner::new().expect(
+                Expectation::new("curl")
+                    .args(["-s", "--max-time", "10", "--", url])
+                    .returns(output),
+            ),
+        )
+    }
+
+    #[test]
+    fn current_parses_report() {
+        let runner = curl(
+            "https://wttr.in/New+York?format=%l|%C|%c|%t&m",
+            CommandOutput::success("New York | Partly cloudy | ⛅️ | +11°C\n"),
+        );
+
+        let weather = WeatherController::with_runner(runner.clone())
+            .current(Some("New York"))
+            .unwrap();
+        assert_eq!(
+            weather,
+            Weather {
+                location: "New York".into(),
+                condition: "Partly cloudy".into(),
+                icon: "⛅️".into(),
+                temperature_c: Some(11.0),
+            }
+        );
+        assert_eq!(weather.summary_in(Units::Imperial), "New York: ⛅️ +52°F");
+        runner.assert_done();
+    }
+
+    #[test]
+    fn location_cannot_change_the_request() {
+        let runner = curl(
+            "https://wttr.in/-o%2Fetc%2Fx%3Fformat%3Dj1%23%26a+b%2B?format=3&m",
+            CommandOutput::success("x: ☀️ +20°C"),
+        );
+
+        WeatherController::with_runner(runner.clone())
+            .get_weather(Some("-o/etc/x?format=j1#&a b+"))
+            .unwrap();
+        runner.assert_done();
+    }
+
+    #[test]
+    fn current_rejects_unexpected_report() {
+        let runner = curl(
+            "https://wttr.in/?format=%l|%C|%c|%t&m",
+            CommandOutput::success("Unknown location; please try ~London"),
+        );
+
+        let err = WeatherController::with_runner(runner)
+            .current(None)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Parse);
+    }
+
+    #[test]
+    fn curl_failure_maps_to_network() {
+        let runner = curl(
+            "https://wttr.in/?format=3&m",
+            CommandOutput::failure(6, "Could not resolve host: wttr.in"),
+        );
+
+        let err = WeatherController::with_runner(runner)
+            .get_weather(None)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Network);
+        assert_eq!(err.subsystem(), Subsystem::Weather);
+    }
+
+    #[test]
+    fn empty_response_maps_to_network() {
+        let runner = curl("https://wttr.in/?format=3&m", CommandOutput::success("\n"));
+
+        let err = WeatherController::with_runner(runner)
+            .get_weather(None)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Network);
+    }
 }